@@ -0,0 +1,107 @@
+//! Latencia comando → ack de un cliente WS mientras el servidor difunde telemetría a
+//! todo lo que da, con otro cliente conectado que no lee nada (su cola se llena y
+//! descarta telemetría sin frenar al resto). `cargo bench --features bench --bench ack_latency`
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use artheris::ws_server::WsContext;
+use artheris::ws_server::server::start_ws_server;
+use artheris::ws_server::transport::{TelemetryTransport, UdpTransport};
+use artheris::ws_server::udp::run_receiver;
+use common::Latencies;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::UdpSocket;
+use tokio_tungstenite::tungstenite::Message;
+
+const SAMPLES: usize = 300;
+
+type Ws = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    // ESP32 falso que contesta cada comando con su ack
+    let esp32 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let bridge = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let link: Arc<dyn TelemetryTransport> = Arc::new(UdpTransport::new(bridge, esp32.local_addr().unwrap()).unwrap());
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        while let Ok((n, from)) = esp32.recv_from(&mut buf).await {
+            let cmd: serde_json::Value = serde_json::from_slice(&buf[..n]).unwrap();
+            let ack = serde_json::json!({ "type": "ack", "request_id": cmd["request_id"], "ok": true });
+            let _ = esp32.send_to(ack.to_string().as_bytes(), from).await;
+        }
+    });
+
+    let mut ctx = WsContext::for_tests(Some(link.clone()));
+    let addr = free_addr();
+    ctx.ws_addr = Some(addr);
+    tokio::spawn(run_receiver(link, ctx.clone()));
+    tokio::spawn(start_ws_server(ctx.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut ws = connect(addr).await;
+    let _slow = connect(addr).await;
+
+    round_trips(&mut ws, "idle").await.row("ack sin telemetría");
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let firehose = {
+        let (ctx, stop) = (ctx.clone(), stop.clone());
+        tokio::spawn(async move {
+            let mut seq = 0u64;
+            while !stop.load(Ordering::Relaxed) {
+                let msg = serde_json::json!({ "type": "telemetry", "payload": { "seq": seq, "AngleRoll": 1.5 } });
+                ctx.publish(msg.to_string());
+                seq += 1;
+                if seq.is_multiple_of(64) {
+                    tokio::task::yield_now().await;
+                }
+            }
+            seq
+        })
+    };
+    let started = Instant::now();
+    round_trips(&mut ws, "load").await.row("ack con telemetría a tope");
+    stop.store(true, Ordering::Relaxed);
+    let published = firehose.await.unwrap();
+    common::rate("telemetría publicada", published, started.elapsed());
+
+    for c in ctx.clients.list() {
+        println!("cliente {:<3} enviados={:<9} descartados={}", c.id, c.sent, c.dropped);
+    }
+}
+
+/// `SAMPLES` comandos de uno en uno; cada latencia hasta ver su ack (la telemetría se salta)
+async fn round_trips(ws: &mut Ws, tag: &str) -> Latencies {
+    let mut samples = Vec::with_capacity(SAMPLES);
+    for i in 0..SAMPLES {
+        let rid = format!("{tag}-{i}");
+        let cmd = serde_json::json!({ "type": "command", "payload": { "command": "calibrate" }, "request_id": rid });
+        let sent = Instant::now();
+        ws.send(Message::Text(cmd.to_string())).await.unwrap();
+        loop {
+            let Some(Ok(Message::Text(text))) = ws.next().await else { continue };
+            let v: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if v["type"] == "ack" && v["request_id"] == rid.as_str() {
+                break;
+            }
+        }
+        samples.push(sent.elapsed());
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    Latencies::of(samples)
+}
+
+async fn connect(addr: SocketAddr) -> Ws {
+    let (ws, _) = tokio_tungstenite::connect_async_with_config(format!("ws://{addr}/"), None, true).await.unwrap();
+    ws
+}
+
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
@@ -0,0 +1,132 @@
+//! Reparto a clientes suscritos solo a acks: un único canal de `String` en el que cada
+//! consumidor vuelve a leer el `type` del JSON (como antes del `Bus`) frente al `Bus`,
+//! que lo clasifica una vez al publicar. Runtime de un hilo: el tiempo es el trabajo
+//! total del servidor. Después, ráfagas mayores que el canal: cuántos acks llegan.
+//! `cargo bench --features bench --bench bus_fanout`
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use artheris::ws_server::bus::{Bus, Class};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const MESSAGES: usize = 200_000;
+const CONSUMERS: usize = 8;
+/// Uno de cada `ACK_EVERY` es un ack; el resto, telemetría
+const ACK_EVERY: usize = 20;
+const ROUNDS: usize = 3;
+/// Canal pequeño y ráfagas que no caben, para el caso con pérdidas
+const SMALL_CAP: usize = 1024;
+const BURST: usize = 4096;
+
+#[derive(Deserialize)]
+struct Kind<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<&'a str>,
+}
+
+fn messages() -> Vec<String> {
+    (0..MESSAGES)
+        .map(|i| match i % ACK_EVERY {
+            0 => serde_json::json!({ "type": "ack", "request_id": format!("r-{i}"), "ok": true }).to_string(),
+            _ => serde_json::json!({
+                "type": "telemetry",
+                "payload": { "seq": i, "AngleRoll": 1.25, "AnglePitch": -0.5, "AngleYaw": 90.0, "alt": 12.5 }
+            }).to_string(),
+        })
+        .collect()
+}
+
+/// Todos los mensajes por un canal; cada consumidor parsea cada uno para filtrar.
+/// Devuelve el tiempo y los acks que recibió cada consumidor
+async fn single_channel(msgs: &[String], capacity: usize, burst: usize) -> (Duration, Vec<usize>) {
+    let (tx, _) = broadcast::channel::<String>(capacity);
+    let consumers: Vec<_> = (0..CONSUMERS)
+        .map(|_| {
+            let mut rx = tx.subscribe();
+            tokio::spawn(async move {
+                let mut acks = 0;
+                loop {
+                    match rx.recv().await {
+                        Ok(text) if serde_json::from_str::<Kind>(&text).ok().and_then(|k| k.kind) == Some("ack") => acks += 1,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return acks,
+                    }
+                }
+            })
+        })
+        .collect();
+    let started = Instant::now();
+    for chunk in msgs.chunks(burst) {
+        for text in chunk {
+            let _ = tx.send(text.clone());
+        }
+        tokio::task::yield_now().await;
+    }
+    drop(tx);
+    let mut acks = Vec::new();
+    for c in consumers {
+        acks.push(c.await.unwrap());
+    }
+    (started.elapsed(), acks)
+}
+
+/// Mismo tráfico por el `Bus`: la clase viene ya en el `Frame`
+async fn typed_bus(msgs: &[String], capacity: usize, burst: usize) -> (Duration, Vec<usize>) {
+    let bus = Bus::new(capacity);
+    let consumers: Vec<_> = (0..CONSUMERS)
+        .map(|_| {
+            let mut rx = bus.subscribe();
+            tokio::spawn(async move {
+                let mut acks = 0;
+                loop {
+                    match rx.recv().await {
+                        Ok(frame) if frame.class == Class::Ack => acks += 1,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return acks,
+                    }
+                }
+            })
+        })
+        .collect();
+    let started = Instant::now();
+    for chunk in msgs.chunks(burst) {
+        for text in chunk {
+            bus.publish(text.clone());
+        }
+        tokio::task::yield_now().await;
+    }
+    drop(bus);
+    let mut acks = Vec::new();
+    for c in consumers {
+        acks.push(c.await.unwrap());
+    }
+    (started.elapsed(), acks)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let msgs = messages();
+    let total_acks = MESSAGES / ACK_EVERY;
+
+    println!("{MESSAGES} mensajes (1 ack cada {ACK_EVERY}) a {CONSUMERS} consumidores, mejor de {ROUNDS}");
+    let mut best = (Duration::MAX, Duration::MAX);
+    for _ in 0..ROUNDS {
+        let (took, acks) = single_channel(&msgs, MESSAGES, MESSAGES).await;
+        assert!(acks.iter().all(|&a| a == total_acks));
+        best.0 = best.0.min(took);
+        let (took, acks) = typed_bus(&msgs, MESSAGES, MESSAGES).await;
+        assert!(acks.iter().all(|&a| a == total_acks));
+        best.1 = best.1.min(took);
+    }
+    common::rate("un canal, reparseo por cliente", MESSAGES as u64, best.0);
+    common::rate("Bus por clases", MESSAGES as u64, best.1);
+
+    println!("\nráfagas de {BURST} con canales de {SMALL_CAP}: acks recibidos por consumidor (de {total_acks})");
+    let (_, acks) = single_channel(&msgs, SMALL_CAP, BURST).await;
+    println!("{:<34} {:?}", "un canal", acks.iter().min().zip(acks.iter().max()).unwrap());
+    let (_, acks) = typed_bus(&msgs, SMALL_CAP, BURST).await;
+    println!("{:<34} {:?}", "Bus por clases", acks.iter().min().zip(acks.iter().max()).unwrap());
+}
@@ -0,0 +1,31 @@
+//! Utilidades compartidas por los benches (`harness = false`: cada uno es un `main`
+//! que imprime su tabla). Cada bench usa solo una parte
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+/// Percentiles de una serie de latencias
+pub struct Latencies {
+    pub n: usize,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    pub fn of(mut samples: Vec<Duration>) -> Self {
+        assert!(!samples.is_empty(), "sin muestras");
+        samples.sort();
+        let at = |q: f64| samples[((samples.len() - 1) as f64 * q).round() as usize];
+        Self { n: samples.len(), p50: at(0.50), p99: at(0.99), max: *samples.last().unwrap() }
+    }
+
+    pub fn row(&self, label: &str) {
+        println!("{label:<34} n={:<6} p50={:>9.3?} p99={:>9.3?} max={:>9.3?}", self.n, self.p50, self.p99, self.max);
+    }
+}
+
+/// Filas (o mensajes) por segundo
+pub fn rate(label: &str, n: u64, took: Duration) {
+    println!("{label:<34} {n:>9} en {took:>9.3?} → {:>12.0}/s", n as f64 / took.as_secs_f64());
+}
@@ -0,0 +1,94 @@
+//! Filas/s sostenidas de `OptionalDb::insert_flight_logs` por las dos vías de escritura:
+//! `INSERT` multi-fila por PG y líneas ILP por TCP, en lotes como los del `TelemetryWriter`.
+//! Con QuestDB (`QUESTDB_HOST`, `QUESTDB_PORT`, `QUESTDB_ILP_PORT`) se miden las dos contra
+//! él; sin QuestDB solo ILP, contra un socket local que descarta (serialización + TCP).
+//! `cargo bench --bench ingest_paths`
+
+mod common;
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use artheris::ws_server::OptionalDb;
+use artheris::ws_server::questdb::{LogRow, QuestDbConfig};
+use chrono::Utc;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+const ROWS: usize = 100_000;
+/// Filas por lote (`WriterConfig::default().batch_rows`)
+const BATCH: usize = 500;
+
+fn config(host: &str, port: u16, ilp_port: Option<u16>) -> QuestDbConfig {
+    QuestDbConfig {
+        host: host.into(),
+        port,
+        user: env::var("QUESTDB_USER").unwrap_or_else(|_| "admin".into()),
+        password: env::var("QUESTDB_PASSWORD").unwrap_or_else(|_| "quest".into()),
+        database: "qdb".into(),
+        pool_size: 2,
+        ilp_port,
+        write_timeout_ms: 30_000,
+        read_timeout_ms: 30_000,
+        allow_newer_schema: true,
+    }
+}
+
+fn rows() -> Vec<LogRow> {
+    let flight_id = format!("bench_{}", Utc::now().format("%Y%m%d_%H%M%S"));
+    let t0 = Utc::now();
+    (0..ROWS)
+        .map(|i| LogRow {
+            ts: t0 + chrono::Duration::microseconds(i as i64 * 250),
+            flight_id: flight_id.clone(),
+            payload: serde_json::json!({
+                "type": "telemetry",
+                "payload": { "seq": i, "AngleRoll": 1.25, "AnglePitch": -0.5, "AngleYaw": 90.0, "alt": 12.5, "note": "a \"b\"" }
+            }).to_string(),
+            typed: None,
+        })
+        .collect()
+}
+
+async fn ingest(db: &OptionalDb, rows: &[LogRow]) -> Duration {
+    let started = Instant::now();
+    for batch in rows.chunks(BATCH) {
+        db.insert_flight_logs(batch).await.expect("lote rechazado");
+    }
+    started.elapsed()
+}
+
+/// Servidor ILP falso: lee y descarta
+async fn sink() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 64 * 1024];
+                while matches!(stream.read(&mut buf).await, Ok(n) if n > 0) {}
+            });
+        }
+    });
+    port
+}
+
+#[tokio::main]
+async fn main() {
+    let rows = rows();
+    let host = env::var("QUESTDB_HOST").unwrap_or_else(|_| "localhost".into());
+    let port = env::var("QUESTDB_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8812);
+    let ilp_port = env::var("QUESTDB_ILP_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(9009);
+    println!("{ROWS} filas en lotes de {BATCH}");
+
+    let pg = OptionalDb::new(config(&host, port, None));
+    if pg.probe().await {
+        common::rate("PG INSERT (QuestDB)", ROWS as u64, ingest(&pg, &rows).await);
+        let ilp = OptionalDb::new(config(&host, port, Some(ilp_port)));
+        common::rate("ILP (QuestDB)", ROWS as u64, ingest(&ilp, &rows).await);
+    } else {
+        println!("{:<34} sin QuestDB en {host}:{port}, se omite", "PG INSERT");
+        let ilp = OptionalDb::new(config("127.0.0.1", port, Some(sink().await)));
+        common::rate("ILP (socket local)", ROWS as u64, ingest(&ilp, &rows).await);
+    }
+}
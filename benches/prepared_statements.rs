@@ -0,0 +1,97 @@
+//! Inserción fila a fila y lectura de puntos con las sentencias preparadas y cacheadas de
+//! `QuestDb` frente a mandar el texto SQL en cada llamada (que obliga a QuestDB a
+//! analizarlo y planificarlo cada vez). Necesita QuestDB: `QUESTDB_HOST`/`QUESTDB_PORT`
+//! (localhost:8812); sin él se omite. `cargo bench --bench prepared_statements`
+
+mod common;
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use artheris::ws_server::questdb::{QuestDb, QuestDbConfig};
+use chrono::{DateTime, Utc};
+use common::Latencies;
+use tokio_postgres::{Client, NoTls};
+
+const INSERTS: usize = 5_000;
+const FETCHES: usize = 200;
+
+/// Las mismas sentencias que usa `QuestDb`, pero como texto
+const INSERT_TEXT: &str = "INSERT INTO flight_logs (ts, flight_id, payload) VALUES (now(), $1, $2)";
+const FETCH_TEXT: &str = "SELECT ts, payload FROM flight_logs WHERE flight_id=$1 AND ts >= $2 AND ts <= $3 ORDER BY ts LIMIT $4";
+
+fn config() -> QuestDbConfig {
+    QuestDbConfig {
+        host: env::var("QUESTDB_HOST").unwrap_or_else(|_| "localhost".into()),
+        port: env::var("QUESTDB_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8812),
+        user: env::var("QUESTDB_USER").unwrap_or_else(|_| "admin".into()),
+        password: env::var("QUESTDB_PASSWORD").unwrap_or_else(|_| "quest".into()),
+        database: "qdb".into(),
+        pool_size: 2,
+        ilp_port: None,
+        write_timeout_ms: 30_000,
+        read_timeout_ms: 30_000,
+        allow_newer_schema: true,
+    }
+}
+
+/// Conexión aparte, sin la caché de sentencias de `QuestDb`
+async fn raw_client(cfg: &QuestDbConfig) -> Client {
+    let params = format!(
+        "host={} port={} user={} password={} dbname={}",
+        cfg.host, cfg.port, cfg.user, cfg.password, cfg.database
+    );
+    let (client, connection) = tokio_postgres::connect(&params, NoTls).await.unwrap();
+    tokio::spawn(connection);
+    client
+}
+
+fn payload(i: usize) -> String {
+    serde_json::json!({ "type": "telemetry", "payload": { "seq": i, "AngleRoll": 1.25, "alt": 12.5 } }).to_string()
+}
+
+#[tokio::main]
+async fn main() {
+    let cfg = config();
+    let db = match QuestDb::connect(cfg.clone()).await {
+        Ok(db) => db,
+        Err(e) => {
+            println!("sin QuestDB en {}:{} ({e}), se omite", cfg.host, cfg.port);
+            return;
+        }
+    };
+    let raw = raw_client(&cfg).await;
+    let stamp = Utc::now().format("%Y%m%d_%H%M%S");
+    let (prepared_fid, text_fid) = (format!("bench_prep_{stamp}"), format!("bench_text_{stamp}"));
+
+    println!("{INSERTS} inserciones de una fila");
+    let started = Instant::now();
+    for i in 0..INSERTS {
+        db.insert_flight_log(&prepared_fid, &payload(i)).await.unwrap();
+    }
+    common::rate("preparada (QuestDb)", INSERTS as u64, started.elapsed());
+    let started = Instant::now();
+    for i in 0..INSERTS {
+        raw.execute(INSERT_TEXT, &[&text_fid, &payload(i)]).await.unwrap();
+    }
+    common::rate("texto en cada llamada", INSERTS as u64, started.elapsed());
+
+    // Las tablas WAL tardan un poco en mostrar lo insertado
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    println!("\n{FETCHES} lecturas de hasta 1000 puntos");
+    let (from, to, limit) = (DateTime::<Utc>::UNIX_EPOCH, Utc::now() + chrono::Duration::days(1), 1000i64);
+    let mut samples = Vec::with_capacity(FETCHES);
+    for _ in 0..FETCHES {
+        let started = Instant::now();
+        db.fetch_flight_points(&prepared_fid, None, None, limit).await.unwrap();
+        samples.push(started.elapsed());
+    }
+    Latencies::of(samples).row("preparada (QuestDb)");
+    let mut samples = Vec::with_capacity(FETCHES);
+    for _ in 0..FETCHES {
+        let started = Instant::now();
+        raw.query(FETCH_TEXT, &[&prepared_fid, &from, &to, &limit]).await.unwrap();
+        samples.push(started.elapsed());
+    }
+    Latencies::of(samples).row("texto en cada llamada");
+}
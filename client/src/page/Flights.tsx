@@ -3,7 +3,8 @@
 import { useEffect, useState } from "react";
 import Link from "next/link";
 
-type FlightItem = { flight_id: string; last_ts: string };
+type FlightItem = { flight_id: string; first_ts: string; last_ts: string; points: number };
+type FlightList = { items: FlightItem[]; total: number; next: string | null };
 
 export default function FlightsPage() {
   const [loading, setLoading] = useState(true);
@@ -14,8 +15,8 @@ export default function FlightsPage() {
     (async () => {
       try {
         const res = await fetch("/api/flights?limit=100");
-        const data = (await res.json()) as FlightItem[];
-        setFlights(data);
+        const data = (await res.json()) as FlightList;
+        setFlights(data.items);
       } catch (e: any) {
         setErr(e?.message ?? "Error");
       } finally {
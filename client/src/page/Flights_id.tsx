@@ -58,12 +58,11 @@ export default function FlightDetailPage() {
         const res = await fetch(
           `/api/flights/${encodeURIComponent(id)}/series?` + qs.toString()
         );
-        const data = (await res.json()) as SeriesPoint[];
-        setSeries(data);
+        setSeries(res.ok ? ((await res.json()) as SeriesPoint[]) : []);
         const sum = await fetch(
           `/api/flights/${encodeURIComponent(id)}/summary`
         );
-        setSummary(await sum.json());
+        setSummary(sum.ok ? await sum.json() : null);
       } finally {
         setLoading(false);
       }
@@ -1,11 +1,24 @@
 use serde_json::json;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::broadcast;
+
+use crate::ws_server::WsContext;
+
+/// Envía `txt` al ESP32 y lo registra en `command_logs` (sin bloquear en la BD).
+/// Devuelve `false` si no hay socket o falló el envío UDP.
+async fn send_to_esp32(ctx: &WsContext, txt: &str, request_id: Option<&str>, label: &str) -> bool {
+    ctx.log_command(request_id, "out", txt);
+
+    let Some(link) = &ctx.esp32 else { return false };
+    match link.send(txt.as_bytes()).await {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("❌ Error enviando {label} al ESP32: {e}");
+            false
+        }
+    }
+}
 
 /// Mapa de alias -> número
-fn mode_str_to_num(s: &str) -> Option<u8> {
+pub fn mode_str_to_num(s: &str) -> Option<u8> {
     let s = s.trim().to_ascii_lowercase();
     match s.as_str() {
         "pilot" | "piloto" => Some(0),
@@ -19,11 +32,9 @@ fn mode_str_to_num(s: &str) -> Option<u8> {
 /// Envía modo como **número** si es posible (mejor para el ESP)
 pub async fn set_mode(
     mode: &str, // acepta "pilot", "manual", "idle|espera", o "0|1|2"
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     // 1) Normaliza a número si podemos
     let json_payload = if let Some(n) = mode_str_to_num(mode) {
         json!({"type":"command","payload":{"mode": n}})
@@ -35,15 +46,7 @@ pub async fn set_mode(
     let txt = json_payload.to_string();
 
     // 2) Enviar por UDP
-    let mut ok = true;
-    if let Some(socket) = esp32_socket {
-        if let Err(e) = socket.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando modo al ESP32: {}", e);
-            ok = false;
-        }
-    } else {
-        ok = false;
-    }
+    let ok = send_to_esp32(ctx, &txt, request_id, "modo").await;
 
     // 3) ACK opcional
     if let Some(rid) = request_id {
@@ -52,7 +55,7 @@ pub async fn set_mode(
         } else {
             json!({"type":"ack","request_id": rid, "ok": false, "info":"udp_send_failed_or_missing_socket"})
         };
-        let _ = ws_tx.send(ack.to_string());
+        ctx.send_ack(rid, ack.to_string());
     }
 
     // 4) Broadcast para tu UI (puedes mandar lo normalizado si quieres)
@@ -60,240 +63,183 @@ pub async fn set_mode(
         .map(|n| json!({"type":"modo","value": n}))
         .unwrap_or_else(|| json!({"type":"modo","value": mode}));
 
-    let _ = ws_tx.send(emitted.to_string());
+    let _ = ctx.broadcast(emitted.to_string());
 
     println!(
         "📤 Enviando comando de MODO al ESP32: {}",
         mode
     );
+    ok
 }
 
 pub async fn set_motor_one_speed(
     id: u32,
     us: u32,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     let payload = json!({
         "type":"command",
         "payload": { "motor": { "id": id, "speed": us } }
     });
     let txt = payload.to_string();
 
-    let mut ok = true;
-    if let Some(sock) = esp32_socket {
-        if let Err(e) = sock.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando MOTOR ONE SPEED: {e}");
-            ok = false;
-        }
-    } else { ok = false; }
+    let ok = send_to_esp32(ctx, &txt, request_id, "MOTOR ONE SPEED").await;
 
     if let Some(rid) = request_id {
-        let _ = ws_tx.send(json!({
+        ctx.send_ack(rid, json!({
             "type":"ack", "request_id": rid, "ok": ok
         }).to_string());
     }
-    let _ = ws_tx.send(json!({
+    let _ = ctx.broadcast(json!({
         "type":"motor","target":"one","id": id,"speed": us
     }).to_string());
+    ok
 }
 
 pub async fn set_motors_many_speed(
     ids: &[u32],
     us: u32,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     let payload = json!({
         "type":"command",
         "payload": { "motors": { "ids": ids, "speed": us } }
     });
     let txt = payload.to_string();
 
-    let mut ok = true;
-    if let Some(sock) = esp32_socket {
-        if let Err(e) = sock.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando MOTORS MANY SPEED: {e}");
-            ok = false;
-        }
-    } else { ok = false; }
+    let ok = send_to_esp32(ctx, &txt, request_id, "MOTORS MANY SPEED").await;
 
     if let Some(rid) = request_id {
-        let _ = ws_tx.send(json!({
+        ctx.send_ack(rid, json!({
             "type":"ack", "request_id": rid, "ok": ok
         }).to_string());
     }
     if ok {
         for &id in ids {
-            let _ = ws_tx.send(json!({
+            let _ = ctx.broadcast(json!({
                 "type":"motor","target":"one","id": id,"speed": us
             }).to_string());
         }
     }
+    ok
 }
 
 pub async fn set_motors_all_speed(
     us: u32,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     let payload = json!({
         "type":"command",
         "payload": { "motors": { "speed": us } }
     });
     let txt = payload.to_string();
 
-    let mut ok = true;
-    if let Some(sock) = esp32_socket {
-        if let Err(e) = sock.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando MOTORS ALL SPEED: {e}");
-            ok = false;
-        }
-    } else { ok = false; }
+    let ok = send_to_esp32(ctx, &txt, request_id, "MOTORS ALL SPEED").await;
 
     if let Some(rid) = request_id {
-        let _ = ws_tx.send(json!({
+        ctx.send_ack(rid, json!({
             "type":"ack", "request_id": rid, "ok": ok
         }).to_string());
     }
-    let _ = ws_tx.send(json!({
+    let _ = ctx.broadcast(json!({
         "type":"motors","target":"all","speed": us
     }).to_string());
+    ok
 }
 
 
 pub async fn set_led_all(
     on: bool,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     let payload = json!({
         "type": "command",
         "payload": { "led": on }
     });
     let txt = payload.to_string();
 
-    let mut ok = true;
-    if let Some(sock) = esp32_socket {
-        if let Err(e) = sock.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando LED ALL al ESP32: {}", e);
-            ok = false;
-        }
-    } else {
-        ok = false;
-    }
+    let ok = send_to_esp32(ctx, &txt, request_id, "LED ALL").await;
     if let Some(rid) = request_id {
         let ack = if ok {
             json!({"type":"ack","request_id": rid, "ok": true})
         } else {
             json!({"type":"ack","request_id": rid, "ok": false, "info":"udp_send_failed_or_missing_socket"})
         };
-        let _ = ws_tx.send(ack.to_string());
+        ctx.send_ack(rid, ack.to_string());
     }
-    let _ = ws_tx.send(json!({"type":"led","target":"all","value": on}).to_string());
+    let _ = ctx.broadcast(json!({"type":"led","target":"all","value": on}).to_string());
+    ok
 }
 
 /// Un LED específico
 pub async fn set_led_one(
     id: u32,
     on: bool,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     let payload = json!({
         "type": "command",
         "payload": { "led": { "id": id, "state": on } }
     });
     let txt = payload.to_string();
 
-    let mut ok = true;
-    if let Some(sock) = esp32_socket {
-        if let Err(e) = sock.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando LED ONE al ESP32: {}", e);
-            ok = false;
-        }
-    } else {
-        ok = false;
-    }
+    let ok = send_to_esp32(ctx, &txt, request_id, "LED ONE").await;
     if let Some(rid) = request_id {
         let ack = if ok {
             json!({"type":"ack","request_id": rid, "ok": true})
         } else {
             json!({"type":"ack","request_id": rid, "ok": false, "info":"udp_send_failed_or_missing_socket"})
         };
-        let _ = ws_tx.send(ack.to_string());
+        ctx.send_ack(rid, ack.to_string());
     }
-    let _ = ws_tx.send(json!({"type":"led","target":"one","id": id,"value": on}).to_string());
+    let _ = ctx.broadcast(json!({"type":"led","target":"one","id": id,"value": on}).to_string());
+    ok
 }
 
 /// Varios LEDs a la vez
 pub async fn set_led_many(
     ids: &[u32],
     on: bool,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>,
-) {
+) -> bool {
     let payload = json!({
         "type": "command",
         "payload": { "leds": { "ids": ids, "state": on } }
     });
     let txt = payload.to_string();
 
-    let mut ok = true;
-    if let Some(sock) = esp32_socket {
-        if let Err(e) = sock.send_to(txt.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando LED MANY al ESP32: {}", e);
-            ok = false;
-        }
-    } else {
-        ok = false;
-    }
+    let ok = send_to_esp32(ctx, &txt, request_id, "LED MANY").await;
     if let Some(rid) = request_id {
         let ack = if ok {
             json!({"type":"ack","request_id": rid, "ok": true})
         } else {
             json!({"type":"ack","request_id": rid, "ok": false, "info":"udp_send_failed_or_missing_socket"})
         };
-        let _ = ws_tx.send(ack.to_string());
+        ctx.send_ack(rid, ack.to_string());
     }
     if ok {
         for &id in ids {
-            let _ = ws_tx.send(json!({"type":"led","target":"one","id": id,"value": on}).to_string());
+            let _ = ctx.broadcast(json!({"type":"led","target":"one","id": id,"value": on}).to_string());
         }
     }
+    ok
 }
 
 /// Enciende o apaga los motores y notifica
 pub async fn set_motors_state(
     motors_on: bool,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
+    ctx: &WsContext,
     request_id: Option<&str>, // 👈 nuevo
-) {
+) -> bool {
     let command = format!(r#"{{"type":"command","payload":{{"motors":{}}}}}"#, motors_on);
 
-    let mut ok = true;
-    if let Some(socket) = esp32_socket {
-        if let Err(e) = socket.send_to(command.as_bytes(), remote_addr).await {
-            eprintln!("❌ Error enviando motores al ESP32: {}", e);
-            ok = false;
-        }
-    } else {
-        ok = false;
-    }
+    let ok = send_to_esp32(ctx, &command, request_id, "motores").await;
 
     // ACK
     if let Some(rid) = request_id {
@@ -302,10 +248,11 @@ pub async fn set_motors_state(
         } else {
             json!({"type":"ack","request_id": rid, "ok": false, "info":"udp_send_failed_or_missing_socket"})
         };
-        let _ = ws_tx.send(ack.to_string());
+        ctx.send_ack(rid, ack.to_string());
     }
-    let _ = ws_tx.send(json!({"type":"motors","value": motors_on}).to_string());
+    let _ = ctx.broadcast(json!({"type":"motors","value": motors_on}).to_string());
 
     println!("📤 Enviando comando de MOTORES al ESP32: {}", if motors_on { "ON" } else { "OFF" });
+    ok
 }
 
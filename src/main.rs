@@ -2,20 +2,39 @@ use std::sync::Arc;
 use std::net::SocketAddr;
 use tokio::net::UdpSocket;
 use tokio::sync::{broadcast, RwLock};
-use tokio::io::BufReader;
 use std::env;
+use std::time::Duration;
 use tracing::{info, warn, error};
 use tracing_subscriber::{EnvFilter, fmt};
 use tracing_appender::rolling;
+use anyhow::Context;
+use tokio_util::sync::CancellationToken;
 
-mod config;
-mod ws_server;
+use artheris::ws_server;
 
 use tracing_subscriber::prelude::*;
 
-use crate::ws_server::{start_ws_server, start_http_server, WsContext};
-use crate::ws_server::questdb::{QuestDb, QuestDbConfig};
+use crate::ws_server::{start_ws_server, start_http_server, stop_recording_on_shutdown, WsContext};
+use crate::ws_server::questdb::{QuestDb, QuestDbConfig, SchemaTooNew};
 use crate::ws_server::OptionalDb;
+use crate::ws_server::auth::AuthConfig;
+use crate::ws_server::cors::CorsOrigins;
+use crate::ws_server::params;
+use crate::ws_server::capture::Capture;
+use crate::ws_server::logger_config::AppliedConfig;
+use crate::ws_server::clients::KeepaliveConfig;
+use crate::ws_server::ratelimit::{HttpRateLimiter, HttpRateLimits, RateLimitConfig, RateLimits};
+use crate::ws_server::tls::load_acceptor;
+use crate::ws_server::clock::ClockSync;
+use crate::ws_server::failover::{run_failover, RemoteFailover};
+use crate::ws_server::schema::SchemaValidator;
+use crate::ws_server::transport::{SerialTransport, TelemetryTransport, UdpTransport};
+use crate::ws_server::bus::Bus;
+use crate::ws_server::status::run_status;
+use crate::ws_server::ws_stats::BroadcastStats;
+use crate::ws_server::udp::{broadcast_timing_stats, run_rebind_watchdog, run_receiver, CsvMapping, StreamRate, UdpStats};
+use crate::ws_server::writer::{TelemetryWriter, WriterConfig};
+use crate::ws_server::spool::Spool;
 
 fn init_logging() -> anyhow::Result<()> {
     // Log a archivo rotativo diario en ./logs/artheris.log.YYYY-MM-DD
@@ -44,6 +63,55 @@ fn init_logging() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// "8889, 8890" → [8889, 8890]
+fn parse_port_list(v: &str) -> anyhow::Result<Vec<u16>> {
+    let ports = v
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse::<u16>().with_context(|| format!("puerto UDP inválido: {p:?}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if ports.is_empty() {
+        anyhow::bail!("ARTHERIS_UDP_PORTS no contiene ningún puerto");
+    }
+    Ok(ports)
+}
+
+/// "192.168.1.50:8888, 192.168.4.1" → direcciones (sin puerto usa `default_port`)
+fn parse_addr_list(v: &str, default_port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs = v
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| {
+            a.parse::<SocketAddr>()
+                .or_else(|_| a.parse::<std::net::IpAddr>().map(|ip| SocketAddr::new(ip, default_port)))
+                .with_context(|| format!("dirección de ESP32 inválida: {a:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if addrs.is_empty() {
+        anyhow::bail!("ARTHERIS_ESP32_ADDRS no contiene ninguna dirección");
+    }
+    Ok(addrs)
+}
+
+#[cfg(feature = "sqlite")]
+fn open_embedded(config: QuestDbConfig, path: &str) -> OptionalDb {
+    match ws_server::sqlite::SqliteStore::open(path) {
+        Ok(store) => OptionalDb::with_store(config, Arc::new(store)),
+        Err(e) => {
+            error!("❌ No se pudo abrir el SQLite {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_embedded(_config: QuestDbConfig, _path: &str) -> OptionalDb {
+    error!("❌ ARTHERIS_STORAGE=sqlite necesita compilar con --features sqlite");
+    std::process::exit(1);
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     if let Err(e) = init_logging() {
@@ -58,11 +126,37 @@ async fn main() -> anyhow::Result<()> {
         user: env::var("QUESTDB_USER").unwrap_or_else(|_| "admin".into()),
         password: env::var("QUESTDB_PASSWORD").unwrap_or_else(|_| "quest".into()),
         database: env::var("QUESTDB_DB").unwrap_or_else(|_| "qdb".into()),
+        // QUESTDB_POOL_SIZE conexiones: una para escrituras, el resto para lecturas
+        pool_size: env::var("QUESTDB_POOL_SIZE").ok().and_then(|v| v.parse().ok()).filter(|n| *n > 1).unwrap_or(4),
+        // QUESTDB_ILP_PORT=9009 → telemetría por ILP (más filas/s); sin él, INSERT por PG
+        ilp_port: env::var("QUESTDB_ILP_PORT").ok().and_then(|p| p.parse().ok()),
+        // Topes por operación: QUESTDB_WRITE_TIMEOUT_MS (inserciones) y QUESTDB_READ_TIMEOUT_MS (lecturas)
+        write_timeout_ms: env::var("QUESTDB_WRITE_TIMEOUT_MS").ok().and_then(|v| v.parse().ok()).filter(|ms| *ms > 0).unwrap_or(5_000),
+        read_timeout_ms: env::var("QUESTDB_READ_TIMEOUT_MS").ok().and_then(|v| v.parse().ok()).filter(|ms| *ms > 0).unwrap_or(30_000),
+        // QUESTDB_ALLOW_NEWER_SCHEMA=1 → arrancar aunque la BD venga de un binario más nuevo
+        allow_newer_schema: env::var("QUESTDB_ALLOW_NEWER_SCHEMA").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true")),
     };
 
     info!("🔧 Configuración de QuestDB: host={} port={}", questdb_config.host, questdb_config.port);
+    if let Some(port) = questdb_config.ilp_port {
+        info!("🔧 Telemetría por ILP en {}:{port}", questdb_config.host);
+    }
+
+    // ARTHERIS_STORAGE=sqlite:<fichero> → telemetría en un SQLite local en vez de QuestDB
+    // (requiere la feature `sqlite`); por defecto `questdb`
+    let storage = env::var("ARTHERIS_STORAGE").unwrap_or_else(|_| "questdb".into());
+    let embedded_path = match storage.split_once(':') {
+        Some(("sqlite", path)) if !path.is_empty() => Some(path.to_owned()),
+        _ if storage == "questdb" => None,
+        _ => {
+            error!("❌ ARTHERIS_STORAGE no válido: {storage} (questdb o sqlite:<fichero>)");
+            std::process::exit(1);
+        }
+    };
 
-    let qdb = {
+    let qdb = if let Some(path) = embedded_path {
+        open_embedded(questdb_config.clone(), &path)
+    } else {
         let db = OptionalDb::new(questdb_config.clone());
 
         match QuestDb::connect(questdb_config.clone()).await {
@@ -70,6 +164,10 @@ async fn main() -> anyhow::Result<()> {
                 info!("✅ Conectado a QuestDB");
                 db
             }
+            Err(e) if e.is::<SchemaTooNew>() => {
+                error!("❌ Esquema de QuestDB más nuevo que este binario; QUESTDB_ALLOW_NEWER_SCHEMA=1 para arrancar igualmente");
+                std::process::exit(1);
+            }
             Err(e) => {
                 warn!("⚠️  No se pudo conectar a QuestDB al inicio: {e}. Se intentará bajo demanda.");
                 db
@@ -79,45 +177,283 @@ async fn main() -> anyhow::Result<()> {
 
     // 🔹 Estado compartido
     let current_flight_id: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
-    let last_config: Arc<RwLock<Option<serde_json::Value>>> = Arc::new(RwLock::new(None));
+    let last_config: Arc<RwLock<Option<AppliedConfig>>> = Arc::new(RwLock::new(None));
+    // Huecos de telemetría mayores a esto se registran con warn
+    let gap_warn_ms: u64 = env::var("ARTHERIS_UDP_GAP_WARN_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(500);
+    let udp_stats = Arc::new(UdpStats::new(Duration::from_millis(gap_warn_ms)));
+    // Campo de la telemetría con su propio timestamp absoluto (p. ej. ARTHERIS_TS_FIELD=ts);
+    // sin él se usa `t_us` corregido o la llegada por UDP
+    let ts_field = env::var("ARTHERIS_TS_FIELD").ok().filter(|f| !f.trim().is_empty());
+    // Máx. mensajes/s de telemetría hacia WS (0 = sin límite)
+    let stream_max_hz: u32 = env::var("ARTHERIS_STREAM_MAX_HZ").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+    let stream_rate = Arc::new(StreamRate::new(stream_max_hz));
+    // Captura cruda de datagramas (rotación por tamaño, 64 MB por defecto)
+    let capture_max: u64 = env::var("ARTHERIS_CAPTURE_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(64 * 1024 * 1024);
+    let capture = Arc::new(Capture::new("./captures", capture_max));
 
     // Canal broadcast para WS
-    let (tx, _) = broadcast::channel::<String>(100);
+    // Capacidad del canal broadcast para WS (ARTHERIS_WS_CHANNEL_CAP)
+    let ws_channel_cap: usize = env::var("ARTHERIS_WS_CHANNEL_CAP").ok().and_then(|v| v.parse().ok()).filter(|c| *c > 0).unwrap_or(100);
+    let (tx, _) = broadcast::channel::<String>(ws_channel_cap);
 
-    // --------- UDP ----------
-    const LOCAL_PORT: u16 = 8889;
+    // --------- Enlace con el ESP32 ----------
+    // Serie (ARTHERIS_SERIAL_PORT=/dev/ttyUSB0) como alternativa a UDP.
+    // Puertos UDP locales: ARTHERIS_UDP_PORTS=8889,8890 (el primero es el socket de comandos)
+    const DEFAULT_LOCAL_PORT: u16 = 8889;
     const REMOTE_IP: &str = "192.168.1.50";
     const REMOTE_PORT: u16 = 8888;
 
-    let local_addr = format!("0.0.0.0:{}", LOCAL_PORT);
-    let remote_addr: SocketAddr = format!("{}:{}", REMOTE_IP, REMOTE_PORT).parse().unwrap();
+    let mut links: Vec<Arc<dyn TelemetryTransport>> = Vec::new();
+    let mut udp_links: Vec<Arc<UdpTransport>> = Vec::new();
+    let mut remote_addrs: Vec<SocketAddr> = Vec::new();
+    if let Ok(path) = env::var("ARTHERIS_SERIAL_PORT") {
+        let baud: u32 = env::var("ARTHERIS_SERIAL_BAUD").ok().and_then(|b| b.parse().ok()).unwrap_or(115_200);
+        links.push(Arc::new(SerialTransport::open(&path, baud)?));
+        println!("✅ Serial abierto en {} @ {} baud", path, baud);
+    } else {
+        let local_ports = match env::var("ARTHERIS_UDP_PORTS") {
+            Ok(v) => parse_port_list(&v)?,
+            Err(_) => vec![DEFAULT_LOCAL_PORT],
+        };
+        // Direcciones del ESP32 en orden de prioridad: ARTHERIS_ESP32_ADDRS=192.168.1.50:8888,192.168.4.1:8888
+        remote_addrs = match env::var("ARTHERIS_ESP32_ADDRS") {
+            Ok(v) => parse_addr_list(&v, REMOTE_PORT)?,
+            Err(_) => vec![format!("{}:{}", REMOTE_IP, REMOTE_PORT).parse().unwrap()],
+        };
+        let remote_addr = remote_addrs[0];
+
+        // Bind UDP local (error claro con el puerto que falló)
+        for port in local_ports {
+            let local_addr = format!("0.0.0.0:{}", port);
+            let sock = UdpSocket::bind(&local_addr)
+                .await
+                .with_context(|| format!("no se pudo enlazar UDP en {local_addr}"))?;
+            println!("✅ UDP listening on {}", local_addr);
+            let udp = Arc::new(UdpTransport::new(sock, remote_addr)?);
+            udp_links.push(udp.clone());
+            links.push(udp);
+        }
+    }
+    let link = links[0].clone();
+
+    // Failover entre direcciones del ESP32 sobre el socket de comandos
+    let silence_ms: u64 = env::var("ARTHERIS_ESP32_SILENCE_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(3000);
+    let esp32_failover = udp_links.first()
+        .map(|udp| Arc::new(RemoteFailover::new(udp.clone(), remote_addrs.clone(), Duration::from_millis(silence_ms))));
+
+    // Token compartido opcional para WS y HTTP (ARTHERIS_TOKEN); sin él todo queda abierto
+    let auth = AuthConfig {
+        token: env::var("ARTHERIS_TOKEN").ok().filter(|t| !t.is_empty()),
+        ws_read_only: env::var("ARTHERIS_WS_READONLY").is_ok_and(|v| v == "1" || v == "true"),
+        // Orígenes permitidos para el WS: ARTHERIS_WS_ORIGINS=http://groundstation:5173,https://ui.local
+        allowed_origins: env::var("ARTHERIS_WS_ORIGINS").ok()
+            .map(|v| v.split(',').map(|o| o.trim().to_string()).filter(|o| !o.is_empty()).collect::<Vec<_>>())
+            .filter(|list| !list.is_empty()),
+        require_origin: env::var("ARTHERIS_WS_REQUIRE_ORIGIN").is_ok_and(|v| v == "1" || v == "true"),
+        // Clave solo para el HTTP (X-Api-Key o Bearer); sin ella vale ARTHERIS_TOKEN
+        api_key: env::var("ARTHERIS_API_KEY").ok().filter(|k| !k.is_empty()),
+        protect_reads: env::var("ARTHERIS_API_KEY_READS").is_ok_and(|v| v == "1" || v == "true"),
+        exempt_localhost: env::var("ARTHERIS_API_KEY_LOCAL_EXEMPT").is_ok_and(|v| v == "1" || v == "true"),
+    };
+    if let Some(origins) = &auth.allowed_origins {
+        info!("🔒 Orígenes WS permitidos: {origins:?}");
+    }
+    if auth.enabled() {
+        info!("🔒 Autenticación por token activada (WS solo lectura sin token: {})", auth.ws_read_only);
+    }
+    if auth.api_key.is_some() {
+        info!(
+            "🔑 Clave de API HTTP activada (GET protegidos: {}, localhost exento: {})",
+            auth.protect_reads, auth.exempt_localhost
+        );
+    }
+
+    // TLS opcional (wss:// y https://): ARTHERIS_TLS_CERT + ARTHERIS_TLS_KEY en PEM
+    let tls = match (env::var("ARTHERIS_TLS_CERT"), env::var("ARTHERIS_TLS_KEY")) {
+        (Ok(cert), Ok(key)) => {
+            let acceptor = load_acceptor(cert.as_ref(), key.as_ref()).context("configuración TLS inválida")?;
+            info!("🔐 TLS activado con {cert}");
+            Some(acceptor)
+        }
+        (Err(_), Err(_)) => None,
+        _ => anyhow::bail!("ARTHERIS_TLS_CERT y ARTHERIS_TLS_KEY deben configurarse juntos"),
+    };
+
+    // CORS del HTTP: ARTHERIS_CORS=http://groundstation:5173,https://ui.local (o `*`); sin él, solo mismo origen
+    let cors = match env::var("ARTHERIS_CORS") {
+        Ok(v) => CorsOrigins::parse(&v).context("ARTHERIS_CORS inválida")?,
+        Err(_) => CorsOrigins::default(),
+    };
+    match &cors {
+        CorsOrigins::Any => warn!("⚠️  CORS abierto a cualquier origen (ARTHERIS_CORS=*)"),
+        CorsOrigins::List(origins) => info!("🌍 Orígenes CORS permitidos: {origins:?}"),
+        CorsOrigins::SameOrigin => {}
+    }
+
+    // Dirección del servidor WS: ARTHERIS_WS_ADDR=127.0.0.1:9101 (por defecto 0.0.0.0:9001)
+    let ws_addr: SocketAddr = match env::var("ARTHERIS_WS_ADDR") {
+        Ok(v) => v.parse().with_context(|| format!("ARTHERIS_WS_ADDR inválida: {v:?}"))?,
+        Err(_) => SocketAddr::from(([0, 0, 0, 0], 9001)),
+    };
+    // El WS también se sirve en GET /ws (:3000); ARTHERIS_WS_LEGACY=0 apaga el listener propio
+    let ws_legacy = !matches!(env::var("ARTHERIS_WS_LEGACY").as_deref(), Ok("0" | "false"));
+    let ws_addr = ws_legacy.then_some(ws_addr);
+    // permessage-deflate con los clientes WS que lo ofrezcan; ARTHERIS_WS_DEFLATE=0 lo apaga (CPU justa)
+    let ws_deflate = !matches!(env::var("ARTHERIS_WS_DEFLATE").as_deref(), Ok("0" | "false"));
+    // Panel web en el mismo puerto HTTP: ARTHERIS_STATIC_DIR=/opt/artheris/dist (sin ella, nada cambia)
+    let static_dir: Option<Arc<std::path::Path>> = env::var("ARTHERIS_STATIC_DIR").ok()
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| std::path::Path::new(d.trim()).into());
+    if let Some(dir) = &static_dir {
+        if dir.join("index.html").is_file() {
+            info!("🖥️  Panel web servido desde {}", dir.display());
+        } else {
+            warn!("⚠️  ARTHERIS_STATIC_DIR sin index.html: {}", dir.display());
+        }
+    }
+    // Puntos por mensaje en las consultas históricas por WS
+    let query_chunk: usize = env::var("ARTHERIS_WS_QUERY_CHUNK").ok().and_then(|v| v.parse().ok()).filter(|c| *c > 0).unwrap_or(500);
+    // Tope de `limit` en la API HTTP: ARTHERIS_MAX_QUERY_LIMIT=500000 (por defecto 200000)
+    let max_query_limit: i64 = env::var("ARTHERIS_MAX_QUERY_LIMIT").ok().and_then(|v| v.parse().ok()).filter(|l| *l > 0)
+        .unwrap_or(params::DEFAULT_MAX_LIMIT);
+
+    // Escritura por lotes de la telemetría: ARTHERIS_DB_BUFFER filas en cola como mucho,
+    // un INSERT cada ARTHERIS_DB_FLUSH_MS o al juntar ARTHERIS_DB_BATCH_ROWS
+    let writer_defaults = WriterConfig::default();
+    let writer_config = WriterConfig {
+        capacity: env::var("ARTHERIS_DB_BUFFER").ok().and_then(|v| v.parse().ok()).unwrap_or(writer_defaults.capacity),
+        batch_rows: env::var("ARTHERIS_DB_BATCH_ROWS").ok().and_then(|v| v.parse().ok()).unwrap_or(writer_defaults.batch_rows),
+        flush_every: env::var("ARTHERIS_DB_FLUSH_MS").ok().and_then(|v| v.parse().ok()).filter(|ms| *ms > 0)
+            .map(Duration::from_millis).unwrap_or(writer_defaults.flush_every),
+    };
 
-    // Bind UDP local
-    let socket = Arc::new(UdpSocket::bind(local_addr.clone()).await?);
-    println!("✅ UDP listening on {}", local_addr);
+    // Spool de telemetría con QuestDB caído: ARTHERIS_SPOOL_DIR (./spool), aviso al pasar
+    // de ARTHERIS_SPOOL_MAX_MB (1024)
+    let spool_dir = env::var("ARTHERIS_SPOOL_DIR").unwrap_or_else(|_| "./spool".into());
+    let spool_max_mb: u64 = env::var("ARTHERIS_SPOOL_MAX_MB").ok().and_then(|v| v.parse().ok()).unwrap_or(1024);
+    let spool = Arc::new(Spool::new(spool_dir, spool_max_mb * 1024 * 1024));
+
+    // Límite por cliente WS (mensajes/s, 0 = sin límite)
+    let rate_limits = RateLimits {
+        command_per_sec: env::var("ARTHERIS_WS_CMD_RATE").ok().and_then(|v| v.parse().ok()).unwrap_or(20.0),
+        data_per_sec: env::var("ARTHERIS_WS_DATA_RATE").ok().and_then(|v| v.parse().ok()).unwrap_or(200.0),
+    };
+    // Límite HTTP por IP (peticiones/s, 0 = sin límite): lecturas de vuelos/series y escrituras
+    let http_limits = HttpRateLimits {
+        read_per_sec: env::var("ARTHERIS_HTTP_READ_RATE").ok().and_then(|v| v.parse().ok()).unwrap_or(20.0),
+        write_per_sec: env::var("ARTHERIS_HTTP_WRITE_RATE").ok().and_then(|v| v.parse().ok()).unwrap_or(5.0),
+    };
+
+    // Keepalive WS: ping cada ARTHERIS_WS_PING_SECS (0 = off), cierre tras ARTHERIS_WS_IDLE_TIMEOUT_SECS sin frames
+    let ping_secs: u64 = env::var("ARTHERIS_WS_PING_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(15);
+    let idle_secs: u64 = env::var("ARTHERIS_WS_IDLE_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(45);
+    let keepalive = KeepaliveConfig {
+        ping_every: (ping_secs > 0).then(|| Duration::from_secs(ping_secs)),
+        idle_timeout: Duration::from_secs(idle_secs),
+    };
 
     // 🔹 Contexto compartido
     let ws_ctx = WsContext {
         tx: tx.clone(),
-        esp32_socket: Some(socket.clone()),
-        remote_addr,
+        bus: Arc::new(Bus::new(ws_channel_cap)),
+        esp32: Some(link.clone()),
+        esp32_failover: esp32_failover.clone(),
         questdb: qdb.clone(),                 // ahora es ws_server::server::OptionalDb
         flight_id: current_flight_id.clone(),
         last_config: last_config.clone(),
+        udp_stats: udp_stats.clone(),
+        stream_rate: stream_rate.clone(),
+        capture: capture.clone(),
+        schema: Arc::new(SchemaValidator::default()),
+        csv_map: Arc::new(CsvMapping::default()),
+        clock: Arc::new(ClockSync::new(ts_field)),
+        auth: Arc::new(auth),
+        ws_stats: Arc::new(BroadcastStats::new(ws_channel_cap)),
+        acks: Default::default(),
+        esp32_replies: Default::default(),
+        clients: Default::default(),
+        last_values: Default::default(),
+        keepalive,
+        ws_deflate,
+        tls,
+        cors,
+        rate_limits: Arc::new(RateLimitConfig::new(rate_limits)),
+        http_limiter: Arc::new(HttpRateLimiter::new(http_limits)),
+        ws_addr,
+        static_dir,
+        shutdown: CancellationToken::new(),
+        query_chunk,
+        max_query_limit,
+        status: Default::default(),
+        replay: Default::default(),
+        commands: Default::default(),
+        metrics: Default::default(),
+        webhooks: Default::default(),
+        jobs: Default::default(),
+        telemetry_writer: Arc::new(TelemetryWriter::new(writer_config, Some(spool.clone()))),
+        spool: spool.clone(),
+        retention: Default::default(),
     };
+    tokio::spawn(crate::ws_server::spool::run_importer(spool, qdb.clone()));
+    tokio::spawn(qdb.clone().run_breaker_probe());
+    tokio::spawn(crate::ws_server::writer::run(ws_ctx.telemetry_writer.clone(), qdb.clone()));
 
-    // WS server
-    let _ws_server = tokio::spawn({
+    // Recupera el último mapeo CSV guardado (sobrevive reinicios)
+    match qdb.latest_logger_event("csv_map").await {
+        Ok(Some((_, json))) => {
+            let fields = serde_json::from_str::<serde_json::Value>(&json).ok()
+                .and_then(|v| serde_json::from_value::<Vec<String>>(v["fields"].clone()).ok());
+            if let Some(fields) = fields {
+                info!("📄 Mapeo CSV restaurado: {:?}", fields);
+                ws_ctx.csv_map.set(fields);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("⚠️  No se pudo leer el mapeo CSV: {e}"),
+    }
+
+    // Webhooks registrados (POST /api/webhooks)
+    match qdb.list_webhooks().await {
+        Ok(hooks) => {
+            if !hooks.is_empty() {
+                info!("🪝 {} webhook(s) restaurados", hooks.len());
+            }
+            for (id, url, events) in hooks {
+                ws_ctx.webhooks.restore(id, url, &events);
+            }
+        }
+        Err(e) => warn!("⚠️  No se pudieron leer los webhooks: {e}"),
+    }
+
+    // Última config del logger aplicada (GET /api/logger/config tras reiniciar)
+    match qdb.latest_logger_config().await {
+        Ok(Some((ts, json))) => match AppliedConfig::parse(&json, ts) {
+            Ok(cfg) => {
+                info!("⚙️  Config del logger restaurada ({ts})");
+                ws_ctx.retention.apply(&ws_ctx, cfg.ttl_s());
+                *last_config.write().await = Some(cfg);
+            }
+            Err(e) => warn!("⚠️  Config del logger guardada inválida: {e}"),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("⚠️  No se pudo leer la config del logger: {e}"),
+    }
+
+    // WS server (se detiene con `shutdown`: Ctrl-C o `exit`)
+    let shutdown = ws_ctx.shutdown.clone();
+    let ws_server = tokio::spawn({
         let ctx = ws_ctx.clone();
         async move {
-            info!("🔌 Iniciando servidor WebSocket en ws://0.0.0.0:9001");
-            start_ws_server(ctx).await;
-            info!("✅ Servidor WebSocket detenido");
+            info!("🔌 Iniciando servidor WebSocket");
+            match start_ws_server(ctx).await {
+                Ok(()) => info!("✅ Servidor WebSocket detenido"),
+                Err(e) => error!("❌ Error en el servidor WebSocket: {e}"),
+            }
         }
     });
 
-    // HTTP server
-    let _http_server = tokio::spawn({
+    // HTTP server (también se detiene con `shutdown`, dejando terminar las peticiones en curso)
+    let http_server = tokio::spawn({
         let ctx = ws_ctx.clone();
         async move {
             info!("🌍 Iniciando servidor HTTP en http://0.0.0.0:3000");
@@ -127,82 +463,127 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    {
-        let socket_recv = Arc::clone(&socket);
-        let tx_udp = tx.clone();
-        let qdb_writer = qdb.clone();
-        let flight_state = current_flight_id.clone();
-
-        tokio::spawn(async move {
-            let mut buf = vec![0u8; 4096];
-            loop {
-                match socket_recv.recv_from(&mut buf).await {
-                    Ok((len, _src)) => {
-                        if let Ok(text) = std::str::from_utf8(&buf[..len]) {
-                            let (to_ws, to_store) = match serde_json::from_str::<serde_json::Value>(text) {
-                                Ok(v) => match v.get("type").and_then(|t| t.as_str()) {
-                                    Some("ack") | Some("telemetry") => (v.to_string(), Some(v)),
-                                    _ => {
-                                        let wrapped = serde_json::json!({ "type":"telemetry", "payload": v });
-                                        (wrapped.to_string(), Some(wrapped))
-                                    }
-                                },
-                                Err(_) => {
-                                    let wrapped = serde_json::json!({ "type":"telemetry", "payload": text });
-                                    (wrapped.to_string(), Some(wrapped))
-                                }
-                            };
-
-                            let _ = tx_udp.send(to_ws);
-
-                            if let Some(flog) = to_store {
-                                let fid_opt = { flight_state.read().await.clone() };
-                                if let Some(ref fid) = fid_opt {
-                                    if let Err(e) = qdb_writer.insert_flight_log(&fid, &flog.to_string()).await {
-                                        error!("❌ Error guardando telemetría en QuestDB: {e}");
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("❌ UDP recv error: {e}");
-                        break;
-                    }
-                }
-            }
-        });
+    // Una task de recepción por enlace; todas alimentan el mismo pipeline
+    for l in &links {
+        tokio::spawn(run_receiver(l.clone(), ws_ctx.clone()));
     }
 
-    // --------- Envío manual por stdin ----------
-    use tokio::io::AsyncBufReadExt; // (ya importado arriba)
-    let stdin = BufReader::new(tokio::io::stdin());
-    let mut lines = stdin.lines();
+    // MAVLink en un puerto UDP aparte (ARTHERIS_MAVLINK_PORT)
+    #[cfg(feature = "mavlink")]
+    if let Some(port) = env::var("ARTHERIS_MAVLINK_PORT").ok().and_then(|p| p.parse::<u16>().ok()) {
+        let local_addr = format!("0.0.0.0:{}", port);
+        let sock = UdpSocket::bind(&local_addr)
+            .await
+            .with_context(|| format!("no se pudo enlazar UDP MAVLink en {local_addr}"))?;
+        tokio::spawn(crate::ws_server::mavlink::run_mavlink_receiver(Arc::new(sock), ws_ctx.clone()));
+    }
 
-    println!("Escribe un mensaje para enviar al ESP32 (exit para salir):");
-    while let Ok(Some(line)) = lines.next_line().await {
+    let timing_every: u64 = env::var("ARTHERIS_TIMING_STATS_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    if timing_every > 0 {
+        tokio::spawn(broadcast_timing_stats(ws_ctx.clone(), Duration::from_secs(timing_every)));
+    }
+
+    // Estado del servidor por WS cada N s (0 = off)
+    let status_every: u64 = env::var("ARTHERIS_STATUS_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    if status_every > 0 {
+        tokio::spawn(run_status(ws_ctx.clone(), Duration::from_secs(status_every)));
+    }
+
+    // Webhooks: repartidor y aviso `link_lost` tras N s sin datos del ESP32 (0 = sin aviso)
+    tokio::spawn(crate::ws_server::webhooks::run_dispatcher(ws_ctx.clone()));
+    let link_lost_secs: u64 = env::var("ARTHERIS_LINK_LOST_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(10);
+    if link_lost_secs > 0 {
+        tokio::spawn(crate::ws_server::webhooks::run_link_watch(ws_ctx.clone(), Duration::from_secs(link_lost_secs)));
+    }
+
+    if let Some(failover) = &esp32_failover {
+        tokio::spawn(run_failover(failover.clone(), ws_ctx.clone()));
+    }
+
+    // Watchdog de re-enlace UDP: fallos de envío seguidos (0 = off) y sondeo de interfaz (0 = off)
+    let rebind_failures: u32 = env::var("ARTHERIS_UDP_REBIND_FAILURES").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    let iface_poll: u64 = env::var("ARTHERIS_UDP_IFACE_POLL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    let iface_poll = (iface_poll > 0).then(|| Duration::from_secs(iface_poll));
+    for udp in &udp_links {
+        tokio::spawn(run_rebind_watchdog(udp.clone(), ws_ctx.clone(), rebind_failures, iface_poll));
+    }
+
+    // --------- Envío manual por stdin (ARTHERIS_STDIN=0 lo desactiva) ----------
+    // Como servicio se usa POST /api/esp32/send; si stdin se cierra se sigue hasta la señal
+    let stdin_enabled = !matches!(env::var("ARTHERIS_STDIN").as_deref(), Ok("0" | "false"));
+    let mut lines = stdin_lines(stdin_enabled);
+    let mut stdin_open = stdin_enabled;
+    if stdin_enabled {
+        println!("Escribe un mensaje para enviar al ESP32 (exit para salir):");
+    }
+    loop {
+        let line = tokio::select! {
+            line = lines.recv(), if stdin_open => match line {
+                Some(line) => line,
+                None => {
+                    stdin_open = false;
+                    continue;
+                }
+            },
+            _ = shutdown_signal() => {
+                println!("👋 Ctrl-C/SIGTERM, saliendo...");
+                break;
+            }
+        };
         if line.trim().eq_ignore_ascii_case("exit") {
             println!("👋 Saliendo...");
             break;
         }
-        if let Err(e) = socket.send_to(line.as_bytes(), &remote_addr).await {
+        if let Err(e) = link.send(line.as_bytes()).await {
             error!("❌ Error enviando: {e}");
         } else {
-            println!("📤 Sent to {} -> {}", remote_addr, line);
+            println!("📤 Sent to {} -> {}", link.describe(), line);
         }
     }
 
-    // --------- Servidor HTTP ----------
-    {
-        let http_ctx = ws_ctx.clone();
-        let _http_server = tokio::spawn(async move {
-            info!("🌐 Iniciando servidor HTTP en http://0.0.0.0:3000");
-            match start_http_server(http_ctx).await {
-                Ok(_) => info!("✅ Servidor HTTP detenido"),
-                Err(e) => error!("❌ Error en servidor HTTP: {e}"),
-            }
-        });
+    // Cierre ordenado: los clientes WS reciben 1001 y las peticiones HTTP en curso
+    // tienen ARTHERIS_SHUTDOWN_GRACE_SECS (por defecto 10) para terminar
+    shutdown.cancel();
+    let grace: u64 = env::var("ARTHERIS_SHUTDOWN_GRACE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(10);
+    match tokio::time::timeout(Duration::from_secs(grace), http_server).await {
+        Ok(Ok(())) => info!("✅ Servidor HTTP detenido"),
+        Ok(Err(e)) => error!("❌ La task del servidor HTTP terminó con error: {e}"),
+        Err(_) => warn!("⏱️  Periodo de gracia agotado ({grace} s); se cortan las peticiones HTTP en curso"),
+    }
+    stop_recording_on_shutdown(&ws_ctx).await;
+    ws_ctx.telemetry_writer.flush(&ws_ctx.questdb).await;
+    if let Err(e) = ws_server.await {
+        error!("❌ La task del servidor WebSocket terminó con error: {e}");
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+/// Ctrl-C o SIGTERM (systemd, `docker stop`)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    if let Ok(mut term) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+        return;
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Líneas de stdin leídas en un hilo propio: una lectura bloqueada no retiene el cierre
+/// del runtime como el `stdin` de tokio. Sin `enabled`, el canal nace cerrado
+fn stdin_lines(enabled: bool) -> tokio::sync::mpsc::Receiver<String> {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    if enabled {
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if tx.blocking_send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+/// Tiempo que se recuerda qué cliente pidió un `request_id` (ack local + ack del ESP32)
+const ACK_ROUTE_TTL: Duration = Duration::from_secs(30);
+
+/// `request_id` → cliente WS que lo originó, para entregarle sus acks solo a él
+#[derive(Debug, Default)]
+pub struct AckRoutes {
+    pending: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+impl AckRoutes {
+    pub fn track(&self, request_id: &str, client_id: u64) {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        // Los request_id que nunca reciben ack caducan aquí
+        pending.retain(|_, (_, at)| now.duration_since(*at) < ACK_ROUTE_TTL);
+        pending.insert(request_id.to_owned(), (client_id, now));
+    }
+
+    /// Cliente que originó `request_id`, si sigue vigente. No se borra: el mismo id
+    /// recibe el ack local del envío y después el del firmware.
+    pub fn client_for(&self, request_id: &str) -> Option<u64> {
+        let pending = self.pending.lock().unwrap();
+        pending.get(request_id)
+            .filter(|(_, at)| at.elapsed() < ACK_ROUTE_TTL)
+            .map(|(id, _)| *id)
+    }
+}
+
+/// Quién espera respuesta de un envío crudo (`POST /api/esp32/send`)
+struct ReplyWaiter {
+    /// `request_id` del JSON enviado; sin él solo vale el eco del mismo texto
+    request_id: Option<String>,
+    text: String,
+    tx: oneshot::Sender<String>,
+}
+
+/// Envíos HTTP esperando el ack (mismo `request_id`) o el eco de lo que mandaron
+#[derive(Default)]
+pub struct ReplyWaiters {
+    waiting: Mutex<Vec<ReplyWaiter>>,
+}
+
+impl ReplyWaiters {
+    /// Registrar antes de enviar, para no perder una respuesta rápida
+    pub fn wait(&self, request_id: Option<String>, text: &str) -> oneshot::Receiver<String> {
+        let (tx, rx) = oneshot::channel();
+        let mut waiting = self.waiting.lock().unwrap();
+        // Los que agotaron su espera ya soltaron el receptor
+        waiting.retain(|w| !w.tx.is_closed());
+        waiting.push(ReplyWaiter { request_id, text: text.trim().to_owned(), tx });
+        rx
+    }
+
+    /// Entrega un mensaje entrante a quien lo espere; `request_id` solo viene en los acks
+    pub fn offer(&self, request_id: Option<&str>, text: &str) {
+        let mut waiting = self.waiting.lock().unwrap();
+        if waiting.is_empty() {
+            return;
+        }
+        let text = text.trim();
+        let matches = |w: &ReplyWaiter| match (&w.request_id, request_id) {
+            (Some(want), Some(got)) => want == got,
+            _ => w.text == text,
+        };
+        if let Some(i) = waiting.iter().position(matches) {
+            let _ = waiting.swap_remove(i).tx.send(text.to_owned());
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::questdb::FlightPoint;
+
+/// Agregado por intervalo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Agg {
+    Min,
+    Max,
+    Avg,
+    Count,
+}
+
+impl Agg {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "avg" => Some(Self::Avg),
+            "count" => Some(Self::Count),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Avg => "avg",
+            Self::Count => "count",
+        }
+    }
+}
+
+/// Intervalo de `bucket=5s`: `<n>ms|s|m|h|d`
+#[derive(Debug, Clone, Copy)]
+pub struct Bucket {
+    n: u32,
+    unit: &'static str,
+}
+
+impl Bucket {
+    pub fn parse(s: &str) -> Option<Self> {
+        let split = s.find(|c: char| !c.is_ascii_digit())?;
+        let (n, unit) = s.split_at(split);
+        let n: u32 = n.parse().ok().filter(|n| *n > 0)?;
+        let unit = match unit {
+            "ms" => "T",
+            "s" => "s",
+            "m" => "m",
+            "h" => "h",
+            "d" => "d",
+            _ => return None,
+        };
+        Some(Self { n, unit })
+    }
+
+    pub fn micros(self) -> i64 {
+        let unit: i64 = match self.unit {
+            "T" => 1_000,
+            "s" => 1_000_000,
+            "m" => 60_000_000,
+            "h" => 3_600_000_000,
+            _ => 86_400_000_000,
+        };
+        self.n as i64 * unit
+    }
+
+    /// Unidad para `SAMPLE BY` de QuestDB (`T` = milisegundos)
+    pub fn sample_by(self) -> String {
+        format!("{}{}", self.n, self.unit)
+    }
+}
+
+/// Petición ya validada
+#[derive(Debug, Clone)]
+pub struct AggRequest {
+    pub fields: Vec<String>,
+    pub aggs: Vec<Agg>,
+    pub bucket: Bucket,
+    /// `true` = los intervalos sin datos salen con nulls; `false` = se omiten
+    pub fill_null: bool,
+}
+
+impl AggRequest {
+    /// Los campos van dentro de la consulta (ruta JSON y alias); solo [A-Za-z0-9_]
+    pub fn valid_field(f: &str) -> bool {
+        !f.is_empty() && f.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// Consulta `SAMPLE BY`; columnas: ts y luego campo×agregado. Con `typed` sobre las
+    /// columnas de `flight_metrics`, si no sobre el JSON guardado
+    pub fn sql(&self, typed: bool) -> String {
+        let mut cols = vec!["ts".to_string()];
+        for f in &self.fields {
+            let expr = if typed { format!("\"{f}\"") } else { format!("json_extract(payload, '$.payload.{f}')::double") };
+            for a in &self.aggs {
+                cols.push(format!("{}({expr})", a.name()));
+            }
+        }
+        let fill = if self.fill_null { " FILL(NULL)" } else { "" };
+        format!(
+            "SELECT {} FROM {} WHERE flight_id=$1 SAMPLE BY {}{fill} ALIGN TO CALENDAR",
+            cols.join(", "),
+            if typed { "flight_metrics" } else { "flight_logs" },
+            self.bucket.sample_by()
+        )
+    }
+}
+
+/// Una fila por intervalo: `{"ts":..., "values":{"AngleRoll":{"min":..,"max":..}}}`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AggRow {
+    pub ts: String,
+    /// campo → agregado → valor (`null` si el intervalo no tiene datos)
+    #[schema(value_type = Object)]
+    pub values: BTreeMap<String, BTreeMap<&'static str, Value>>,
+}
+
+impl AggRow {
+    /// Fila de `sql()`: tras `ts`, las columnas van en orden campo×agregado
+    pub fn from_sql(req: &AggRequest, row: &tokio_postgres::Row) -> Self {
+        let ts: DateTime<Utc> = row.get(0);
+        let mut values = BTreeMap::new();
+        let mut col = 1;
+        for f in &req.fields {
+            let mut aggs = BTreeMap::new();
+            for a in &req.aggs {
+                let v = match a {
+                    Agg::Count => row.get::<_, Option<i64>>(col).map(Value::from),
+                    _ => row.get::<_, Option<f64>>(col).map(Value::from),
+                };
+                aggs.insert(a.name(), v.unwrap_or(Value::Null));
+                col += 1;
+            }
+            values.insert(f.clone(), aggs);
+        }
+        Self { ts: ts.to_rfc3339(), values }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Acc {
+    min: f64,
+    max: f64,
+    sum: f64,
+    n: i64,
+}
+
+impl Acc {
+    fn push(&mut self, v: f64) {
+        if self.n == 0 {
+            (self.min, self.max) = (v, v);
+        } else {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+        self.sum += v;
+        self.n += 1;
+    }
+
+    fn get(&self, a: Agg) -> Value {
+        match a {
+            Agg::Count => Value::from(self.n),
+            _ if self.n == 0 => Value::Null,
+            Agg::Min => Value::from(self.min),
+            Agg::Max => Value::from(self.max),
+            Agg::Avg => Value::from(self.sum / self.n as f64),
+        }
+    }
+}
+
+/// Mismo resultado que `sql()` calculado en Rust, para cuando QuestDB no puede
+/// (versiones sin `json_extract`)
+pub fn compute(points: &[FlightPoint], req: &AggRequest) -> Vec<AggRow> {
+    let width = req.bucket.micros();
+    let mut buckets: BTreeMap<i64, Vec<Acc>> = BTreeMap::new();
+    for p in points {
+        let start = p.ts.timestamp_micros().div_euclid(width) * width;
+        let accs = buckets.entry(start).or_insert_with(|| vec![Acc::default(); req.fields.len()]);
+        let Some(obj) = p.payload.get("payload").and_then(|v| v.as_object()) else { continue };
+        for (acc, f) in accs.iter_mut().zip(&req.fields) {
+            if let Some(v) = obj.get(f).and_then(Value::as_f64) {
+                acc.push(v);
+            }
+        }
+    }
+
+    if req.fill_null
+        && let (Some(&first), Some(&last)) = (buckets.keys().next(), buckets.keys().next_back())
+    {
+        let mut t = first;
+        while t < last {
+            buckets.entry(t).or_insert_with(|| vec![Acc::default(); req.fields.len()]);
+            t += width;
+        }
+    }
+
+    buckets
+        .into_iter()
+        .map(|(start, accs)| {
+            let values = req
+                .fields
+                .iter()
+                .zip(accs)
+                .map(|(f, acc)| (f.clone(), req.aggs.iter().map(|a| (a.name(), acc.get(*a))).collect()))
+                .collect();
+            let ts = DateTime::<Utc>::from_timestamp_micros(start).unwrap_or_default();
+            AggRow { ts: ts.to_rfc3339(), values }
+        })
+        .collect()
+}
@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::http_trace;
+use super::questdb::DbError;
+
+/// Error de la API HTTP con cuerpo `{"error":{"code":"...","message":"..."}}`
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    /// Parámetro de la consulta inválido; `param` lo nombra
+    InvalidParam { param: String, message: String },
+    /// Falta la clave de la API o no coincide
+    Unauthorized(String),
+    NotFound(String),
+    /// Choca con el estado actual (p. ej. el vuelo que se está grabando)
+    Conflict(String),
+    /// Cupo de peticiones agotado; va con `Retry-After`
+    RateLimited { retry_after_secs: u64 },
+    /// La petición es válida pero supone demasiado trabajo (p. ej. un resumen de millones de puntos)
+    TooLarge(String),
+    /// QuestDB sin conexión
+    Unavailable(String),
+    /// Fallo de una consulta con la BD conectada
+    Database(String),
+    /// QuestDB no respondió a tiempo
+    Timeout(String),
+}
+
+/// Cuerpo JSON de `ApiError`
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail {
+    /// `bad_request`, `invalid_param`, `unauthorized`, `not_found`, `conflict`, `rate_limited`, `too_large`, `db_unavailable`, `db_error` o `db_timeout`
+    code: &'static str,
+    message: String,
+    /// Solo en `invalid_param`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    param: Option<String>,
+    /// El mismo `x-request-id` de la respuesta y de la línea de log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ApiError {
+    fn parts(&self) -> (StatusCode, &'static str, Cow<'_, str>) {
+        match self {
+            Self::BadRequest(m) => (StatusCode::BAD_REQUEST, "bad_request", m.into()),
+            Self::InvalidParam { message, .. } => (StatusCode::BAD_REQUEST, "invalid_param", message.into()),
+            Self::Unauthorized(m) => (StatusCode::UNAUTHORIZED, "unauthorized", m.into()),
+            Self::NotFound(m) => (StatusCode::NOT_FOUND, "not_found", m.into()),
+            Self::Conflict(m) => (StatusCode::CONFLICT, "conflict", m.into()),
+            Self::RateLimited { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                format!("Too many requests, retry in {retry_after_secs} s").into(),
+            ),
+            Self::TooLarge(m) => (StatusCode::UNPROCESSABLE_ENTITY, "too_large", m.into()),
+            Self::Unavailable(m) => (StatusCode::SERVICE_UNAVAILABLE, "db_unavailable", m.into()),
+            Self::Database(m) => (StatusCode::INTERNAL_SERVER_ERROR, "db_error", m.into()),
+            Self::Timeout(m) => (StatusCode::GATEWAY_TIMEOUT, "db_timeout", m.into()),
+        }
+    }
+}
+
+impl ApiError {
+    /// Solo el mensaje, para quien no responde por HTTP (p. ej. `query_error` en el WS)
+    pub fn message(&self) -> Cow<'_, str> {
+        self.parts().2
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        match e {
+            DbError::Unavailable(_) => Self::Unavailable(e.to_string()),
+            DbError::Query(_) => Self::Database(e.to_string()),
+            DbError::Timeout(_) => Self::Timeout(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code, message) = self.parts();
+        let param = match &self {
+            Self::InvalidParam { param, .. } => Some(param.clone()),
+            _ => None,
+        };
+        if status.is_server_error() {
+            warn!("❌ {code}: {message}");
+        }
+        let request_id = http_trace::current();
+        let body = ErrorBody { error: ErrorDetail { code, message: message.into_owned(), param, request_id } };
+        let mut res = (status, Json(body)).into_response();
+        if let Self::RateLimited { retry_after_secs } = self {
+            res.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn render(e: ApiError) -> (StatusCode, serde_json::Value) {
+        let res = e.into_response();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn db_errors_map_to_distinct_statuses_and_codes() {
+        let cases = [
+            (DbError::Unavailable("connection refused".into()), StatusCode::SERVICE_UNAVAILABLE, "db_unavailable"),
+            (DbError::Query("syntax error".into()), StatusCode::INTERNAL_SERVER_ERROR, "db_error"),
+            (DbError::Timeout("read after 5000 ms".into()), StatusCode::GATEWAY_TIMEOUT, "db_timeout"),
+        ];
+        for (db, status, code) in cases {
+            let message = db.to_string();
+            let (got, body) = render(db.into()).await;
+            assert_eq!(got, status);
+            assert_eq!(body, serde_json::json!({ "error": { "code": code, "message": message } }));
+        }
+    }
+
+    #[tokio::test]
+    async fn client_errors_carry_param_and_retry_after() {
+        let (status, body) = render(ApiError::NotFound("Flight f not found".into())).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (StatusCode::NOT_FOUND, Some("not_found")));
+
+        let e = ApiError::InvalidParam { param: "limit".into(), message: "'limit' must be positive".into() };
+        let (status, body) = render(e).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!((body["error"]["code"].as_str(), body["error"]["param"].as_str()), (Some("invalid_param"), Some("limit")));
+
+        let res = ApiError::RateLimited { retry_after_secs: 3 }.into_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[header::RETRY_AFTER], "3");
+    }
+}
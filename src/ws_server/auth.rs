@@ -0,0 +1,153 @@
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::api_error::ApiError;
+use super::server::WsContext;
+
+/// Tiempo que tiene un cliente WS para mandar `{"type":"auth","token":...}`
+pub const WS_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Token compartido opcional (`ARTHERIS_TOKEN`); sin token todo queda abierto como antes
+#[derive(Debug, Default, Clone)]
+pub struct AuthConfig {
+    pub token: Option<String>,
+    /// Clientes WS sin token reciben la difusión pero no pueden mandar comandos
+    pub ws_read_only: bool,
+    /// `Origin` aceptados en el upgrade WS (`ARTHERIS_WS_ORIGINS`); `None` = cualquiera
+    pub allowed_origins: Option<Vec<String>>,
+    /// Rechaza upgrades sin `Origin` (por defecto se aceptan: clientes que no son navegador)
+    pub require_origin: bool,
+    /// Clave de la API HTTP (`ARTHERIS_API_KEY`); sin ella se usa `token`
+    pub api_key: Option<String>,
+    /// Pide la clave también en GET (`ARTHERIS_API_KEY_READS`)
+    pub protect_reads: bool,
+    /// Peticiones desde 127.0.0.1/::1 no necesitan clave (`ARTHERIS_API_KEY_LOCAL_EXEMPT`)
+    pub exempt_localhost: bool,
+}
+
+impl AuthConfig {
+    pub fn enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Evita que cualquier página de la LAN abra un WS desde el navegador del usuario
+    pub fn origin_allowed(&self, origin: Option<&str>) -> bool {
+        match (origin, &self.allowed_origins) {
+            (None, _) => !self.require_origin,
+            (Some(_), None) => true,
+            (Some(o), Some(list)) => {
+                let o = o.trim_end_matches('/');
+                list.iter().any(|a| a == "*" || a.trim_end_matches('/').eq_ignore_ascii_case(o))
+            }
+        }
+    }
+
+    /// Comparación en tiempo constante
+    pub fn check(&self, presented: &str) -> bool {
+        self.token.as_deref().is_none_or(|t| constant_time_eq(t, presented))
+    }
+
+    /// Clave exigida por el router HTTP
+    pub fn http_key(&self) -> Option<&str> {
+        self.api_key.as_deref().or(self.token.as_deref())
+    }
+}
+
+/// Recorre siempre toda la clave presentada; solo la longitud se filtra
+fn constant_time_eq(expected: &str, presented: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), presented.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Tipos de comando que se dejan pasar al ESP32: `led`, `mode`, `motors` y `raw`
+/// (reenvío crudo). `None` = todos.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct CommandWhitelist {
+    pub allowed: Option<BTreeSet<String>>,
+}
+
+#[derive(Default)]
+pub struct CommandPolicy(RwLock<CommandWhitelist>);
+
+impl CommandPolicy {
+    pub fn allows(&self, kind: &str) -> bool {
+        self.0.read().unwrap().allowed.as_ref().is_none_or(|a| a.contains(kind))
+    }
+
+    pub fn get(&self) -> CommandWhitelist {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, whitelist: CommandWhitelist) {
+        *self.0.write().unwrap() = whitelist;
+    }
+}
+
+/// Middleware HTTP: con clave configurada, POST/PUT/DELETE (y GET si `protect_reads`)
+/// exigen `X-Api-Key: <clave>` o `Authorization: Bearer <clave>`
+pub async fn require_token(
+    State(ctx): State<WsContext>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(key) = ctx.auth.http_key() else {
+        return Ok(next.run(req).await);
+    };
+    // El preflight CORS nunca lleva credenciales
+    let open = match *req.method() {
+        Method::OPTIONS => true,
+        Method::GET | Method::HEAD => !ctx.auth.protect_reads,
+        _ => false,
+    };
+    let local = ctx.auth.exempt_localhost
+        && req.extensions().get::<ConnectInfo<SocketAddr>>().is_some_and(|c| c.0.ip().is_loopback());
+    if !open && !local {
+        let headers = req.headers();
+        let presented = headers.get("x-api-key")
+            .or_else(|| headers.get(header::AUTHORIZATION))
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.strip_prefix("Bearer ").unwrap_or(v).trim());
+        if !presented.is_some_and(|p| constant_time_eq(key, p)) {
+            return Err(ApiError::Unauthorized("Missing or invalid API key".to_string()));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_allowlist_matches_exactly_ignoring_case_and_trailing_slash() {
+        let open = AuthConfig::default();
+        assert!(open.origin_allowed(None));
+        assert!(open.origin_allowed(Some("http://evil.lan")));
+
+        let auth = AuthConfig {
+            allowed_origins: Some(vec!["http://groundstation:5173/".into(), "https://artheris.local".into()]),
+            ..Default::default()
+        };
+        assert!(auth.origin_allowed(Some("http://groundstation:5173")));
+        assert!(auth.origin_allowed(Some("HTTPS://Artheris.local/")));
+        assert!(!auth.origin_allowed(Some("http://groundstation:8080")));
+        assert!(!auth.origin_allowed(Some("https://artheris.local.evil.lan")));
+        // Sin Origin (no navegador) pasa salvo que se exija
+        assert!(auth.origin_allowed(None));
+        let strict = AuthConfig { require_origin: true, ..auth };
+        assert!(!strict.origin_allowed(None));
+
+        let any = AuthConfig { allowed_origins: Some(vec!["*".into()]), require_origin: true, ..Default::default() };
+        assert!(any.origin_allowed(Some("http://whatever")));
+        assert!(!any.origin_allowed(None));
+    }
+}
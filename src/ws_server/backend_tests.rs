@@ -0,0 +1,197 @@
+//! Suite compartida de la API contra cada backend de almacenamiento: los mismos recorridos
+//! sobre SQLite (siempre) y sobre QuestDB (`#[ignore]`, con `QUESTDB_HOST`/`QUESTDB_PORT`).
+//! Lo delicado entre backends es la fidelidad de timestamps (µs) y del JSON guardado
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use super::questdb::OptionalDb;
+use super::tests::{call, call_json};
+use super::{router, WsContext};
+
+const CONFIG: &str = r#"{"schemaVersion":1,"selectedFields":["AngleRoll","alt"],"retention":{"mode":"infinite"},
+    "triggers":{"startWhen":{"key":"AngleRoll","between":[-90,90]},"stopWhen":null},"metadata":{"pilot":"Ñandú"}}"#;
+
+fn sqlite() -> WsContext {
+    WsContext::for_tests_sqlite()
+}
+
+fn questdb() -> WsContext {
+    let mut ctx = WsContext::for_tests(None);
+    ctx.questdb = OptionalDb::new(super::questdb::tests::live_config());
+    ctx
+}
+
+/// GET repetido hasta que `ok` (las tablas WAL de QuestDB muestran lo escrito con retraso)
+async fn until(app: &Router, uri: &str, ok: impl Fn(StatusCode, &Value) -> bool) -> (StatusCode, Value) {
+    for _ in 0..50 {
+        let (status, body) = call_json(app, Method::GET, uri, "").await;
+        if ok(status, &body) {
+            return (status, body);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{uri} no llegó al estado esperado");
+}
+
+/// Mensajes con lo que suele romperse al pasar por la BD: µs, unicode, comillas,
+/// saltos de línea, enteros grandes y objetos anidados
+fn samples() -> Vec<(DateTime<Utc>, Value)> {
+    let t0 = DateTime::parse_from_rfc3339("2024-05-01T10:00:00.123456Z").unwrap().to_utc();
+    (0..3)
+        .map(|i| {
+            let msg = json!({
+                "type": "telemetry",
+                "payload": {
+                    "AngleRoll": 1.5 + i as f64, "alt": -0.000125 * i as f64, "seq": 9_007_199_254_740_991_i64 - i,
+                    "note": "ñ \"x\" \\ \n fin", "gps": { "fix": true, "sats": [7, 8] }
+                }
+            });
+            (t0 + chrono::Duration::microseconds(i * 1_001), msg)
+        })
+        .collect()
+}
+
+/// Graba un vuelo con `samples()` por la API y el writer; devuelve su id
+async fn record(ctx: &WsContext, app: &Router) -> String {
+    let (status, started) = call_json(app, Method::POST, "/api/recordings/start", CONFIG).await;
+    assert_eq!(status, StatusCode::OK, "{started}");
+    let fid = started["flightId"].as_str().unwrap().to_owned();
+    for (ts, msg) in samples() {
+        ctx.telemetry_writer.push(&fid, &msg, ts);
+    }
+    let (status, _) = call_json(app, Method::POST, "/api/recordings/stop", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ctx.telemetry_writer.snapshot().written, 3);
+    fid
+}
+
+async fn config_round_trip(ctx: WsContext) {
+    let app = router(ctx);
+    let (status, _) = call_json(&app, Method::POST, "/api/logger/config", CONFIG).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, got) = until(&app, "/api/logger/config", |s, v| s == StatusCode::OK && !v["config"].is_null()).await;
+    let sent: Value = serde_json::from_str(CONFIG).unwrap();
+    assert_eq!(got["config"]["selectedFields"], sent["selectedFields"]);
+    assert_eq!(got["config"]["metadata"], sent["metadata"]);
+}
+
+async fn flight_round_trip(ctx: WsContext) {
+    let app = router(ctx.clone());
+    let fid = record(&ctx, &app).await;
+    let expected = samples();
+
+    let (_, detail) = until(&app, &format!("/api/flights/{fid}"), |s, v| s == StatusCode::OK && v["points"] == 3).await;
+    assert_eq!(detail["start_ts"].as_str().map(parse), Some(expected[0].0));
+    assert_eq!(detail["end_ts"].as_str().map(parse), Some(expected[2].0));
+    assert_eq!(detail["recording"], false);
+
+    let (_, list) = until(&app, "/api/flights?limit=200", |_, v| listed(v, &fid).is_some()).await;
+    let item = listed(&list, &fid).unwrap();
+    assert_eq!(item["first_ts"].as_str().map(parse), Some(expected[0].0));
+
+    // El payload vuelve byte a byte como JSON equivalente y con su timestamp exacto
+    let (status, _, raw) = call(&app, Method::GET, &format!("/api/flights/{fid}/raw.jsonl"), "").await;
+    assert_eq!(status, StatusCode::OK);
+    let rows: Vec<Value> = raw.split(|&b| b == b'\n').filter(|l| !l.is_empty())
+        .map(|l| serde_json::from_slice(l).unwrap()).collect();
+    assert_eq!(rows.len(), 3);
+    for (row, (ts, msg)) in rows.iter().zip(&expected) {
+        assert_eq!(row["ts"].as_str().map(parse), Some(*ts));
+        assert_eq!(&row["payload"], msg);
+    }
+
+    let (status, series) = call_json(&app, Method::GET, &format!("/api/flights/{fid}/series?fields=AngleRoll,alt"), "").await;
+    assert_eq!(status, StatusCode::OK);
+    let values: Vec<_> = series.as_array().unwrap().iter().map(|p| (p["values"]["AngleRoll"].as_f64(), p["values"]["alt"].as_f64())).collect();
+    assert_eq!(values, [(Some(1.5), Some(0.0)), (Some(2.5), Some(-0.000125)), (Some(3.5), Some(-0.00025))]);
+
+    let (status, _, csv) = call(&app, Method::GET, &format!("/api/flights/{fid}/export.csv"), "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 4);
+}
+
+async fn deleted_flight_is_gone(ctx: WsContext) {
+    let app = router(ctx.clone());
+    let fid = record(&ctx, &app).await;
+    until(&app, &format!("/api/flights/{fid}"), |s, _| s == StatusCode::OK).await;
+
+    let (status, _) = call_json(&app, Method::DELETE, &format!("/api/flights/{fid}"), "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = until(&app, &format!("/api/flights/{fid}"), |s, _| s == StatusCode::NOT_FOUND).await;
+    assert_eq!(body["error"]["code"], "not_found");
+    let (status, _) = call_json(&app, Method::GET, &format!("/api/flights/{fid}/series"), "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, list) = call_json(&app, Method::GET, "/api/flights?limit=200", "").await;
+    assert!(listed(&list, &fid).is_none());
+}
+
+async fn unknown_flight_is_not_found(ctx: WsContext) {
+    let app = router(ctx);
+    for uri in ["/api/flights/nope", "/api/flights/nope/series", "/api/flights/nope/raw.jsonl"] {
+        let (status, body) = call_json(&app, Method::GET, uri, "").await;
+        assert_eq!((status, body["error"]["code"].as_str()), (StatusCode::NOT_FOUND, Some("not_found")), "{uri}");
+    }
+}
+
+/// Filas con `ts` algo desordenados (como tras un reintento o el spool): la lectura va por `ts`
+async fn out_of_order_inserts_come_back_sorted(ctx: WsContext) {
+    let fid = format!("o3_{}", uuid::Uuid::new_v4().simple());
+    let t0 = DateTime::parse_from_rfc3339("2024-05-01T10:00:00.000250Z").unwrap().to_utc();
+    for ms in [20, 0, 30, 10, 5, 40] {
+        let msg = json!({ "type": "telemetry", "payload": { "AngleRoll": ms } });
+        let ts = t0 + chrono::Duration::milliseconds(ms);
+        ctx.questdb.insert_flight_log(&fid, &msg.to_string(), Some(ts)).await.unwrap();
+    }
+
+    let mut points = Vec::new();
+    for _ in 0..50 {
+        points = ctx.questdb.fetch_flight_points(&fid, None, None, 100).await.unwrap();
+        if points.len() == 6 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let got: Vec<_> = points.iter().map(|p| (p.ts, p.payload["payload"]["AngleRoll"].as_i64().unwrap())).collect();
+    let expected: Vec<_> = [0, 5, 10, 20, 30, 40].into_iter().map(|ms| (t0 + chrono::Duration::milliseconds(ms), ms)).collect();
+    assert_eq!(got, expected);
+
+    // Con intervalo también se respeta el orden y los límites caen en el `ts` de cada fila
+    let from = t0 + chrono::Duration::milliseconds(5);
+    let to = t0 + chrono::Duration::milliseconds(30);
+    let window = ctx.questdb.fetch_flight_points(&fid, Some(from), Some(to), 100).await.unwrap();
+    assert_eq!(window.iter().map(|p| p.ts).collect::<Vec<_>>(), expected[1..5].iter().map(|e| e.0).collect::<Vec<_>>());
+}
+
+fn parse(ts: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(ts).unwrap().to_utc()
+}
+
+fn listed<'a>(list: &'a Value, fid: &str) -> Option<&'a Value> {
+    list["items"].as_array()?.iter().find(|f| f["flight_id"] == fid)
+}
+
+/// Un módulo por backend con un test por recorrido
+macro_rules! backend_suite {
+    ($backend:ident $(, #[$attr:meta])?) => {
+        mod $backend {
+            #[tokio::test] $(#[$attr])?
+            async fn config_round_trip() { super::config_round_trip(super::$backend()).await }
+            #[tokio::test] $(#[$attr])?
+            async fn flight_round_trip() { super::flight_round_trip(super::$backend()).await }
+            #[tokio::test] $(#[$attr])?
+            async fn deleted_flight_is_gone() { super::deleted_flight_is_gone(super::$backend()).await }
+            #[tokio::test] $(#[$attr])?
+            async fn unknown_flight_is_not_found() { super::unknown_flight_is_not_found(super::$backend()).await }
+            #[tokio::test] $(#[$attr])?
+            async fn out_of_order_inserts_come_back_sorted() { super::out_of_order_inserts_come_back_sorted(super::$backend()).await }
+        }
+    };
+}
+
+backend_suite!(sqlite);
+backend_suite!(questdb, #[ignore = "necesita QuestDB (QUESTDB_HOST/QUESTDB_PORT)"]);
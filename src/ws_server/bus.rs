@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::{RecvError, TryRecvError}};
+
+/// Clase de un mensaje difundido; cada una va por su propio canal broadcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Muestras del ESP32 / MAVLink / datos de vuelo: alto caudal, descartables
+    Telemetry,
+    Ack,
+    /// Cambios de estado (`modo`, `led`, `motors`...) y todo lo no clasificado
+    State,
+    /// Avisos del propio servidor (`warning`, `timing_stats`, `replay`...)
+    System,
+}
+
+impl Class {
+    pub fn of(kind: Option<&str>) -> Self {
+        match kind {
+            Some("telemetry" | "data" | "mavlink") => Self::Telemetry,
+            Some("ack") => Self::Ack,
+            Some(
+                "warning" | "error" | "timing_stats" | "udp_rebound" | "esp32_address" | "replay"
+                | "stream_rate" | "status",
+            ) => Self::System,
+            _ => Self::State,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Kind<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<&'a str>,
+}
+
+/// Mensaje ya clasificado: el `type` se lee una vez al publicar y no en cada cliente
+#[derive(Debug)]
+pub struct Frame {
+    pub class: Class,
+    /// `type` del JSON; `None` si no tiene (o no es JSON)
+    pub kind: Option<String>,
+    pub text: String,
+}
+
+impl Frame {
+    pub fn new(text: String) -> Self {
+        let kind = serde_json::from_str::<Kind>(&text).ok().and_then(|k| k.kind).map(str::to_owned);
+        Self { class: Class::of(kind.as_deref()), kind, text }
+    }
+}
+
+/// Canales internos de difusión hacia WS, uno por clase de mensaje.
+/// Un cliente lento pierde telemetría sin perder acks ni cambios de estado.
+pub struct Bus {
+    telemetry: broadcast::Sender<Arc<Frame>>,
+    ack: broadcast::Sender<Arc<Frame>>,
+    state: broadcast::Sender<Arc<Frame>>,
+    system: broadcast::Sender<Arc<Frame>>,
+}
+
+impl Bus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            telemetry: broadcast::channel(capacity).0,
+            ack: broadcast::channel(capacity).0,
+            state: broadcast::channel(capacity).0,
+            system: broadcast::channel(capacity).0,
+        }
+    }
+
+    fn channel(&self, class: Class) -> &broadcast::Sender<Arc<Frame>> {
+        match class {
+            Class::Telemetry => &self.telemetry,
+            Class::Ack => &self.ack,
+            Class::State => &self.state,
+            Class::System => &self.system,
+        }
+    }
+
+    /// Publica en el canal de su clase; devuelve los receptores alcanzados (`None` = ninguno)
+    pub fn publish(&self, text: String) -> Option<usize> {
+        let frame = Frame::new(text);
+        self.channel(frame.class).send(Arc::new(frame)).ok()
+    }
+
+    pub fn subscribe(&self) -> BusReceiver {
+        BusReceiver {
+            telemetry: self.telemetry.subscribe(),
+            ack: self.ack.subscribe(),
+            state: self.state.subscribe(),
+            system: self.system.subscribe(),
+        }
+    }
+}
+
+/// Suscripción a todos los canales del `Bus`, mezclados
+pub struct BusReceiver {
+    telemetry: broadcast::Receiver<Arc<Frame>>,
+    ack: broadcast::Receiver<Arc<Frame>>,
+    state: broadcast::Receiver<Arc<Frame>>,
+    system: broadcast::Receiver<Arc<Frame>>,
+}
+
+impl BusReceiver {
+    /// Siguiente mensaje; acks, estado y sistema tienen prioridad sobre la telemetría
+    pub async fn recv(&mut self) -> Result<Arc<Frame>, RecvError> {
+        // Con algo ya en cola no se espera: `try_recv` no registra un waiter en cada
+        // canal vacío, que con telemetría a tope es casi todo el coste del `select!`
+        for rx in [&mut self.ack, &mut self.state, &mut self.system, &mut self.telemetry] {
+            match rx.try_recv() {
+                Ok(frame) => return Ok(frame),
+                Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+                Err(TryRecvError::Empty | TryRecvError::Closed) => {}
+            }
+        }
+        tokio::select! {
+            biased;
+            res = self.ack.recv() => res,
+            res = self.state.recv() => res,
+            res = self.system.recv() => res,
+            res = self.telemetry.recv() => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queued_acks_jump_ahead_of_telemetry() {
+        let bus = Bus::new(8);
+        let mut rx = bus.subscribe();
+        bus.publish(r#"{"type":"telemetry","payload":{}}"#.into());
+        bus.publish(r#"{"type":"ack","request_id":"r"}"#.into());
+        assert_eq!(rx.recv().await.unwrap().class, Class::Ack);
+        assert_eq!(rx.recv().await.unwrap().class, Class::Telemetry);
+    }
+
+    #[tokio::test]
+    async fn lagging_telemetry_does_not_touch_acks() {
+        let bus = Bus::new(4);
+        let mut rx = bus.subscribe();
+        bus.publish(r#"{"type":"ack","request_id":"r"}"#.into());
+        for _ in 0..10 {
+            bus.publish(r#"{"type":"telemetry","payload":{}}"#.into());
+        }
+        assert_eq!(rx.recv().await.unwrap().kind.as_deref(), Some("ack"));
+        assert!(matches!(rx.recv().await, Err(RecvError::Lagged(6))));
+        for _ in 0..4 {
+            assert_eq!(rx.recv().await.unwrap().class, Class::Telemetry);
+        }
+    }
+}
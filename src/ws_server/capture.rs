@@ -0,0 +1,232 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+/// Cola entre el hot path y el escritor; si se llena se descarta y se cuenta
+const CAPTURE_QUEUE: usize = 8192;
+
+/// Un datagrama capturado tal cual llegó
+struct CaptureRecord {
+    ts: chrono::DateTime<Utc>,
+    src: Option<SocketAddr>,
+    ingress: String,
+    bytes: Vec<u8>,
+}
+
+impl CaptureRecord {
+    fn to_line(&self) -> String {
+        let mut line = serde_json::json!({
+            "ts": self.ts.to_rfc3339(),
+            "src": self.src.map(|s| s.to_string()),
+            "ingress": self.ingress,
+        });
+        match std::str::from_utf8(&self.bytes) {
+            Ok(text) => line["text"] = text.into(),
+            Err(_) => line["hex"] = to_hex(&self.bytes).into(),
+        }
+        let mut out = line.to_string();
+        out.push('\n');
+        out
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Debug, Default, Clone, Serialize, ToSchema)]
+pub struct CaptureState {
+    pub active: bool,
+    pub file: Option<String>,
+    pub started_at: Option<String>,
+    pub written: u64,
+    pub dropped: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CaptureFile {
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// Captura cruda de datagramas a NDJSON en `./captures/` con rotación por tamaño
+pub struct Capture {
+    dir: PathBuf,
+    max_bytes: u64,
+    tx: Mutex<Option<mpsc::Sender<CaptureRecord>>>,
+    current: Arc<Mutex<Option<String>>>,
+    started_at: Mutex<Option<String>>,
+    written: Arc<AtomicU64>,
+    dropped: AtomicU64,
+}
+
+impl Capture {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            tx: Mutex::new(None),
+            current: Default::default(),
+            started_at: Mutex::new(None),
+            written: Default::default(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Hot path: nunca bloquea
+    pub fn record(&self, src: Option<SocketAddr>, ingress: &str, bytes: &[u8]) {
+        let guard = self.tx.lock().unwrap();
+        let Some(tx) = guard.as_ref() else { return };
+        let rec = CaptureRecord { ts: Utc::now(), src, ingress: ingress.to_string(), bytes: bytes.to_vec() };
+        if tx.try_send(rec).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Inicia la captura; `None` si ya hay una activa
+    pub async fn start(&self) -> anyhow::Result<Option<String>> {
+        if self.tx.lock().unwrap().is_some() {
+            return Ok(None);
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let (tx, rx) = mpsc::channel(CAPTURE_QUEUE);
+        let first = self.next_file_name(0);
+
+        *self.current.lock().unwrap() = Some(first.clone());
+        *self.started_at.lock().unwrap() = Some(Utc::now().to_rfc3339());
+        self.written.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+
+        let writer = CaptureWriter {
+            dir: self.dir.clone(),
+            max_bytes: self.max_bytes,
+            current: self.current.clone(),
+            written: self.written.clone(),
+            base: first.trim_end_matches(".ndjson").to_string(),
+        };
+        tokio::spawn(writer.run(rx));
+
+        *self.tx.lock().unwrap() = Some(tx);
+        info!("🎙️  Captura UDP iniciada en {}", self.dir.join(&first).display());
+        Ok(Some(first))
+    }
+
+    /// Detiene la captura; el escritor vacía la cola y cierra el archivo
+    pub fn stop(&self) -> bool {
+        let was_active = self.tx.lock().unwrap().take().is_some();
+        if was_active {
+            *self.started_at.lock().unwrap() = None;
+            info!("🎙️  Captura UDP detenida");
+        }
+        was_active
+    }
+
+    pub fn state(&self) -> CaptureState {
+        let active = self.tx.lock().unwrap().is_some();
+        CaptureState {
+            active,
+            file: if active { self.current.lock().unwrap().clone() } else { None },
+            started_at: self.started_at.lock().unwrap().clone(),
+            written: self.written.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Capturas terminadas (excluye el archivo en escritura)
+    pub async fn files(&self) -> anyhow::Result<Vec<CaptureFile>> {
+        let active = if self.tx.lock().unwrap().is_some() { self.current.lock().unwrap().clone() } else { None };
+        let mut out = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.ends_with(".ndjson") || active.as_deref() == Some(name.as_str()) {
+                continue;
+            }
+            let bytes = entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+            out.push(CaptureFile { name, bytes });
+        }
+        out.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(out)
+    }
+
+    /// Ruta de una captura terminada para descarga (rechaza nombres con rutas)
+    pub fn file_path(&self, name: &str) -> Option<PathBuf> {
+        let valid = name.ends_with(".ndjson") && !name.contains(['/', '\\']) && !name.contains("..");
+        let active = self.tx.lock().unwrap().is_some() && self.current.lock().unwrap().as_deref() == Some(name);
+        (valid && !active).then(|| self.dir.join(name))
+    }
+
+    fn next_file_name(&self, seq: u32) -> String {
+        let base = format!("capture_{}", Utc::now().format("%Y%m%d_%H%M%S"));
+        rotated_name(&base, seq)
+    }
+}
+
+fn rotated_name(base: &str, seq: u32) -> String {
+    if seq == 0 { format!("{base}.ndjson") } else { format!("{base}_{seq}.ndjson") }
+}
+
+struct CaptureWriter {
+    dir: PathBuf,
+    max_bytes: u64,
+    current: Arc<Mutex<Option<String>>>,
+    written: Arc<AtomicU64>,
+    base: String,
+}
+
+impl CaptureWriter {
+    async fn run(self, mut rx: mpsc::Receiver<CaptureRecord>) {
+        let mut seq = 0u32;
+        let mut size = 0u64;
+        let mut file = match self.open(seq).await {
+            Some(f) => f,
+            None => return,
+        };
+
+        while let Some(rec) = rx.recv().await {
+            let line = rec.to_line();
+            if self.max_bytes > 0 && size + line.len() as u64 > self.max_bytes && size > 0 {
+                let _ = file.flush().await;
+                seq += 1;
+                size = 0;
+                file = match self.open(seq).await {
+                    Some(f) => f,
+                    None => return,
+                };
+            }
+            if let Err(e) = file.write_all(line.as_bytes()).await {
+                error!("❌ Error escribiendo captura: {e}");
+                return;
+            }
+            size += line.len() as u64;
+            self.written.fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = file.flush().await;
+    }
+
+    async fn open(&self, seq: u32) -> Option<tokio::io::BufWriter<tokio::fs::File>> {
+        let name = rotated_name(&self.base, seq);
+        match tokio::fs::OpenOptions::new().create(true).append(true).open(self.dir.join(&name)).await {
+            Ok(f) => {
+                *self.current.lock().unwrap() = Some(name);
+                Some(tokio::io::BufWriter::new(f))
+            }
+            Err(e) => {
+                error!("❌ No se pudo abrir archivo de captura {name}: {e}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
+
+use super::deflate::DeflateStats;
+
+/// Mensajes dirigidos a un cliente concreto pendientes de enviar
+const CLIENT_DIRECT_QUEUE: usize = 64;
+
+/// Ping del servidor a cada cliente WS; sin ningún frame en `idle_timeout` se cierra
+#[derive(Debug, Clone, Copy)]
+pub struct KeepaliveConfig {
+    /// `None` = sin ping ni cierre por inactividad
+    pub ping_every: Option<Duration>,
+    pub idle_timeout: Duration,
+}
+
+/// Estado vivo de una conexión WS (lo actualizan sus dos tasks)
+pub struct ClientInfo {
+    pub id: u64,
+    addr: SocketAddr,
+    connected_at: DateTime<Utc>,
+    sent: AtomicU64,
+    received: AtomicU64,
+    last_activity_ms: AtomicI64,
+    topics: Mutex<Option<Vec<String>>>,
+    /// Desconectado vía `DELETE /api/ws/clients/:id` (no por apagado)
+    kicked: AtomicBool,
+    /// `operator`, `viewer` (`?role=viewer`) o `unauthenticated` (solo lectura sin token)
+    role: Mutex<&'static str>,
+    /// Mensajes rechazados por rol o por la lista de comandos permitidos
+    rejected: AtomicU64,
+    /// Telemetría descartada con la cola de salida llena
+    dropped: AtomicU64,
+    /// Versión de protocolo anunciada por el cliente en su `hello` (0 = ninguna)
+    proto: AtomicU32,
+    /// Intervalo mínimo entre mensajes de telemetría (`max_rate`), en µs; 0 = sin tope
+    min_interval_us: AtomicU64,
+    /// Ventana de agrupado de telemetría (`batch`), en ms; 0 = sin agrupar
+    batch_ms: AtomicU64,
+    /// permessage-deflate negociado (`None` = sin compresión)
+    deflate: Mutex<Option<Arc<DeflateStats>>>,
+}
+
+impl ClientInfo {
+    pub fn record_sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub fn touch(&self) {
+        self.last_activity_ms.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// Tiempo desde el último frame recibido (incluye Pong)
+    pub fn idle(&self) -> Duration {
+        let last = self.last_activity_ms.load(Ordering::Relaxed);
+        Duration::from_millis((Utc::now().timestamp_millis() - last).max(0) as u64)
+    }
+
+    pub fn set_topics(&self, topics: Option<Vec<String>>) {
+        *self.topics.lock().unwrap() = topics;
+    }
+
+    pub fn set_role(&self, role: &'static str) {
+        *self.role.lock().unwrap() = role;
+    }
+
+    pub fn set_deflate(&self, stats: Arc<DeflateStats>) {
+        *self.deflate.lock().unwrap() = Some(stats);
+    }
+
+    pub fn set_proto(&self, proto: u32) {
+        self.proto.store(proto, Ordering::Relaxed);
+    }
+
+    /// `hz <= 0` quita el tope
+    pub fn set_max_hz(&self, hz: f64) {
+        let us = if hz > 0.0 { (1_000_000.0 / hz) as u64 } else { 0 };
+        self.min_interval_us.store(us, Ordering::Relaxed);
+    }
+
+    pub fn max_hz(&self) -> Option<f64> {
+        self.min_interval().map(|iv| 1.0 / iv.as_secs_f64())
+    }
+
+    pub fn min_interval(&self) -> Option<Duration> {
+        let us = self.min_interval_us.load(Ordering::Relaxed);
+        (us > 0).then(|| Duration::from_micros(us))
+    }
+
+    pub fn set_batch_ms(&self, ms: u64) {
+        self.batch_ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn batch_ms(&self) -> Option<u64> {
+        Some(self.batch_ms.load(Ordering::Relaxed)).filter(|ms| *ms > 0)
+    }
+
+    pub fn batch_interval(&self) -> Option<Duration> {
+        self.batch_ms().map(Duration::from_millis)
+    }
+
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn kicked(&self) -> bool {
+        self.kicked.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self) -> ClientSnapshot {
+        let last = self.last_activity_ms.load(Ordering::Relaxed);
+        ClientSnapshot {
+            id: self.id,
+            addr: self.addr.to_string(),
+            connected_at: self.connected_at.to_rfc3339(),
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            last_activity: Utc.timestamp_millis_opt(last).single().map(|t| t.to_rfc3339()),
+            topics: self.topics.lock().unwrap().clone(),
+            role: *self.role.lock().unwrap(),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            proto: Some(self.proto.load(Ordering::Relaxed)).filter(|p| *p > 0),
+            max_hz: self.max_hz(),
+            batch_ms: self.batch_ms(),
+            compression: self.deflate.lock().unwrap().as_ref().map(|d| CompressionSnapshot { ratio: d.ratio() }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClientSnapshot {
+    pub id: u64,
+    pub addr: String,
+    pub connected_at: String,
+    pub sent: u64,
+    pub received: u64,
+    pub last_activity: Option<String>,
+    /// `None` = recibe todo (nunca se suscribió)
+    pub topics: Option<Vec<String>>,
+    pub role: &'static str,
+    pub rejected: u64,
+    pub dropped: u64,
+    pub proto: Option<u32>,
+    pub max_hz: Option<f64>,
+    pub batch_ms: Option<u64>,
+    /// permessage-deflate; `None` si el cliente no lo negoció
+    pub compression: Option<CompressionSnapshot>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CompressionSnapshot {
+    /// Bytes originales / bytes enviados de lo que va al cliente; `None` hasta el primer mensaje
+    pub ratio: Option<f64>,
+}
+
+struct ClientEntry {
+    info: Arc<ClientInfo>,
+    cancel: CancellationToken,
+    direct: mpsc::Sender<String>,
+}
+
+/// Conexiones WS activas; cada una se cancela con su token
+#[derive(Default)]
+pub struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: Mutex<HashMap<u64, ClientEntry>>,
+}
+
+impl ClientRegistry {
+    /// Registra una conexión; se da de baja al soltar el guard. El receptor devuelto
+    /// trae los mensajes dirigidos solo a este cliente (p. ej. sus acks).
+    pub fn register(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+        cancel: CancellationToken,
+    ) -> (ClientGuard, mpsc::Receiver<String>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = Utc::now();
+        let info = Arc::new(ClientInfo {
+            id,
+            addr,
+            connected_at: now,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            last_activity_ms: AtomicI64::new(now.timestamp_millis()),
+            topics: Mutex::new(None),
+            kicked: AtomicBool::new(false),
+            role: Mutex::new("operator"),
+            rejected: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            proto: AtomicU32::new(0),
+            min_interval_us: AtomicU64::new(0),
+            batch_ms: AtomicU64::new(0),
+            deflate: Mutex::new(None),
+        });
+        let (direct, direct_rx) = mpsc::channel(CLIENT_DIRECT_QUEUE);
+        self.clients.lock().unwrap().insert(id, ClientEntry { info: info.clone(), cancel, direct });
+        (ClientGuard { registry: self.clone(), info }, direct_rx)
+    }
+
+    /// Entrega `text` solo al cliente `id`; `false` si ya no está o su cola está llena
+    pub fn send_to(&self, id: u64, text: String) -> bool {
+        let clients = self.clients.lock().unwrap();
+        clients.get(&id).is_some_and(|entry| entry.direct.try_send(text).is_ok())
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn list(&self) -> Vec<ClientSnapshot> {
+        let mut out: Vec<_> = self.clients.lock().unwrap().values().map(|e| e.info.snapshot()).collect();
+        out.sort_by_key(|c| c.id);
+        out
+    }
+
+    /// Fuerza la desconexión de un cliente; `false` si no existe
+    pub fn disconnect(&self, id: u64) -> bool {
+        let clients = self.clients.lock().unwrap();
+        let Some(entry) = clients.get(&id) else { return false };
+        entry.info.kicked.store(true, Ordering::Relaxed);
+        entry.cancel.cancel();
+        true
+    }
+
+    /// Pide el cierre de todas las conexiones
+    pub fn close_all(&self) {
+        for entry in self.clients.lock().unwrap().values() {
+            entry.cancel.cancel();
+        }
+    }
+
+    /// Espera a que todas las conexiones terminen (como mucho `timeout`)
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !self.is_empty() {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+}
+
+pub struct ClientGuard {
+    registry: Arc<ClientRegistry>,
+    info: Arc<ClientInfo>,
+}
+
+impl ClientGuard {
+    pub fn info(&self) -> &Arc<ClientInfo> {
+        &self.info
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.registry.clients.lock().unwrap().remove(&self.info.id);
+    }
+}
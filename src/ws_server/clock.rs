@@ -0,0 +1,177 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// Campo del firmware con micros desde el arranque
+pub const T_US_FIELD: &str = "t_us";
+
+/// Un salto del offset mayor a esto se trata como reinicio del ESP32
+const RESYNC_JUMP_US: f64 = 2_000_000.0;
+/// Seguimiento lento de la deriva del reloj del firmware
+const DRIFT_ALPHA: f64 = 0.01;
+
+#[derive(Debug, Default)]
+struct SyncState {
+    /// server_us - t_us estimado (mínimo retardo observado + deriva)
+    offset_us: Option<f64>,
+    last_t_us: Option<u64>,
+    resyncs: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClockState {
+    pub synced: bool,
+    pub offset_ms: Option<f64>,
+    pub resyncs: u64,
+}
+
+/// Estima el offset entre `t_us` del ESP32 y el reloj del servidor para
+/// guardar cada muestra con el instante en que el firmware la tomó.
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    state: Mutex<SyncState>,
+    /// Campo con un timestamp absoluto propio de la telemetría (RFC 3339 o epoch en
+    /// s/ms/µs); manda sobre `t_us`
+    ts_field: Option<String>,
+}
+
+impl ClockSync {
+    pub fn new(ts_field: Option<String>) -> Self {
+        Self { ts_field, ..Default::default() }
+    }
+
+    /// Instante con el que se guarda la muestra: el timestamp propio del mensaje (`ts_field`),
+    /// si no `t_us` corregido y si no la llegada
+    pub fn timestamp(&self, msg: &Value, arrival: DateTime<Utc>) -> DateTime<Utc> {
+        self.own_timestamp(msg)
+            .or_else(|| extract_t_us(msg).map(|t_us| self.correct(t_us, arrival)))
+            .unwrap_or(arrival)
+    }
+
+    /// Solo el campo `ts_field` (nivel superior o dentro de `payload`)
+    pub fn own_timestamp(&self, msg: &Value) -> Option<DateTime<Utc>> {
+        let field = self.ts_field.as_deref()?;
+        let v = msg.get(field).or_else(|| msg.get("payload").and_then(|p| p.get(field)))?;
+        match v {
+            Value::String(s) => DateTime::parse_from_rfc3339(s).ok().map(|t| t.with_timezone(&Utc)),
+            Value::Number(n) => {
+                let n = n.as_f64()?;
+                // La magnitud dice la unidad: µs desde ~2001 pasan de 1e15, ms de 1e12
+                let us = if n >= 1e15 { n } else if n >= 1e12 { n * 1e3 } else { n * 1e6 };
+                Utc.timestamp_micros(us as i64).single()
+            }
+            _ => None,
+        }
+    }
+
+    /// Timestamp corregido para una muestra con `t_us` que llegó en `arrival`
+    pub fn correct(&self, t_us: u64, arrival: DateTime<Utc>) -> DateTime<Utc> {
+        let sample = arrival.timestamp_micros() as f64 - t_us as f64;
+        let mut st = self.state.lock().unwrap();
+
+        let rebooted = st.last_t_us.is_some_and(|prev| t_us < prev);
+        let offset = match st.offset_us {
+            Some(off) if !rebooted && (sample - off).abs() <= RESYNC_JUMP_US => {
+                // El menor retardo de red es la mejor estimación; si no, deriva lenta
+                if sample < off { sample } else { off + (sample - off) * DRIFT_ALPHA }
+            }
+            prev => {
+                if prev.is_some() {
+                    st.resyncs += 1;
+                    info!("⏱️  Re-sincronizando reloj del ESP32 (reinicio o salto de offset)");
+                }
+                sample
+            }
+        };
+        st.offset_us = Some(offset);
+        st.last_t_us = Some(t_us);
+
+        let corrected = t_us as f64 + offset;
+        Utc.timestamp_micros(corrected as i64).single().unwrap_or(arrival)
+    }
+
+    pub fn state(&self) -> ClockState {
+        let st = self.state.lock().unwrap();
+        ClockState {
+            synced: st.offset_us.is_some(),
+            offset_ms: st.offset_us.map(|o| o / 1000.0),
+            resyncs: st.resyncs,
+        }
+    }
+}
+
+/// `t_us` en el mensaje (nivel superior o dentro de `payload`)
+pub fn extract_t_us(msg: &Value) -> Option<u64> {
+    msg.get(T_US_FIELD)
+        .or_else(|| msg.get("payload").and_then(|p| p.get(T_US_FIELD)))
+        .and_then(|v| v.as_u64().or_else(|| v.as_f64().filter(|x| *x >= 0.0).map(|x| x as u64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn jittery_arrivals_collapse_to_the_firmware_spacing() {
+        let clock = ClockSync::new(None);
+        let boot = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        // Retardo de red en ms; el mínimo (1 ms) aparece en la segunda muestra
+        let delays = [3, 1, 4, 1, 5, 9, 2, 6];
+        for (i, delay) in delays.iter().cycle().take(200).enumerate() {
+            let t_us = 5_000_000 + i as u64 * 10_000;
+            let arrival = boot + Duration::microseconds(t_us as i64) + Duration::milliseconds(*delay);
+            let got = clock.correct(t_us, arrival);
+            if i >= 1 {
+                let error = (got - boot - Duration::microseconds(t_us as i64) - Duration::milliseconds(1)).num_microseconds().unwrap();
+                assert!((0..500).contains(&error), "muestra {i}: {error} µs");
+            }
+        }
+        let st = clock.state();
+        assert!(st.synced);
+        assert_eq!(st.resyncs, 0);
+    }
+
+    #[test]
+    fn reboot_resyncs_and_own_timestamp_wins() {
+        let clock = ClockSync::new(Some("ts".into()));
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        clock.correct(60_000_000, now);
+        // t_us vuelve atrás: el ESP32 se reinició y el offset se toma de cero
+        let after = now + Duration::seconds(1);
+        assert_eq!(clock.correct(100_000, after), after);
+        assert_eq!(clock.state().resyncs, 1);
+
+        let msg = serde_json::json!({ "payload": { "t_us": 200_000, "ts": 1_714_564_800_000_u64 } });
+        assert_eq!(extract_t_us(&msg), Some(200_000));
+        assert_eq!(clock.timestamp(&msg, after), Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap());
+        assert_eq!(clock.own_timestamp(&serde_json::json!({ "ts": "2024-05-01T12:00:00Z" })), Some(now));
+    }
+
+    #[test]
+    fn numeric_own_timestamp_guesses_the_unit_from_its_size() {
+        let clock = ClockSync::new(Some("ts".into()));
+        let at = |v: Value| clock.own_timestamp(&serde_json::json!({ "ts": v }));
+        let t = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        // El mismo instante en s, ms y µs
+        assert_eq!(at(serde_json::json!(1_714_564_800_u64)), Some(t));
+        assert_eq!(at(serde_json::json!(1_714_564_800_000_u64)), Some(t));
+        assert_eq!(at(serde_json::json!(1_714_564_800_000_000_u64)), Some(t));
+        // Fracciones de segundo y de milisegundo
+        assert_eq!(at(serde_json::json!(1_714_564_800.25)), Some(t + Duration::milliseconds(250)));
+        assert_eq!(at(serde_json::json!(1_714_564_800_000.5)), Some(t + Duration::microseconds(500)));
+        // Justo en los umbrales: 1e12 ya son ms (2001-09-09) y 1e15 ya son µs
+        let edge = Utc.timestamp_micros(1_000_000_000_000_000).single();
+        assert_eq!(at(serde_json::json!(1e12)), edge);
+        assert_eq!(at(serde_json::json!(1e15)), edge);
+        // Por debajo de 1e12 son segundos, incluso números pequeños
+        assert_eq!(at(serde_json::json!(0)), Some(DateTime::UNIX_EPOCH));
+        assert_eq!(at(serde_json::json!(true)), None);
+        // Sin `ts_field` configurado no se mira
+        assert_eq!(ClockSync::new(None).own_timestamp(&serde_json::json!({ "ts": 1_714_564_800 })), None);
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// `kind` de las filas de ack en `flight_commands`
+pub const ACK_KIND: &str = "ack";
+
+/// Rango de pulso aceptado para los ESC (µs)
+pub const MOTOR_US_RANGE: std::ops::RangeInclusive<u32> = 1000..=2000;
+
+/// Velocidad de motor fuera de `MOTOR_US_RANGE` → mensaje de error (el mismo por HTTP y WS)
+pub fn check_motor_us(us: u32) -> Result<u32, String> {
+    if MOTOR_US_RANGE.contains(&us) {
+        Ok(us)
+    } else {
+        Err(format!("Speed {us} µs out of range {}-{}", MOTOR_US_RANGE.start(), MOTOR_US_RANGE.end()))
+    }
+}
+
+/// Comando enviado durante un vuelo con su ack, si llegó (`GET /api/flights/:id/commands`)
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FlightCommand {
+    pub ts: String,
+    pub request_id: Option<String>,
+    /// `mode`, `motor`, `leds`... (la clave del `payload` del comando) o `raw`
+    pub kind: String,
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    /// `null` sin ack (o ack sin campo `ok`)
+    pub ack_ok: Option<bool>,
+    pub ack_ts: Option<String>,
+}
+
+/// Tipo de un comando saliente: `{"type":"command","payload":{"mode":..}}` → `mode`;
+/// otro JSON → su `type`; texto → `raw`
+pub fn kind_of(payload: &str) -> String {
+    let Ok(msg) = serde_json::from_str::<Value>(payload) else { return "raw".into() };
+    let inner = msg.get("payload").and_then(|p| p.as_object()).and_then(|o| o.keys().next());
+    let ty = msg.get("type").and_then(|t| t.as_str());
+    match (ty, inner) {
+        (Some("command"), Some(key)) => key.clone(),
+        (Some(ty), _) => ty.to_owned(),
+        _ => "raw".into(),
+    }
+}
+
+/// `ok` del ack del firmware
+pub fn ack_ok(payload: &str) -> Option<bool> {
+    serde_json::from_str::<Value>(payload).ok()?.get("ok")?.as_bool()
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::questdb::FlightPoint;
+use super::series::extract_values;
+
+/// Puntos de la rejilla común si no se pide `points`
+pub const DEFAULT_GRID_POINTS: usize = 1_000;
+pub const MAX_GRID_POINTS: usize = 100_000;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FlightSpan {
+    pub flight_id: String,
+    pub start_ts: String,
+    pub duration_sec: f64,
+}
+
+/// Diferencias `a - b` de un campo sobre la rejilla (solo donde ambos tienen valor)
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct DiffStats {
+    pub samples: usize,
+    pub mean_abs_diff: Option<f64>,
+    /// Diferencia de mayor valor absoluto, con su signo
+    pub max_diff: Option<f64>,
+    pub rms: Option<f64>,
+}
+
+/// Series paralelas de un campo; `null` donde un vuelo no tiene dato
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldComparison {
+    pub a: Vec<Option<f64>>,
+    pub b: Vec<Option<f64>>,
+    pub stats: DiffStats,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Comparison {
+    pub a: FlightSpan,
+    pub b: FlightSpan,
+    /// Tramo comparado: la duración del vuelo más corto
+    pub overlap_sec: f64,
+    pub step_ms: f64,
+    /// Tiempo desde el inicio de cada vuelo, en ms
+    pub t_ms: Vec<f64>,
+    pub fields: BTreeMap<String, FieldComparison>,
+}
+
+/// Un campo de un vuelo: (ms desde el inicio, valor), en orden
+fn track(points: &[FlightPoint], field: &str) -> Vec<(f64, f64)> {
+    let start = points[0].ts;
+    let wanted = [field.to_string()];
+    points
+        .iter()
+        .filter_map(|p| {
+            let v = extract_values(p, &wanted).remove(field)?;
+            Some(((p.ts - start).num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0, v))
+        })
+        .collect()
+}
+
+/// Interpolación lineal en `t`; fuera del rango del campo → `None`
+fn resample(track: &[(f64, f64)], grid: &[f64]) -> Vec<Option<f64>> {
+    let mut i = 0;
+    grid.iter()
+        .map(|&t| {
+            while i + 1 < track.len() && track[i + 1].0 < t {
+                i += 1;
+            }
+            let (t0, v0) = *track.get(i)?;
+            if t < t0 {
+                return None;
+            }
+            match track.get(i + 1) {
+                Some(&(t1, v1)) if t1 > t0 && t <= t1 => Some(v0 + (v1 - v0) * (t - t0) / (t1 - t0)),
+                _ if t == t0 => Some(v0),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn diff_stats(a: &[Option<f64>], b: &[Option<f64>]) -> DiffStats {
+    let diffs: Vec<f64> = a.iter().zip(b).filter_map(|(x, y)| Some((*x)? - (*y)?)).collect();
+    if diffs.is_empty() {
+        return DiffStats::default();
+    }
+    let n = diffs.len() as f64;
+    let max_diff = diffs.iter().copied().fold(0.0f64, |m, d| if d.abs() > m.abs() { d } else { m });
+    DiffStats {
+        samples: diffs.len(),
+        mean_abs_diff: Some(diffs.iter().map(|d| d.abs()).sum::<f64>() / n),
+        max_diff: Some(max_diff),
+        rms: Some((diffs.iter().map(|d| d * d).sum::<f64>() / n).sqrt()),
+    }
+}
+
+fn span(flight_id: &str, points: &[FlightPoint]) -> FlightSpan {
+    let (first, last) = (points[0].ts, points[points.len() - 1].ts);
+    FlightSpan {
+        flight_id: flight_id.to_string(),
+        start_ts: first.to_rfc3339(),
+        duration_sec: (last - first).num_milliseconds() as f64 / 1000.0,
+    }
+}
+
+/// Alinea ambos vuelos por tiempo desde su inicio y los remuestrea a `grid_points`
+/// instantes comunes dentro del tramo que comparten. Ninguno puede venir vacío.
+pub fn compare(
+    (id_a, a): (&str, &[FlightPoint]),
+    (id_b, b): (&str, &[FlightPoint]),
+    fields: &[String],
+    grid_points: usize,
+) -> Comparison {
+    let (span_a, span_b) = (span(id_a, a), span(id_b, b));
+    let overlap_ms = span_a.duration_sec.min(span_b.duration_sec) * 1000.0;
+    let grid_points = if overlap_ms > 0.0 { grid_points.max(2) } else { 1 };
+    let step_ms = if grid_points > 1 { overlap_ms / (grid_points - 1) as f64 } else { 0.0 };
+    let t_ms: Vec<f64> = (0..grid_points).map(|i| i as f64 * step_ms).collect();
+
+    let fields = fields
+        .iter()
+        .map(|f| {
+            let ra = resample(&track(a, f), &t_ms);
+            let rb = resample(&track(b, f), &t_ms);
+            let stats = diff_stats(&ra, &rb);
+            (f.clone(), FieldComparison { a: ra, b: rb, stats })
+        })
+        .collect();
+
+    Comparison { a: span_a, b: span_b, overlap_sec: overlap_ms / 1000.0, step_ms, t_ms, fields }
+}
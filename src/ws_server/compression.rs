@@ -0,0 +1,19 @@
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Por debajo de esto no compensa comprimir
+const MIN_SIZE: u16 = 1024;
+
+/// gzip/br/deflate según `Accept-Encoding` para las respuestas grandes (series, comparaciones...).
+/// Se excluyen SSE y las exportaciones por streaming: el compresor acumula datos y
+/// rompería la entrega incremental
+pub fn layer() -> CompressionLayer<impl Predicate> {
+    let predicate = SizeAbove::new(MIN_SIZE)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::const_new("application/x-ndjson"))
+        .and(NotForContentType::const_new("text/csv"))
+        .and(NotForContentType::const_new("application/vnd.apache.parquet"));
+    CompressionLayer::new().compress_when(predicate)
+}
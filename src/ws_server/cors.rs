@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Orígenes que pueden llamar a la API desde otro dominio (`ARTHERIS_CORS`)
+#[derive(Debug, Clone, Default)]
+pub enum CorsOrigins {
+    /// Sin cabeceras CORS: solo mismo origen (la UI en desarrollo va por el proxy de Vite)
+    #[default]
+    SameOrigin,
+    /// `ARTHERIS_CORS=*`, solo si se pide explícitamente
+    Any,
+    /// `ARTHERIS_CORS=http://groundstation:5173,https://ui.local`
+    List(Vec<HeaderValue>),
+}
+
+impl CorsOrigins {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        if value == "*" {
+            return Ok(Self::Any);
+        }
+        let origins = value
+            .split(',')
+            .map(|o| o.trim().trim_end_matches('/'))
+            .filter(|o| !o.is_empty())
+            .map(|o| HeaderValue::from_str(o).with_context(|| format!("origen CORS inválido: {o:?}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(if origins.is_empty() { Self::SameOrigin } else { Self::List(origins) })
+    }
+
+    /// Solo los métodos y cabeceras que usa la API
+    pub fn layer(&self) -> CorsLayer {
+        let layer = CorsLayer::new()
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION, HeaderName::from_static("x-api-key")])
+            .expose_headers([header::CONTENT_DISPOSITION])
+            .max_age(Duration::from_secs(3600));
+        match self {
+            Self::SameOrigin => layer,
+            Self::Any => layer.allow_origin(Any),
+            Self::List(origins) => layer.allow_origin(AllowOrigin::list(origins.clone())),
+        }
+    }
+}
@@ -0,0 +1,667 @@
+//! permessage-deflate (RFC 7692) para las conexiones WS.
+//!
+//! tungstenite no implementa la extensión (rechaza frames con RSV1), así que se hace
+//! debajo de él: `DeflateStream` envuelve el socket, comprime los mensajes de datos que
+//! escribe el servidor y descomprime los que llegan comprimidos del cliente antes de
+//! que tungstenite los lea. Hasta `enable` deja pasar los bytes tal cual (handshake HTTP).
+//! Con `before_handshake` entrega solo hasta el final de la petición HTTP: lo que el
+//! cliente mande detrás en la misma lectura espera a `enable` y pasa por el descompresor.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+pub const EXTENSION: &str = "permessage-deflate";
+
+/// Mensajes más cortos no se comprimen (no compensa la cabecera de deflate)
+const MIN_COMPRESS: usize = 32;
+/// Tope de una cabecera HTTP en `before_handshake`
+const MAX_HEAD: usize = 64 * 1024;
+/// Con más que esto pendiente de salir, `poll_write` espera al socket
+const HIGH_WATER: usize = 256 * 1024;
+/// Cola que deja el flush síncrono y que RFC 7692 manda quitar
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const MASK: u8 = 0x80;
+
+/// Parámetros aceptados para una conexión
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Agreed {
+    /// Reiniciar el compresor tras cada mensaje del servidor (lo pidió el cliente)
+    pub server_no_context_takeover: bool,
+    /// El cliente reinicia el suyo tras cada mensaje
+    pub client_no_context_takeover: bool,
+}
+
+impl Agreed {
+    /// Valor de `Sec-WebSocket-Extensions` de la respuesta 101
+    pub fn response(&self) -> String {
+        let mut out = EXTENSION.to_string();
+        if self.server_no_context_takeover {
+            out.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            out.push_str("; client_no_context_takeover");
+        }
+        out
+    }
+}
+
+/// Primera oferta de permessage-deflate aceptable en las cabeceras
+/// `Sec-WebSocket-Extensions` del cliente. No se aceptan `server_max_window_bits` < 15
+/// (el compresor siempre usa ventana de 32 KiB); `client_max_window_bits` es solo un
+/// aviso y el descompresor admite cualquier ventana
+pub fn negotiate<'a>(offers: impl IntoIterator<Item = &'a str>) -> Option<Agreed> {
+    offers.into_iter().flat_map(|h| h.split(',')).find_map(|offer| {
+        let mut parts = offer.split(';').map(str::trim);
+        if !parts.next()?.eq_ignore_ascii_case(EXTENSION) {
+            return None;
+        }
+        let mut agreed = Agreed { server_no_context_takeover: false, client_no_context_takeover: false };
+        let mut seen = Vec::new();
+        for param in parts.filter(|p| !p.is_empty()) {
+            let (name, value) = match param.split_once('=') {
+                Some((n, v)) => (n.trim(), Some(v.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            if seen.contains(&name) {
+                return None;
+            }
+            seen.push(name);
+            match (name, value) {
+                ("server_no_context_takeover", None) => agreed.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => agreed.client_no_context_takeover = true,
+                ("server_max_window_bits", Some("15")) => {}
+                ("client_max_window_bits", None) => {}
+                ("client_max_window_bits", Some(v)) if v.parse::<u8>().is_ok_and(|b| (8..=15).contains(&b)) => {}
+                _ => return None,
+            }
+        }
+        Some(agreed)
+    })
+}
+
+/// Bytes de los mensajes servidor→cliente antes y después de comprimir
+#[derive(Debug, Default)]
+pub struct DeflateStats {
+    raw: AtomicU64,
+    wire: AtomicU64,
+}
+
+impl DeflateStats {
+    fn record(&self, raw: usize, wire: usize) {
+        self.raw.fetch_add(raw as u64, Ordering::Relaxed);
+        self.wire.fetch_add(wire as u64, Ordering::Relaxed);
+    }
+
+    /// Tamaño original / tamaño enviado (3.0 = un tercio del tráfico); `None` sin datos aún
+    pub fn ratio(&self) -> Option<f64> {
+        let wire = self.wire.load(Ordering::Relaxed);
+        (wire > 0).then(|| self.raw.load(Ordering::Relaxed) as f64 / wire as f64)
+    }
+}
+
+/// Cabecera de un frame WS ya completo en el buffer
+struct Frame {
+    first: u8,
+    masked: bool,
+    header_len: usize,
+    payload_len: usize,
+}
+
+impl Frame {
+    /// `max`: tope del payload (los frames del cliente; los del servidor no lo tienen)
+    fn parse(buf: &[u8], max: usize) -> io::Result<Option<Self>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let masked = buf[1] & MASK != 0;
+        let (len, mut header_len) = match buf[1] & 0x7f {
+            126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() >= 10 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+            126 | 127 => return Ok(None),
+            n => (n as u64, 2),
+        };
+        if masked {
+            header_len += 4;
+        }
+        let payload_len = usize::try_from(len)
+            .ok()
+            .filter(|l| *l <= max)
+            .ok_or_else(|| invalid("WebSocket frame too large"))?;
+        if buf.len() < header_len + payload_len {
+            return Ok(None);
+        }
+        Ok(Some(Self { first: buf[0], masked, header_len, payload_len }))
+    }
+
+    fn opcode(&self) -> u8 {
+        self.first & 0x0f
+    }
+
+    fn fin(&self) -> bool {
+        self.first & FIN != 0
+    }
+
+    fn rsv1(&self) -> bool {
+        self.first & RSV1 != 0
+    }
+
+    fn len(&self) -> usize {
+        self.header_len + self.payload_len
+    }
+
+    /// Payload sin máscara
+    fn payload(&self, buf: &[u8]) -> Vec<u8> {
+        let mut data = buf[self.header_len..self.len()].to_vec();
+        if self.masked {
+            let key: [u8; 4] = buf[self.header_len - 4..self.header_len].try_into().unwrap();
+            data.iter_mut().enumerate().for_each(|(i, b)| *b ^= key[i % 4]);
+        }
+        data
+    }
+}
+
+/// Frame con `payload`; `masked` pone máscara nula (tungstenite exige máscara en los
+/// frames del cliente y con clave 0 el payload no cambia)
+fn encode_frame(out: &mut Vec<u8>, first: u8, masked: bool, payload: &[u8]) {
+    out.push(first);
+    let mask = if masked { MASK } else { 0 };
+    match payload.len() {
+        n if n < 126 => out.push(mask | n as u8),
+        n if n <= u16::MAX as usize => {
+            out.push(mask | 126);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            out.push(mask | 127);
+            out.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        out.extend_from_slice(&[0; 4]);
+    }
+    out.extend_from_slice(payload);
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Estado de compresión de una conexión ya negociada
+struct Codec {
+    agreed: Agreed,
+    compress: Compress,
+    decompress: Decompress,
+    /// Mensaje comprimido del cliente a medias (opcode y payload de sus fragmentos)
+    inflating: Option<(u8, Vec<u8>)>,
+    /// El servidor está enviando un mensaje fragmentado: va sin comprimir
+    plain_fragments: bool,
+    /// Tope de un mensaje del cliente, comprimido o no: se le entrega a tungstenite como
+    /// un solo frame, así que es su `max_frame_size` (el de `WebSocketConfig` por defecto,
+    /// que es el de los dos endpoints)
+    max_frame: usize,
+    stats: Arc<DeflateStats>,
+}
+
+impl Codec {
+    fn new(agreed: Agreed, stats: Arc<DeflateStats>) -> Self {
+        Self {
+            agreed,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            inflating: None,
+            plain_fragments: false,
+            max_frame: WebSocketConfig::default().max_frame_size.unwrap_or(usize::MAX),
+            stats,
+        }
+    }
+
+    fn deflate(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            if out.capacity() - out.len() < 64 {
+                out.reserve(out.capacity().max(64));
+            }
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&input[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            // Flush completo cuando se consumió todo y sobró sitio en la salida
+            if (self.compress.total_in() - start) as usize == input.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+        if out.ends_with(&TAIL) {
+            out.truncate(out.len() - TAIL.len());
+        }
+        if self.agreed.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(out)
+    }
+
+    fn inflate(&mut self, mut input: Vec<u8>) -> io::Result<Vec<u8>> {
+        input.extend_from_slice(&TAIL);
+        let mut out = Vec::with_capacity(input.len() * 4);
+        let start = self.decompress.total_in();
+        loop {
+            if out.capacity() - out.len() < 256 {
+                // Sin reservar mucho más allá del tope: basta con pasarlo para saberlo
+                let room = (self.max_frame + 1).saturating_sub(out.len());
+                out.reserve(out.capacity().min(room).max(256));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let before = out.len();
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| invalid(&e.to_string()))?;
+            if out.len() > self.max_frame {
+                return Err(invalid("inflated WebSocket message too large"));
+            }
+            let now = (self.decompress.total_in() - start) as usize;
+            if now == input.len() && out.len() < out.capacity() {
+                break;
+            }
+            if now == consumed && out.len() == before && out.len() < out.capacity() {
+                return Err(invalid("truncated deflate data"));
+            }
+        }
+        if self.agreed.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+
+    /// Cliente → servidor: descomprime los mensajes con RSV1 y deja el resto igual
+    fn incoming(&mut self, buf: &[u8], frame: &Frame, out: &mut Vec<u8>) -> io::Result<()> {
+        let op = frame.opcode();
+        let compressed = match op {
+            OP_TEXT | OP_BINARY if frame.rsv1() => {
+                self.inflating = Some((op, frame.payload(buf)));
+                true
+            }
+            OP_CONTINUATION if !frame.rsv1() => match &mut self.inflating {
+                Some((_, data)) => {
+                    data.extend_from_slice(&frame.payload(buf));
+                    if data.len() > self.max_frame {
+                        return Err(invalid("WebSocket message too large"));
+                    }
+                    true
+                }
+                None => false,
+            },
+            // Control, mensajes sin comprimir y RSV1 fuera de sitio: tungstenite decide
+            _ => false,
+        };
+        if !compressed {
+            out.extend_from_slice(&buf[..frame.len()]);
+        } else if frame.fin() {
+            let (op, data) = self.inflating.take().unwrap();
+            let data = self.inflate(data)?;
+            encode_frame(out, FIN | op, frame.masked, &data);
+        }
+        Ok(())
+    }
+
+    /// Servidor → cliente: comprime los mensajes de datos enteros (no fragmentados)
+    fn outgoing(&mut self, buf: &[u8], frame: &Frame, out: &mut Vec<u8>) -> io::Result<()> {
+        let op = frame.opcode();
+        let data_start = matches!(op, OP_TEXT | OP_BINARY);
+        if data_start {
+            self.plain_fragments = !frame.fin();
+        }
+        if data_start && frame.fin() && frame.payload_len >= MIN_COMPRESS {
+            let payload = frame.payload(buf);
+            let compressed = self.deflate(&payload)?;
+            // Sin contexto compartido se puede mandar el original si comprimido no gana
+            if self.agreed.server_no_context_takeover && compressed.len() >= payload.len() {
+                self.stats.record(payload.len(), payload.len());
+                out.extend_from_slice(&buf[..frame.len()]);
+            } else {
+                self.stats.record(payload.len(), compressed.len());
+                encode_frame(out, FIN | RSV1 | op, frame.masked, &compressed);
+            }
+        } else {
+            if data_start || (op == OP_CONTINUATION && self.plain_fragments) {
+                self.stats.record(frame.payload_len, frame.payload_len);
+            }
+            out.extend_from_slice(&buf[..frame.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// Socket WS con permessage-deflate opcional (ver el módulo)
+pub struct DeflateStream<S> {
+    inner: S,
+    codec: Option<Codec>,
+    /// Bytes leídos del socket aún sin formar un frame completo
+    read_raw: Vec<u8>,
+    /// Ya transformados, pendientes de entregar a tungstenite
+    read_ready: Vec<u8>,
+    /// Aún dentro de la petición HTTP (ver `before_handshake`)
+    in_head: bool,
+    read_pos: usize,
+    /// Bytes escritos por tungstenite aún sin formar un frame completo
+    write_raw: Vec<u8>,
+    /// Ya transformados, pendientes de escribir en el socket
+    write_ready: Vec<u8>,
+}
+
+impl<S> DeflateStream<S> {
+    /// Sin compresión hasta `enable`; `inner` ya está en WebSocket (tras el upgrade)
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            codec: None,
+            read_raw: Vec::new(),
+            read_ready: Vec::new(),
+            in_head: false,
+            read_pos: 0,
+            write_raw: Vec::new(),
+            write_ready: Vec::new(),
+        }
+    }
+
+    /// Para hacer el handshake HTTP encima: las lecturas se paran al final de la
+    /// petición, y los frames que lleguen pegados a ella esperan a `enable`
+    pub fn before_handshake(inner: S) -> Self {
+        Self { in_head: true, ..Self::new(inner) }
+    }
+
+    /// Activa la compresión (tras el 101); los contadores van a `stats`
+    pub fn enable(&mut self, agreed: Agreed, stats: Arc<DeflateStats>) {
+        self.codec = Some(Codec::new(agreed, stats));
+    }
+
+    fn passthrough(&self) -> bool {
+        self.codec.is_none()
+    }
+}
+
+/// Transforma los frames completos al principio de `raw` con `f`
+fn drain_frames(
+    raw: &mut Vec<u8>,
+    max: usize,
+    out: &mut Vec<u8>,
+    mut f: impl FnMut(&[u8], &Frame, &mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut pos = 0;
+    while let Some(frame) = Frame::parse(&raw[pos..], max)? {
+        f(&raw[pos..], &frame, out)?;
+        pos += frame.len();
+    }
+    raw.drain(..pos);
+    Ok(())
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    /// Escribe en el socket lo ya transformado
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_ready.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_ready))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_ready.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> DeflateStream<S> {
+    /// Añade a `read_raw` lo que haya en el socket; `false` en EOF
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        let mut chunk = [0u8; 8192];
+        let mut chunk_buf = ReadBuf::new(&mut chunk);
+        ready!(Pin::new(&mut self.inner).poll_read(cx, &mut chunk_buf))?;
+        self.read_raw.extend_from_slice(chunk_buf.filled());
+        Poll::Ready(Ok(!chunk_buf.filled().is_empty()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.read_pos < this.read_ready.len() {
+                let n = buf.remaining().min(this.read_ready.len() - this.read_pos);
+                buf.put_slice(&this.read_ready[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                if this.read_pos == this.read_ready.len() {
+                    this.read_ready.clear();
+                    this.read_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if this.in_head {
+                if let Some(end) = this.read_raw.windows(4).position(|w| w == b"\r\n\r\n") {
+                    this.read_ready = this.read_raw.drain(..end + 4).collect();
+                    this.in_head = false;
+                    continue;
+                }
+                if this.read_raw.len() > MAX_HEAD {
+                    return Poll::Ready(Err(invalid("HTTP request head too large")));
+                }
+                if !ready!(this.poll_fill(cx))? {
+                    // EOF a mitad de la cabecera: que el handshake vea lo que llegó
+                    this.read_ready = std::mem::take(&mut this.read_raw);
+                    this.in_head = false;
+                }
+                continue;
+            }
+            let Some(codec) = &mut this.codec else {
+                // Sin compresión, lo que llegó tras la cabecera sale primero y tal cual
+                if !this.read_raw.is_empty() {
+                    this.read_ready = std::mem::take(&mut this.read_raw);
+                    continue;
+                }
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            };
+            drain_frames(&mut this.read_raw, codec.max_frame, &mut this.read_ready, |b, f, out| codec.incoming(b, f, out))?;
+            if !this.read_ready.is_empty() {
+                continue;
+            }
+            if !ready!(this.poll_fill(cx))? {
+                // EOF: lo que quede a medias lo verá tungstenite como conexión cortada
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.passthrough() && this.write_ready.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        if this.write_ready.len() >= HIGH_WATER {
+            ready!(this.poll_drain(cx))?;
+        }
+        match &mut this.codec {
+            Some(codec) => {
+                this.write_raw.extend_from_slice(buf);
+                drain_frames(&mut this.write_raw, usize::MAX, &mut this.write_ready, |b, f, out| codec.outgoing(b, f, out))?;
+            }
+            None => this.write_ready.extend_from_slice(buf),
+        }
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::WebSocketStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    #[test]
+    fn negotiate_accepts_browser_offers() {
+        let browser = negotiate(["permessage-deflate; client_max_window_bits"]).unwrap();
+        assert_eq!(browser.response(), "permessage-deflate");
+        let nct = negotiate(["x-webkit-deflate-frame, permessage-deflate; server_no_context_takeover; client_no_context_takeover"]).unwrap();
+        assert_eq!(nct.response(), "permessage-deflate; server_no_context_takeover; client_no_context_takeover");
+    }
+
+    #[test]
+    fn negotiate_skips_offers_it_cannot_honour() {
+        assert_eq!(negotiate([]), None);
+        assert_eq!(negotiate(["permessage-deflate; server_max_window_bits=10"]), None);
+        assert_eq!(negotiate(["permessage-deflate; server_no_context_takeover; server_no_context_takeover"]), None);
+        assert_eq!(negotiate(["permessage-deflate; foo"]), None);
+        // La segunda oferta sí vale
+        let second = negotiate(["permessage-deflate; server_max_window_bits=9, permessage-deflate"]).unwrap();
+        assert!(!second.server_no_context_takeover);
+    }
+
+    type Ws = WebSocketStream<DeflateStream<tokio::io::DuplexStream>>;
+
+    /// Cliente que comprime con su propio `Codec` sobre un `DeflateStream` de servidor
+    async fn pair(agreed: Agreed) -> (Ws, Ws, Arc<DeflateStats>) {
+        let (a, b) = tokio::io::duplex(1 << 20);
+        let stats = Arc::new(DeflateStats::default());
+        let mut server = DeflateStream::new(a);
+        server.enable(agreed, stats.clone());
+        let mut client = DeflateStream::new(b);
+        // Mismo códec en el cliente, con los papeles de context takeover cruzados
+        client.enable(
+            Agreed {
+                server_no_context_takeover: agreed.client_no_context_takeover,
+                client_no_context_takeover: agreed.server_no_context_takeover,
+            },
+            Arc::new(DeflateStats::default()),
+        );
+        let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        (server, client, stats)
+    }
+
+    #[tokio::test]
+    async fn messages_round_trip_compressed_both_ways() {
+        for server_nct in [false, true] {
+            let agreed = Agreed { server_no_context_takeover: server_nct, client_no_context_takeover: !server_nct };
+            let (mut server, mut client, stats) = pair(agreed).await;
+            // Lote de telemetría como los de `batch`
+            let sample = r#"{"type":"telemetry","payload":{"AngleRoll":1.25,"AnglePitch":-0.5,"AngleYaw":90.0}}"#;
+            let telemetry = format!("[{}]", [sample; 10].join(","));
+            for _ in 0..3 {
+                server.send(Message::Text(telemetry.clone())).await.unwrap();
+                assert_eq!(client.next().await.unwrap().unwrap(), Message::Text(telemetry.clone()));
+            }
+            server.send(Message::Text("ok".into())).await.unwrap();
+            assert_eq!(client.next().await.unwrap().unwrap(), Message::Text("ok".into()));
+            let big = vec![7u8; 100_000];
+            client.send(Message::Binary(big.clone())).await.unwrap();
+            assert_eq!(server.next().await.unwrap().unwrap(), Message::Binary(big));
+            client.send(Message::Ping(vec![1])).await.unwrap();
+            assert_eq!(server.next().await.unwrap().unwrap(), Message::Ping(vec![1]));
+            assert!(stats.ratio().unwrap() > 1.5, "ratio {:?}", stats.ratio());
+        }
+    }
+
+    #[tokio::test]
+    async fn compressed_frames_carry_rsv1() {
+        let (a, mut raw) = tokio::io::duplex(1 << 16);
+        let stats = Arc::new(DeflateStats::default());
+        let mut server = DeflateStream::new(a);
+        server.enable(Agreed { server_no_context_takeover: true, client_no_context_takeover: false }, stats);
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let text = "abcabcabcabcabcabcabcabcabcabcabcabcabcabc";
+        server.send(Message::Text(text.into())).await.unwrap();
+        let mut buf = [0u8; 256];
+        let n = tokio::io::AsyncReadExt::read(&mut raw, &mut buf).await.unwrap();
+        assert_eq!(buf[0], FIN | RSV1 | OP_TEXT);
+        assert!(n - 2 < text.len());
+        // Con server_no_context_takeover cada mensaje se descomprime por separado
+        let mut d = Decompress::new(false);
+        let mut payload = buf[2..n].to_vec();
+        payload.extend_from_slice(&TAIL);
+        let mut out = Vec::with_capacity(256);
+        d.decompress_vec(&payload, &mut out, FlushDecompress::Sync).unwrap();
+        assert_eq!(out, text.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn corrupt_compressed_frame_is_an_error() {
+        let (a, mut raw) = tokio::io::duplex(1 << 16);
+        let mut server = DeflateStream::new(a);
+        server.enable(Agreed { server_no_context_takeover: false, client_no_context_takeover: false }, Default::default());
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let mut frame = Vec::new();
+        encode_frame(&mut frame, FIN | RSV1 | OP_TEXT, true, &[0xff, 0xff, 0xff]);
+        tokio::io::AsyncWriteExt::write_all(&mut raw, &frame).await.unwrap();
+        assert!(server.next().await.unwrap().is_err());
+    }
+
+    /// Frame comprimido de cliente (enmascarado) con `text`
+    fn client_frame(text: &[u8]) -> Vec<u8> {
+        let agreed = Agreed { server_no_context_takeover: false, client_no_context_takeover: false };
+        let payload = Codec::new(agreed, Default::default()).deflate(text).unwrap();
+        let mut frame = Vec::new();
+        encode_frame(&mut frame, FIN | RSV1 | OP_TEXT, true, &payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn frames_sent_along_with_the_upgrade_request_are_inflated() {
+        let (a, mut raw) = tokio::io::duplex(1 << 16);
+        let text = "abcabcabcabcabcabcabcabcabcabcabcabcabcabc";
+        let mut request = concat!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n",
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n",
+            "Sec-WebSocket-Extensions: permessage-deflate\r\n\r\n",
+        ).as_bytes().to_vec();
+        request.extend(client_frame(text.as_bytes()));
+        // Petición y primer frame en una sola escritura, como un cliente que no espera al 101
+        tokio::io::AsyncWriteExt::write_all(&mut raw, &request).await.unwrap();
+        let mut server = tokio_tungstenite::accept_async(DeflateStream::before_handshake(a)).await.unwrap();
+        server.get_mut().enable(Agreed { server_no_context_takeover: false, client_no_context_takeover: false }, Default::default());
+        assert_eq!(server.next().await.unwrap().unwrap(), Message::Text(text.into()));
+    }
+
+    #[tokio::test]
+    async fn inflated_message_is_capped_at_the_max_frame_size() {
+        let (a, mut raw) = tokio::io::duplex(1 << 16);
+        let mut server = DeflateStream::new(a);
+        server.enable(Agreed { server_no_context_takeover: false, client_no_context_takeover: false }, Default::default());
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        // Unos pocos KiB comprimidos que se inflan justo por encima del tope
+        let max = WebSocketConfig::default().max_frame_size.unwrap();
+        let frame = client_frame(&vec![b'a'; max + 1]);
+        assert!(frame.len() < 64 * 1024);
+        tokio::io::AsyncWriteExt::write_all(&mut raw, &frame).await.unwrap();
+        match server.next().await.unwrap() {
+            Err(tokio_tungstenite::tungstenite::Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            other => panic!("{other:?}"),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use super::questdb::DbError;
+use super::server::WsContext;
+
+/// Un vuelo cerrado solo cambia si se borra; aun así el navegador revalida cada minuto
+const CLOSED_CACHE: &str = "private, max-age=60";
+/// El vuelo que se está grabando crece con cada muestra: siempre se pide entero
+const ACTIVE_CACHE: &str = "no-cache";
+
+/// ETag de una respuesta de series/resumen: nº de puntos y último `ts` del vuelo (una
+/// sola consulta agregada) más la query, porque cada combinación de parámetros da otro cuerpo.
+/// Sin ETag para el vuelo en grabación o uno sin puntos
+pub struct Conditional {
+    etag: Option<String>,
+}
+
+impl Conditional {
+    pub async fn new(ctx: &WsContext, flight_id: &str, query: Option<&str>) -> Result<Self, DbError> {
+        if ctx.flight_id.read().await.as_deref() == Some(flight_id) {
+            return Ok(Self { etag: None });
+        }
+        let (points, last_ts) = ctx.questdb.flight_span(flight_id).await?;
+        let etag = last_ts.filter(|_| points > 0).map(|last| {
+            let mut h = DefaultHasher::new();
+            (flight_id, query.unwrap_or_default()).hash(&mut h);
+            format!("W/\"{points}-{}-{:016x}\"", last.timestamp_micros(), h.finish())
+        });
+        Ok(Self { etag })
+    }
+
+    /// Sin ETag: la respuesta no se cachea (vuelo activo o que no existe)
+    pub fn uncached() -> Self {
+        Self { etag: None }
+    }
+
+    /// 304 si `If-None-Match` trae este ETag (comparación débil, como manda RFC 9110)
+    pub fn not_modified(&self, headers: &HeaderMap) -> Option<Response> {
+        let etag = self.etag.as_deref()?;
+        let strip = |t: &str| t.trim().trim_start_matches("W/").to_string();
+        let wanted = headers.get(header::IF_NONE_MATCH)?.to_str().ok()?;
+        let hit = wanted.split(',').any(|t| t.trim() == "*" || strip(t) == strip(etag));
+        hit.then(|| self.with_headers(StatusCode::NOT_MODIFIED.into_response()))
+    }
+
+    /// Añade `ETag` y `Cache-Control` a la respuesta completa
+    pub fn respond(&self, body: impl IntoResponse) -> Response {
+        let res = body.into_response();
+        if !res.status().is_success() {
+            return res;
+        }
+        self.with_headers(res)
+    }
+
+    fn with_headers(&self, mut res: Response) -> Response {
+        let headers = res.headers_mut();
+        match self.etag.as_deref().and_then(|e| HeaderValue::from_str(e).ok()) {
+            Some(etag) => {
+                headers.insert(header::ETAG, etag);
+                headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(CLOSED_CACHE));
+            }
+            None => {
+                headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(ACTIVE_CACHE));
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    async fn sample(ctx: &WsContext, fid: &str, secs: i64) {
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000 + secs, 0);
+        ctx.questdb.insert_flight_log(fid, r#"{"type":"telemetry","payload":{"AngleRoll":1.0}}"#, ts).await.unwrap();
+    }
+
+    fn if_none_match(etag: &str) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert(header::IF_NONE_MATCH, HeaderValue::from_str(etag).unwrap());
+        h
+    }
+
+    #[tokio::test]
+    async fn etag_changes_when_the_flight_does_and_active_flight_is_never_cached() {
+        let ctx = WsContext::for_tests_sqlite();
+        sample(&ctx, "f", 0).await;
+        sample(&ctx, "f", 1).await;
+
+        let res = Conditional::new(&ctx, "f", Some("fields=AngleRoll")).await.unwrap().respond("{}");
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(res.headers()[header::CACHE_CONTROL], CLOSED_CACHE);
+        // Misma query y mismos datos: 304; otra query es otro cuerpo
+        let same = Conditional::new(&ctx, "f", Some("fields=AngleRoll")).await.unwrap();
+        let hit = same.not_modified(&if_none_match(&etag.replace("W/", ""))).expect("sin 304");
+        assert_eq!((hit.status(), hit.headers()[header::ETAG].to_str().unwrap()), (StatusCode::NOT_MODIFIED, etag.as_str()));
+        let other = Conditional::new(&ctx, "f", Some("fields=AnglePitch")).await.unwrap();
+        assert!(other.not_modified(&if_none_match(&etag)).is_none());
+
+        sample(&ctx, "f", 2).await;
+        let grown = Conditional::new(&ctx, "f", Some("fields=AngleRoll")).await.unwrap();
+        assert!(grown.not_modified(&if_none_match(&etag)).is_none());
+        assert_ne!(grown.respond("{}").headers()[header::ETAG], etag);
+
+        // En grabación: ni ETag ni 304, aunque el cliente mande `*`
+        *ctx.flight_id.write().await = Some("f".into());
+        let active = Conditional::new(&ctx, "f", Some("fields=AngleRoll")).await.unwrap();
+        assert!(active.not_modified(&if_none_match("*")).is_none());
+        let res = active.respond("{}");
+        assert!(res.headers().get(header::ETAG).is_none());
+        assert_eq!(res.headers()[header::CACHE_CONTROL], ACTIVE_CACHE);
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::questdb::DbError;
+use super::server::WsContext;
+
+/// Longitud máxima de la etiqueta de una marca
+const MAX_LABEL: usize = 200;
+
+/// Marca dentro de un vuelo (`flight_events`)
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FlightEvent {
+    pub ts: String,
+    pub flight_id: String,
+    pub label: String,
+}
+
+#[derive(Debug)]
+pub enum MarkError {
+    Invalid(String),
+    Db(DbError),
+}
+
+impl std::fmt::Display for MarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(m) => f.write_str(m),
+            Self::Db(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Guarda la marca y la difunde (`{"type":"mark",...}`) para que todos los paneles la vean
+pub async fn mark(ctx: &WsContext, flight_id: &str, label: &str, ts: DateTime<Utc>) -> Result<FlightEvent, MarkError> {
+    let label = label.trim();
+    if label.is_empty() || label.chars().count() > MAX_LABEL {
+        return Err(MarkError::Invalid(format!("Label must be 1-{MAX_LABEL} characters")));
+    }
+    ctx.questdb.insert_flight_event(flight_id, label, ts).await.map_err(MarkError::Db)?;
+
+    let event = FlightEvent { ts: ts.to_rfc3339(), flight_id: flight_id.to_string(), label: label.to_string() };
+    let msg = serde_json::json!({ "type": "mark", "ts": &event.ts, "flight_id": &event.flight_id, "label": &event.label });
+    let _ = ctx.publish(msg.to_string());
+    Ok(event)
+}
+
+/// Marca ahora en el vuelo que se está grabando
+pub async fn mark_active(ctx: &WsContext, label: &str) -> Result<FlightEvent, MarkError> {
+    let Some(fid) = ctx.flight_id.read().await.clone() else {
+        return Err(MarkError::Invalid("No active recording".to_string()));
+    };
+    mark(ctx, &fid, label, Utc::now()).await
+}
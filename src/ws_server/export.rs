@@ -0,0 +1,341 @@
+use std::io;
+
+use axum::body::Body;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use super::questdb::{DbError, FlightPoint, OptionalDb};
+use super::series::payload_obj;
+
+/// Puntos por consulta al exportar; el vuelo nunca se carga entero en memoria
+const EXPORT_PAGE: i64 = 5_000;
+/// Puntos que se miran para descubrir columnas cuando no se piden `fields`
+const DISCOVER_POINTS: usize = 200;
+/// Trozos ya generados que esperan a que el cliente los lea
+const EXPORT_QUEUE: usize = 4;
+
+/// Recorre un vuelo en orden de `ts` por páginas de `fetch_flight_points`.
+/// Cada página empieza en el último `ts` visto y se saltan los puntos de ese
+/// instante que ya se entregaron, así no se duplican ni se pierden filas.
+pub struct Pager {
+    db: OptionalDb,
+    flight_id: String,
+    cursor: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    /// Puntos ya entregados con `ts == cursor`
+    at_cursor: usize,
+    /// Puntos que quedan por entregar si se pidió `limit`
+    remaining: Option<usize>,
+    /// `drop_nulls`: se saltan los puntos sin ninguno de estos campos
+    non_null: Option<Vec<String>>,
+    done: bool,
+}
+
+impl Pager {
+    pub fn new(db: OptionalDb, flight_id: String, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Self {
+        Self { db, flight_id, cursor: from, to, at_cursor: 0, remaining: None, non_null: None, done: false }
+    }
+
+    /// Corta la exportación tras `limit` puntos
+    pub fn with_limit(mut self, limit: Option<i64>) -> Self {
+        self.remaining = limit.map(|l| l.max(0) as usize);
+        self
+    }
+
+    /// Salta los puntos que no tienen ninguno de `fields` (`drop_nulls=true`)
+    pub fn drop_nulls(mut self, fields: &[String]) -> Self {
+        self.non_null = Some(fields.to_vec());
+        self
+    }
+
+    /// Siguiente página no vacía; `None` al terminar
+    pub async fn next_page(&mut self) -> Result<Option<Vec<FlightPoint>>, DbError> {
+        loop {
+            if self.done || self.remaining == Some(0) {
+                return Ok(None);
+            }
+            let page_len = self.remaining.map_or(EXPORT_PAGE, |r| EXPORT_PAGE.min(r as i64));
+            let limit = page_len + self.at_cursor as i64;
+            let mut page = self.db.fetch_flight_points(&self.flight_id, self.cursor, self.to, limit).await?;
+            if (page.len() as i64) < limit {
+                self.done = true;
+            }
+            let mut page = page.split_off(self.at_cursor.min(page.len()));
+            let Some(last_ts) = page.last().map(|p| p.ts) else {
+                self.done = true;
+                return Ok(None);
+            };
+            let same = page.iter().rev().take_while(|p| p.ts == last_ts).count();
+            self.at_cursor = if self.cursor == Some(last_ts) { self.at_cursor + same } else { same };
+            self.cursor = Some(last_ts);
+
+            // El cursor avanza sobre la página leída; el filtro solo decide qué se entrega
+            if let Some(fields) = &self.non_null {
+                page.retain(|p| has_any(p, fields));
+                if page.is_empty() {
+                    continue;
+                }
+            }
+            if let Some(r) = self.remaining.as_mut() {
+                page.truncate(*r);
+                *r -= page.len();
+            }
+            return Ok(Some(page));
+        }
+    }
+}
+
+/// ¿Trae el punto alguno de `fields` con valor (no null)?
+pub fn has_any(p: &FlightPoint, fields: &[String]) -> bool {
+    payload_obj(p).is_some_and(|o| fields.iter().any(|f| o.get(f).is_some_and(|v| !v.is_null())))
+}
+
+/// Campos numéricos de los primeros puntos, en orden de aparición
+pub fn discover_fields(points: &[FlightPoint]) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for obj in points.iter().take(DISCOVER_POINTS).filter_map(payload_obj) {
+        for (k, v) in obj {
+            if v.is_number() && !fields.contains(k) {
+                fields.push(k.clone());
+            }
+        }
+    }
+    fields
+}
+
+/// Celda CSV según RFC 4180: comillas si hace falta, duplicando las internas
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn csv_cell(v: Option<&Value>) -> String {
+    match v {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::Bool(b)) => b.to_string(),
+        Some(Value::String(s)) => csv_escape(s),
+        Some(other) => csv_escape(&other.to_string()),
+    }
+}
+
+pub fn csv_header(fields: &[String]) -> String {
+    let mut line = String::from("ts");
+    for f in fields {
+        line.push(',');
+        line.push_str(&csv_escape(f));
+    }
+    line.push_str("\r\n");
+    line
+}
+
+/// Una fila por punto; un campo ausente deja la celda vacía
+pub fn csv_rows(points: &[FlightPoint], fields: &[String]) -> String {
+    let mut out = String::new();
+    for p in points {
+        let obj = payload_obj(p);
+        out.push_str(&p.ts.to_rfc3339());
+        for f in fields {
+            out.push(',');
+            out.push_str(&csv_cell(obj.and_then(|o| o.get(f))));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Una línea JSONL por fila tal cual se guardó: `{"ts":"...","payload":{...}}`.
+/// Con `fields`, la muestra interna se queda solo con esos campos
+pub fn jsonl_rows(points: &[FlightPoint], fields: Option<&[String]>) -> String {
+    let mut out = String::new();
+    for p in points {
+        let payload = match (fields, payload_obj(p)) {
+            (Some(fields), Some(obj)) => {
+                let kept: serde_json::Map<String, Value> =
+                    fields.iter().filter_map(|f| Some((f.clone(), obj.get(f)?.clone()))).collect();
+                let mut payload = p.payload.clone();
+                payload["payload"] = Value::Object(kept);
+                payload
+            }
+            _ => p.payload.clone(),
+        };
+        out.push_str(&serde_json::json!({ "ts": p.ts.to_rfc3339(), "payload": payload }).to_string());
+        out.push('\n');
+    }
+    out
+}
+
+/// Nombre de fichero seguro para `Content-Disposition`
+pub fn file_name(flight_id: &str, ext: &str) -> String {
+    let stem: String = flight_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("flight_{stem}.{ext}")
+}
+
+/// Cuerpo HTTP que se va llenando con lo que envía el productor; el canal
+/// acotado hace que la lectura de QuestDB vaya al ritmo del cliente
+pub fn channel_body() -> (mpsc::Sender<Result<Vec<u8>, io::Error>>, Body) {
+    let (tx, rx) = mpsc::channel(EXPORT_QUEUE);
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    (tx, Body::from_stream(stream))
+}
+
+/// Vuelca `head` y luego cada página formateada con `rows`; un error a mitad corta la respuesta
+async fn stream_pages(
+    mut pager: Pager,
+    head: String,
+    rows: impl Fn(&[FlightPoint]) -> String,
+    tx: mpsc::Sender<Result<Vec<u8>, io::Error>>,
+) {
+    if tx.send(Ok(head.into_bytes())).await.is_err() {
+        return;
+    }
+    loop {
+        match pager.next_page().await {
+            Ok(Some(page)) => {
+                if tx.send(Ok(rows(&page).into_bytes())).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                error!("❌ export: {e}");
+                let _ = tx.send(Err(io::Error::other(e))).await;
+                return;
+            }
+        }
+    }
+}
+
+pub async fn stream_csv(
+    pager: Pager,
+    first: Vec<FlightPoint>,
+    fields: Vec<String>,
+    tx: mpsc::Sender<Result<Vec<u8>, io::Error>>,
+) {
+    let head = csv_header(&fields) + &csv_rows(&first, &fields);
+    stream_pages(pager, head, move |page| csv_rows(page, &fields), tx).await;
+}
+
+pub async fn stream_jsonl(
+    pager: Pager,
+    first: Vec<FlightPoint>,
+    fields: Option<Vec<String>>,
+    tx: mpsc::Sender<Result<Vec<u8>, io::Error>>,
+) {
+    let head = jsonl_rows(&first, fields.as_deref());
+    stream_pages(pager, head, move |page| jsonl_rows(page, fields.as_deref()), tx).await;
+}
+
+/// Línea de `raw.jsonl` a importar
+#[derive(serde::Deserialize)]
+pub struct RawRow {
+    pub ts: DateTime<Utc>,
+    pub payload: Value,
+}
+
+impl RawRow {
+    /// Payload a guardar: los no JSON vuelven a su texto original (`{"raw":"..."}`)
+    pub fn stored_payload(&self) -> String {
+        match self.payload.as_object() {
+            Some(obj) if obj.len() == 1 && obj.get("raw").is_some_and(Value::is_string) => {
+                obj["raw"].as_str().unwrap_or_default().to_string()
+            }
+            _ => self.payload.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, TimestampMicrosecondArray};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+    use parquet::arrow::ArrowWriter;
+
+    use super::*;
+    use crate::ws_server::series::field_value;
+
+    fn schema(fields: &[String]) -> SchemaRef {
+        let mut cols = vec![Field::new("ts", DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())), false)];
+        cols.extend(fields.iter().map(|f| Field::new(f, DataType::Float64, true)));
+        Arc::new(Schema::new(cols))
+    }
+
+    /// Una página → un row group; los campos no numéricos o ausentes quedan a null
+    fn batch(schema: &SchemaRef, points: &[FlightPoint], fields: &[String]) -> Result<RecordBatch, String> {
+        let ts = TimestampMicrosecondArray::from_iter_values(points.iter().map(|p| p.ts.timestamp_micros()))
+            .with_timezone("UTC");
+        let mut cols: Vec<ArrayRef> = vec![Arc::new(ts)];
+        for f in fields {
+            let col: Float64Array = points
+                .iter()
+                .map(|p| payload_obj(p).and_then(|o| field_value(o, f)))
+                .collect();
+            cols.push(Arc::new(col));
+        }
+        RecordBatch::try_new(schema.clone(), cols).map_err(|e| e.to_string())
+    }
+
+    /// Escribe el vuelo como Parquet, un row group por página, enviando los bytes
+    /// de cada row group en cuanto se cierran
+    pub async fn stream_parquet(
+        mut pager: Pager,
+        first: Vec<FlightPoint>,
+        fields: Vec<String>,
+        tx: mpsc::Sender<Result<Vec<u8>, io::Error>>,
+    ) {
+        let schema = schema(&fields);
+        let mut writer = match ArrowWriter::try_new(Vec::new(), schema.clone(), None) {
+            Ok(w) => w,
+            Err(e) => {
+                let _ = tx.send(Err(io::Error::other(e))).await;
+                return;
+            }
+        };
+        let mut page = Some(first);
+        while let Some(points) = page {
+            let res = batch(&schema, &points, &fields)
+                .and_then(|b| writer.write(&b).map_err(|e| e.to_string()))
+                .and_then(|_| writer.flush().map_err(|e| e.to_string()));
+            if let Err(e) = res {
+                fail(&tx, e).await;
+                return;
+            }
+            if tx.send(Ok(std::mem::take(writer.inner_mut()))).await.is_err() {
+                return;
+            }
+            page = match pager.next_page().await {
+                Ok(p) => p,
+                Err(e) => {
+                    fail(&tx, e.to_string()).await;
+                    return;
+                }
+            };
+        }
+        // Pie con los metadatos del fichero
+        if let Err(e) = writer.finish() {
+            fail(&tx, e.to_string()).await;
+            return;
+        }
+        let _ = tx.send(Ok(std::mem::take(writer.inner_mut()))).await;
+    }
+
+    async fn fail(tx: &mpsc::Sender<Result<Vec<u8>, io::Error>>, e: String) {
+        error!("❌ export parquet: {e}");
+        let _ = tx.send(Err(io::Error::other(e))).await;
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub use parquet_export::stream_parquet;
@@ -0,0 +1,147 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use super::server::WsContext;
+use super::transport::UdpTransport;
+
+struct FailoverState {
+    active: usize,
+    active_since: Instant,
+    /// Fijada por `PUT /api/esp32/address`; tiene prioridad sobre la lista
+    override_addr: Option<SocketAddr>,
+    last_heard: Vec<Option<Instant>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CandidateState {
+    pub address: String,
+    pub last_heard_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Esp32State {
+    pub transport: String,
+    /// Socket local desde el que se envía
+    pub local: String,
+    pub active: String,
+    pub override_address: Option<String>,
+    pub silence_ms: u64,
+    /// Envíos UDP fallidos seguidos (se pone a 0 con el primero que sale bien)
+    pub send_failures: u32,
+    pub candidates: Vec<CandidateState>,
+}
+
+/// Lista priorizada de direcciones del ESP32 (p. ej. IP STA y 192.168.4.1 en modo AP).
+/// Los comandos van a la primera que haya enviado telemetría/acks recientemente;
+/// si la activa calla más de `silence` se prueba la siguiente.
+pub struct RemoteFailover {
+    link: Arc<UdpTransport>,
+    candidates: Vec<SocketAddr>,
+    silence: Duration,
+    state: Mutex<FailoverState>,
+}
+
+impl RemoteFailover {
+    pub fn new(link: Arc<UdpTransport>, candidates: Vec<SocketAddr>, silence: Duration) -> Self {
+        assert!(!candidates.is_empty(), "se necesita al menos una dirección del ESP32");
+        link.set_remote(candidates[0]);
+        Self {
+            link,
+            silence,
+            state: Mutex::new(FailoverState {
+                active: 0,
+                active_since: Instant::now(),
+                override_addr: None,
+                last_heard: vec![None; candidates.len()],
+            }),
+            candidates,
+        }
+    }
+
+    /// Marca como viva la dirección candidata con esa IP (el puerto de origen puede variar)
+    pub fn heard(&self, ip: IpAddr) {
+        let Some(i) = self.candidates.iter().position(|c| c.ip() == ip) else { return };
+        self.state.lock().unwrap().last_heard[i] = Some(Instant::now());
+    }
+
+    /// Puerto a usar cuando el override llega solo con IP
+    pub fn default_port(&self) -> u16 {
+        self.candidates[0].port()
+    }
+
+    /// Fija (o con `None` libera) una dirección explícita
+    pub fn set_override(&self, addr: Option<SocketAddr>) {
+        let mut st = self.state.lock().unwrap();
+        st.override_addr = addr;
+        let target = addr.unwrap_or(self.candidates[st.active]);
+        st.active_since = Instant::now();
+        self.link.set_remote(target);
+    }
+
+    /// Evalúa la lista; devuelve la nueva dirección si hubo conmutación
+    fn evaluate(&self) -> Option<SocketAddr> {
+        let mut st = self.state.lock().unwrap();
+        if st.override_addr.is_some() {
+            return None;
+        }
+        let now = Instant::now();
+        let alive = |t: Option<Instant>| t.is_some_and(|t| now.duration_since(t) < self.silence);
+
+        let next = match st.last_heard.iter().position(|t| alive(*t)) {
+            // La de mayor prioridad con tráfico reciente
+            Some(i) => i,
+            // Nadie responde: tras `silence` en la activa se prueba la siguiente
+            None if now.duration_since(st.active_since) >= self.silence => (st.active + 1) % self.candidates.len(),
+            None => st.active,
+        };
+        if next == st.active {
+            return None;
+        }
+        st.active = next;
+        st.active_since = now;
+        let addr = self.candidates[next];
+        self.link.set_remote(addr);
+        Some(addr)
+    }
+
+    pub fn state(&self) -> Esp32State {
+        let st = self.state.lock().unwrap();
+        let candidates = self.candidates.iter().zip(&st.last_heard)
+            .map(|(addr, heard)| CandidateState {
+                address: addr.to_string(),
+                last_heard_ms: heard.map(|t| t.elapsed().as_millis() as u64),
+            })
+            .collect();
+        Esp32State {
+            transport: "udp".into(),
+            local: self.link.local().to_string(),
+            active: self.link.remote().to_string(),
+            override_address: st.override_addr.map(|a| a.to_string()),
+            silence_ms: self.silence.as_millis() as u64,
+            send_failures: self.link.send_failures(),
+            candidates,
+        }
+    }
+}
+
+/// Revisa periódicamente la lista y avisa por WS cuando cambia la dirección activa
+pub async fn run_failover(failover: Arc<RemoteFailover>, ctx: WsContext) {
+    if failover.candidates.len() < 2 {
+        return;
+    }
+    info!("🔀 Failover de ESP32 entre {:?}", failover.candidates);
+    let mut tick = tokio::time::interval(Duration::from_millis(500));
+    loop {
+        tick.tick().await;
+        if let Some(addr) = failover.evaluate() {
+            warn!("🔀 ESP32: usando ahora {addr}");
+            let msg = serde_json::json!({ "type": "esp32_address", "address": addr.to_string() });
+            let _ = ctx.broadcast(msg.to_string());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use super::questdb::FlightPoint;
+
+/// Puntos que se miran si no se pide `sample`
+pub const DEFAULT_SAMPLE: i64 = 1_000;
+pub const MAX_SAMPLE: i64 = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    Number,
+    String,
+    Bool,
+    Array,
+    /// El campo llega con tipos distintos según la muestra
+    Mixed,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldInfo {
+    /// Ruta con puntos para objetos anidados (`gps.lat`)
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    /// Muestras que traían el campo (sin contar `null`)
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FlightFields {
+    pub flight_id: String,
+    pub sampled: usize,
+    pub fields: Vec<FieldInfo>,
+}
+
+#[derive(Default)]
+struct Discovery {
+    fields: Vec<FieldInfo>,
+    index: HashMap<String, usize>,
+}
+
+impl Discovery {
+    fn walk(&mut self, prefix: &str, obj: &Map<String, Value>) {
+        for (k, v) in obj {
+            let name = if prefix.is_empty() { k.clone() } else { format!("{prefix}.{k}") };
+            let kind = match v {
+                Value::Null => continue,
+                Value::Object(inner) => {
+                    self.walk(&name, inner);
+                    continue;
+                }
+                Value::Number(_) => FieldKind::Number,
+                Value::String(_) => FieldKind::String,
+                Value::Bool(_) => FieldKind::Bool,
+                Value::Array(_) => FieldKind::Array,
+            };
+            self.record(name, kind, v.as_f64());
+        }
+    }
+
+    fn record(&mut self, name: String, kind: FieldKind, num: Option<f64>) {
+        let i = match self.index.get(&name) {
+            Some(&i) => i,
+            None => {
+                self.index.insert(name.clone(), self.fields.len());
+                self.fields.push(FieldInfo { name, kind, count: 0, min: None, max: None });
+                self.fields.len() - 1
+            }
+        };
+        let f = &mut self.fields[i];
+        f.count += 1;
+        if f.kind != kind {
+            f.kind = FieldKind::Mixed;
+        }
+        if let Some(x) = num {
+            f.min = Some(f.min.map_or(x, |m| m.min(x)));
+            f.max = Some(f.max.map_or(x, |m| m.max(x)));
+        }
+    }
+}
+
+/// Claves de `payload.payload` en orden de aparición, con tipo, frecuencia y rango
+pub fn discover(flight_id: &str, points: &[FlightPoint]) -> FlightFields {
+    let mut d = Discovery::default();
+    for obj in points.iter().filter_map(|p| p.payload.get("payload").and_then(Value::as_object)) {
+        d.walk("", obj);
+    }
+    FlightFields { flight_id: flight_id.to_string(), sampled: points.len(), fields: d.fields }
+}
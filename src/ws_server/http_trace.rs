@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::Response;
+use axum::middleware::{self, Next};
+use axum::Router;
+use tower::ServiceBuilder;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{info, info_span, warn, Span};
+
+/// Cabecera con el id de la petición; si el cliente ya manda una, se respeta
+pub const HEADER: &str = "x-request-id";
+/// A partir de aquí la petición se registra como lenta (warn)
+const SLOW: Duration = Duration::from_secs(1);
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id de la petición HTTP en curso, para los logs y el cuerpo de los errores
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// Deja el id disponible para `current()` mientras corre el handler
+async fn scope(req: Request, next: Next) -> axum::response::Response {
+    let id = request_id(&req).to_string();
+    REQUEST_ID.scope(id, next.run(req)).await
+}
+
+fn request_id<B>(req: &axum::http::Request<B>) -> &str {
+    req.headers().get(HEADER).and_then(|v| v.to_str().ok()).unwrap_or("-")
+}
+
+/// Una línea por petición (método, ruta, estado, latencia, id) y la cabecera `x-request-id`
+/// en la respuesta. Los logs de los handlers van dentro del span y llevan el mismo id
+pub fn layer(app: Router) -> Router {
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(|req: &axum::http::Request<Body>| {
+            info_span!("http", request_id = %request_id(req), method = %req.method(), path = %req.uri().path())
+        })
+        .on_request(())
+        .on_response(|res: &Response<_>, latency: Duration, _span: &Span| {
+            let ms = latency.as_secs_f64() * 1000.0;
+            if latency > SLOW {
+                warn!(status = res.status().as_u16(), latency_ms = ms, "🐢 Petición lenta");
+            } else {
+                info!(status = res.status().as_u16(), latency_ms = ms, "↩️  Respuesta");
+            }
+        })
+        // El estado ya sale en `on_response`
+        .on_failure(());
+
+    app.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(trace)
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(middleware::from_fn(scope)),
+    )
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Trabajos terminados que se siguen pudiendo consultar; los más viejos se olvidan
+const KEEP_FINISHED: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Trabajo largo lanzado desde la API (`GET /api/jobs/:id`)
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    /// Qué hace, p. ej. `cleanup`
+    pub kind: &'static str,
+    pub state: JobState,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    /// Pasos hechos de `total`
+    pub done: usize,
+    pub total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Registro en memoria de trabajos en curso y recientes (se pierde al reiniciar)
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl Jobs {
+    /// Da de alta un trabajo `running` y devuelve su id
+    pub fn start(&self, kind: &'static str, total: usize) -> String {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let job = Job {
+            id: id.clone(),
+            kind,
+            state: JobState::Running,
+            started_at: Utc::now().to_rfc3339(),
+            finished_at: None,
+            done: 0,
+            total,
+            result: None,
+            error: None,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        Self::prune(&mut jobs);
+        jobs.insert(id.clone(), job);
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn progress(&self, id: &str, done: usize) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.done = done;
+        }
+    }
+
+    pub fn finish(&self, id: &str, result: Result<serde_json::Value, String>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else { return };
+        job.finished_at = Some(Utc::now().to_rfc3339());
+        match result {
+            Ok(value) => {
+                job.state = JobState::Done;
+                job.done = job.total;
+                job.result = Some(value);
+            }
+            Err(e) => {
+                job.state = JobState::Failed;
+                job.error = Some(e);
+            }
+        }
+    }
+
+    /// Olvida los terminados más antiguos por encima de `KEEP_FINISHED`
+    fn prune(jobs: &mut HashMap<String, Job>) {
+        let mut finished: Vec<(String, String)> = jobs
+            .values()
+            .filter_map(|j| j.finished_at.clone().map(|at| (at, j.id.clone())))
+            .collect();
+        if finished.len() < KEEP_FINISHED {
+            return;
+        }
+        finished.sort();
+        for (_, id) in finished.iter().take(finished.len() + 1 - KEEP_FINISHED) {
+            jobs.remove(id);
+        }
+    }
+}
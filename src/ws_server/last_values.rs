@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Eventos puntuales que no tiene sentido reenviar a un cliente nuevo
+const NOT_CACHED: &[&str] = &["ack", "warning", "error", "udp_rebound"];
+
+#[derive(Deserialize)]
+struct Key<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<&'a str>,
+    #[serde(borrow)]
+    msg: Option<&'a str>,
+}
+
+struct Cached {
+    text: String,
+    at: Instant,
+    received_at: DateTime<Utc>,
+}
+
+/// Último mensaje de un tipo y cuándo llegó
+pub struct Latest {
+    pub value: Value,
+    pub received_at: DateTime<Utc>,
+    pub age: Duration,
+}
+
+/// Último mensaje difundido por tipo (`type`, o `type:msg` para MAVLink);
+/// se reenvía a cada cliente WS al conectar para no mostrar indicadores vacíos
+#[derive(Default)]
+pub struct LastValues(Mutex<HashMap<String, Cached>>);
+
+impl LastValues {
+    pub fn update(&self, text: &str) {
+        let Ok(Key { kind: Some(kind), msg }) = serde_json::from_str::<Key>(text) else { return };
+        if NOT_CACHED.contains(&kind) {
+            return;
+        }
+        let key = match msg {
+            Some(m) => format!("{kind}:{m}"),
+            None => kind.to_string(),
+        };
+        let cached = Cached { text: text.to_string(), at: Instant::now(), received_at: Utc::now() };
+        self.0.lock().unwrap().insert(key, cached);
+    }
+
+    /// Último mensaje de la clave `key` (p. ej. `telemetry`), ya parseado
+    pub fn latest(&self, key: &str) -> Option<Latest> {
+        let map = self.0.lock().unwrap();
+        let c = map.get(key)?;
+        Some(Latest {
+            value: serde_json::from_str(&c.text).ok()?,
+            received_at: c.received_at,
+            age: c.at.elapsed(),
+        })
+    }
+
+    /// Copia de la caché con `"replay": true` en cada mensaje
+    pub fn snapshot(&self) -> Vec<String> {
+        let cached: Vec<String> = self.0.lock().unwrap().values().map(|c| c.text.clone()).collect();
+        cached
+            .into_iter()
+            .filter_map(|text| {
+                let mut v = serde_json::from_str::<Value>(&text).ok()?;
+                v.as_object_mut()?.insert("replay".into(), Value::Bool(true));
+                Some(v.to_string())
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use utoipa::ToSchema;
+
+/// Configuración del logger que envía la UI (`/api/logger/config`, `/api/recordings/start`)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggerConfig {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u8,
+    #[serde(rename = "selectedFields")]
+    pub selected_fields: Vec<String>,
+    pub retention: RetentionConfig,
+    pub triggers: TriggerConfig,
+    pub metadata: Option<MetadataConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum RetentionConfig {
+    // `Ttl` primero: con `untagged` la primera variante que encaje gana
+    Ttl { mode: String, seconds: u64 },
+    Infinite { mode: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TriggerConfig {
+    #[serde(rename = "startWhen")]
+    pub start_when: StartCondition,
+    #[serde(rename = "stopWhen")]
+    pub stop_when: Option<StopCondition>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StartCondition {
+    pub key: String,
+    pub between: [f64; 2],
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StopCondition {
+    pub key: String,
+    #[serde(rename = "outsideForSeconds")]
+    pub outside_for_seconds: u64,
+    pub range: [f64; 2],
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MetadataConfig {
+    pub mass: Option<f64>,
+    #[serde(rename = "armLength")]
+    pub arm_length: Option<f64>,
+}
+
+impl LoggerConfig {
+    /// Parseo tipado + coherencia de los rangos de los triggers
+    pub fn from_value(v: serde_json::Value) -> Result<Self, String> {
+        let cfg: Self = serde_json::from_value(v).map_err(|e| format!("Invalid config format: {e}"))?;
+        let [lo, hi] = cfg.triggers.start_when.between;
+        if lo > hi {
+            return Err(format!("Invalid startWhen.between for {}: {lo} > {hi}", cfg.triggers.start_when.key));
+        }
+        if let Some(stop) = &cfg.triggers.stop_when {
+            let [lo, hi] = stop.range;
+            if lo > hi {
+                return Err(format!("Invalid stopWhen.range for {}: {lo} > {hi}", stop.key));
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+/// Config aplicada tal cual llegó (el JSON original, sin reordenar) y cuándo
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedConfig {
+    pub config: Box<RawValue>,
+    pub applied_at: DateTime<Utc>,
+}
+
+impl AppliedConfig {
+    /// `selectedFields` de la config (ya validada en `parse`)
+    pub fn selected_fields(&self) -> Vec<String> {
+        serde_json::from_str::<LoggerConfig>(self.config.get()).map(|c| c.selected_fields).unwrap_or_default()
+    }
+
+    /// Segundos de `retention` en modo `ttl`; `None` en `infinite`
+    pub fn ttl_s(&self) -> Option<u64> {
+        match serde_json::from_str::<LoggerConfig>(self.config.get()).ok()?.retention {
+            RetentionConfig::Ttl { mode, seconds } if mode == "ttl" => Some(seconds),
+            _ => None,
+        }
+    }
+
+    /// Valida el cuerpo como `LoggerConfig` y lo guarda sin re-serializar
+    pub fn parse(body: &str, applied_at: DateTime<Utc>) -> Result<Self, String> {
+        let value: serde_json::Value = serde_json::from_str(body).map_err(|e| format!("Invalid JSON: {e}"))?;
+        LoggerConfig::from_value(value)?;
+        let config = RawValue::from_string(body.trim().to_string()).map_err(|e| format!("Invalid JSON: {e}"))?;
+        Ok(Self { config, applied_at })
+    }
+}
+
+/// Qué es cada fila de `logger_configs`, según su forma
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryKind {
+    /// Config aplicada (sin clave `event`)
+    Config,
+    Start,
+    Stop,
+    /// Otros eventos: `delete`, `import`, `csv_map`...
+    Event,
+    /// El texto guardado no es JSON
+    Unknown,
+}
+
+/// Fila del historial; `data` es el JSON guardado, o `raw` si no se pudo leer
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub ts: String,
+    pub kind: HistoryKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub data: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn from_row(ts: DateTime<Utc>, stored: String) -> Self {
+        let ts = ts.to_rfc3339();
+        let Ok(data) = serde_json::from_str::<serde_json::Value>(&stored) else {
+            return Self { ts, kind: HistoryKind::Unknown, data: None, raw: Some(stored) };
+        };
+        let kind = match data.get("event") {
+            None => HistoryKind::Config,
+            Some(e) if e == "start" => HistoryKind::Start,
+            Some(e) if e == "stop" => HistoryKind::Stop,
+            Some(_) => HistoryKind::Event,
+        };
+        Self { ts, kind, data: Some(data), raw: None }
+    }
+}
@@ -0,0 +1,290 @@
+//! Decodificador MAVLink mínimo (v1/v2) para flotas ArduPilot.
+//! Traduce ATTITUDE, VFR_HUD, SYS_STATUS y GLOBAL_POSITION_INT al JSON de
+//! telemetría propio para que el resto del pipeline no cambie.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+use tokio::net::UdpSocket;
+use tracing::{error, info};
+
+use super::server::WsContext;
+use super::udp::handle_inbound;
+
+const STX_V1: u8 = 0xFE;
+const STX_V2: u8 = 0xFD;
+
+const MSG_HEARTBEAT: u32 = 0;
+const MSG_SYS_STATUS: u32 = 1;
+const MSG_ATTITUDE: u32 = 30;
+const MSG_GLOBAL_POSITION_INT: u32 = 33;
+const MSG_VFR_HUD: u32 = 74;
+
+/// CRC_EXTRA y longitud de payload (v1) de los mensajes soportados. HEARTBEAT se
+/// valida pero no se traduce: llega a 1 Hz y no lleva telemetría
+fn message_spec(msgid: u32) -> Option<(u8, usize)> {
+    match msgid {
+        MSG_HEARTBEAT => Some((50, 9)),
+        MSG_SYS_STATUS => Some((124, 31)),
+        MSG_ATTITUDE => Some((39, 28)),
+        MSG_GLOBAL_POSITION_INT => Some((104, 28)),
+        MSG_VFR_HUD => Some((20, 20)),
+        _ => None,
+    }
+}
+
+/// CRC-16/MCRF4XX (X.25) usado por MAVLink
+fn crc_accumulate(crc: u16, byte: u8) -> u16 {
+    let mut tmp = byte ^ (crc as u8);
+    tmp ^= tmp << 4;
+    (crc >> 8) ^ ((tmp as u16) << 8) ^ ((tmp as u16) << 3) ^ ((tmp as u16) >> 4)
+}
+
+fn crc_x25(bytes: &[u8], extra: u8) -> u16 {
+    let crc = bytes.iter().fold(0xFFFF, |c, b| crc_accumulate(c, *b));
+    crc_accumulate(crc, extra)
+}
+
+/// Resultado de decodificar un datagrama
+#[derive(Debug, Default)]
+pub struct Decoded {
+    pub telemetry: Vec<Value>,
+    pub unknown: u64,
+    pub bad_crc: u64,
+}
+
+/// Decodifica todas las tramas de un datagrama
+pub fn decode_datagram(mut buf: &[u8]) -> Decoded {
+    let mut out = Decoded::default();
+
+    while let Some(start) = buf.iter().position(|b| *b == STX_V1 || *b == STX_V2) {
+        buf = &buf[start..];
+        let v2 = buf[0] == STX_V2;
+        let header_len = if v2 { 10 } else { 6 };
+        if buf.len() < header_len + 2 {
+            break;
+        }
+        let len = buf[1] as usize;
+        let signed = v2 && (buf[2] & 0x01) != 0;
+        let frame_len = header_len + len + 2 + if signed { 13 } else { 0 };
+        if buf.len() < frame_len {
+            break;
+        }
+
+        let msgid = if v2 {
+            u32::from_le_bytes([buf[7], buf[8], buf[9], 0])
+        } else {
+            buf[5] as u32
+        };
+        let payload = &buf[header_len..header_len + len];
+        let crc_rx = u16::from_le_bytes([buf[header_len + len], buf[header_len + len + 1]]);
+
+        match message_spec(msgid) {
+            None => out.unknown += 1,
+            Some((extra, _)) if crc_x25(&buf[1..header_len + len], extra) != crc_rx => out.bad_crc += 1,
+            Some((_, full_len)) => {
+                // v2 recorta ceros finales: se rellena hasta la longitud completa
+                let mut p = payload.to_vec();
+                if p.len() < full_len {
+                    p.resize(full_len, 0);
+                }
+                if let Some(t) = translate(msgid, &p) {
+                    out.telemetry.push(t);
+                }
+            }
+        }
+        buf = &buf[frame_len..];
+    }
+    out
+}
+
+fn f32_at(p: &[u8], off: usize) -> f64 {
+    f32::from_le_bytes([p[off], p[off + 1], p[off + 2], p[off + 3]]) as f64
+}
+fn i32_at(p: &[u8], off: usize) -> i32 {
+    i32::from_le_bytes([p[off], p[off + 1], p[off + 2], p[off + 3]])
+}
+fn u16_at(p: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([p[off], p[off + 1]])
+}
+fn i16_at(p: &[u8], off: usize) -> i16 {
+    i16::from_le_bytes([p[off], p[off + 1]])
+}
+
+/// Mensaje MAVLink → `{"type":"telemetry","payload":{...}}` con los nombres de campo propios
+fn translate(msgid: u32, p: &[u8]) -> Option<Value> {
+    let mut fields = Map::new();
+    let name = match msgid {
+        MSG_ATTITUDE => {
+            fields.insert("AngleRoll".into(), json!(f32_at(p, 4).to_degrees()));
+            fields.insert("AnglePitch".into(), json!(f32_at(p, 8).to_degrees()));
+            fields.insert("AngleYaw".into(), json!(f32_at(p, 12).to_degrees()));
+            fields.insert("RateRoll".into(), json!(f32_at(p, 16).to_degrees()));
+            fields.insert("RatePitch".into(), json!(f32_at(p, 20).to_degrees()));
+            fields.insert("RateYaw".into(), json!(f32_at(p, 24).to_degrees()));
+            "ATTITUDE"
+        }
+        MSG_VFR_HUD => {
+            let throttle_pct = u16_at(p, 18);
+            // El firmware propio usa µs de PWM (1000–2000)
+            fields.insert("InputThrottle".into(), json!(1000 + 10 * throttle_pct as u32));
+            fields.insert("ThrottlePercent".into(), json!(throttle_pct));
+            fields.insert("Airspeed".into(), json!(f32_at(p, 0)));
+            fields.insert("Groundspeed".into(), json!(f32_at(p, 4)));
+            fields.insert("Altitude".into(), json!(f32_at(p, 8)));
+            fields.insert("Climb".into(), json!(f32_at(p, 12)));
+            fields.insert("Heading".into(), json!(i16_at(p, 16)));
+            "VFR_HUD"
+        }
+        MSG_SYS_STATUS => {
+            fields.insert("Voltage".into(), json!(u16_at(p, 14) as f64 / 1000.0));
+            let current = i16_at(p, 16);
+            if current >= 0 {
+                fields.insert("Current".into(), json!(current as f64 / 100.0));
+            }
+            let remaining = p[30] as i8;
+            if remaining >= 0 {
+                fields.insert("BatteryRemaining".into(), json!(remaining));
+            }
+            "SYS_STATUS"
+        }
+        MSG_GLOBAL_POSITION_INT => {
+            fields.insert("lat".into(), json!(i32_at(p, 4) as f64 / 1e7));
+            fields.insert("lon".into(), json!(i32_at(p, 8) as f64 / 1e7));
+            fields.insert("alt".into(), json!(i32_at(p, 12) as f64 / 1000.0));
+            fields.insert("relative_alt".into(), json!(i32_at(p, 16) as f64 / 1000.0));
+            "GLOBAL_POSITION_INT"
+        }
+        _ => return None,
+    };
+    Some(json!({ "type": "telemetry", "source": "mavlink", "msg": name, "payload": fields }))
+}
+
+/// Escucha MAVLink en `socket` y alimenta el pipeline común de telemetría
+pub async fn run_mavlink_receiver(socket: Arc<UdpSocket>, ctx: WsContext) {
+    let port = socket.local_addr().map(|a| a.port()).unwrap_or(0);
+    info!("🛩️  Recepción MAVLink en UDP {port}");
+    let ingress = json!(port);
+    let label = port.to_string();
+    let mut buf = vec![0u8; 2048];
+
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, src)) => {
+                ctx.capture.record(Some(src), &label, &buf[..len]);
+                let decoded = decode_datagram(&buf[..len]);
+                ctx.udp_stats.mavlink_unknown.fetch_add(decoded.unknown, Ordering::Relaxed);
+                ctx.udp_stats.mavlink_bad_crc.fetch_add(decoded.bad_crc, Ordering::Relaxed);
+                for t in decoded.telemetry {
+                    handle_inbound(&ctx, Some(src), t.to_string().as_bytes(), &ingress, &label).await;
+                }
+            }
+            Err(e) => {
+                error!("❌ UDP recv error en MAVLink {port}: {e}");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tramas generadas con CRC-16/MCRF4XX y los CRC_EXTRA de common.xml (sysid 1, compid 1)
+    const HEARTBEAT_V1: &[u8] = &[0xFE, 0x09, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x51, 0x04, 0x03, 0x7D, 0xDD];
+    const HEARTBEAT_V2: &[u8] = &[0xFD, 0x09, 0x00, 0x00, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x51, 0x04, 0x03, 0xF7, 0x90];
+    /// roll 0.25, pitch -0.5, yaw 1.0 rad; rollspeed 0.125, pitchspeed 0, yawspeed -0.0625 rad/s
+    const ATTITUDE_V1: &[u8] = &[
+        0xFE, 0x1C, 0x02, 0x01, 0x01, 0x1E, 0xE8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3E, 0x00, 0x00, 0x00, 0xBF,
+        0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x00, 0x3E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0xBD, 0x91, 0xC2,
+    ];
+    const ATTITUDE_V2: &[u8] = &[
+        0xFD, 0x1C, 0x00, 0x00, 0x03, 0x01, 0x01, 0x1E, 0x00, 0x00, 0xE8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3E, 0x00, 0x00,
+        0x00, 0xBF, 0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x00, 0x3E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0xBD, 0x9C, 0xC8,
+    ];
+    /// lat 40.7123456, lon -74.0059876, alt 12.345 m, relative_alt 2.5 m; v2 recorta los 10 bytes finales a cero
+    const GLOBAL_POSITION_INT_V2: &[u8] = &[
+        0xFD, 0x12, 0x00, 0x00, 0x04, 0x01, 0x01, 0x21, 0x00, 0x00, 0xD0, 0x07, 0x00, 0x00, 0x00, 0x36, 0x44, 0x18, 0x1C,
+        0x95, 0xE3, 0xD3, 0x39, 0x30, 0x00, 0x00, 0xC4, 0x09, 0x73, 0x97,
+    ];
+    /// STATUSTEXT (253), válido pero no soportado
+    const STATUSTEXT_V2: &[u8] = &[
+        0xFD, 0x0D, 0x00, 0x00, 0x05, 0x01, 0x01, 0xFD, 0x00, 0x00, 0x06, 0x50, 0x72, 0x65, 0x41, 0x72, 0x6D, 0x3A, 0x20, 0x68,
+        0x6F, 0x6C, 0x61, 0xF9, 0x09,
+    ];
+
+    fn close(v: &Value, expected: f64) -> bool {
+        v.as_f64().is_some_and(|x| (x - expected).abs() < 1e-6)
+    }
+
+    #[test]
+    fn crc_matches_the_mcrf4xx_check_value() {
+        let crc = b"123456789".iter().fold(0xFFFF, |c, b| crc_accumulate(c, *b));
+        assert_eq!(crc, 0x6F91);
+    }
+
+    #[test]
+    fn attitude_decodes_the_same_from_v1_and_v2() {
+        for frame in [ATTITUDE_V1, ATTITUDE_V2] {
+            let d = decode_datagram(frame);
+            assert_eq!((d.telemetry.len(), d.unknown, d.bad_crc), (1, 0, 0));
+            let t = &d.telemetry[0];
+            assert_eq!((t["type"].as_str(), t["msg"].as_str()), (Some("telemetry"), Some("ATTITUDE")));
+            let p = &t["payload"];
+            assert!(close(&p["AngleRoll"], 0.25f64.to_degrees()), "{p}");
+            assert!(close(&p["AnglePitch"], -0.5f64.to_degrees()), "{p}");
+            assert!(close(&p["AngleYaw"], 1.0f64.to_degrees()), "{p}");
+            assert!(close(&p["RateRoll"], 0.125f64.to_degrees()), "{p}");
+            assert!(close(&p["RateYaw"], -0.0625f64.to_degrees()), "{p}");
+        }
+    }
+
+    #[test]
+    fn truncated_v2_payload_is_zero_padded() {
+        let d = decode_datagram(GLOBAL_POSITION_INT_V2);
+        assert_eq!((d.telemetry.len(), d.unknown, d.bad_crc), (1, 0, 0));
+        let p = &d.telemetry[0]["payload"];
+        assert!(close(&p["lat"], 40.7123456) && close(&p["lon"], -74.0059876), "{p}");
+        assert!(close(&p["alt"], 12.345) && close(&p["relative_alt"], 2.5), "{p}");
+    }
+
+    #[test]
+    fn heartbeats_are_checked_but_not_forwarded() {
+        for frame in [HEARTBEAT_V1, HEARTBEAT_V2] {
+            let d = decode_datagram(frame);
+            assert_eq!((d.telemetry.len(), d.unknown, d.bad_crc), (0, 0, 0));
+        }
+        let mut bad = HEARTBEAT_V1.to_vec();
+        bad[10] = 0x01;
+        assert_eq!(decode_datagram(&bad).bad_crc, 1);
+    }
+
+    #[test]
+    fn bad_crc_unknown_and_truncated_frames_are_counted_or_skipped() {
+        let mut bad = ATTITUDE_V1.to_vec();
+        *bad.last_mut().unwrap() ^= 0xFF;
+        let d = decode_datagram(&bad);
+        assert_eq!((d.telemetry.len(), d.bad_crc), (0, 1));
+
+        let d = decode_datagram(STATUSTEXT_V2);
+        assert_eq!((d.telemetry.len(), d.unknown, d.bad_crc), (0, 1, 0));
+
+        // Varias tramas en un datagrama, con basura delante y la última cortada
+        let mut datagram = vec![0x00, 0x42];
+        for frame in [HEARTBEAT_V2, ATTITUDE_V1, STATUSTEXT_V2, GLOBAL_POSITION_INT_V2] {
+            datagram.extend_from_slice(frame);
+        }
+        datagram.extend_from_slice(&ATTITUDE_V2[..ATTITUDE_V2.len() - 5]);
+        let d = decode_datagram(&datagram);
+        let msgs: Vec<_> = d.telemetry.iter().map(|t| t["msg"].as_str().unwrap()).collect();
+        assert_eq!(msgs, ["ATTITUDE", "GLOBAL_POSITION_INT"]);
+        assert_eq!((d.unknown, d.bad_crc), (1, 0));
+
+        for cut in [1, 5, ATTITUDE_V1.len() - 1] {
+            let d = decode_datagram(&ATTITUDE_V1[..cut]);
+            assert_eq!((d.telemetry.len(), d.unknown, d.bad_crc), (0, 0, 0), "{cut} bytes");
+        }
+    }
+}
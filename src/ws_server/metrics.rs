@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Un comando sin ack del firmware en este tiempo cuenta como `timed_out`
+const COMMAND_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Contadores del proceso para `GET /api/stats`; los de la BD viven en `OptionalDb`
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    pub commands: CommandMetrics,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self { started: Instant::now(), commands: CommandMetrics::default() }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProcessSnapshot {
+    pub version: &'static str,
+    pub uptime_s: u64,
+}
+
+impl Metrics {
+    pub fn process(&self) -> ProcessSnapshot {
+        ProcessSnapshot { version: env!("CARGO_PKG_VERSION"), uptime_s: self.started.elapsed().as_secs() }
+    }
+}
+
+/// Escrituras en QuestDB (telemetría, configs, logs de comandos, marcas...)
+#[derive(Debug, Default)]
+pub struct DbMetrics {
+    inserts_ok: AtomicU64,
+    inserts_failed: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DbSnapshot {
+    pub connected: bool,
+    pub inserts_ok: u64,
+    pub inserts_failed: u64,
+    pub last_error: Option<String>,
+}
+
+impl DbMetrics {
+    pub fn record<T, E: std::fmt::Display>(&self, res: &Result<T, E>) {
+        self.record_rows(1, res);
+    }
+
+    /// Una escritura de `rows` filas (lotes de telemetría)
+    pub fn record_rows<T, E: std::fmt::Display>(&self, rows: u64, res: &Result<T, E>) {
+        match res {
+            Ok(_) => { self.inserts_ok.fetch_add(rows, Ordering::Relaxed); }
+            Err(e) => {
+                self.inserts_failed.fetch_add(rows, Ordering::Relaxed);
+                *self.last_error.lock().unwrap() = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn snapshot(&self, connected: bool) -> DbSnapshot {
+        DbSnapshot {
+            connected,
+            inserts_ok: self.inserts_ok.load(Ordering::Relaxed),
+            inserts_failed: self.inserts_failed.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Comandos enviados al ESP32 y acks del firmware, emparejados por `request_id`
+#[derive(Debug, Default)]
+pub struct CommandMetrics {
+    sent: AtomicU64,
+    acked: AtomicU64,
+    timed_out: AtomicU64,
+    /// `request_id` enviados aún sin ack
+    pending: Mutex<HashMap<String, Instant>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CommandSnapshot {
+    pub sent: u64,
+    pub acked: u64,
+    pub timed_out: u64,
+    /// Esperando ack ahora mismo (gauge)
+    pub pending: usize,
+}
+
+impl CommandMetrics {
+    pub fn record_sent(&self, request_id: Option<&str>) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        let Some(rid) = request_id else { return };
+        let mut pending = self.pending.lock().unwrap();
+        self.expire(&mut pending);
+        pending.insert(rid.to_owned(), Instant::now());
+    }
+
+    /// Ack del firmware; solo cuenta si corresponde a un comando pendiente
+    pub fn record_ack(&self, request_id: Option<&str>) {
+        let Some(rid) = request_id else { return };
+        let mut pending = self.pending.lock().unwrap();
+        self.expire(&mut pending);
+        if pending.remove(rid).is_some() {
+            self.acked.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn expire(&self, pending: &mut HashMap<String, Instant>) {
+        let before = pending.len();
+        pending.retain(|_, at| at.elapsed() < COMMAND_ACK_TIMEOUT);
+        self.timed_out.fetch_add((before - pending.len()) as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CommandSnapshot {
+        let mut pending = self.pending.lock().unwrap();
+        self.expire(&mut pending);
+        CommandSnapshot {
+            sent: self.sent.load(Ordering::Relaxed),
+            acked: self.acked.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            pending: pending.len(),
+        }
+    }
+}
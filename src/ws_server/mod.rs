@@ -1,54 +1,211 @@
+pub mod acks;
+pub mod aggregate;
+pub mod api_error;
+pub mod bus;
 pub mod questdb;
+pub mod ratelimit;
+pub mod replay;
+pub mod retention;
 pub mod server;
-pub mod http_server;
+pub mod spool;
+pub mod status;
+pub mod store;
+#[cfg(any(test, feature = "sqlite"))]
+pub mod sqlite;
+#[cfg(test)]
+mod backend_tests;
+pub mod summary;
+pub mod tls;
+pub mod auth;
+pub mod capture;
+pub mod clients;
+pub mod clock;
+pub mod commands;
+pub mod compare;
+pub mod compression;
+pub mod deflate;
+pub mod etag;
+pub mod cors;
+pub mod events;
+pub mod export;
+pub mod failover;
+pub mod fields;
+pub mod http_trace;
+pub mod jobs;
+pub mod last_values;
+pub mod logger_config;
+pub mod metrics;
+pub mod openapi;
+pub mod overlay;
+pub mod params;
+pub mod schema;
+pub mod series;
+pub mod sse;
+pub mod static_files;
+#[cfg(any(test, feature = "mavlink"))]
+pub mod mavlink;
+pub mod transport;
+pub mod udp;
+pub mod webhooks;
+pub mod writer;
+pub mod ws_stats;
 
 pub use server::{start_ws_server, WsContext};
 pub use questdb::OptionalDb;
 
-use axum::{routing::{get, post}, extract::{State, Path, Query}, Json, Router};
+use axum::{routing::{get, post}, extract::{State, Path, Query, RawQuery}, Json, Router};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
 use std::time::Duration;
-use tower_http::cors::{CorsLayer, Any};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use tracing::{info, warn, Instrument};
+use utoipa::{IntoParams, ToSchema};
+
+use api_error::{ApiError, ErrorBody};
+use params::ApiQuery;
+use logger_config::LoggerConfig;
+use crate::config::function;
 
 // ====== HTTP payloads ======
-#[derive(Debug, Deserialize)]
-struct LoggerConfig {
-    #[serde(flatten)]
-    rest: serde_json::Value,
+#[derive(Debug, Serialize, ToSchema)]
+struct ApiOk { status: String }
+#[derive(Debug, Serialize, ToSchema)]
+struct StartResp {
+    status: String,
+    #[serde(rename = "flightId")]
+    flight_id: String,
 }
 
-#[derive(Debug, Serialize)]
-struct ApiOk { status: String }
-#[derive(Debug, Serialize)]
-struct StartResp { status: String, flightId: String }
+/// Error de QuestDB para los handlers de respuesta en texto: 504 si no respondió a tiempo, 503 si no
+fn db_failure(e: questdb::DbError) -> (StatusCode, String) {
+    match e {
+        questdb::DbError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, e.to_string()),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    }
+}
 
+#[utoipa::path(
+    post,
+    path = "/api/logger/config",
+    tag = "logger",
+    request_body = LoggerConfig,
+    responses(
+        (status = 200, description = "Config aplicada", body = ApiOk),
+        (status = 400, description = "Config inválida", body = String, content_type = "text/plain"),
+    )
+)]
 async fn apply_config(
     State(ctx): State<WsContext>,
-    Json(cfg): Json<LoggerConfig>,
-) -> Json<ApiOk> {
-    // Guarda para referencia
-    {
-        let mut last = ctx.last_config.write().await;
-        *last = Some(cfg.rest.clone());
-    }
+    body: String,
+) -> Result<Json<ApiOk>, (StatusCode, String)> {
+    let cfg = logger_config::AppliedConfig::parse(&body, chrono::Utc::now())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
-    // Intenta guardar en QuestDB (opcional)
-    match ctx.questdb.insert_logger_config(&cfg.rest.to_string()).await {
+    // Intenta guardar en QuestDB (opcional); se guarda el texto recibido, no una re-serialización
+    match ctx.questdb.insert_logger_config(cfg.config.get()).await {
         Ok(_) => {},
-        Err(e) => eprintln!("⚠️  {e}"),
+        Err(e) => warn!("⚠️  Config no guardada en QuestDB: {e}"),
     }
 
-    Json(ApiOk { status: "ok".into() })
+    // Guarda para referencia
+    ctx.retention.apply(&ctx, cfg.ttl_s());
+    *ctx.last_config.write().await = Some(cfg);
+
+    Ok(Json(ApiOk { status: "ok".into() }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct ConfigResp {
+    #[schema(value_type = Option<Object>)]
+    config: Option<Box<serde_json::value::RawValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    applied_at: Option<String>,
+}
+
+/// Última config aplicada, idéntica a como se envió; `{"config":null}` si no hay
+#[utoipa::path(
+    get,
+    path = "/api/logger/config",
+    tag = "logger",
+    responses(
+        (status = 200, description = "Última config tal cual se envió; `config` null si no hay", body = ConfigResp),
+    )
+)]
+async fn get_config(State(ctx): State<WsContext>) -> Json<ConfigResp> {
+    let last = ctx.last_config.read().await.clone();
+    Json(match last {
+        Some(c) => ConfigResp { config: Some(c.config), applied_at: Some(c.applied_at.to_rfc3339()) },
+        None => ConfigResp { config: None, applied_at: None },
+    })
+}
+
+/// Filas devueltas si no se pide `limit`
+const CONFIG_HISTORY_LIMIT: i64 = 100;
+const CONFIG_HISTORY_MAX: i64 = 10_000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ConfigHistoryQuery {
+    limit: Option<i64>,
+    from: Option<String>,
+    to: Option<String>,
 }
 
+/// Historial de `logger_configs` (configs y eventos start/stop/...), más reciente primero;
+/// sirve para saber qué config estaba activa en cada vuelo comparando fechas
+#[utoipa::path(
+    get,
+    path = "/api/logger/config/history",
+    tag = "logger",
+    params(ConfigHistoryQuery),
+    responses(
+        (status = 200, description = "Configs y eventos guardados", body = Vec<logger_config::HistoryEntry>),
+        (status = 400, description = "Fecha inválida", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn get_config_history(
+    State(ctx): State<WsContext>,
+    ApiQuery(q): ApiQuery<ConfigHistoryQuery>,
+) -> Result<Json<Vec<logger_config::HistoryEntry>>, ApiError> {
+    let (from, to) = params::range(q.from.as_deref(), q.to.as_deref())?;
+    let limit = params::limit(q.limit, CONFIG_HISTORY_LIMIT, CONFIG_HISTORY_MAX)?;
+    let rows = ctx.questdb.list_logger_configs(from, to, limit).await?;
+    Ok(Json(rows.into_iter().map(|(ts, s)| logger_config::HistoryEntry::from_row(ts, s)).collect()))
+}
+
+/// Empieza una grabación; `WsContext.flight_id` es la única fuente del vuelo activo.
+/// Con otra en curso da 409: hay que pararla antes (su `stop` cierra el vuelo)
+#[utoipa::path(
+    post,
+    path = "/api/recordings/start",
+    tag = "recordings",
+    request_body = LoggerConfig,
+    responses(
+        (status = 200, description = "Grabación iniciada", body = StartResp),
+        (status = 400, description = "Config inválida", body = String, content_type = "text/plain"),
+        (status = 409, description = "Ya hay una grabación en curso", body = String, content_type = "text/plain"),
+    )
+)]
 async fn start_recording(
     State(ctx): State<WsContext>,
-    Json(cfg): Json<serde_json::Value>,
-) -> Json<StartResp> {
-    let flight_id = format!("flt_{}", chrono::Utc::now().format("%Y%m%d_%H%M%S"));
+    body: String,
+) -> Result<Json<StartResp>, (StatusCode, String)> {
+    let cfg = logger_config::AppliedConfig::parse(&body, chrono::Utc::now())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    // Sufijo aleatorio: dos grabaciones en el mismo segundo no comparten id
+    let flight_id = format!(
+        "flt_{}_{}",
+        chrono::Utc::now().format("%Y%m%d_%H%M%S"),
+        &uuid::Uuid::new_v4().simple().to_string()[..8],
+    );
     {
         let mut guard = ctx.flight_id.write().await;
+        if let Some(active) = guard.as_ref() {
+            return Err((StatusCode::CONFLICT, format!("Recording {active} already active; stop it first")));
+        }
         *guard = Some(flight_id.clone());
     }
 
@@ -56,210 +213,2508 @@ async fn start_recording(
     let event = serde_json::json!({
         "event": "start",
         "flightId": &flight_id,
-        "config": cfg
+        "config": &cfg.config
     }).to_string();
     
     if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
-        eprintln!("⚠️  {e}");
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
     }
+    // `selectedFields` → columnas DOUBLE en `flight_metrics`; sin BD el vuelo queda solo en crudo
+    match ctx.questdb.add_metric_columns(&flight_id, &cfg.selected_fields()).await {
+        Ok(fields) if !fields.is_empty() => ctx.telemetry_writer.set_typed(Some((flight_id.clone(), fields))),
+        Ok(_) => ctx.telemetry_writer.set_typed(None),
+        Err(e) => {
+            warn!("⚠️  Vuelo {flight_id} sin campos tipados: {e}");
+            ctx.telemetry_writer.set_typed(None);
+        }
+    }
+    ctx.retention.apply(&ctx, cfg.ttl_s());
+    *ctx.last_config.write().await = Some(cfg);
+    status::push_status(&ctx).await;
+    ctx.webhooks.notify(
+        webhooks::WebhookEvent::Start,
+        format!("🛫 Artheris: grabación {flight_id} iniciada"),
+        serde_json::json!({ "flight_id": &flight_id }),
+    );
     
-    Json(StartResp { status: "ok".into(), flightId: flight_id })
+    Ok(Json(StartResp { status: "ok".into(), flight_id }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/recordings/stop",
+    tag = "recordings",
+    responses(
+        (status = 200, description = "Grabación detenida", body = StartResp),
+        (status = 400, description = "No hay grabación activa", body = String, content_type = "text/plain"),
+    )
+)]
 async fn stop_recording(
     State(ctx): State<WsContext>,
-) -> Json<ApiOk> {
+) -> Result<Json<StartResp>, (StatusCode, String)> {
     let fid = {
         let mut guard = ctx.flight_id.write().await;
-        guard.take().unwrap_or_else(|| "none".into())
+        guard.take().ok_or((StatusCode::BAD_REQUEST, "No active recording".to_string()))?
     };
+    // Lo que quede en el buffer es de este vuelo: a la BD antes del resumen
+    ctx.telemetry_writer.set_typed(None);
+    ctx.telemetry_writer.flush(&ctx.questdb).await;
     
     // Intenta guardar el evento de parada (opcional)
     let event = serde_json::json!({
         "event": "stop",
-        "flightId": fid
+        "flightId": &fid
     }).to_string();
     
     if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
-        eprintln!("⚠️  {e}");
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
     }
-    
-    Json(ApiOk { status: "ok".into() })
+    status::push_status(&ctx).await;
+    ctx.webhooks.notify(
+        webhooks::WebhookEvent::Stop,
+        format!("🛬 Artheris: grabación {fid} detenida"),
+        serde_json::json!({ "flight_id": &fid }),
+    );
+
+    // El resumen se calcula una vez al cerrar el vuelo y queda en `flight_summaries`
+    let (db, flight) = (ctx.questdb.clone(), fid.clone());
+    tokio::spawn(async move {
+        if let Err(e) = summary::refresh(&db, &flight, &summary::SummaryParams::default()).await {
+            warn!("⚠️  Resumen de {flight} no calculado: {e}");
+        }
+    }.instrument(tracing::Span::current()));
+
+    Ok(Json(StartResp { status: "ok".into(), flight_id: fid }))
 }
 
-// Lanza el servidor HTTP en :3000
-pub async fn start_http_server(ctx: WsContext) -> anyhow::Result<()> {
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any)
-        .max_age(Duration::from_secs(3600));
+/// Al apagar con una grabación en curso: evento `stop` con `"reason":"shutdown"` para
+/// que el vuelo no quede abierto en `logger_configs`
+pub async fn stop_recording_on_shutdown(ctx: &WsContext) {
+    let Some(fid) = ctx.flight_id.write().await.take() else {
+        return;
+    };
+    ctx.telemetry_writer.flush(&ctx.questdb).await;
+    ctx.webhooks.notify(
+        webhooks::WebhookEvent::Stop,
+        format!("🛬 Artheris: grabación {fid} detenida al apagar"),
+        serde_json::json!({ "flight_id": &fid, "reason": "shutdown" }),
+    );
+    let event = serde_json::json!({ "event": "stop", "flightId": &fid, "reason": "shutdown" }).to_string();
+    match ctx.questdb.insert_logger_config(&event).await {
+        Ok(()) => info!("⏹️  Grabación {fid} cerrada al apagar"),
+        Err(e) => warn!("⚠️  No se pudo cerrar la grabación {fid}: {e}"),
+    }
+}
+
+// ====== Comandos al dron por HTTP ======
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CommandResp { status: String, request_id: String }
+
+/// Comprueba la whitelist y, tras ejecutar, traduce el resultado del envío UDP a la respuesta
+async fn run_command(
+    ctx: &WsContext,
+    kind: &str,
+    exec: impl AsyncFnOnce(&WsContext, Option<&str>) -> bool,
+) -> Result<Json<CommandResp>, (StatusCode, String)> {
+    if !ctx.commands.allows(kind) {
+        return Err((StatusCode::FORBIDDEN, format!("Command '{kind}' not allowed")));
+    }
+    let rid = format!("http-{}", uuid::Uuid::new_v4().simple());
+    if exec(ctx, Some(&rid)).await {
+        Ok(Json(CommandResp { status: "ok".into(), request_id: rid }))
+    } else {
+        Err((StatusCode::BAD_GATEWAY, format!("Command {rid} not sent: ESP32 link missing or UDP send failed")))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ModeReq { mode: String }
+
+#[utoipa::path(
+    post,
+    path = "/api/command/mode",
+    tag = "commands",
+    request_body = ModeReq,
+    responses(
+        (status = 200, description = "Comando enviado", body = CommandResp),
+        (status = 400, description = "Parámetros inválidos", body = String, content_type = "text/plain"),
+        (status = 403, description = "Comando fuera de la whitelist", body = String, content_type = "text/plain"),
+        (status = 502, description = "Sin enlace con el ESP32 o fallo del envío UDP", body = String, content_type = "text/plain"),
+    )
+)]
+async fn command_mode(
+    State(ctx): State<WsContext>,
+    Json(req): Json<ModeReq>,
+) -> Result<Json<CommandResp>, (StatusCode, String)> {
+    if function::mode_str_to_num(&req.mode).is_none() {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown mode '{}': use pilot, idle, manual or 0-2", req.mode)));
+    }
+    run_command(&ctx, "mode", async |ctx, rid| function::set_mode(&req.mode, ctx, rid).await).await
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct MotorsReq { on: bool }
+
+#[utoipa::path(
+    post,
+    path = "/api/command/motors",
+    tag = "commands",
+    request_body = MotorsReq,
+    responses(
+        (status = 200, description = "Comando enviado", body = CommandResp),
+        (status = 403, description = "Comando fuera de la whitelist", body = String, content_type = "text/plain"),
+        (status = 502, description = "Sin enlace con el ESP32 o fallo del envío UDP", body = String, content_type = "text/plain"),
+    )
+)]
+async fn command_motors(
+    State(ctx): State<WsContext>,
+    Json(req): Json<MotorsReq>,
+) -> Result<Json<CommandResp>, (StatusCode, String)> {
+    run_command(&ctx, "motors", async |ctx, rid| function::set_motors_state(req.on, ctx, rid).await).await
+}
+
+/// `{"on":true}` todos, `{"id":1,"on":true}` uno, `{"ids":[1,2],"on":true}` varios
+#[derive(Debug, Deserialize, ToSchema)]
+struct LedsReq { id: Option<u32>, ids: Option<Vec<u32>>, on: bool }
+
+#[utoipa::path(
+    post,
+    path = "/api/command/leds",
+    tag = "commands",
+    request_body = LedsReq,
+    responses(
+        (status = 200, description = "Comando enviado", body = CommandResp),
+        (status = 400, description = "Parámetros inválidos", body = String, content_type = "text/plain"),
+        (status = 403, description = "Comando fuera de la whitelist", body = String, content_type = "text/plain"),
+        (status = 502, description = "Sin enlace con el ESP32 o fallo del envío UDP", body = String, content_type = "text/plain"),
+    )
+)]
+async fn command_leds(
+    State(ctx): State<WsContext>,
+    Json(req): Json<LedsReq>,
+) -> Result<Json<CommandResp>, (StatusCode, String)> {
+    match (req.id, req.ids) {
+        (Some(_), Some(_)) => Err((StatusCode::BAD_REQUEST, "Use either 'id' or 'ids', not both".to_string())),
+        (Some(id), None) => run_command(&ctx, "led", async |ctx, rid| function::set_led_one(id, req.on, ctx, rid).await).await,
+        (None, Some(ids)) if ids.is_empty() => Err((StatusCode::BAD_REQUEST, "Empty 'ids'".to_string())),
+        (None, Some(ids)) => run_command(&ctx, "led", async |ctx, rid| function::set_led_many(&ids, req.on, ctx, rid).await).await,
+        (None, None) => run_command(&ctx, "led", async |ctx, rid| function::set_led_all(req.on, ctx, rid).await).await,
+    }
+}
+
+/// `{"id":1,"us":1500}` un motor, `{"ids":[..],"us":..}` varios, sin id todos
+#[derive(Debug, Deserialize, ToSchema)]
+struct MotorSpeedReq { id: Option<u32>, ids: Option<Vec<u32>>, us: u32 }
+
+#[utoipa::path(
+    post,
+    path = "/api/command/motor-speed",
+    tag = "commands",
+    request_body = MotorSpeedReq,
+    responses(
+        (status = 200, description = "Comando enviado", body = CommandResp),
+        (status = 400, description = "Parámetros inválidos", body = String, content_type = "text/plain"),
+        (status = 403, description = "Comando fuera de la whitelist", body = String, content_type = "text/plain"),
+        (status = 502, description = "Sin enlace con el ESP32 o fallo del envío UDP", body = String, content_type = "text/plain"),
+    )
+)]
+async fn command_motor_speed(
+    State(ctx): State<WsContext>,
+    Json(req): Json<MotorSpeedReq>,
+) -> Result<Json<CommandResp>, (StatusCode, String)> {
+    let us = commands::check_motor_us(req.us).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    match (req.id, req.ids) {
+        (Some(_), Some(_)) => Err((StatusCode::BAD_REQUEST, "Use either 'id' or 'ids', not both".to_string())),
+        (Some(id), None) => run_command(&ctx, "motors", async |ctx, rid| function::set_motor_one_speed(id, us, ctx, rid).await).await,
+        (None, Some(ids)) if ids.is_empty() => Err((StatusCode::BAD_REQUEST, "Empty 'ids'".to_string())),
+        (None, Some(ids)) => run_command(&ctx, "motors", async |ctx, rid| function::set_motors_many_speed(&ids, us, ctx, rid).await).await,
+        (None, None) => run_command(&ctx, "motors", async |ctx, rid| function::set_motors_all_speed(us, ctx, rid).await).await,
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct StreamRateReq { max_hz: Option<u32> }
+
+#[derive(Debug, Serialize, ToSchema)]
+struct StreamRateResp { status: String, max_hz: u32 }
+
+/// Cambia en caliente la decimación de telemetría hacia WS (0/null = sin límite)
+#[utoipa::path(
+    post,
+    path = "/api/stream/rate",
+    tag = "stream",
+    request_body = StreamRateReq,
+    responses(
+        (status = 200, description = "Decimación aplicada", body = StreamRateResp),
+    )
+)]
+async fn set_stream_rate(
+    State(ctx): State<WsContext>,
+    Json(req): Json<StreamRateReq>,
+) -> Json<StreamRateResp> {
+    let hz = req.max_hz.unwrap_or(0);
+    ctx.stream_rate.set_max_hz(hz);
+
+    let _ = ctx.broadcast(serde_json::json!({ "type": "stream_rate", "max_hz": hz }).to_string());
+
+    Json(StreamRateResp { status: "ok".into(), max_hz: hz })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LatestQuery { fields: Option<String> }
+
+#[derive(Serialize, ToSchema)]
+struct LatestTelemetry {
+    available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    payload: Option<serde_json::Value>,
+}
+
+/// Última telemetría difundida (la misma caché que el snapshot WS); `fields=` filtra campos
+#[utoipa::path(
+    get,
+    path = "/api/telemetry/latest",
+    tag = "telemetry",
+    params(LatestQuery),
+    responses(
+        (status = 200, description = "Última telemetría; `available=false` si aún no llegó ninguna", body = LatestTelemetry),
+    )
+)]
+async fn latest_telemetry(State(ctx): State<WsContext>, Query(q): Query<LatestQuery>) -> Json<LatestTelemetry> {
+    let Some(latest) = ctx.last_values.latest("telemetry") else {
+        return Json(LatestTelemetry { available: false, received_at: None, age_ms: None, payload: None });
+    };
+    let mut payload = latest.value.get("payload").cloned().unwrap_or(latest.value);
+    if let (Some(fields), Some(obj)) = (q.fields.as_deref().map(series::split_fields), payload.as_object_mut()) {
+        obj.retain(|k, _| fields.contains(k));
+    }
+    Json(LatestTelemetry {
+        available: true,
+        received_at: Some(latest.received_at.to_rfc3339()),
+        age_ms: Some(latest.age.as_millis() as u64),
+        payload: Some(payload),
+    })
+}
+
+/// Contadores CRC y estadísticas de llegada (intervalo medio, jitter, hueco máx.)
+#[utoipa::path(
+    get,
+    path = "/api/stats/udp",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Contadores UDP", body = udp::UdpStatsSnapshot),
+    )
+)]
+async fn udp_stats(State(ctx): State<WsContext>) -> Json<udp::UdpStatsSnapshot> {
+    Json(ctx.udp_stats.snapshot())
+}
+
+/// Contadores por dirección de origen UDP (fuentes inactivas >10 min se olvidan)
+#[utoipa::path(
+    get,
+    path = "/api/stats/udp/sources",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Contadores por origen", body = Vec<udp::SourceStats>),
+    )
+)]
+async fn udp_sources(State(ctx): State<WsContext>) -> Json<Vec<udp::SourceStats>> {
+    Json(ctx.udp_stats.sources())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CaptureResp { status: String, file: Option<String> }
+
+/// Empieza a volcar cada datagrama recibido a `./captures/*.ndjson`
+#[utoipa::path(
+    post,
+    path = "/api/capture/start",
+    tag = "capture",
+    responses(
+        (status = 200, description = "Captura iniciada", body = CaptureResp),
+        (status = 409, description = "Ya hay una captura activa", body = String, content_type = "text/plain"),
+        (status = 500, description = "No se pudo crear el fichero", body = String, content_type = "text/plain"),
+    )
+)]
+async fn capture_start(State(ctx): State<WsContext>) -> Result<Json<CaptureResp>, (StatusCode, String)> {
+    match ctx.capture.start().await {
+        Ok(Some(file)) => Ok(Json(CaptureResp { status: "ok".into(), file: Some(file) })),
+        Ok(None) => Err((StatusCode::CONFLICT, "Capture already active".to_string())),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Cannot start capture: {e}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/capture/stop",
+    tag = "capture",
+    responses(
+        (status = 200, description = "Captura detenida", body = ApiOk),
+        (status = 400, description = "No hay captura activa", body = String, content_type = "text/plain"),
+    )
+)]
+async fn capture_stop(State(ctx): State<WsContext>) -> Result<Json<ApiOk>, (StatusCode, String)> {
+    if ctx.capture.stop() {
+        Ok(Json(ApiOk { status: "ok".into() }))
+    } else {
+        Err((StatusCode::BAD_REQUEST, "No active capture".to_string()))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/capture/files",
+    tag = "capture",
+    responses(
+        (status = 200, description = "Ficheros de captura", body = Vec<capture::CaptureFile>),
+        (status = 500, description = "No se pudo leer el directorio", body = String, content_type = "text/plain"),
+    )
+)]
+async fn capture_files(State(ctx): State<WsContext>) -> Result<Json<Vec<capture::CaptureFile>>, (StatusCode, String)> {
+    ctx.capture.files().await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/capture/files/{name}",
+    tag = "capture",
+    params(("name" = String, Path, description = "Nombre del fichero")),
+    responses(
+        (status = 200, description = "Fichero NDJSON", body = String, content_type = "application/x-ndjson"),
+        (status = 400, description = "Nombre inválido", body = String, content_type = "text/plain"),
+        (status = 404, description = "No existe", body = String, content_type = "text/plain"),
+    )
+)]
+async fn capture_download(
+    State(ctx): State<WsContext>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let path = ctx.capture.file_path(&name)
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid capture name".to_string()))?;
+    let body = tokio::fs::read(&path).await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("Capture {name} not found")))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{name}\"")),
+        ],
+        body,
+    ))
+}
+
+/// Registra el esquema de campos de telemetría (vacío = sin validación)
+#[utoipa::path(
+    post,
+    path = "/api/telemetry/schema",
+    tag = "telemetry",
+    request_body = schema::TelemetrySchema,
+    responses(
+        (status = 200, description = "Esquema aplicado", body = schema::SchemaState),
+        (status = 400, description = "Rango inválido", body = String, content_type = "text/plain"),
+    )
+)]
+async fn set_telemetry_schema(
+    State(ctx): State<WsContext>,
+    Json(schema): Json<schema::TelemetrySchema>,
+) -> Result<Json<schema::SchemaState>, (StatusCode, String)> {
+    for (name, rule) in &schema.fields {
+        if let (Some(lo), Some(hi)) = (rule.min, rule.max)
+            && lo > hi
+        {
+            return Err((StatusCode::BAD_REQUEST, format!("Invalid range for {name}: min > max")));
+        }
+    }
+    ctx.schema.set(schema);
+    Ok(Json(ctx.schema.state()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/telemetry/schema",
+    tag = "telemetry",
+    responses(
+        (status = 200, description = "Esquema y violaciones", body = schema::SchemaState),
+    )
+)]
+async fn get_telemetry_schema(State(ctx): State<WsContext>) -> Json<schema::SchemaState> {
+    Json(ctx.schema.state())
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct CsvMapReq { fields: Vec<String> }
+
+/// Columnas (en orden) para convertir líneas CSV en telemetría JSON
+#[utoipa::path(
+    post,
+    path = "/api/telemetry/csv-map",
+    tag = "telemetry",
+    request_body = CsvMapReq,
+    responses(
+        (status = 200, description = "Columnas aplicadas", body = CsvMapReq),
+        (status = 400, description = "Nombre de campo vacío", body = String, content_type = "text/plain"),
+    )
+)]
+async fn set_csv_map(
+    State(ctx): State<WsContext>,
+    Json(req): Json<CsvMapReq>,
+) -> Result<Json<CsvMapReq>, (StatusCode, String)> {
+    if req.fields.iter().any(|f| f.trim().is_empty()) {
+        return Err((StatusCode::BAD_REQUEST, "Empty field name in CSV map".to_string()));
+    }
+    ctx.csv_map.set(req.fields.clone());
+
+    // Persistido en logger_configs para restaurarlo al reiniciar
+    let event = serde_json::json!({ "event": "csv_map", "fields": &req.fields }).to_string();
+    if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
+    }
+    Ok(Json(req))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/telemetry/csv-map",
+    tag = "telemetry",
+    responses(
+        (status = 200, description = "Columnas vigentes", body = CsvMapReq),
+    )
+)]
+async fn get_csv_map(State(ctx): State<WsContext>) -> Json<CsvMapReq> {
+    Json(CsvMapReq { fields: ctx.csv_map.fields() })
+}
+
+/// Dirección activa del ESP32, candidatas y override
+#[utoipa::path(
+    get,
+    path = "/api/esp32",
+    tag = "esp32",
+    responses(
+        (status = 200, description = "Estado del enlace", body = failover::Esp32State),
+        (status = 404, description = "El enlace con el ESP32 no es UDP", body = String, content_type = "text/plain"),
+    )
+)]
+async fn get_esp32(State(ctx): State<WsContext>) -> Result<Json<failover::Esp32State>, (StatusCode, String)> {
+    let failover = ctx.esp32_failover.as_ref()
+        .ok_or((StatusCode::NOT_FOUND, "ESP32 link is not UDP".to_string()))?;
+    Ok(Json(failover.state()))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct Esp32AddressReq {
+    #[serde(alias = "addr")]
+    address: Option<String>,
+}
+
+/// Fija la dirección del ESP32 (`ip` o `ip:puerto`, también como `addr`); `null` vuelve a la
+/// lista priorizada. El cambio queda en `logger_configs` para verlo en el historial
+#[utoipa::path(
+    put,
+    path = "/api/esp32/address",
+    tag = "esp32",
+    request_body = Esp32AddressReq,
+    responses(
+        (status = 200, description = "Override aplicado", body = failover::Esp32State),
+        (status = 400, description = "Dirección inválida", body = String, content_type = "text/plain"),
+        (status = 404, description = "El enlace con el ESP32 no es UDP", body = String, content_type = "text/plain"),
+    )
+)]
+async fn set_esp32_address(
+    State(ctx): State<WsContext>,
+    Json(req): Json<Esp32AddressReq>,
+) -> Result<Json<failover::Esp32State>, (StatusCode, String)> {
+    let failover = ctx.esp32_failover.as_ref()
+        .ok_or((StatusCode::NOT_FOUND, "ESP32 link is not UDP".to_string()))?;
+    let addr = match req.address.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(s) => Some(
+            s.parse::<std::net::SocketAddr>()
+                .or_else(|_| s.parse::<std::net::IpAddr>().map(|ip| (ip, failover.default_port()).into()))
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid address: {s}")))?,
+        ),
+    };
+    failover.set_override(addr);
+    let state = failover.state();
+    let _ = ctx.broadcast(serde_json::json!({ "type": "esp32_address", "address": &state.active }).to_string());
+
+    let event = serde_json::json!({
+        "event": "esp32_address",
+        "address": &state.active,
+        "override": &state.override_address,
+    }).to_string();
+    if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
+    }
+    info!("🔀 ESP32: dirección fijada a {}", state.active);
+    Ok(Json(state))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct WebhookReq {
+    /// `http://` o `https://`
+    url: String,
+    /// Eventos que se avisan: `start`, `stop`, `link_lost`, `alert`
+    events: Vec<webhooks::WebhookEvent>,
+}
+
+/// Registra un webhook: cada evento suscrito se envía como POST JSON (`event`, `ts`,
+/// `text` para Slack y los datos del evento), con reintentos y espera exponencial
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    request_body = WebhookReq,
+    responses(
+        (status = 200, description = "Webhook registrado", body = webhooks::Webhook),
+        (status = 400, description = "URL inválida, sin eventos o demasiados webhooks", body = ErrorBody),
+    )
+)]
+async fn add_webhook(State(ctx): State<WsContext>, Json(req): Json<WebhookReq>) -> Result<Json<webhooks::Webhook>, ApiError> {
+    let url = req.url.trim();
+    if !(url.starts_with("http://") || url.starts_with("https://")) || reqwest::Url::parse(url).is_err() {
+        return Err(ApiError::BadRequest(format!("Invalid webhook URL '{url}': expected http(s)://...")));
+    }
+    let mut events: Vec<webhooks::WebhookEvent> = Vec::new();
+    for e in req.events {
+        if !events.contains(&e) {
+            events.push(e);
+        }
+    }
+    if events.is_empty() {
+        return Err(ApiError::BadRequest("At least one event is required".to_string()));
+    }
+    if ctx.webhooks.count() >= webhooks::MAX_WEBHOOKS {
+        return Err(ApiError::BadRequest(format!("At most {} webhooks", webhooks::MAX_WEBHOOKS)));
+    }
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    if let Err(e) = ctx.questdb.store_webhook(&id, url, &webhooks::events_csv(&events), false).await {
+        warn!("⚠️  Webhook no guardado en QuestDB (solo hasta reiniciar): {e}");
+    }
+    info!("🪝 Webhook {id} → {url}");
+    Ok(Json(ctx.webhooks.add(id, url.to_string(), events)))
+}
+
+/// Webhooks registrados con el estado de su última entrega
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Webhooks registrados", body = Vec<webhooks::Webhook>),
+    )
+)]
+async fn list_webhooks(State(ctx): State<WsContext>) -> Json<Vec<webhooks::Webhook>> {
+    Json(ctx.webhooks.list())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "id del webhook")),
+    responses(
+        (status = 200, description = "Webhook dado de baja", body = ApiOk),
+        (status = 404, description = "No existe", body = ErrorBody),
+    )
+)]
+async fn delete_webhook(State(ctx): State<WsContext>, Path(id): Path<String>) -> Result<Json<ApiOk>, ApiError> {
+    let Some(hook) = ctx.webhooks.list().into_iter().find(|h| h.id == id) else {
+        return Err(ApiError::NotFound(format!("Webhook {id} not found")));
+    };
+    ctx.webhooks.remove(&id);
+    if let Err(e) = ctx.questdb.store_webhook(&id, &hook.url, &webhooks::events_csv(&hook.events), true).await {
+        warn!("⚠️  Baja del webhook no guardada en QuestDB: {e}");
+    }
+    Ok(Json(ApiOk { status: "ok".into() }))
+}
+
+/// Tamaño máximo de un envío crudo
+const ESP32_SEND_MAX_BYTES: usize = 4096;
+/// Espera por defecto y máxima del ack/eco (ms)
+const ESP32_REPLY_TIMEOUT_MS: u64 = 2000;
+const ESP32_REPLY_MAX_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct Esp32SendReq {
+    text: String,
+    /// Esperar el ack (mismo `request_id`) o el eco del texto
+    #[serde(default)]
+    expect_ack: bool,
+    /// Espera máxima en ms (por defecto 2000, como mucho 10000)
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Esp32SendResp {
+    status: String,
+    bytes: usize,
+    /// Ack o eco recibido; solo con `expect_ack`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply: Option<String>,
+}
+
+/// Envía un mensaje tal cual al ESP32 (lo que antes se escribía por stdin)
+#[utoipa::path(
+    post,
+    path = "/api/esp32/send",
+    tag = "esp32",
+    request_body = Esp32SendReq,
+    responses(
+        (status = 200, description = "Mensaje enviado (y respondido, con expect_ack)", body = Esp32SendResp),
+        (status = 400, description = "Texto vacío o de más de 4 KB", body = String, content_type = "text/plain"),
+        (status = 403, description = "Comando 'raw' fuera de la whitelist", body = String, content_type = "text/plain"),
+        (status = 502, description = "Sin enlace con el ESP32 o fallo del envío UDP", body = String, content_type = "text/plain"),
+        (status = 504, description = "Sin ack ni eco dentro del plazo", body = String, content_type = "text/plain"),
+    )
+)]
+async fn send_esp32_raw(
+    State(ctx): State<WsContext>,
+    Json(req): Json<Esp32SendReq>,
+) -> Result<Json<Esp32SendResp>, (StatusCode, String)> {
+    let text = req.text.trim();
+    if text.is_empty() || text.len() > ESP32_SEND_MAX_BYTES {
+        return Err((StatusCode::BAD_REQUEST, format!("Text must be 1-{ESP32_SEND_MAX_BYTES} bytes")));
+    }
+    if !ctx.commands.allows("raw") {
+        return Err((StatusCode::FORBIDDEN, "Command 'raw' not allowed".to_string()));
+    }
+    let link = ctx.esp32.as_ref().ok_or((StatusCode::BAD_GATEWAY, "ESP32 link missing".to_string()))?;
+    let rid = serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|v| server::extract_request_id(&v).map(str::to_string));
+
+    // Se registra antes de enviar para no perder una respuesta rápida
+    let reply = req.expect_ack.then(|| ctx.esp32_replies.wait(rid.clone(), text));
+    ctx.log_command(rid.as_deref(), "out", text);
+    link.send(text.as_bytes()).await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("UDP send failed: {e}")))?;
+
+    let reply = match reply {
+        None => None,
+        Some(rx) => {
+            let ms = req.timeout_ms.unwrap_or(ESP32_REPLY_TIMEOUT_MS).min(ESP32_REPLY_MAX_TIMEOUT_MS);
+            match tokio::time::timeout(Duration::from_millis(ms), rx).await {
+                Ok(Ok(reply)) => Some(reply),
+                _ => return Err((StatusCode::GATEWAY_TIMEOUT, format!("No ack or echo within {ms} ms"))),
+            }
+        }
+    };
+    Ok(Json(Esp32SendResp { status: "ok".into(), bytes: text.len(), reply }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReplayQuery { speed: Option<f64>, force: Option<bool> }
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ReplayResp { status: String, flight_id: String, points: usize, speed: f64 }
+
+/// Reproduce un vuelo grabado por WS (mensajes con `"replay":true` y su `ts` original)
+#[utoipa::path(
+    post,
+    path = "/api/flights/{id}/replay",
+    tag = "replay",
+    params(("id" = String, Path, description = "flight_id"), ReplayQuery),
+    responses(
+        (status = 200, description = "Replay iniciado", body = ReplayResp),
+        (status = 400, description = "speed inválido", body = String, content_type = "text/plain"),
+        (status = 404, description = "Vuelo sin puntos", body = String, content_type = "text/plain"),
+        (status = 409, description = "Grabación o replay en curso", body = String, content_type = "text/plain"),
+        (status = 500, description = "Error de la BD", body = String, content_type = "text/plain"),
+    )
+)]
+async fn start_replay(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    Query(q): Query<ReplayQuery>,
+) -> Result<Json<ReplayResp>, (StatusCode, String)> {
+    let speed = q.speed.unwrap_or(1.0);
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err((StatusCode::BAD_REQUEST, "speed must be > 0".to_string()));
+    }
+    if ctx.flight_id.read().await.is_some() && !q.force.unwrap_or(false) {
+        return Err((StatusCode::CONFLICT, "A live recording is active (use force=true)".to_string()));
+    }
+    if let Some(active) = ctx.replay.active() {
+        return Err((StatusCode::CONFLICT, format!("Replay of {active} already running")));
+    }
+    let points = ctx.questdb.fetch_flight_points(&fid, None, None, 500_000).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if points.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("Flight {fid} has no points")));
+    }
+    let n = points.len();
+    if !ctx.replay.start(ctx.clone(), fid.clone(), points, speed) {
+        return Err((StatusCode::CONFLICT, "Replay already running".to_string()));
+    }
+    Ok(Json(ReplayResp { status: "ok".into(), flight_id: fid, points: n, speed }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/replay/stop",
+    tag = "replay",
+    responses(
+        (status = 200, description = "Replay detenido", body = ApiOk),
+        (status = 400, description = "No hay replay activo", body = String, content_type = "text/plain"),
+    )
+)]
+async fn stop_replay(State(ctx): State<WsContext>) -> Result<Json<ApiOk>, (StatusCode, String)> {
+    if ctx.replay.stop() {
+        Ok(Json(ApiOk { status: "ok".into() }))
+    } else {
+        Err((StatusCode::BAD_REQUEST, "No active replay".to_string()))
+    }
+}
+
+/// Lista global de comandos permitidos por WS
+#[utoipa::path(
+    get,
+    path = "/api/security/commands",
+    tag = "security",
+    responses(
+        (status = 200, description = "Whitelist vigente", body = auth::CommandWhitelist),
+    )
+)]
+async fn get_command_whitelist(State(ctx): State<WsContext>) -> Json<auth::CommandWhitelist> {
+    Json(ctx.commands.get())
+}
+
+const COMMAND_KINDS: &[&str] = &["led", "mode", "motors", "raw"];
+
+/// Fija los comandos permitidos (`{"allowed":["led"]}`; `null` = todos)
+#[utoipa::path(
+    post,
+    path = "/api/security/commands",
+    tag = "security",
+    request_body = auth::CommandWhitelist,
+    responses(
+        (status = 200, description = "Whitelist aplicada", body = auth::CommandWhitelist),
+        (status = 400, description = "Tipo de comando desconocido", body = String, content_type = "text/plain"),
+    )
+)]
+async fn set_command_whitelist(
+    State(ctx): State<WsContext>,
+    Json(req): Json<auth::CommandWhitelist>,
+) -> Result<Json<auth::CommandWhitelist>, (StatusCode, String)> {
+    if let Some(bad) = req.allowed.iter().flatten().find(|k| !COMMAND_KINDS.contains(&k.as_str())) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown command kind {bad:?} (expected one of {COMMAND_KINDS:?})")));
+    }
+    ctx.commands.set(req.clone());
+    Ok(Json(req))
+}
+
+/// Límites vigentes de mensajes por cliente WS
+#[utoipa::path(
+    get,
+    path = "/api/ws/rate-limit",
+    tag = "ws",
+    responses(
+        (status = 200, description = "Límites vigentes", body = ratelimit::RateLimits),
+    )
+)]
+async fn get_rate_limits(State(ctx): State<WsContext>) -> Json<ratelimit::RateLimits> {
+    Json(ctx.rate_limits.get())
+}
+
+/// Cambia en caliente los límites (0 = sin límite); aplica también a conexiones abiertas
+#[utoipa::path(
+    post,
+    path = "/api/ws/rate-limit",
+    tag = "ws",
+    request_body = ratelimit::RateLimits,
+    responses(
+        (status = 200, description = "Límites aplicados", body = ratelimit::RateLimits),
+        (status = 400, description = "Límite negativo", body = String, content_type = "text/plain"),
+    )
+)]
+async fn set_rate_limits(
+    State(ctx): State<WsContext>,
+    Json(limits): Json<ratelimit::RateLimits>,
+) -> Result<Json<ratelimit::RateLimits>, (StatusCode, String)> {
+    if !(limits.command_per_sec >= 0.0 && limits.data_per_sec >= 0.0) {
+        return Err((StatusCode::BAD_REQUEST, "Rate limits must be >= 0".to_string()));
+    }
+    ctx.rate_limits.set(limits);
+    Ok(Json(limits))
+}
+
+/// Conexiones WS activas con sus contadores
+#[utoipa::path(
+    get,
+    path = "/api/ws/clients",
+    tag = "ws",
+    responses(
+        (status = 200, description = "Clientes conectados", body = Vec<clients::ClientSnapshot>),
+    )
+)]
+async fn ws_clients(State(ctx): State<WsContext>) -> Json<Vec<clients::ClientSnapshot>> {
+    Json(ctx.clients.list())
+}
+
+/// Fuerza la desconexión de un cliente WS (cierre 1008)
+#[utoipa::path(
+    delete,
+    path = "/api/ws/clients/{id}",
+    tag = "ws",
+    params(("id" = u64, Path, description = "Id del cliente WS")),
+    responses(
+        (status = 200, description = "Cliente desconectado", body = ApiOk),
+        (status = 404, description = "Cliente no encontrado", body = String, content_type = "text/plain"),
+    )
+)]
+async fn ws_client_disconnect(
+    State(ctx): State<WsContext>,
+    Path(id): Path<u64>,
+) -> Result<Json<ApiOk>, (StatusCode, String)> {
+    if ctx.clients.disconnect(id) {
+        Ok(Json(ApiOk { status: "ok".into() }))
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("WS client {id} not found")))
+    }
+}
+
+/// Sin datagramas UDP durante más de esto el enlace se considera caído
+const HEALTH_UDP_STALE: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, ToSchema)]
+struct HealthResp {
+    status: &'static str,
+    questdb: bool,
+    /// Última migración aplicada en QuestDB (`null` sin conexión)
+    schema_version: Option<i32>,
+    /// Circuito de reconexión a QuestDB
+    db_breaker: questdb::BreakerSnapshot,
+    udp_last_packet_s: Option<f64>,
+    ws_clients: usize,
+    ws_addr: Option<String>,
+    flight_id: Option<String>,
+}
+
+/// `ok` con todo funcionando, `degraded` si falla la BD o el UDP, `down` (503) si fallan ambos
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "`ok` o `degraded`", body = HealthResp),
+        (status = 503, description = "`down`: sin BD ni UDP", body = HealthResp),
+    )
+)]
+async fn health(State(ctx): State<WsContext>) -> (StatusCode, Json<HealthResp>) {
+    let questdb = ctx.questdb.probe().await;
+    let udp_age = ctx.udp_stats.last_packet_age();
+    let udp_ok = udp_age.is_some_and(|age| age < HEALTH_UDP_STALE);
+    let (status, code) = match (questdb, udp_ok) {
+        (true, true) => ("ok", StatusCode::OK),
+        (false, false) => ("down", StatusCode::SERVICE_UNAVAILABLE),
+        _ => ("degraded", StatusCode::OK),
+    };
+    (code, Json(HealthResp {
+        status,
+        questdb,
+        schema_version: ctx.questdb.schema_version().await,
+        db_breaker: ctx.questdb.breaker(),
+        udp_last_packet_s: udp_age.map(|a| a.as_secs_f64()),
+        ws_clients: ctx.clients.len(),
+        ws_addr: ctx.ws_addr.map(|a| a.to_string()),
+        flight_id: ctx.flight_id.read().await.clone(),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct StatsResp {
+    udp: udp::UdpStatsSnapshot,
+    capture: capture::CaptureState,
+    clock: clock::ClockState,
+    ws_dropped: u64,
+    ws: WsStatsResp,
+    db: metrics::DbSnapshot,
+    /// Circuito de reconexión a QuestDB
+    db_breaker: questdb::BreakerSnapshot,
+    commands: metrics::CommandSnapshot,
+    /// Buffer de escritura de telemetría
+    writer: writer::WriterSnapshot,
+    /// Telemetría en disco esperando a QuestDB
+    spool: spool::SpoolSnapshot,
+    /// Borrados de la retención por TTL
+    retention: retention::RetentionSnapshot,
+    process: metrics::ProcessSnapshot,
+}
+
+#[derive(Serialize, ToSchema)]
+struct WsStatsResp {
+    clients: usize,
+    #[serde(flatten)]
+    broadcast: ws_stats::BroadcastStatsSnapshot,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/stats/ws",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Contadores del broadcast", body = ws_stats::BroadcastStatsSnapshot),
+    )
+)]
+async fn ws_stats(State(ctx): State<WsContext>) -> Json<ws_stats::BroadcastStatsSnapshot> {
+    Json(ctx.ws_stats.snapshot())
+}
+
+/// Todo en un objeto para un widget de estado: contadores desde el arranque y algún gauge
+/// (clientes WS, BD conectada, comandos pendientes)
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Resumen de estadísticas", body = StatsResp),
+    )
+)]
+async fn stats(State(ctx): State<WsContext>) -> Json<StatsResp> {
+    Json(StatsResp {
+        udp: ctx.udp_stats.snapshot(),
+        capture: ctx.capture.state(),
+        clock: ctx.clock.state(),
+        ws_dropped: ctx.ws_stats.dropped(),
+        ws: WsStatsResp { clients: ctx.clients.len(), broadcast: ctx.ws_stats.snapshot() },
+        db: ctx.questdb.metrics.snapshot(ctx.questdb.is_connected().await),
+        db_breaker: ctx.questdb.breaker(),
+        commands: ctx.metrics.commands.snapshot(),
+        writer: ctx.telemetry_writer.snapshot(),
+        spool: ctx.spool.snapshot(),
+        retention: ctx.retention.snapshot(),
+        process: ctx.metrics.process(),
+    })
+}
 
-        let app = Router::new()
+/// Router HTTP completo (API, `/ws`, docs y panel) con todas sus capas
+pub fn router(ctx: WsContext) -> Router {
+    let cors = ctx.cors.layer();
+    let app = Router::new()
         // existentes:
-        .route("/api/logger/config", post(apply_config))
+        .route("/api/logger/config", get(get_config).post(apply_config))
+        .route("/api/logger/config/history", get(get_config_history))
         .route("/api/recordings/start", post(start_recording))
         .route("/api/recordings/stop", post(stop_recording))
+        .route("/api/stream/rate", post(set_stream_rate))
+        .route("/api/command/mode", post(command_mode))
+        .route("/api/command/motors", post(command_motors))
+        .route("/api/command/leds", post(command_leds))
+        .route("/api/command/motor-speed", post(command_motor_speed))
+        .route("/ws", get(server::ws_upgrade))
+        .route("/api/stream", get(sse::stream))
+        .route("/api/health", get(health))
+        .route("/api/stats", get(stats))
+        .route("/api/stats/udp", get(udp_stats))
+        .route("/api/stats/ws", get(ws_stats))
+        .route("/api/capture/start", post(capture_start))
+        .route("/api/telemetry/latest", get(latest_telemetry))
+        .route("/api/telemetry/schema", get(get_telemetry_schema).post(set_telemetry_schema))
+        .route("/api/telemetry/csv-map", get(get_csv_map).post(set_csv_map))
+        .route("/api/capture/stop", post(capture_stop))
+        .route("/api/capture/files", get(capture_files))
+        .route("/api/capture/files/:name", get(capture_download))
+        .route("/api/stats/udp/sources", get(udp_sources))
+        .route("/api/esp32", get(get_esp32))
+        .route("/api/ws/clients", get(ws_clients))
+        .route("/api/ws/rate-limit", get(get_rate_limits).post(set_rate_limits))
+        .route("/api/security/commands", get(get_command_whitelist).post(set_command_whitelist))
+        .route("/api/ws/clients/:id", axum::routing::delete(ws_client_disconnect))
+        .route("/api/esp32/address", axum::routing::put(set_esp32_address))
+        .route("/api/esp32/send", post(send_esp32_raw))
+        .route("/api/webhooks", get(list_webhooks).post(add_webhook))
+        .route("/api/webhooks/:id", axum::routing::delete(delete_webhook))
+        .route("/api/jobs/:id", get(get_job))
+        .route("/api/spool/replay", post(replay_spool))
         // NUEVOS análisis:
         .route("/api/flights", get(list_flights))
+        .route("/api/flights/cleanup", post(cleanup_flights))
+        .route("/api/flights/:id", get(get_flight).delete(delete_flight))
+        .route("/api/flights/:id/meta", axum::routing::put(set_flight_meta))
+        .route("/api/flights/:id/archive", post(archive_flight))
+        .route("/api/flights/:id/unarchive", post(unarchive_flight))
         .route("/api/flights/:id/series", get(get_flight_series))
+        .route("/api/flights/:id/fields", get(get_flight_fields))
+        .route("/api/flights/:id/events", get(list_flight_events).post(add_flight_event))
+        .route("/api/flights/:id/commands", get(list_flight_commands))
+        .route("/api/flights/:id/export.csv", get(export_flight_csv))
+        .route("/api/flights/:id/raw.jsonl", get(export_flight_raw))
+        .route("/api/flights/import", post(import_flight))
+        .route("/api/flights/compare", get(compare_flights))
+        .route("/api/series/overlay", get(overlay_series))
         .route("/api/flights/:id/summary", get(get_flight_summary))
+        .route("/api/flights/:id/aggregate", get(get_flight_aggregate))
+        .route("/api/flights/:id/replay", post(start_replay))
+        .route("/api/replay/stop", post(stop_replay));
+    #[cfg(feature = "parquet")]
+    let app = app.route("/api/flights/:id/export.parquet", get(export_flight_parquet));
+    let app = app
+        .merge(utoipa_swagger_ui::SwaggerUi::new("/api/docs").url("/api/openapi.json", openapi::spec()))
+        .layer(axum::middleware::from_fn_with_state(ctx.clone(), auth::require_token))
+        .layer(axum::middleware::from_fn_with_state(ctx.clone(), ratelimit::limit_http));
+    // El panel web va fuera de la API key: el navegador no la manda al pedir la página
+    let app = match ctx.static_dir.clone() {
+        Some(dir) => app.fallback(move |req: axum::extract::Request| static_files::serve(dir.clone(), req)),
+        None => app,
+    };
+    let app = app
         .with_state(ctx)
-        .layer(cors);
+        .layer(compression::layer());
+    http_trace::layer(app).layer(cors)
+}
+
+// Lanza el servidor HTTP en :3000
+/// Al cancelarse `ctx.shutdown` deja de aceptar conexiones y espera a las peticiones en curso
+pub async fn start_http_server(ctx: WsContext) -> anyhow::Result<()> {
+    let tls = ctx.tls.clone();
+    let shutdown = ctx.shutdown.clone();
+    let app = router(ctx);
 
     let addr = std::net::SocketAddr::from(([0,0,0,0], 3000));
-    println!("🌐 HTTP listening on http://{addr}");
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let Some(acceptor) = tls else {
+        println!("🌐 HTTP listening on http://{addr}");
+        // Sin Nagle, igual que el listener WS propio (acks de `/ws` sin esperas de 40 ms)
+        axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+            .tcp_nodelay(true)
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await?;
+        return Ok(());
+    };
+
+    // HTTPS: handshake TLS por conexión y luego hyper con el mismo router
+    println!("🌐 HTTPS listening on https://{addr}");
+    let connections = tokio_util::task::TaskTracker::new();
+    loop {
+        let (tcp, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.cancelled() => break,
+        };
+        let _ = tcp.set_nodelay(true);
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let stream = match acceptor.accept(tcp).await {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::debug!("🔒 Handshake TLS fallido desde {peer}: {e}");
+                    return;
+                }
+            };
+            // `GET /ws` necesita la dirección del cliente
+            let app = app.layer(axum::Extension(axum::extract::ConnectInfo(peer)));
+            let service = hyper_util::service::TowerToHyperService::new(app);
+            let builder = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+            let conn = builder.serve_connection_with_upgrades(hyper_util::rt::TokioIo::new(stream), service);
+            tokio::pin!(conn);
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = shutdown.cancelled() => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.as_mut().await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("Conexión HTTPS con {peer} terminada: {e}");
+            }
+        });
+    }
+    connections.close();
+    connections.wait().await;
     Ok(())
 }
 
-#[derive(Deserialize)]
-struct ListFlightsQuery { limit: Option<i64> }
+#[derive(Serialize, ToSchema)]
+struct StartConfig {
+    /// Cuándo se aplicó (o se empezó la grabación con ella)
+    applied_at: String,
+    #[schema(value_type = Object)]
+    config: serde_json::Value,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FlightDetail {
+    flight_id: String,
+    start_ts: String,
+    end_ts: String,
+    duration_sec: f64,
+    points: i64,
+    points_per_sec: Option<f64>,
+    tags: Vec<String>,
+    notes: String,
+    /// Fuera del listado por defecto; series, resumen y exportaciones siguen funcionando
+    archived: bool,
+    /// Se está grabando ahora
+    recording: bool,
+    /// Hay un resumen en caché (`GET /api/flights/:id/summary` no tendrá que calcularlo)
+    has_summary: bool,
+    /// Config del logger vigente al empezar el vuelo
+    config: Option<StartConfig>,
+}
+
+/// Cabecera del vuelo para la vista de detalle, sin leer sus puntos
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    responses(
+        (status = 200, description = "Detalle del vuelo", body = FlightDetail),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn get_flight(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+) -> Result<Json<FlightDetail>, ApiError> {
+    let info = ctx.questdb.flight_info(&fid).await?
+        .ok_or_else(|| ApiError::NotFound(format!("Flight {fid} not found")))?;
+    let duration_sec = (info.last_ts - info.first_ts).num_milliseconds() as f64 / 1000.0;
+    // Un evento `start` guarda la config bajo `config`; una config aplicada, tal cual
+    let config = info.config.and_then(|(ts, raw)| {
+        let mut value: serde_json::Value = serde_json::from_str(&raw).ok()?;
+        if value.get("event").is_some() {
+            value = value.get_mut("config")?.take();
+        }
+        Some(StartConfig { applied_at: ts.to_rfc3339(), config: value })
+    });
+    Ok(Json(FlightDetail {
+        recording: ctx.flight_id.read().await.as_deref() == Some(fid.as_str()),
+        flight_id: fid,
+        start_ts: info.first_ts.to_rfc3339(),
+        end_ts: info.last_ts.to_rfc3339(),
+        duration_sec,
+        points: info.points,
+        points_per_sec: (duration_sec > 0.0).then(|| info.points as f64 / duration_sec),
+        tags: info.meta.tags,
+        notes: info.meta.notes,
+        archived: info.meta.archived,
+        has_summary: info.has_summary,
+        config,
+    }))
+}
+
+/// Borra un vuelo (tombstone en `deleted_flights`) y lo deja auditado en `logger_configs`
+#[utoipa::path(
+    delete,
+    path = "/api/flights/{id}",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    responses(
+        (status = 200, description = "Vuelo borrado", body = ApiOk),
+        (status = 409, description = "El vuelo se está grabando", body = String, content_type = "text/plain"),
+        (status = 503, description = "QuestDB no disponible", body = String, content_type = "text/plain"),
+    )
+)]
+async fn delete_flight(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+) -> Result<Json<ApiOk>, (StatusCode, String)> {
+    if ctx.flight_id.read().await.as_deref() == Some(fid.as_str()) {
+        return Err((StatusCode::CONFLICT, format!("Flight {fid} is currently recording")));
+    }
+    ctx.questdb.mark_flight_deleted(&fid).await
+        .map_err(db_failure)?;
 
-#[derive(Serialize)]
+    let event = serde_json::json!({ "event": "delete", "flightId": &fid }).to_string();
+    if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
+    }
+    Ok(Json(ApiOk { status: "ok".into() }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListFlightsQuery {
+    limit: Option<i64>,
+    /// Cursor: `next` de la página anterior
+    before_ts: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    min_points: Option<i64>,
+    tag: Option<String>,
+    /// Incluir los vuelos archivados
+    include_archived: Option<bool>,
+    /// `legacy=1` → array plano `[{flight_id,last_ts}]` como antes
+    legacy: Option<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
 struct FlightItem { flight_id: String, last_ts: String }
 
-async fn list_flights(State(ctx): State<WsContext>, Query(q): Query<ListFlightsQuery>) -> Json<Vec<FlightItem>> {
-    let limit = q.limit.unwrap_or(50);
-    let mut items = Vec::new();
-    match ctx.questdb.list_flights(limit).await {
-        Ok(rows) => {
-            for (fid, ts) in rows {
-                items.push(FlightItem { flight_id: fid, last_ts: ts.to_rfc3339() });
-            }
+#[derive(Serialize, ToSchema)]
+struct FlightListItem {
+    flight_id: String,
+    first_ts: String,
+    last_ts: String,
+    points: i64,
+    tags: Vec<String>,
+    notes: String,
+    archived: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum FlightList {
+    Legacy(Vec<FlightItem>),
+    Page { items: Vec<FlightListItem>, total: i64, next: Option<String> },
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flights",
+    tag = "flights",
+    params(ListFlightsQuery),
+    responses(
+        (status = 200, description = "Página de vuelos (`legacy=1`: array plano)", body = FlightList),
+        (status = 400, description = "Fecha inválida", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn list_flights(
+    State(ctx): State<WsContext>,
+    ApiQuery(q): ApiQuery<ListFlightsQuery>,
+) -> Result<Json<FlightList>, ApiError> {
+    let (from, to) = params::range(q.from.as_deref(), q.to.as_deref())?;
+    let filter = questdb::FlightFilter {
+        limit: params::limit(q.limit, 50, ctx.max_query_limit)?,
+        before: params::ts("before_ts", q.before_ts.as_deref())?,
+        from,
+        to,
+        min_points: q.min_points,
+        tag: q.tag.filter(|t| !t.is_empty()),
+        include_archived: q.include_archived == Some(true),
+    };
+    let (rows, total) = ctx.questdb.list_flights(&filter).await?;
+
+    if q.legacy == Some(1) {
+        let items = rows.into_iter()
+            .map(|r| FlightItem { flight_id: r.flight_id, last_ts: r.last_ts.to_rfc3339() })
+            .collect();
+        return Ok(Json(FlightList::Legacy(items)));
+    }
+    // Página llena → puede haber más
+    let next = (rows.len() as i64 == filter.limit)
+        .then(|| rows.last().map(|r| r.last_ts.to_rfc3339()))
+        .flatten();
+    let items = rows.into_iter()
+        .map(|r| FlightListItem {
+            flight_id: r.flight_id,
+            first_ts: r.first_ts.to_rfc3339(),
+            last_ts: r.last_ts.to_rfc3339(),
+            points: r.points,
+            tags: r.meta.tags,
+            notes: r.meta.notes,
+            archived: r.meta.archived,
+        })
+        .collect();
+    Ok(Json(FlightList::Page { items, total, next }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ArchiveResp { flight_id: String, archived: bool }
+
+/// Oculta el vuelo de `GET /api/flights` (salvo `include_archived=true`); no borra nada
+#[utoipa::path(
+    post,
+    path = "/api/flights/{id}/archive",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    responses(
+        (status = 200, description = "Vuelo archivado", body = ArchiveResp),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 409, description = "Es el vuelo que se está grabando", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+    )
+)]
+async fn archive_flight(State(ctx): State<WsContext>, Path(fid): Path<String>) -> Result<Json<ArchiveResp>, ApiError> {
+    if ctx.flight_id.read().await.as_deref() == Some(fid.as_str()) {
+        return Err(ApiError::Conflict(format!("Flight {fid} is being recorded")));
+    }
+    set_archived(&ctx, fid, true).await
+}
+
+/// Vuelve a mostrar el vuelo en el listado
+#[utoipa::path(
+    post,
+    path = "/api/flights/{id}/unarchive",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    responses(
+        (status = 200, description = "Vuelo desarchivado", body = ArchiveResp),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+    )
+)]
+async fn unarchive_flight(State(ctx): State<WsContext>, Path(fid): Path<String>) -> Result<Json<ArchiveResp>, ApiError> {
+    set_archived(&ctx, fid, false).await
+}
+
+/// Nueva versión de `flight_meta` con las mismas etiquetas/notas y el archivado cambiado
+async fn set_archived(ctx: &WsContext, fid: String, archived: bool) -> Result<Json<ArchiveResp>, ApiError> {
+    if !ctx.questdb.flight_exists(&fid).await? {
+        return Err(ApiError::NotFound(format!("Flight {fid} not found")));
+    }
+    let mut meta = ctx.questdb.flight_meta(&fid).await?;
+    if meta.archived != archived {
+        meta.archived = archived;
+        ctx.questdb.set_flight_meta(&fid, &meta).await?;
+        info!("🗄️  Vuelo {fid} {}", if archived { "archivado" } else { "desarchivado" });
+    }
+    Ok(Json(ArchiveResp { flight_id: fid, archived }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct CleanupReq {
+    /// Vuelos cuyo último punto es más antiguo que esto
+    older_than_days: u32,
+    /// Solo listar (por defecto `true`)
+    dry_run: Option<bool>,
+    /// Conservar los vuelos con etiquetas (por defecto `true`)
+    keep_tagged: Option<bool>,
+    /// Conservar los vuelos archivados (por defecto `true`)
+    keep_archived: Option<bool>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CleanupFlight { flight_id: String, last_ts: String, points: i64 }
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CleanupResp {
+    cutoff: String,
+    dry_run: bool,
+    flights: Vec<CleanupFlight>,
+    /// Filas de `flight_logs` que se van
+    total_points: i64,
+    /// Trabajo del borrado (`GET /api/jobs/:id`); no hay en `dry_run`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job_id: Option<String>,
+}
+
+/// Borra en bloque los vuelos viejos. Nunca toca el que se está grabando; con `dry_run`
+/// solo devuelve la lista. El borrado va en segundo plano: cada vuelo recibe su
+/// tombstone como en `DELETE /api/flights/:id` y después se sueltan las particiones
+/// de `flight_logs` que ya solo tienen vuelos borrados
+#[utoipa::path(
+    post,
+    path = "/api/flights/cleanup",
+    tag = "flights",
+    request_body = CleanupReq,
+    responses(
+        (status = 200, description = "Vuelos que se borrarían (`dry_run`)", body = CleanupResp),
+        (status = 202, description = "Borrado en marcha; ver `job_id`", body = CleanupResp),
+        (status = 400, description = "`older_than_days` inválido", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+    )
+)]
+async fn cleanup_flights(
+    State(ctx): State<WsContext>,
+    Json(req): Json<CleanupReq>,
+) -> Result<(StatusCode, Json<CleanupResp>), ApiError> {
+    if req.older_than_days == 0 {
+        return Err(ApiError::InvalidParam {
+            param: "older_than_days".into(),
+            message: "older_than_days must be at least 1".into(),
+        });
+    }
+    let dry_run = req.dry_run.unwrap_or(true);
+    let (keep_tagged, keep_archived) = (req.keep_tagged.unwrap_or(true), req.keep_archived.unwrap_or(true));
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(req.older_than_days.into());
+    let active = ctx.flight_id.read().await.clone();
+    let flights: Vec<questdb::FlightRow> = ctx.questdb.flights_before(cutoff).await?
+        .into_iter()
+        .filter(|f| active.as_deref() != Some(f.flight_id.as_str()))
+        .filter(|f| !keep_tagged || f.meta.tags.is_empty())
+        .filter(|f| !keep_archived || !f.meta.archived)
+        .collect();
+
+    let mut resp = CleanupResp {
+        cutoff: cutoff.to_rfc3339(),
+        dry_run,
+        total_points: flights.iter().map(|f| f.points).sum(),
+        flights: flights.iter()
+            .map(|f| CleanupFlight { flight_id: f.flight_id.clone(), last_ts: f.last_ts.to_rfc3339(), points: f.points })
+            .collect(),
+        job_id: None,
+    };
+    if dry_run {
+        return Ok((StatusCode::OK, Json(resp)));
+    }
+
+    let ids: Vec<String> = flights.into_iter().map(|f| f.flight_id).collect();
+    let job_id = ctx.jobs.start("cleanup", ids.len());
+    info!("🧹 Limpieza {job_id}: {} vuelo(s) anteriores a {cutoff}", ids.len());
+    tokio::spawn(run_cleanup(ctx.clone(), job_id.clone(), ids, cutoff));
+    resp.job_id = Some(job_id);
+    Ok((StatusCode::ACCEPTED, Json(resp)))
+}
+
+async fn run_cleanup(ctx: WsContext, job_id: String, ids: Vec<String>, cutoff: chrono::DateTime<chrono::Utc>) {
+    let (mut deleted, mut skipped) = (Vec::new(), Vec::new());
+    for (i, fid) in ids.into_iter().enumerate() {
+        // Puede haberse reanudado la grabación de ese id entre tanto
+        if ctx.flight_id.read().await.as_deref() == Some(fid.as_str()) {
+            skipped.push(fid);
+            continue;
+        }
+        if let Err(e) = ctx.questdb.mark_flight_deleted(&fid).await {
+            warn!("❌ Limpieza {job_id}: {fid} sin borrar: {e}");
+            ctx.jobs.finish(&job_id, Err(format!("Deleting {fid}: {e}")));
+            return;
+        }
+        let event = serde_json::json!({ "event": "delete", "flightId": &fid, "reason": "cleanup" }).to_string();
+        if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
+            warn!("⚠️  Evento no guardado en logger_configs: {e}");
         }
-        Err(e) => eprintln!("❌ list_flights: {e}"),
+        deleted.push(fid);
+        ctx.jobs.progress(&job_id, i + 1);
+    }
+    let partitions = match ctx.questdb.drop_deleted_partitions(cutoff).await {
+        Ok(n) => n,
+        Err(e) => {
+            warn!("⚠️  Limpieza {job_id}: particiones sin soltar: {e}");
+            0
+        }
+    };
+    info!("🧹 Limpieza {job_id} terminada: {} vuelo(s) borrados", deleted.len());
+    let result = serde_json::json!({ "deleted": deleted, "skipped": skipped, "partitions_dropped": partitions });
+    ctx.jobs.finish(&job_id, Ok(result));
+}
+
+/// Reimporta ya la telemetría guardada en el spool mientras QuestDB no respondía (el
+/// importador de fondo lo hace solo cada 30 s). Si se corta, la próxima vez sigue donde iba
+#[utoipa::path(
+    post,
+    path = "/api/spool/replay",
+    tag = "telemetry",
+    responses(
+        (status = 200, description = "Reimportado", body = spool::ReplayReport),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo a mitad; lo hecho no se repite", body = ErrorBody),
+    )
+)]
+async fn replay_spool(State(ctx): State<WsContext>) -> Result<Json<spool::ReplayReport>, ApiError> {
+    Ok(Json(ctx.spool.replay(&ctx.questdb).await?))
+}
+
+/// Estado de un trabajo en segundo plano (p. ej. `POST /api/flights/cleanup`)
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "id del trabajo")),
+    responses(
+        (status = 200, description = "Estado y progreso", body = jobs::Job),
+        (status = 404, description = "No existe o ya se ha olvidado", body = ErrorBody),
+    )
+)]
+async fn get_job(State(ctx): State<WsContext>, Path(id): Path<String>) -> Result<Json<jobs::Job>, ApiError> {
+    ctx.jobs.get(&id).map(Json).ok_or_else(|| ApiError::NotFound(format!("Job {id} not found")))
+}
+
+/// Etiquetas y notas del vuelo; cada PUT guarda una versión nueva completa
+#[utoipa::path(
+    put,
+    path = "/api/flights/{id}/meta",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    request_body = questdb::FlightMeta,
+    responses(
+        (status = 200, description = "Metadatos guardados", body = questdb::FlightMeta),
+        (status = 400, description = "Etiqueta con comas", body = String, content_type = "text/plain"),
+        (status = 404, description = "Vuelo no encontrado", body = String, content_type = "text/plain"),
+        (status = 503, description = "QuestDB no disponible", body = String, content_type = "text/plain"),
+    )
+)]
+async fn set_flight_meta(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    Json(mut meta): Json<questdb::FlightMeta>,
+) -> Result<Json<questdb::FlightMeta>, (StatusCode, String)> {
+    meta.tags = meta.tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+    if let Some(bad) = meta.tags.iter().find(|t| t.contains(',')) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid tag '{bad}': commas are not allowed")));
     }
-    Json(items)
+    let exists = ctx.questdb.flight_exists(&fid).await
+        .map_err(db_failure)?;
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, format!("Flight {fid} not found")));
+    }
+    // El archivado solo cambia por /archive y /unarchive
+    meta.archived = ctx.questdb.flight_meta(&fid).await
+        .map_err(db_failure)?
+        .archived;
+    ctx.questdb.set_flight_meta(&fid, &meta).await
+        .map_err(db_failure)?;
+    Ok(Json(meta))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct SeriesQuery {
     // campos de interés ej: AngleRoll,AnglePitch,InputThrottle
     fields: Option<String>,
     from: Option<String>,
     to: Option<String>,
     limit: Option<i64>,
+    /// Si se indica, la respuesta es `{points, downsampled, ...}` con como mucho ~max_points
+    max_points: Option<usize>,
+    #[param(inline)]
+    method: Option<series::Downsample>,
+    /// `events=true` → respuesta `{points, ..., events}` con las marcas dentro del rango
+    events: Option<bool>,
+    /// `columns` → `{ts:[...], fields:{campo:[...]}}` en vez de un objeto por punto
+    #[param(inline)]
+    format: Option<series::SeriesFormat>,
+    /// `epoch_ms` → `ts` numéricos (solo con `format=columns`)
+    #[param(inline)]
+    ts_format: Option<series::TsFormat>,
 }
 
-#[derive(Serialize)]
-struct SeriesPoint {
-    ts: String,
-    values: HashMap<String, f64>,
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum SeriesResp {
+    Raw(Vec<series::SeriesPoint>),
+    Downsampled(series::Downsampled),
+    WithEvents {
+        #[serde(flatten)]
+        series: series::Downsampled,
+        events: Vec<events::FlightEvent>,
+    },
+    Columns {
+        #[serde(flatten)]
+        columns: series::Columns,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        events: Option<Vec<events::FlightEvent>>,
+    },
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/series",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), SeriesQuery),
+    responses(
+        (status = 200, description = "Serie completa o reducida si se pide `max_points`; `format=columns` en arrays paralelos", body = SeriesResp),
+        (status = 304, description = "Vuelo cerrado sin cambios desde el `ETag` de `If-None-Match`"),
+        (status = 400, description = "Fecha inválida", body = ErrorBody),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
 async fn get_flight_series(
     State(ctx): State<WsContext>,
     Path(fid): Path<String>,
-    Query(q): Query<SeriesQuery>,
-) -> Json<Vec<SeriesPoint>> {
-    // parse fechas
-    let parse_dt = |s: &str| chrono::DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.with_timezone(&chrono::Utc));
-    let from = q.from.as_deref().and_then(parse_dt);
-    let to = q.to.as_deref().and_then(parse_dt);
-
-    let limit = q.limit.unwrap_or(50_000);
-    let fields: Vec<String> = q.fields
-        .as_ref()
-        .map(|csv| csv.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
-        .unwrap_or_else(|| vec!["AngleRoll".into(),"AnglePitch".into(),"InputThrottle".into()]);
-
-    let mut out = Vec::new();
-
-    match ctx.questdb.fetch_flight_points(&fid, from, to, limit).await {
-        Ok(points) => {
-            for p in points {
-                // payload → {"type":"telemetry","payload":{ ...pares clave:valor... }}
-                let mut map = HashMap::new();
-                let inner = p.payload.get("payload").and_then(|v| v.as_object());
-                if let Some(obj) = inner {
-                    for f in &fields {
-                        if let Some(val) = obj.get(f) {
-                            if let Some(x) = val.as_f64() {
-                                map.insert(f.clone(), x);
-                            } else if let Some(xi) = val.as_i64() { map.insert(f.clone(), xi as f64); }
-                            else if let Some(xu) = val.as_u64() { map.insert(f.clone(), xu as f64); }
-                        }
-                    }
-                }
-                out.push(SeriesPoint { ts: p.ts.to_rfc3339(), values: map });
+    headers: axum::http::HeaderMap,
+    RawQuery(raw_query): RawQuery,
+    ApiQuery(q): ApiQuery<SeriesQuery>,
+) -> Result<axum::response::Response, ApiError> {
+    let (from, to) = params::range(q.from.as_deref(), q.to.as_deref())?;
+    let limit = params::limit(q.limit, series::DEFAULT_LIMIT, ctx.max_query_limit)?;
+    let fields = params::fields(q.fields.as_deref())?;
+    params::positive("max_points", q.max_points)?;
+    let format = q.format.unwrap_or_default();
+    if q.ts_format.is_some_and(|t| t != series::TsFormat::Rfc3339) && format != series::SeriesFormat::Columns {
+        return Err(ApiError::InvalidParam {
+            param: "ts_format".to_string(),
+            message: "'ts_format=epoch_ms' requires 'format=columns'".to_string(),
+        });
+    }
+
+    // Las marcas se pueden añadir a un vuelo cerrado sin que cambien sus puntos
+    let cache = match q.events {
+        Some(true) => etag::Conditional::uncached(),
+        _ => etag::Conditional::new(&ctx, &fid, raw_query.as_deref()).await?,
+    };
+    if let Some(not_modified) = cache.not_modified(&headers) {
+        return Ok(not_modified);
+    }
+
+    let points = ctx.questdb.fetch_field_points(&fid, &fields, from, to, limit).await?;
+    if points.is_empty() && from.is_none() && to.is_none() {
+        return Err(ApiError::NotFound(format!("Flight {fid} not found")));
+    }
+    // Misma extracción/reducción para los dos formatos; solo cambia la salida
+    let reduced = match q.max_points {
+        Some(max) => series::reduce(&points, &fields, max, q.method.unwrap_or_default()),
+        None => series::samples(&points, &fields),
+    };
+    let events = match q.events {
+        Some(true) => Some(ctx.questdb.list_flight_events(&fid, from, to).await?),
+        _ => None,
+    };
+    let resp = match (format, events) {
+        (series::SeriesFormat::Columns, events) => {
+            SeriesResp::Columns { columns: reduced.into_columns(&fields, q.ts_format.unwrap_or_default()), events }
+        }
+        (series::SeriesFormat::Points, Some(events)) => SeriesResp::WithEvents { series: reduced.into_points(), events },
+        (series::SeriesFormat::Points, None) if q.max_points.is_some() => SeriesResp::Downsampled(reduced.into_points()),
+        (series::SeriesFormat::Points, None) => SeriesResp::Raw(reduced.into_points().points),
+    };
+    Ok(cache.respond(Json(resp)))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct MarkReq {
+    label: String,
+    /// RFC 3339; sin él, ahora (solo para el vuelo que se está grabando)
+    ts: Option<String>,
+}
+
+/// Añade una marca con etiqueta al vuelo y la difunde por WS/SSE (`{"type":"mark",...}`)
+#[utoipa::path(
+    post,
+    path = "/api/flights/{id}/events",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    request_body = MarkReq,
+    responses(
+        (status = 200, description = "Marca guardada", body = events::FlightEvent),
+        (status = 400, description = "Etiqueta o fecha inválida, o sin `ts` en un vuelo que no se está grabando", body = ErrorBody),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn add_flight_event(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    Json(req): Json<MarkReq>,
+) -> Result<Json<events::FlightEvent>, ApiError> {
+    let active = ctx.flight_id.read().await.as_deref() == Some(fid.as_str());
+    let ts = match params::ts("ts", req.ts.as_deref())? {
+        Some(ts) => ts,
+        None if active => chrono::Utc::now(),
+        None => return Err(ApiError::BadRequest(format!("Flight {fid} is not recording: 'ts' is required"))),
+    };
+    if !active && !ctx.questdb.flight_exists(&fid).await? {
+        return Err(ApiError::NotFound(format!("Flight {fid} not found")));
+    }
+    match events::mark(&ctx, &fid, &req.label, ts).await {
+        Ok(event) => Ok(Json(event)),
+        Err(events::MarkError::Invalid(m)) => Err(ApiError::BadRequest(m)),
+        Err(events::MarkError::Db(e)) => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct EventsQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Marcas del vuelo en orden de tiempo
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/events",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), EventsQuery),
+    responses(
+        (status = 200, description = "Marcas del vuelo", body = Vec<events::FlightEvent>),
+        (status = 400, description = "Fecha inválida", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn list_flight_events(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    ApiQuery(q): ApiQuery<EventsQuery>,
+) -> Result<Json<Vec<events::FlightEvent>>, ApiError> {
+    let (from, to) = params::range(q.from.as_deref(), q.to.as_deref())?;
+    Ok(Json(ctx.questdb.list_flight_events(&fid, from, to).await?))
+}
+
+/// Comandos enviados durante el vuelo con su ack, para superponerlos a la telemetría
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/commands",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id")),
+    responses(
+        (status = 200, description = "Comandos en orden de tiempo", body = Vec<commands::FlightCommand>),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn list_flight_commands(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+) -> Result<Json<Vec<commands::FlightCommand>>, ApiError> {
+    Ok(Json(ctx.questdb.list_flight_commands(&fid).await?))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FieldsQuery {
+    /// Puntos a mirar desde el inicio del vuelo (por defecto 1000, máx. 50000)
+    sample: Option<i64>,
+}
+
+/// Campos que aparecen en el vuelo (anidados con puntos), para construir el selector de la UI
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/fields",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), FieldsQuery),
+    responses(
+        (status = 200, description = "Campos descubiertos con tipo, frecuencia y rango", body = fields::FlightFields),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn get_flight_fields(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    ApiQuery(q): ApiQuery<FieldsQuery>,
+) -> Result<Json<fields::FlightFields>, ApiError> {
+    let sample = q.sample.unwrap_or(fields::DEFAULT_SAMPLE).clamp(1, fields::MAX_SAMPLE);
+    let points = ctx.questdb.fetch_flight_points(&fid, None, None, sample).await?;
+    if points.is_empty() {
+        return Err(ApiError::NotFound(format!("Flight {fid} not found")));
+    }
+    Ok(Json(fields::discover(&fid, &points)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportQuery {
+    /// Sin `fields` se exportan los campos numéricos de los primeros puntos
+    fields: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    /// Salta las filas sin ninguno de los campos exportados
+    drop_nulls: Option<bool>,
+}
+
+/// Primera página y columnas de una exportación; 404 si el vuelo no tiene datos
+async fn open_export(
+    ctx: &WsContext,
+    fid: &str,
+    q: &ExportQuery,
+) -> Result<(export::Pager, Vec<questdb::FlightPoint>, Vec<String>), ApiError> {
+    let (from, to) = params::range(q.from.as_deref(), q.to.as_deref())?;
+    let fields = q.fields.as_deref().map(|f| params::fields(Some(f))).transpose()?;
+
+    let mut pager = export::Pager::new(ctx.questdb.clone(), fid.to_string(), from, to);
+    let mut first = pager.next_page().await?.unwrap_or_default();
+    if first.is_empty() && from.is_none() && to.is_none() {
+        return Err(ApiError::NotFound(format!("Flight {fid} not found")));
+    }
+    let fields = fields.unwrap_or_else(|| export::discover_fields(&first));
+    if q.drop_nulls == Some(true) {
+        if fields.is_empty() {
+            return Err(ApiError::InvalidParam {
+                param: "fields".into(),
+                message: "'drop_nulls' needs at least one field to export".into(),
+            });
+        }
+        // La primera página se leyó sin filtro para descubrir columnas
+        first.retain(|p| export::has_any(p, &fields));
+        pager = pager.drop_nulls(&fields);
+    }
+    Ok((pager, first, fields))
+}
+
+/// Vuelo completo en CSV (`ts` + una columna por campo), leído por páginas
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/export.csv",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), ExportQuery),
+    responses(
+        (status = 200, description = "CSV por streaming", body = String, content_type = "text/csv"),
+        (status = 400, description = "Parámetro inválido", body = ErrorBody),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+    )
+)]
+async fn export_flight_csv(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    ApiQuery(q): ApiQuery<ExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (pager, first, fields) = open_export(&ctx, &fid, &q).await?;
+    let (tx, body) = export::channel_body();
+    tokio::spawn(export::stream_csv(pager, first, fields, tx));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", export::file_name(&fid, "csv"))),
+        ],
+        body,
+    ))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RawExportQuery {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i64>,
+    /// Deja en cada muestra solo estos campos
+    fields: Option<String>,
+    /// Con `fields`: salta las filas sin ninguno de ellos
+    drop_nulls: Option<bool>,
+}
+
+/// Filas de `flight_logs` tal cual, una por línea, en orden de `ts`
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/raw.jsonl",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), RawExportQuery),
+    responses(
+        (status = 200, description = "Una línea `{ts,payload}` por fila", body = String, content_type = "application/x-ndjson"),
+        (status = 400, description = "Parámetro inválido", body = ErrorBody),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+    )
+)]
+async fn export_flight_raw(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    ApiQuery(q): ApiQuery<RawExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (from, to) = params::range(q.from.as_deref(), q.to.as_deref())?;
+    // Sin `limit` se exporta el vuelo entero (va por páginas)
+    let limit = match q.limit {
+        Some(_) => Some(params::limit(q.limit, 0, ctx.max_query_limit)?),
+        None => None,
+    };
+
+    let fields = q.fields.as_deref().map(|f| params::fields(Some(f))).transpose()?;
+    let drop_nulls = q.drop_nulls == Some(true);
+    if drop_nulls && fields.is_none() {
+        return Err(ApiError::InvalidParam { param: "fields".into(), message: "'drop_nulls' requires 'fields'".into() });
+    }
+
+    let mut pager = export::Pager::new(ctx.questdb.clone(), fid.clone(), from, to).with_limit(limit);
+    if let (true, Some(fields)) = (drop_nulls, &fields) {
+        pager = pager.drop_nulls(fields);
+    }
+    let first = pager.next_page().await?.unwrap_or_default();
+    if first.is_empty() && from.is_none() && to.is_none() && !drop_nulls {
+        return Err(ApiError::NotFound(format!("Flight {fid} not found")));
+    }
+
+    let (tx, body) = export::channel_body();
+    tokio::spawn(export::stream_jsonl(pager, first, fields, tx));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", export::file_name(&fid, "jsonl"))),
+        ],
+        body,
+    ))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ImportResp { status: String, flight_id: String, imported: usize, skipped: usize }
+
+/// Importa un `raw.jsonl` bajo un flight_id nuevo; las líneas inválidas se saltan y se cuentan
+#[utoipa::path(
+    post,
+    path = "/api/flights/import",
+    tag = "flights",
+    request_body(content = String, content_type = "application/x-ndjson", description = "Salida de `raw.jsonl`"),
+    responses(
+        (status = 200, description = "Vuelo importado", body = ImportResp),
+        (status = 400, description = "Cuerpo ilegible o sin filas válidas", body = String, content_type = "text/plain"),
+        (status = 503, description = "QuestDB no disponible", body = String, content_type = "text/plain"),
+    )
+)]
+async fn import_flight(
+    State(ctx): State<WsContext>,
+    body: axum::body::Body,
+) -> Result<Json<ImportResp>, (StatusCode, String)> {
+    use futures_util::StreamExt;
+
+    let flight_id = format!(
+        "flt_imp_{}_{}",
+        chrono::Utc::now().format("%Y%m%d_%H%M%S"),
+        &uuid::Uuid::new_v4().simple().to_string()[..8],
+    );
+    let (mut imported, mut skipped) = (0usize, 0usize);
+    let mut buf: Vec<u8> = Vec::new();
+    let mut stream = body.into_data_stream();
+
+    loop {
+        let chunk = stream.next().await;
+        let eof = chunk.is_none();
+        if let Some(chunk) = chunk {
+            let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("Cannot read body: {e}")))?;
+            buf.extend_from_slice(&chunk);
+        }
+        // Líneas completas; al final también la última sin '\n'
+        let end = if eof { buf.len() } else { buf.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1) };
+        for line in buf[..end].split(|&b| b == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
             }
+            let Ok(row) = serde_json::from_slice::<export::RawRow>(line) else {
+                skipped += 1;
+                continue;
+            };
+            ctx.questdb.insert_flight_log_at(&flight_id, &row.stored_payload(), row.ts).await
+                .map_err(db_failure)?;
+            imported += 1;
         }
-        Err(e) => eprintln!("❌ get_flight_series: {e}"),
+        buf.drain(..end);
+        if eof {
+            break;
+        }
+    }
+    if imported == 0 {
+        return Err((StatusCode::BAD_REQUEST, format!("No valid rows to import ({skipped} skipped)")));
     }
-    Json(out)
+
+    let event = serde_json::json!({ "event": "import", "flightId": &flight_id, "rows": imported }).to_string();
+    if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
+    }
+    Ok(Json(ImportResp { status: "ok".into(), flight_id, imported, skipped }))
 }
 
-#[derive(Serialize)]
-struct FlightSummary {
-    flight_id: String,
-    start_ts: String,
-    end_ts: String,
-    duration_sec: f64,
-    // ejemplo de métricas
-    max_roll: Option<f64>,
-    max_pitch: Option<f64>,
-    throttle_time_in_range_sec: f64,
-    throttle_time_out_range_sec: f64,
+/// Igual que el CSV pero en Parquet (`ts` + columnas Float64), un row group por página
+#[cfg(feature = "parquet")]
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/export.parquet",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), ExportQuery),
+    responses(
+        (status = 200, description = "Parquet por streaming (feature `parquet`)", body = Vec<u8>, content_type = "application/vnd.apache.parquet"),
+        (status = 400, description = "Parámetro inválido", body = ErrorBody),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+    )
+)]
+async fn export_flight_parquet(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    ApiQuery(q): ApiQuery<ExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (pager, first, fields) = open_export(&ctx, &fid, &q).await?;
+    let (tx, body) = export::channel_body();
+    tokio::spawn(export::stream_parquet(pager, first, fields, tx));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.apache.parquet".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", export::file_name(&fid, "parquet"))),
+        ],
+        body,
+    ))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AggregateQuery {
+    fields: Option<String>,
+    /// `500ms`, `5s`, `1m`, `1h`, `1d`
+    bucket: String,
+    /// `min,max,avg,count`
+    agg: Option<String>,
+    /// `none` (intervalos vacíos omitidos, por defecto) o `null`
+    fill: Option<String>,
+}
+
+/// Mín/máx/media/nº por intervalo; en QuestDB con `SAMPLE BY` y, si falla, en Rust
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/aggregate",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), AggregateQuery),
+    responses(
+        (status = 200, description = "Una fila por intervalo", body = Vec<aggregate::AggRow>),
+        (status = 400, description = "bucket, agg, fill o campo inválido", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn get_flight_aggregate(
+    State(ctx): State<WsContext>,
+    Path(fid): Path<String>,
+    ApiQuery(q): ApiQuery<AggregateQuery>,
+) -> Result<Json<Vec<aggregate::AggRow>>, ApiError> {
+    let bad = |param: &str, message: String| ApiError::InvalidParam { param: param.to_string(), message };
+
+    let bucket = aggregate::Bucket::parse(&q.bucket)
+        .ok_or_else(|| bad("bucket", format!("Invalid bucket '{}': expected e.g. 500ms, 5s, 1m, 1h, 1d", q.bucket)))?;
+    let aggs = series::split_fields(q.agg.as_deref().unwrap_or("min,max,avg"))
+        .iter()
+        .map(|a| aggregate::Agg::parse(a).ok_or_else(|| bad("agg", format!("Unknown aggregate '{a}': use min, max, avg or count"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if aggs.is_empty() {
+        return Err(bad("agg", "No aggregates requested".to_string()));
+    }
+    let fields = params::fields(q.fields.as_deref())?;
+    if let Some(f) = fields.iter().find(|f| !aggregate::AggRequest::valid_field(f)) {
+        return Err(bad("fields", format!("Invalid field name '{f}'")));
+    }
+    let fill_null = match q.fill.as_deref() {
+        None | Some("none") => false,
+        Some("null") => true,
+        Some(other) => return Err(bad("fill", format!("Invalid fill '{other}': use none or null"))),
+    };
+    let req = aggregate::AggRequest { fields, aggs, bucket, fill_null };
+
+    match ctx.questdb.aggregate_flight(&fid, &req).await {
+        Ok(rows) => Ok(Json(rows)),
+        Err(e) => {
+            warn!("⚠️  SAMPLE BY no disponible ({e}); agregando en Rust");
+            let points = ctx.questdb.fetch_flight_points(&fid, None, None, 1_000_000).await?;
+            Ok(Json(aggregate::compute(&points, &req)))
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CompareQuery {
+    a: String,
+    b: String,
+    fields: Option<String>,
+    /// Puntos de la rejilla común (por defecto 1000)
+    points: Option<usize>,
+}
+
+/// Compara dos vuelos sobre el tramo que comparten, remuestreados a una rejilla común
+#[utoipa::path(
+    get,
+    path = "/api/flights/compare",
+    tag = "flights",
+    params(CompareQuery),
+    responses(
+        (status = 200, description = "Series alineadas por tiempo desde el inicio y diferencias por campo", body = compare::Comparison),
+        (status = 404, description = "Vuelo no encontrado (se indica cuál)", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn compare_flights(
+    State(ctx): State<WsContext>,
+    ApiQuery(q): ApiQuery<CompareQuery>,
+) -> Result<Json<compare::Comparison>, ApiError> {
+    let fields = params::fields(q.fields.as_deref())?;
+    let (a, b) = tokio::try_join!(
+        ctx.questdb.fetch_flight_points(&q.a, None, None, 1_000_000),
+        ctx.questdb.fetch_flight_points(&q.b, None, None, 1_000_000),
+    )?;
+    for (id, points) in [(&q.a, &a), (&q.b, &b)] {
+        if points.is_empty() {
+            return Err(ApiError::NotFound(format!("Flight {id} not found")));
+        }
+    }
+    let points = q.points.unwrap_or(compare::DEFAULT_GRID_POINTS).min(compare::MAX_GRID_POINTS);
+    Ok(Json(compare::compare((&q.a, &a), (&q.b, &b), &fields, points)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OverlayQuery {
+    /// `flights=id1,id2,id3`
+    flights: String,
+    fields: Option<String>,
+    /// `start` (por defecto) o `marker:<label>`
+    align: Option<String>,
+    /// Puntos en total entre todos los vuelos (por defecto 20000)
+    max_points: Option<usize>,
+    #[param(inline)]
+    method: Option<series::Downsample>,
+}
+
+/// Series de varios vuelos con el tiempo en segundos desde su inicio (o desde una marca)
+#[utoipa::path(
+    get,
+    path = "/api/series/overlay",
+    tag = "flights",
+    params(OverlayQuery),
+    responses(
+        (status = 200, description = "Series por vuelo; los vuelos sin datos o sin la marca van en `warnings`", body = overlay::Overlay),
+        (status = 400, description = "Lista de vuelos o `align` inválidos", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
+async fn overlay_series(
+    State(ctx): State<WsContext>,
+    ApiQuery(q): ApiQuery<OverlayQuery>,
+) -> Result<Json<overlay::Overlay>, ApiError> {
+    let flights = series::split_fields(&q.flights);
+    if flights.is_empty() || flights.len() > overlay::MAX_FLIGHTS {
+        return Err(ApiError::BadRequest(format!("'flights' must list 1-{} flight ids", overlay::MAX_FLIGHTS)));
+    }
+    let align = overlay::Align::parse(q.align.as_deref().unwrap_or("start")).ok_or_else(|| {
+        ApiError::BadRequest("Invalid 'align': expected 'start' or 'marker:<label>'".to_string())
+    })?;
+    let fields = params::fields(q.fields.as_deref())?;
+    let max_points = params::positive("max_points", q.max_points)?.unwrap_or(overlay::DEFAULT_MAX_POINTS).min(overlay::MAX_POINTS);
+    let method = q.method.unwrap_or_default();
+    Ok(Json(overlay::build(&ctx.questdb, &flights, &fields, &align, max_points, method).await?))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct SummaryQuery {
     throttle_min: Option<f64>,
     throttle_max: Option<f64>,
+    /// Campos con estadísticas en `metrics.fields` (por defecto AngleRoll,AnglePitch,InputThrottle)
+    fields: Option<String>,
+    /// Hueco mínimo entre muestras que cuenta en `metrics.gaps` (por defecto 100 ms)
+    gap_ms: Option<f64>,
+    /// Ignora la caché de `flight_summaries` y vuelve a calcular
+    recompute: Option<bool>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/flights/{id}/summary",
+    tag = "flights",
+    params(("id" = String, Path, description = "flight_id"), SummaryQuery),
+    responses(
+        (status = 200, description = "Resumen del vuelo (cacheado en `flight_summaries` mientras no cambien sus puntos)", body = summary::FlightSummary),
+        (status = 304, description = "Vuelo cerrado sin cambios desde el `ETag` de `If-None-Match`"),
+        (status = 404, description = "Vuelo no encontrado", body = ErrorBody),
+        (status = 422, description = "Vuelo con más de 1 000 000 puntos: sin resumen al vuelo", body = ErrorBody),
+        (status = 503, description = "QuestDB no disponible", body = ErrorBody),
+        (status = 500, description = "Fallo de la consulta", body = ErrorBody),
+    )
+)]
 async fn get_flight_summary(
     State(ctx): State<WsContext>,
     Path(fid): Path<String>,
-    Query(q): Query<SummaryQuery>,
-) -> Json<Option<FlightSummary>> {
-    let points = match ctx.questdb.fetch_flight_points(&fid, None, None, 1_000_000).await {
-        Ok(v) => v,
-        Err(e) => { eprintln!("❌ get_flight_summary: {e}"); return Json(None); }
+    headers: axum::http::HeaderMap,
+    RawQuery(raw_query): RawQuery,
+    ApiQuery(q): ApiQuery<SummaryQuery>,
+) -> Result<axum::response::Response, ApiError> {
+    let defaults = summary::SummaryParams::default();
+    let params = summary::SummaryParams {
+        throttle_min: q.throttle_min.unwrap_or(defaults.throttle_min),
+        throttle_max: q.throttle_max.unwrap_or(defaults.throttle_max),
+        fields: params::fields(q.fields.as_deref())?,
+        gap_ms: params::positive("gap_ms", q.gap_ms)?.unwrap_or(defaults.gap_ms),
     };
-    if points.is_empty() { return Json(None); }
-
-    let start_ts = points.first().unwrap().ts;
-    let end_ts = points.last().unwrap().ts;
-    let duration = (end_ts - start_ts).num_milliseconds() as f64 / 1000.0;
-
-    let thr_min = q.throttle_min.unwrap_or(1200.0);
-    let thr_max = q.throttle_max.unwrap_or(2000.0);
-
-    let mut max_roll = None::<f64>;
-    let mut max_pitch = None::<f64>;
-    let mut in_range = 0.0f64;
-    let mut out_range = 0.0f64;
-
-    // integramos por “tramos” (asumiendo frecuencia relativamente uniforme)
-    for w in points.windows(2) {
-        let a = &w[0];
-        let b = &w[1];
-        let dt = (b.ts - a.ts).num_milliseconds() as f64 / 1000.0;
-
-        let inner = a.payload.get("payload").and_then(|v| v.as_object());
-        if let Some(obj) = inner {
-            if let Some(v) = obj.get("AngleRoll").and_then(|x| x.as_f64()) {
-                max_roll = Some(max_roll.map(|m| m.max(v.abs())).unwrap_or(v.abs()));
-            }
-            if let Some(v) = obj.get("AnglePitch").and_then(|x| x.as_f64()) {
-                max_pitch = Some(max_pitch.map(|m| m.max(v.abs())).unwrap_or(v.abs()));
-            }
-            if let Some(th) = obj.get("InputThrottle").and_then(|x| x.as_f64()) {
-                if th >= thr_min && th <= thr_max { in_range += dt; } else { out_range += dt; }
-            }
+    if params.throttle_min > params.throttle_max {
+        return Err(ApiError::InvalidParam {
+            param: "throttle_min".to_string(),
+            message: "'throttle_min' must not be greater than 'throttle_max'".to_string(),
+        });
+    }
+    let cache = etag::Conditional::new(&ctx, &fid, raw_query.as_deref()).await?;
+    if !q.recompute.unwrap_or(false) {
+        if let Some(not_modified) = cache.not_modified(&headers) {
+            return Ok(not_modified);
         }
+        if let Some(cached) = summary::cached(&ctx.questdb, &fid, &params).await? {
+            return Ok(cache.respond(Json(cached)));
+        }
+    }
+    let points = ctx.questdb.count_flight_points(&fid, None, None).await?;
+    if points > summary::SUMMARY_MAX_POINTS {
+        return Err(ApiError::TooLarge(format!(
+            "Flight {fid} has {points} points; summaries are computed from at most {}. Use /api/flights/{fid}/aggregate for bucketed statistics",
+            summary::SUMMARY_MAX_POINTS
+        )));
     }
+    summary::refresh(&ctx.questdb, &fid, &params).await?
+        .map(|s| cache.respond(Json(s)))
+        .ok_or_else(|| ApiError::NotFound(format!("Flight {fid} not found")))
+}
 
-    Json(Some(FlightSummary {
-        flight_id: fid,
-        start_ts: start_ts.to_rfc3339(),
-        end_ts: end_ts.to_rfc3339(),
-        duration_sec: duration,
-        max_roll,
-        max_pitch,
-        throttle_time_in_range_sec: in_range,
-        throttle_time_out_range_sec: out_range,
-    }))
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{HeaderMap, Method, Request};
+    use tower::ServiceExt;
+
+    const CONFIG: &str = r#"{"schemaVersion":1,"selectedFields":["AngleRoll"],"retention":{"mode":"infinite"},
+        "triggers":{"startWhen":{"key":"AngleRoll","between":[-90,90]},"stopWhen":null},"metadata":null}"#;
+
+    /// Respuesta del router completo: estado, cabeceras y cuerpo
+    pub(crate) async fn call(app: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let req = Request::builder().method(method).uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_owned())).unwrap();
+        send(app, req).await
+    }
+
+    pub(crate) async fn send(app: &Router, req: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let (parts, body) = resp.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, bytes.to_vec())
+    }
+
+    pub(crate) async fn call_json(app: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let (status, _, bytes) = call(app, method, uri, body).await;
+        (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+    }
+
+    /// Telemetría como si llegara del ESP32
+    pub(crate) async fn telemetry(ctx: &WsContext, roll: f64) {
+        let text = serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": roll } }).to_string();
+        udp::handle_inbound(ctx, None, text.as_bytes(), &"udp".into(), "udp").await;
+    }
+
+    #[tokio::test]
+    async fn config_start_series_stop_on_the_router() {
+        let ctx = WsContext::for_tests_sqlite();
+        let app = router(ctx.clone());
+
+        let (status, body) = call_json(&app, Method::POST, "/api/logger/config", CONFIG).await;
+        assert_eq!((status, body["status"].as_str()), (StatusCode::OK, Some("ok")));
+        let (_, cfg) = call_json(&app, Method::GET, "/api/logger/config", "").await;
+        assert_eq!(cfg["config"]["selectedFields"][0], "AngleRoll");
+
+        let (status, started) = call_json(&app, Method::POST, "/api/recordings/start", CONFIG).await;
+        assert_eq!(status, StatusCode::OK);
+        let fid = started["flightId"].as_str().unwrap().to_owned();
+        assert_eq!(ctx.flight_id.read().await.as_deref(), Some(fid.as_str()));
+
+        for roll in [1.0, 2.0, 3.0] {
+            telemetry(&ctx, roll).await;
+        }
+        ctx.telemetry_writer.flush(&ctx.questdb).await;
+        let (status, series) = call_json(&app, Method::GET, &format!("/api/flights/{fid}/series?fields=AngleRoll"), "").await;
+        assert_eq!(status, StatusCode::OK, "{series}");
+        let rolls: Vec<f64> = series.as_array().unwrap().iter().map(|p| p["values"]["AngleRoll"].as_f64().unwrap()).collect();
+        assert_eq!(rolls, [1.0, 2.0, 3.0]);
+
+        let (status, stopped) = call_json(&app, Method::POST, "/api/recordings/stop", "").await;
+        assert_eq!((status, stopped["flightId"].as_str()), (StatusCode::OK, Some(fid.as_str())));
+        assert!(ctx.flight_id.read().await.is_none());
+        let (status, _, _) = call(&app, Method::POST, "/api/recordings/stop", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn motor_speed_out_of_range_is_rejected_over_http() {
+        let app = router(WsContext::for_tests_sqlite());
+        let (status, _, body) = call(&app, Method::POST, "/api/command/motor-speed", r#"{"id":1,"us":2500}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(String::from_utf8(body).unwrap(), commands::check_motor_us(2500).unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn second_start_conflicts_and_ids_are_unique() {
+        let ctx = WsContext::for_tests_sqlite();
+        let app = router(ctx.clone());
+        let (_, first) = call_json(&app, Method::POST, "/api/recordings/start", CONFIG).await;
+        let (status, _, _) = call(&app, Method::POST, "/api/recordings/start", CONFIG).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        // La grabación activa sigue siendo la primera
+        assert_eq!(ctx.flight_id.read().await.as_deref(), first["flightId"].as_str());
+
+        call(&app, Method::POST, "/api/recordings/stop", "").await;
+        let (status, second) = call_json(&app, Method::POST, "/api/recordings/start", CONFIG).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(first["flightId"], second["flightId"]);
+    }
+
+    async fn preflight(app: &Router, origin: &str) -> (StatusCode, HeaderMap) {
+        let req = Request::builder().method(Method::OPTIONS).uri("/api/flights")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty()).unwrap();
+        let (status, headers, _) = send(app, req).await;
+        (status, headers)
+    }
+
+    #[tokio::test]
+    async fn preflight_only_answers_listed_origins() {
+        let mut ctx = WsContext::for_tests_sqlite();
+        ctx.cors = cors::CorsOrigins::parse("http://ui.local:5173, https://gs.example/").unwrap();
+        let app = router(ctx);
+
+        let (status, headers) = preflight(&app, "https://gs.example").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://gs.example");
+        let methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap();
+        assert!(methods.contains("DELETE") && !methods.contains("PATCH"), "{methods}");
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains("authorization"));
+
+        let (_, headers) = preflight(&app, "http://evil.example").await;
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[tokio::test]
+    async fn preflight_wildcard_is_opt_in() {
+        let app = router(WsContext::for_tests_sqlite());
+        let (_, headers) = preflight(&app, "http://ui.local:5173").await;
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let mut ctx = WsContext::for_tests_sqlite();
+        ctx.cors = cors::CorsOrigins::parse("*").unwrap();
+        let (_, headers) = preflight(&router(ctx), "http://ui.local:5173").await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[tokio::test]
+    async fn errors_use_the_json_envelope_with_the_request_id() {
+        let app = router(WsContext::for_tests_sqlite());
+        let cases = [
+            ("/api/flights?limit=abc", StatusCode::BAD_REQUEST, "invalid_param", Some("limit")),
+            ("/api/flights?limit=0", StatusCode::BAD_REQUEST, "invalid_param", Some("limit")),
+            ("/api/flights/f/series?from=ayer", StatusCode::BAD_REQUEST, "invalid_param", Some("from")),
+            ("/api/flights/f/series?fields=", StatusCode::BAD_REQUEST, "invalid_param", Some("fields")),
+            ("/api/flights/nope", StatusCode::NOT_FOUND, "not_found", None),
+            ("/api/flights/nope/series", StatusCode::NOT_FOUND, "not_found", None),
+        ];
+        for (uri, status, code, param) in cases {
+            let (got, headers, body) = call(&app, Method::GET, uri, "").await;
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(got, status, "{uri} → {body}");
+            assert_eq!(body["error"]["code"], code, "{uri}");
+            assert_eq!(body["error"]["param"].as_str(), param, "{uri}");
+            assert!(body["error"]["message"].as_str().is_some_and(|m| !m.is_empty()));
+            assert_eq!(headers["x-request-id"].to_str().unwrap(), body["error"]["request_id"], "{uri}");
+        }
+    }
+
+    /// Vuelo de `n` puntos grabado por la API; devuelve su id
+    async fn long_flight(ctx: &WsContext, app: &Router, n: i64) -> String {
+        let (_, started) = call_json(app, Method::POST, "/api/recordings/start", CONFIG).await;
+        let fid = started["flightId"].as_str().unwrap().to_owned();
+        let t0 = chrono::Utc::now();
+        for i in 0..n {
+            let msg = serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": i as f64 * 0.01, "AnglePitch": -1.5 } });
+            ctx.telemetry_writer.push(&fid, &msg, t0 + chrono::Duration::milliseconds(i));
+        }
+        call(app, Method::POST, "/api/recordings/stop", "").await;
+        fid
+    }
+
+    async fn get_gzip(app: &Router, uri: &str) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let req = Request::builder().uri(uri).header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+        send(app, req).await
+    }
+
+    #[tokio::test]
+    async fn large_series_is_gzipped_and_decodes_to_the_same_json() {
+        use std::io::Read;
+
+        let ctx = WsContext::for_tests_sqlite();
+        let app = router(ctx.clone());
+        let fid = long_flight(&ctx, &app, 5000).await;
+        let uri = format!("/api/flights/{fid}/series?fields=AngleRoll,AnglePitch");
+
+        let (status, headers, plain) = call(&app, Method::GET, &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(header::CONTENT_ENCODING).is_none());
+
+        let (status, headers, gz) = get_gzip(&app, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+        assert!(gz.len() * 4 < plain.len(), "{} → {}", plain.len(), gz.len());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(gz.as_slice()).read_to_end(&mut decoded).unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&decoded).unwrap();
+        assert_eq!(decoded, serde_json::from_slice::<serde_json::Value>(&plain).unwrap());
+        assert_eq!(decoded.as_array().unwrap().len(), 5000);
+    }
+
+    #[tokio::test]
+    async fn small_and_streamed_responses_are_not_compressed() {
+        let ctx = WsContext::for_tests_sqlite();
+        let app = router(ctx.clone());
+        let fid = long_flight(&ctx, &app, 5000).await;
+
+        for uri in [format!("/api/flights/{fid}/export.csv"), format!("/api/flights/{fid}/raw.jsonl"), "/api/health".into()] {
+            let (status, headers, body) = get_gzip(&app, &uri).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            assert!(headers.get(header::CONTENT_ENCODING).is_none(), "{uri}");
+            assert!(!body.is_empty());
+        }
+
+        // SSE: solo las cabeceras, el cuerpo no termina
+        let req = Request::builder().uri("/api/stream").header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/event-stream");
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn dead_questdb_degrades_to_503_and_writes_go_to_the_spool() {
+        // Postgres falso que corta cada conexión en pleno handshake, como un QuestDB caído a medias
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let fake = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let mut ctx = WsContext::for_tests(None);
+        ctx.questdb = OptionalDb::new(questdb::QuestDbConfig { port, ..server::test_db_config() });
+        let app = router(ctx.clone());
+
+        let started = std::time::Instant::now();
+        let (status, body) = call_json(&app, Method::GET, "/api/flights", "").await;
+        assert_eq!((status, body["error"]["code"].as_str()), (StatusCode::SERVICE_UNAVAILABLE, Some("db_unavailable")), "{body}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // Ya con el circuito abierto (y el servidor falso muerto) se falla sin intentar conectar
+        fake.abort();
+        let started = std::time::Instant::now();
+        let (status, body) = call_json(&app, Method::GET, "/api/flights/f/series", "").await;
+        assert_eq!((status, body["error"]["code"].as_str()), (StatusCode::SERVICE_UNAVAILABLE, Some("db_unavailable")));
+        assert!(body["error"]["message"].as_str().unwrap().contains("circuit open"), "{body}");
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        let (status, health) = call_json(&app, Method::GET, "/api/health", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!((health["status"].as_str(), health["questdb"].as_bool()), (Some("down"), Some(false)));
+        assert_eq!(health["db_breaker"]["state"], "open");
+
+        let msg = serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": 1.0 } });
+        for _ in 0..3 {
+            ctx.telemetry_writer.push("f", &msg, chrono::Utc::now());
+        }
+        ctx.telemetry_writer.flush(&ctx.questdb).await;
+        let writer = ctx.telemetry_writer.snapshot();
+        assert_eq!((writer.spilled, writer.failed, writer.written), (3, 0, 0));
+
+        // QuestDB vuelve en el mismo puerto: pasada la espera del circuito, la escritura entra
+        let _ = fake.await;
+        let pg = questdb::tests::FakePg::start_on(port).await;
+        let write = || ctx.questdb.insert_flight_log("f", r#"{"seq":1}"#, None);
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while write().await.is_err() {
+            assert!(std::time::Instant::now() < deadline, "no se reconectó: {:?}", ctx.questdb.breaker());
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(ctx.questdb.breaker().state, questdb::BreakerState::Closed);
+        assert_eq!(pg.connections(), 1);
+
+        // Y se reinicia: el cliente viejo queda cerrado y `ensure_connected` monta otro
+        pg.stop().await;
+        let pg = questdb::tests::FakePg::start_on(port).await;
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while ctx.questdb.is_connected().await {
+            assert!(std::time::Instant::now() < deadline, "el cliente no vio el cierre");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        write().await.unwrap();
+        assert_eq!(pg.connections(), 1);
+        assert_eq!(pg.statements().iter().filter(|sql| sql.starts_with("INSERT INTO flight_logs")).count(), 1);
+        pg.stop().await;
+    }
+}
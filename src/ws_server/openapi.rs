@@ -0,0 +1,269 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{OpenApi as Spec, RefOr, ResponseBuilder, SecurityRequirement};
+use utoipa::{Modify, OpenApi};
+
+/// Especificación de todas las rutas del router HTTP; se sirve en `/api/openapi.json`
+/// y la UI en `/api/docs`. Cada handler lleva su `#[utoipa::path]` al lado.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Artheris bridge API", description = "Puente UDP ↔ WS/HTTP con grabación en QuestDB"),
+    paths(
+        super::get_config,
+        super::get_config_history,
+        super::apply_config,
+        super::start_recording,
+        super::stop_recording,
+        super::set_stream_rate,
+        super::command_mode,
+        super::command_motors,
+        super::command_leds,
+        super::command_motor_speed,
+        super::server::ws_upgrade,
+        super::sse::stream,
+        super::health,
+        super::stats,
+        super::udp_stats,
+        super::ws_stats,
+        super::udp_sources,
+        super::capture_start,
+        super::capture_stop,
+        super::capture_files,
+        super::capture_download,
+        super::latest_telemetry,
+        super::get_telemetry_schema,
+        super::set_telemetry_schema,
+        super::get_csv_map,
+        super::set_csv_map,
+        super::get_esp32,
+        super::set_esp32_address,
+        super::send_esp32_raw,
+        super::add_webhook,
+        super::list_webhooks,
+        super::delete_webhook,
+        super::ws_clients,
+        super::ws_client_disconnect,
+        super::get_rate_limits,
+        super::set_rate_limits,
+        super::get_command_whitelist,
+        super::set_command_whitelist,
+        super::list_flights,
+        super::get_flight,
+        super::delete_flight,
+        super::set_flight_meta,
+        super::archive_flight,
+        super::unarchive_flight,
+        super::cleanup_flights,
+        super::get_job,
+        super::replay_spool,
+        super::get_flight_series,
+        super::get_flight_fields,
+        super::list_flight_events,
+        super::list_flight_commands,
+        super::add_flight_event,
+        super::export_flight_csv,
+        super::export_flight_raw,
+        super::import_flight,
+        super::get_flight_summary,
+        super::compare_flights,
+        super::overlay_series,
+        super::get_flight_aggregate,
+        super::start_replay,
+        super::stop_replay,
+    ),
+    modifiers(&TokenOnWrites, &RateLimited),
+)]
+struct ApiDoc;
+
+#[cfg(feature = "parquet")]
+#[derive(OpenApi)]
+#[openapi(paths(super::export_flight_parquet), modifiers(&TokenOnWrites))]
+struct ParquetDoc;
+
+/// Spec completa, con las rutas de las features activas
+pub fn spec() -> Spec {
+    #[allow(unused_mut)]
+    let mut spec = ApiDoc::openapi();
+    #[cfg(feature = "parquet")]
+    spec.merge(ParquetDoc::openapi());
+    spec
+}
+
+/// Con clave configurada todo lo que no es GET pide `X-Api-Key` o `Authorization: Bearer`
+/// (ver `auth::require_token`)
+struct TokenOnWrites;
+
+impl Modify for TokenOnWrites {
+    fn modify(&self, spec: &mut Spec) {
+        let components = spec.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))));
+        for item in spec.paths.paths.values_mut() {
+            for op in [&mut item.post, &mut item.put, &mut item.delete].into_iter().flatten() {
+                op.security = Some(vec![
+                    SecurityRequirement::new("api_key", Vec::<String>::new()),
+                    SecurityRequirement::new("token", Vec::<String>::new()),
+                ]);
+                op.responses.responses.insert(
+                    "401".to_string(),
+                    RefOr::T(ResponseBuilder::new().description("Missing or invalid API key").build()),
+                );
+            }
+        }
+    }
+}
+
+/// 429 en las rutas con cupo por IP (`ratelimit::limit_http`)
+struct RateLimited;
+
+impl Modify for RateLimited {
+    fn modify(&self, spec: &mut Spec) {
+        for (path, item) in spec.paths.paths.iter_mut() {
+            let reads = ["/api/flights", "/api/series", "/api/logger/config/history"].iter().any(|p| path.starts_with(p));
+            let get = if reads { item.get.as_mut() } else { None };
+            for op in [get, item.post.as_mut(), item.put.as_mut(), item.delete.as_mut()].into_iter().flatten() {
+                op.responses.responses.insert(
+                    "429".to_string(),
+                    RefOr::T(ResponseBuilder::new().description("Too many requests; see `Retry-After`").build()),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::ws_server::router;
+    use crate::ws_server::server::WsContext;
+
+    /// Rutas de `ws_server::router`, en el mismo orden; al añadir una ruta allí va también aquí
+    const ROUTES: &[(&str, &str)] = &[
+        ("GET", "/api/logger/config"),
+        ("POST", "/api/logger/config"),
+        ("GET", "/api/logger/config/history"),
+        ("POST", "/api/recordings/start"),
+        ("POST", "/api/recordings/stop"),
+        ("POST", "/api/stream/rate"),
+        ("POST", "/api/command/mode"),
+        ("POST", "/api/command/motors"),
+        ("POST", "/api/command/leds"),
+        ("POST", "/api/command/motor-speed"),
+        ("GET", "/ws"),
+        ("GET", "/api/stream"),
+        ("GET", "/api/health"),
+        ("GET", "/api/stats"),
+        ("GET", "/api/stats/udp"),
+        ("GET", "/api/stats/ws"),
+        ("POST", "/api/capture/start"),
+        ("GET", "/api/telemetry/latest"),
+        ("GET", "/api/telemetry/schema"),
+        ("POST", "/api/telemetry/schema"),
+        ("GET", "/api/telemetry/csv-map"),
+        ("POST", "/api/telemetry/csv-map"),
+        ("POST", "/api/capture/stop"),
+        ("GET", "/api/capture/files"),
+        ("GET", "/api/capture/files/:name"),
+        ("GET", "/api/stats/udp/sources"),
+        ("GET", "/api/esp32"),
+        ("GET", "/api/ws/clients"),
+        ("GET", "/api/ws/rate-limit"),
+        ("POST", "/api/ws/rate-limit"),
+        ("GET", "/api/security/commands"),
+        ("POST", "/api/security/commands"),
+        ("DELETE", "/api/ws/clients/:id"),
+        ("PUT", "/api/esp32/address"),
+        ("POST", "/api/esp32/send"),
+        ("GET", "/api/webhooks"),
+        ("POST", "/api/webhooks"),
+        ("DELETE", "/api/webhooks/:id"),
+        ("GET", "/api/jobs/:id"),
+        ("POST", "/api/spool/replay"),
+        ("GET", "/api/flights"),
+        ("POST", "/api/flights/cleanup"),
+        ("GET", "/api/flights/:id"),
+        ("DELETE", "/api/flights/:id"),
+        ("PUT", "/api/flights/:id/meta"),
+        ("POST", "/api/flights/:id/archive"),
+        ("POST", "/api/flights/:id/unarchive"),
+        ("GET", "/api/flights/:id/series"),
+        ("GET", "/api/flights/:id/fields"),
+        ("GET", "/api/flights/:id/events"),
+        ("POST", "/api/flights/:id/events"),
+        ("GET", "/api/flights/:id/commands"),
+        ("GET", "/api/flights/:id/export.csv"),
+        ("GET", "/api/flights/:id/raw.jsonl"),
+        ("POST", "/api/flights/import"),
+        ("GET", "/api/flights/compare"),
+        ("GET", "/api/series/overlay"),
+        ("GET", "/api/flights/:id/summary"),
+        ("GET", "/api/flights/:id/aggregate"),
+        ("POST", "/api/flights/:id/replay"),
+        ("POST", "/api/replay/stop"),
+        #[cfg(feature = "parquet")]
+        ("GET", "/api/flights/:id/export.parquet"),
+    ];
+
+    /// `:id` de axum → `{id}` de OpenAPI
+    fn openapi_path(route: &str) -> String {
+        route.split('/')
+            .map(|s| s.strip_prefix(':').map_or_else(|| s.to_string(), |p| format!("{{{p}}}")))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn documented() -> BTreeSet<(String, String)> {
+        let mut out = BTreeSet::new();
+        for (path, item) in spec().paths.paths {
+            let ops = [("GET", &item.get), ("POST", &item.post), ("PUT", &item.put), ("DELETE", &item.delete), ("PATCH", &item.patch)];
+            for (method, op) in ops {
+                if op.is_some() {
+                    out.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn spec_and_route_table_list_the_same_operations() {
+        let routes: BTreeSet<_> = ROUTES.iter().map(|(m, p)| (m.to_string(), openapi_path(p))).collect();
+        assert_eq!(routes.len(), ROUTES.len(), "ruta repetida en ROUTES");
+        let spec = documented();
+        let undocumented: Vec<_> = routes.difference(&spec).collect();
+        let unrouted: Vec<_> = spec.difference(&routes).collect();
+        assert!(undocumented.is_empty(), "rutas sin #[utoipa::path] en ApiDoc: {undocumented:?}");
+        assert!(unrouted.is_empty(), "rutas de la spec que el router no sirve: {unrouted:?}");
+    }
+
+    /// La tabla no vale de nada si no coincide con el router: cada entrada tiene que
+    /// llegar a un handler (un 404 de axum sin ruta no trae cuerpo; un 405 es método sin registrar)
+    #[tokio::test]
+    async fn every_listed_route_reaches_a_handler() {
+        let app = router(WsContext::for_tests_sqlite());
+        for (method, route) in ROUTES {
+            let uri = route.replace(":id", "probe").replace(":name", "probe.ndjson");
+            let req = Request::builder()
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            let status = res.status();
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {route}");
+            if status == StatusCode::NOT_FOUND {
+                // Sin leer cuerpos de streams infinitos (SSE): solo los 404
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                assert!(!body.is_empty(), "{method} {route}: el router no tiene la ruta");
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::questdb::{DbError, OptionalDb};
+use super::series;
+
+/// Puntos en total (repartidos entre los vuelos) si no se pide `max_points`
+pub const DEFAULT_MAX_POINTS: usize = 20_000;
+pub const MAX_POINTS: usize = 200_000;
+pub const MAX_FLIGHTS: usize = 20;
+/// Puntos leídos como mucho de cada vuelo antes de reducir
+const FETCH_LIMIT: i64 = 1_000_000;
+
+/// Origen de tiempo de cada vuelo (`align=start` o `align=marker:<label>`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Align {
+    Start,
+    /// Primera marca del vuelo con esa etiqueta
+    Marker(String),
+}
+
+impl Align {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "" | "start" => Some(Self::Start),
+            s => {
+                let label = s.strip_prefix("marker:")?.trim();
+                (!label.is_empty()).then(|| Self::Marker(label.to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OverlayPoint {
+    /// Segundos desde el origen del vuelo (negativo antes de la marca)
+    pub t: f64,
+    pub values: HashMap<String, f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Overlay {
+    pub flights: BTreeMap<String, Vec<OverlayPoint>>,
+    /// Algún vuelo se redujo para no pasar de `max_points` en total
+    pub downsampled: bool,
+    /// Vuelos que no se incluyen y por qué
+    pub warnings: Vec<String>,
+}
+
+/// Lee los vuelos de uno en uno y reduce cada uno a su parte de `max_points` antes de
+/// pasar al siguiente, para no tener varios vuelos largos en memoria a la vez
+pub async fn build(
+    db: &OptionalDb,
+    flight_ids: &[String],
+    fields: &[String],
+    align: &Align,
+    max_points: usize,
+    method: series::Downsample,
+) -> Result<Overlay, DbError> {
+    let per_flight = (max_points / flight_ids.len().max(1)).max(3);
+    let mut out = Overlay { flights: BTreeMap::new(), downsampled: false, warnings: Vec::new() };
+
+    for fid in flight_ids {
+        if out.flights.contains_key(fid) {
+            continue;
+        }
+        let points = db.fetch_flight_points(fid, None, None, FETCH_LIMIT).await?;
+        let Some(first) = points.first() else {
+            out.warnings.push(format!("Flight {fid} not found"));
+            continue;
+        };
+        let origin = match align {
+            Align::Start => first.ts,
+            Align::Marker(label) => {
+                let events = db.list_flight_events(fid, None, None).await?;
+                let Some(ts) = events.iter().find(|e| &e.label == label).and_then(|e| series::parse_ts(&e.ts)) else {
+                    out.warnings.push(format!("Flight {fid} has no marker '{label}'"));
+                    continue;
+                };
+                ts
+            }
+        };
+        let reduced = series::downsample(&points, fields, per_flight, method);
+        out.downsampled |= reduced.downsampled;
+        out.flights.insert(fid.clone(), reduced.points.into_iter().map(|p| rebase(p, origin)).collect());
+    }
+    Ok(out)
+}
+
+fn rebase(p: series::SeriesPoint, origin: DateTime<Utc>) -> OverlayPoint {
+    let ts = series::parse_ts(&p.ts).unwrap_or(origin);
+    OverlayPoint { t: (ts - origin).num_microseconds().unwrap_or(i64::MAX) as f64 / 1e6, values: p.values }
+}
@@ -0,0 +1,92 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+
+use super::api_error::ApiError;
+use super::series;
+
+/// Tope de `limit` si no se configura `ARTHERIS_MAX_QUERY_LIMIT`
+pub const DEFAULT_MAX_LIMIT: i64 = 200_000;
+
+/// Como `Query`, pero un parámetro que no se puede leer (`limit=abc`, `method=foo`)
+/// responde 400 `invalid_param` con su nombre en vez del texto plano de axum
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let de = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(de).map(ApiQuery).map_err(|e| {
+            let param = e.path().to_string();
+            let param = if param == "." { "query".to_string() } else { param };
+            ApiError::InvalidParam { message: format!("Invalid '{param}': {}", e.inner()), param }
+        })
+    }
+}
+
+fn invalid(param: &str, message: String) -> ApiError {
+    ApiError::InvalidParam { param: param.to_string(), message }
+}
+
+/// Fecha RFC 3339 de un parámetro; sin zona horaria o mal formada → 400
+pub fn ts(name: &str, value: Option<&str>) -> Result<Option<DateTime<Utc>>, ApiError> {
+    value
+        .map(|v| series::parse_ts(v).ok_or_else(|| invalid(
+            name,
+            format!("Invalid '{name}': expected RFC 3339 with offset (e.g. 2024-05-01T00:00:00Z), got '{v}'"),
+        )))
+        .transpose()
+}
+
+/// Intervalo `from`/`to`; cualquiera de los dos puede faltar
+pub type TimeRange = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// `from`/`to` validados y en orden
+pub fn range(from: Option<&str>, to: Option<&str>) -> Result<TimeRange, ApiError> {
+    let (from, to) = (ts("from", from)?, ts("to", to)?);
+    if let (Some(f), Some(t)) = (from, to)
+        && f > t
+    {
+        return Err(invalid("from", "'from' must not be after 'to'".to_string()));
+    }
+    Ok((from, to))
+}
+
+/// `limit` entre 1 y `max`; sin él, `default` (recortado a `max`)
+pub fn limit(value: Option<i64>, default: i64, max: i64) -> Result<i64, ApiError> {
+    match value {
+        None => Ok(default.min(max)),
+        Some(n) if n <= 0 => Err(invalid("limit", format!("'limit' must be positive, got {n}"))),
+        Some(n) if n > max => Err(invalid("limit", format!("'limit' must be at most {max}, got {n}"))),
+        Some(n) => Ok(n),
+    }
+}
+
+/// `fields=a,b`; sin el parámetro, los campos por defecto. Presente pero vacío → 400
+pub fn fields(value: Option<&str>) -> Result<Vec<String>, ApiError> {
+    let Some(csv) = value else {
+        return Ok(series::default_fields());
+    };
+    let fields = series::split_fields(csv);
+    if fields.is_empty() {
+        return Err(invalid("fields", "'fields' is empty".to_string()));
+    }
+    Ok(fields)
+}
+
+/// Número positivo (`max_points`, `gap_ms`...)
+pub fn positive<T: PartialOrd + Default + std::fmt::Display>(name: &str, value: Option<T>) -> Result<Option<T>, ApiError> {
+    match value {
+        Some(v) if v <= T::default() => Err(invalid(name, format!("'{name}' must be positive, got {v}"))),
+        v => Ok(v),
+    }
+}
@@ -1,15 +1,35 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{RwLock, Mutex};
-use tokio_postgres::{Client, NoTls};
+use deadpool_postgres::{ManagerConfig, Pool, PoolConfig, RecyclingMethod, Runtime};
+use tokio_postgres::{Client, NoTls, Row, Statement, ToStatement};
+use tokio_postgres::types::ToSql;
 use tracing::{info, warn, error, debug, trace};
 use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
 
+use super::aggregate::{AggRequest, AggRow};
+use super::commands::{self, FlightCommand};
+use super::events::FlightEvent;
+use super::metrics::DbMetrics;
+use super::store::FlightStore;
+
+/// Una conexión fija para escrituras y un pool para lecturas: una consulta larga
+/// (`fetch_flight_points` de un vuelo grande) no retrasa las inserciones de telemetría
 #[derive(Clone)]
 pub struct QuestDb {
     inner: Arc<RwLock<Client>>,
+    reads: Pool,
+    /// Sentencias ya preparadas en la conexión de escritura; una conexión nueva
+    /// (reconexión) es otro `QuestDb` y empieza sin ninguna
+    prepared: Arc<std::sync::Mutex<HashMap<&'static str, Statement>>>,
+    /// Última migración aplicada (ver `MIGRATIONS`)
+    schema_version: i32,
 }
 
 #[derive(Clone, Deserialize)]
@@ -19,6 +39,33 @@ pub struct QuestDbConfig {
     pub user: String,
     pub password: String,
     pub database: String,
+    /// Conexiones en total: una de escritura y el resto para lecturas
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    /// Puerto ILP (TCP, normalmente 9009): con él la telemetría se escribe por ILP
+    #[serde(default)]
+    pub ilp_port: Option<u16>,
+    /// Tope de cada escritura (inserciones, tombstones...) antes de dar `DbError::Timeout`
+    #[serde(default = "default_write_timeout_ms")]
+    pub write_timeout_ms: u64,
+    /// Tope de cada lectura (listados, series, agregados); más holgado que el de escritura
+    #[serde(default = "default_read_timeout_ms")]
+    pub read_timeout_ms: u64,
+    /// Arrancar aunque la BD tenga migraciones que este binario no conoce
+    #[serde(default)]
+    pub allow_newer_schema: bool,
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_write_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_read_timeout_ms() -> u64 {
+    30_000
 }
 
 #[derive(Clone, Debug)]
@@ -27,73 +74,476 @@ pub struct FlightPoint {
     pub payload: serde_json::Value,
 }
 
+/// Fila pendiente de `flight_logs` (ver `writer::TelemetryWriter`)
+#[derive(Clone, Debug)]
+pub struct LogRow {
+    pub ts: DateTime<Utc>,
+    pub flight_id: String,
+    pub payload: String,
+    /// Valores para `flight_metrics`, si el vuelo tiene campos tipados
+    pub typed: Option<TypedValues>,
+}
+
+/// Campos con columna DOUBLE propia en `flight_metrics` y sus valores, en el mismo orden
+#[derive(Clone, Debug)]
+pub struct TypedValues {
+    pub fields: Arc<[String]>,
+    pub values: Vec<Option<f64>>,
+}
+
+/// Tope de parámetros por `INSERT` multi-fila
+const MAX_INSERT_PARAMS: usize = 8_000;
+
+/// Migraciones del esquema en orden; la versión de cada una es su posición (desde 1).
+/// Solo se añaden al final y no se cambian una vez publicadas. Las tablas:
+/// - flight_logs: telemetría cruda por vuelo
+/// - logger_configs: auditoría de configs/eventos start/stop
+/// - command_logs: comandos enviados al ESP32 (direction="out") y acks recibidos ("ack")
+/// - deleted_flights: vuelos borrados (QuestDB no tiene DELETE; sus filas se ocultan)
+/// - flight_meta: etiquetas/notas por vuelo, versionadas (vale la última fila)
+/// - flight_summaries: resúmenes calculados, válidos mientras el vuelo tenga esos puntos
+/// - flight_events: marcas con etiqueta dentro de un vuelo
+/// - flight_metrics: campos numéricos de la telemetría como columnas DOUBLE (una por campo
+///   de `selectedFields`, se añaden al empezar cada vuelo); el resto solo en flight_logs.payload
+/// - flight_metric_fields: qué campos tipados tiene cada vuelo
+/// - webhooks: registros de webhooks, versionados por id (vale la última fila; `removed` los da de baja)
+/// - flight_commands: comandos enviados durante un vuelo y, en otra fila (`kind`="ack"), su ack
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("tablas iniciales", r#"
+        CREATE TABLE IF NOT EXISTS flight_logs (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            payload STRING
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS logger_configs (
+            ts TIMESTAMP,
+            config_json STRING
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS command_logs (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            request_id STRING,
+            direction SYMBOL,
+            payload STRING
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS deleted_flights (
+            ts TIMESTAMP,
+            flight_id SYMBOL
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS flight_meta (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            tags STRING,
+            notes STRING,
+            archived BOOLEAN
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS flight_summaries (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            params STRING,
+            points LONG,
+            last_ts TIMESTAMP,
+            summary STRING
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS flight_events (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            label STRING
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS flight_metrics (
+            ts TIMESTAMP,
+            flight_id SYMBOL
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+
+        CREATE TABLE IF NOT EXISTS flight_metric_fields (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            fields STRING
+        ) TIMESTAMP(ts) PARTITION BY MONTH;
+
+        CREATE TABLE IF NOT EXISTS webhooks (
+            ts TIMESTAMP,
+            id SYMBOL,
+            url STRING,
+            events STRING,
+            removed BOOLEAN
+        ) TIMESTAMP(ts) PARTITION BY MONTH;
+    "#),
+    // Tablas creadas antes de existir la columna
+    ("flight_meta.archived", "ALTER TABLE flight_meta ADD COLUMN IF NOT EXISTS archived BOOLEAN"),
+    ("flight_commands", r#"
+        CREATE TABLE IF NOT EXISTS flight_commands (
+            ts TIMESTAMP,
+            flight_id SYMBOL,
+            request_id STRING,
+            kind SYMBOL,
+            payload STRING,
+            ack_ok BOOLEAN,
+            ack_ts TIMESTAMP
+        ) TIMESTAMP(ts) PARTITION BY DAY;
+    "#),
+];
+
+/// La BD tiene migraciones que este binario no conoce
+#[derive(Debug)]
+pub struct SchemaTooNew {
+    pub stored: i32,
+    pub known: i32,
+}
+
+impl std::fmt::Display for SchemaTooNew {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "QuestDB schema is at version {}, this build only knows up to {}", self.stored, self.known)
+    }
+}
+
+impl std::error::Error for SchemaTooNew {}
+
+/// Sentencias de `flight_logs` que se preparan una vez por conexión
+const INSERT_LOG_NOW: &str = "INSERT INTO flight_logs (ts, flight_id, payload) VALUES (now(), $1, $2)";
+const INSERT_LOG_AT: &str = "INSERT INTO flight_logs (ts, flight_id, payload) VALUES ($1, $2, $3)";
+
+/// Extremo superior de un rango sin `to` (9999-12-31T23:59:59Z)
+const OPEN_END: DateTime<Utc> = DateTime::from_timestamp(253_402_300_799, 0).unwrap();
+
+/// Las columnas tipadas se llaman como el campo; solo [A-Za-z0-9_] (como en `aggregate`)
+pub fn typed_column(field: &str) -> bool {
+    super::aggregate::AggRequest::valid_field(field)
+}
+
+/// Un vuelo del listado: primer/último punto, nº de puntos y metadatos
+#[derive(Clone, Debug)]
+pub struct FlightRow {
+    pub flight_id: String,
+    pub first_ts: DateTime<Utc>,
+    pub last_ts: DateTime<Utc>,
+    pub points: i64,
+    pub meta: FlightMeta,
+}
+
+/// Etiquetas y notas de un vuelo; las etiquetas se guardan unidas por comas
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct FlightMeta {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub notes: String,
+    /// Oculto del listado por defecto (`POST /api/flights/:id/archive`); `PUT .../meta` no lo cambia
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl FlightMeta {
+    fn from_row(tags: Option<String>, notes: Option<String>, archived: Option<bool>) -> Self {
+        Self {
+            tags: tags.unwrap_or_default().split(',').filter(|t| !t.is_empty()).map(str::to_owned).collect(),
+            notes: notes.unwrap_or_default(),
+            archived: archived.unwrap_or(false),
+        }
+    }
+}
+
+/// Filtros de `list_flights`; `before` es el cursor (last_ts del último vuelo de la página anterior)
+#[derive(Clone, Debug)]
+pub struct FlightFilter {
+    pub limit: i64,
+    pub before: Option<DateTime<Utc>>,
+    /// Vuelos que se solapan con [from, to]
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub min_points: Option<i64>,
+    /// Solo vuelos con esta etiqueta (en su última versión de `flight_meta`)
+    pub tag: Option<String>,
+    /// Sin esto se omiten los archivados
+    pub include_archived: bool,
+}
+
+/// Fila de `flight_summaries`: el resumen en JSON y el estado del vuelo al calcularlo
+#[derive(Clone, Debug)]
+pub struct CachedSummary {
+    pub points: i64,
+    pub last_ts: DateTime<Utc>,
+    pub summary: String,
+}
+
+/// Cabecera de un vuelo para `GET /api/flights/:id`
+#[derive(Clone, Debug)]
+pub struct FlightInfo {
+    pub first_ts: DateTime<Utc>,
+    pub last_ts: DateTime<Utc>,
+    pub points: i64,
+    pub meta: FlightMeta,
+    /// Hay algún resumen en `flight_summaries` (con cualquier parámetro)
+    pub has_summary: bool,
+    /// Última fila de `logger_configs` (config o evento `start`) anterior al primer punto
+    pub config: Option<(DateTime<Utc>, String)>,
+}
+
+/// Un vuelo por fila con sus agregados, excluyendo los borrados
+const FLIGHTS_GROUPED: &str = "SELECT flight_id, min(ts) AS first_ts, max(ts) AS last_ts, count() AS points
+     FROM flight_logs
+     WHERE flight_id NOT IN (SELECT flight_id FROM deleted_flights)
+     GROUP BY flight_id";
+
+/// Última versión de `flight_meta` por vuelo
+const FLIGHT_META_LATEST: &str = "SELECT flight_id, tags, notes, archived FROM flight_meta LATEST ON ts PARTITION BY flight_id";
+
 impl QuestDb {
     pub async fn connect(cfg: QuestDbConfig) -> Result<Self> {
         info!("🔌 Conectando a QuestDB en {}:{}", cfg.host, cfg.port);
 
-        let (client, connection) = match tokio_postgres::connect(
-            &format!(
-                "host={} port={} user={} password={} dbname={}",
-                cfg.host, cfg.port, cfg.user, cfg.password, cfg.database
-            ),
-            NoTls,
-        ).await {
-            Ok(conn) => conn,
-            Err(e) => {
+        let params = format!(
+            "host={} port={} user={} password={} dbname={}",
+            cfg.host, cfg.port, cfg.user, cfg.password, cfg.database
+        );
+        let connect = tokio_postgres::connect(&params, NoTls);
+        let (client, connection) = match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
                 warn!("⚠️  No se pudo conectar a QuestDB: {}", e);
                 return Err(e.into());
             }
+            Err(_) => {
+                warn!("⚠️  No se pudo conectar a QuestDB: sin respuesta en {CONNECT_TIMEOUT:?}");
+                anyhow::bail!("connect timed out after {CONNECT_TIMEOUT:?}");
+            }
         };
 
-        // Inicia la conexión en segundo plano
+        // Inicia la conexión en segundo plano; al terminar, `Client::is_closed` pasa a true
+        // y `OptionalDb::ensure_connected` monta otra
         tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("❌ Error de conexión a QuestDB: {}", e);
+            match connection.await {
+                Ok(()) => warn!("🔌 QuestDB cerró la conexión"),
+                Err(e) => error!("❌ Error de conexión a QuestDB: {}", e),
             }
         });
 
-        let db = Self {
+        // El pool abre sus conexiones según se piden
+        let mut pool_cfg = deadpool_postgres::Config::new();
+        pool_cfg.host = Some(cfg.host.clone());
+        pool_cfg.port = Some(cfg.port);
+        pool_cfg.user = Some(cfg.user.clone());
+        pool_cfg.password = Some(cfg.password.clone());
+        pool_cfg.dbname = Some(cfg.database.clone());
+        pool_cfg.manager = Some(ManagerConfig { recycling_method: RecyclingMethod::Fast });
+        let mut pool = PoolConfig::new(cfg.pool_size.saturating_sub(1).max(1));
+        pool.timeouts.wait = Some(READ_WAIT_TIMEOUT);
+        pool.timeouts.create = Some(CONNECT_TIMEOUT);
+        pool_cfg.pool = Some(pool);
+        let reads = pool_cfg.create_pool(Some(Runtime::Tokio1), NoTls)?;
+
+        let mut db = Self {
             inner: Arc::new(RwLock::new(client)),
+            reads,
+            prepared: Default::default(),
+            schema_version: 0,
         };
 
-        // Crea esquemas si no existen
-        if let Err(e) = db.ensure_schema().await {
-            warn!("⚠️  No se pudo inicializar esquema de QuestDB: {}", e);
+        // Esquema al día (o error si la BD es de un binario más nuevo)
+        if let Err(e) = db.migrate(cfg.allow_newer_schema).await {
+            if e.is::<SchemaTooNew>() {
+                error!("❌ {e}");
+                return Err(e);
+            }
+            warn!("⚠️  No se pudo migrar el esquema de QuestDB: {}", e);
         }
 
         info!("✅ Conexión a QuestDB establecida");
         Ok(db)
     }
 
-    async fn ensure_schema(&self) -> Result<()> {
-        // flight_logs: telemetría cruda por vuelo
-        // logger_configs: auditoría de configs/eventos start/stop
-        let ddl = r#"
-        CREATE TABLE IF NOT EXISTS flight_logs (
-            ts TIMESTAMP,
-            flight_id SYMBOL,
-            payload STRING
-        ) TIMESTAMP(ts) PARTITION BY DAY;
+    /// Aplica las migraciones de `MIGRATIONS` que falten según `schema_migrations`.
+    /// Si la BD viene de un binario más nuevo (versión mayor que las conocidas) falla con
+    /// `SchemaTooNew`, salvo con `allow_newer_schema`, que la deja tal cual
+    async fn migrate(&mut self, allow_newer: bool) -> Result<()> {
+        let inner = self.inner.clone();
+        let client = inner.read().await;
+        client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                ts TIMESTAMP,
+                version INT,
+                name STRING
+            ) TIMESTAMP(ts) PARTITION BY YEAR",
+        ).await?;
+        let stored: i32 = client
+            .query_one("SELECT max(version) FROM schema_migrations", &[])
+            .await?
+            .get::<_, Option<i32>>(0)
+            .unwrap_or(0);
+        self.schema_version = stored;
+        let known = MIGRATIONS.len() as i32;
+        if stored > known {
+            let e = SchemaTooNew { stored, known };
+            if !allow_newer {
+                return Err(e.into());
+            }
+            warn!("⚠️  {e}; se sigue por QUESTDB_ALLOW_NEWER_SCHEMA");
+            return Ok(());
+        }
+        for (version, (name, sql)) in (1..).zip(MIGRATIONS).skip(stored as usize) {
+            client.batch_execute(sql).await
+                .map_err(|e| anyhow::anyhow!("migración {version} ({name}): {e}"))?;
+            client.execute(
+                "INSERT INTO schema_migrations (ts, version, name) VALUES (now(), $1, $2)",
+                &[&version, name],
+            ).await?;
+            self.schema_version = version;
+            info!("🗄️  Migración {version} aplicada: {name}");
+        }
+        Ok(())
+    }
 
-        CREATE TABLE IF NOT EXISTS logger_configs (
-            ts TIMESTAMP,
-            config_json STRING
-        ) TIMESTAMP(ts) PARTITION BY DAY;
-        "#;
+    /// Versión del esquema en la BD (la última migración aplicada)
+    pub fn schema_version(&self) -> i32 {
+        self.schema_version
+    }
+
+    /// Inserta telemetría cruda asociada a un flight_id
+    pub async fn insert_flight_log(&self, flight_id: &str, payload_json: &str) -> Result<()> {
+        let client = self.inner.read().await;
+        let insert = self.prepare(&client, INSERT_LOG_NOW).await?;
+
+        match client.execute(&insert, &[&flight_id, &payload_json]).await {
+            Ok(_) => {
+                trace!("📊 Log de vuelo insertado: {}", flight_id);
+                Ok(())
+            },
+            Err(e) => {
+                error!("❌ Error insertando log de vuelo: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Varias filas en un solo `INSERT ... VALUES (...), (...)`
+    pub async fn insert_flight_logs(&self, rows: &[LogRow]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let client = self.inner.read().await;
+        for chunk in rows.chunks(MAX_INSERT_PARAMS / 3) {
+            let mut sql = String::from("INSERT INTO flight_logs (ts, flight_id, payload) VALUES ");
+            let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(chunk.len() * 3);
+            for (i, row) in chunk.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                sql.push_str(&format!("(${}, ${}, ${})", i * 3 + 1, i * 3 + 2, i * 3 + 3));
+                params.extend([&row.ts as &(dyn ToSql + Sync), &row.flight_id, &row.payload]);
+            }
+            client.execute(&sql, &params).await?;
+        }
+        trace!("📊 {} logs de vuelo insertados", rows.len());
+        drop(client);
+        self.insert_flight_metrics(rows).await
+    }
 
+    /// Solo las filas de `flight_metrics` de `rows` (las que traen valores tipados)
+    pub async fn insert_flight_metrics(&self, rows: &[LogRow]) -> Result<()> {
         let client = self.inner.read().await;
-        client.batch_execute(ddl).await?;
+        // Filas tipadas, por tramos con los mismos campos
+        let typed: Vec<(&LogRow, &TypedValues)> = rows.iter().filter_map(|r| Some((r, r.typed.as_ref()?))).collect();
+        for group in typed.chunk_by(|(_, a), (_, b)| Arc::ptr_eq(&a.fields, &b.fields)) {
+            let fields = &group[0].1.fields;
+            let width = fields.len() + 2;
+            let cols: String = fields.iter().map(|f| format!(", \"{f}\"")).collect();
+            for chunk in group.chunks((MAX_INSERT_PARAMS / width).max(1)) {
+                let mut sql = format!("INSERT INTO flight_metrics (ts, flight_id{cols}) VALUES ");
+                let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(chunk.len() * width);
+                for (i, (row, typed)) in chunk.iter().enumerate() {
+                    let marks: Vec<String> = (1..=width).map(|k| format!("${}", i * width + k)).collect();
+                    sql.push_str(&format!("{}({})", if i > 0 { ", " } else { "" }, marks.join(", ")));
+                    params.push(&row.ts);
+                    params.push(&row.flight_id);
+                    params.extend(typed.values.iter().map(|v| v as &(dyn ToSql + Sync)));
+                }
+                client.execute(&sql, &params).await?;
+            }
+        }
         Ok(())
     }
 
-    /// Inserta telemetría cruda asociada a un flight_id
-    pub async fn insert_flight_log(&self, flight_id: &str, payload_json: &str) -> Result<()> {
+    /// Columnas DOUBLE para `fields` en `flight_metrics` y registro de que el vuelo las usa.
+    /// Devuelve los campos que quedan tipados (los de nombre válido)
+    pub async fn add_metric_columns(&self, flight_id: &str, fields: &[String]) -> Result<Vec<String>> {
+        let fields: Vec<String> = fields.iter().filter(|f| typed_column(f)).cloned().collect();
+        if fields.is_empty() {
+            return Ok(fields);
+        }
         let client = self.inner.read().await;
+        for f in &fields {
+            client.batch_execute(&format!("ALTER TABLE flight_metrics ADD COLUMN IF NOT EXISTS \"{f}\" DOUBLE")).await?;
+        }
+        client
+            .execute(
+                "INSERT INTO flight_metric_fields (ts, flight_id, fields) VALUES (now(), $1, $2)",
+                &[&flight_id, &fields.join(",")],
+            )
+            .await?;
+        Ok(fields)
+    }
 
-        match client.execute(
-            "INSERT INTO flight_logs (ts, flight_id, payload) VALUES (now(), $1, $2)",
-            &[&flight_id, &payload_json],
-        ).await {
+    /// `ts` y payload de lo ya guardado del vuelo en `[from, to]` (reimportado sin duplicados)
+    pub async fn stored_rows(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, String)>> {
+        let client = self.reader().await?;
+        let rows = client
+            .query(
+                "SELECT ts, payload FROM flight_logs WHERE flight_id=$1 AND ts >= $2 AND ts <= $3",
+                &[&flight_id, &from, &to],
+            )
+            .await?;
+        Ok(rows.iter().map(|r| (r.get(0), r.get(1))).collect())
+    }
+
+    /// `ts` de las filas de `flight_metrics` del vuelo en `[from, to]`
+    pub async fn metric_timestamps(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<DateTime<Utc>>> {
+        let client = self.reader().await?;
+        let rows = client
+            .query(
+                "SELECT ts FROM flight_metrics WHERE flight_id=$1 AND ts >= $2 AND ts <= $3",
+                &[&flight_id, &from, &to],
+            )
+            .await?;
+        Ok(rows.iter().map(|r| r.get(0)).collect())
+    }
+
+    /// Campos con columna tipada para este vuelo (vacío para vuelos anteriores a `flight_metrics`)
+    pub async fn typed_fields(&self, flight_id: &str) -> Result<Vec<String>> {
+        let client = self.reader().await?;
+        let row = client
+            .query_opt(
+                "SELECT fields FROM flight_metric_fields WHERE flight_id=$1 ORDER BY ts DESC LIMIT 1",
+                &[&flight_id],
+            )
+            .await?;
+        Ok(row
+            .and_then(|r| r.get::<_, Option<String>>(0))
+            .map(|f| f.split(',').filter(|f| !f.is_empty()).map(str::to_owned).collect())
+            .unwrap_or_default())
+    }
+
+    /// ¿Están todos los `fields` en columnas tipadas de este vuelo?
+    async fn covered(&self, flight_id: &str, fields: &[String]) -> Result<bool> {
+        if fields.is_empty() {
+            return Ok(false);
+        }
+        let typed = self.typed_fields(flight_id).await?;
+        Ok(fields.iter().all(|f| typed.contains(f)))
+    }
+
+    /// Igual que `insert_flight_log` pero con timestamp explícito (p. ej. reloj del ESP32 corregido)
+    pub async fn insert_flight_log_at(&self, flight_id: &str, payload_json: &str, ts: DateTime<Utc>) -> Result<()> {
+        let client = self.inner.read().await;
+        let insert = self.prepare(&client, INSERT_LOG_AT).await?;
+
+        match client.execute(&insert, &[&ts, &flight_id, &payload_json]).await {
             Ok(_) => {
                 trace!("📊 Log de vuelo insertado: {}", flight_id);
                 Ok(())
@@ -124,173 +574,1807 @@ impl QuestDb {
         }
     }
 
-    /// Alternativa: guarda configs dentro de `flight_logs` con flight_id='__config__'
-    pub async fn insert_logger_config_legacy(&self, config_json: &str) -> Result<()> {
-        let q = "INSERT INTO flight_logs (ts, flight_id, payload) VALUES (now(), $1, $2)";
+    /// Registra un comando saliente o un ack entrante en `command_logs`
+    pub async fn insert_command_log(
+        &self,
+        flight_id: Option<&str>,
+        request_id: Option<&str>,
+        direction: &str,
+        payload: &str,
+    ) -> Result<()> {
         let client = self.inner.read().await;
-        client.execute(q, &[&"__config__", &config_json]).await?;
-        Ok(())
+
+        match client.execute(
+            "INSERT INTO command_logs (ts, flight_id, request_id, direction, payload) VALUES (now(), $1, $2, $3, $4)",
+            &[&flight_id, &request_id, &direction, &payload],
+        ).await {
+            Ok(_) => {
+                trace!("📨 Comando registrado ({direction})");
+                Ok(())
+            },
+            Err(e) => {
+                error!("❌ Error registrando comando: {}", e);
+                Err(e.into())
+            }
+        }
     }
 
-    // ---------- NUEVOS MÉTODOS QUE ESPERA mod.rs ----------
+    /// Comando saliente del vuelo en `flight_commands`; el ack va en otra fila (`insert_command_ack`)
+    pub async fn insert_flight_command(&self, flight_id: &str, request_id: Option<&str>, payload: &str) -> Result<()> {
+        let client = self.inner.read().await;
+        client.execute(
+            "INSERT INTO flight_commands (ts, flight_id, request_id, kind, payload) VALUES (now(), $1, $2, $3, $4)",
+            &[&flight_id, &request_id, &commands::kind_of(payload), &payload],
+        ).await?;
+        Ok(())
+    }
 
-    pub async fn list_flights(&self, limit: i64) -> Result<Vec<(String, DateTime<Utc>)>> {
+    /// Ack del comando `request_id`: fila `kind`="ack" que `list_flight_commands` une con la del envío
+    pub async fn insert_command_ack(&self, flight_id: &str, request_id: &str, payload: &str) -> Result<()> {
         let client = self.inner.read().await;
-        // Tomamos el último ts por flight_id para ordenar
+        client.execute(
+            "INSERT INTO flight_commands (ts, flight_id, request_id, kind, payload, ack_ok, ack_ts)
+             VALUES (now(), $1, $2, $3, $4, $5, now())",
+            &[&flight_id, &request_id, &commands::ACK_KIND, &payload, &commands::ack_ok(payload)],
+        ).await?;
+        Ok(())
+    }
+
+    /// Comandos del vuelo en orden de tiempo, cada uno con su ack (el primero que llegó)
+    pub async fn list_flight_commands(&self, flight_id: &str) -> Result<Vec<FlightCommand>> {
+        let client = self.reader().await?;
         let rows = client
             .query(
-                "SELECT flight_id, max(ts) AS last_ts
-                 FROM flight_logs
-                 GROUP BY flight_id
-                 ORDER BY last_ts DESC
-                 LIMIT $1",
-                &[&limit],
+                "SELECT c.ts, c.request_id, c.kind, c.payload, a.ack_ok, a.ack_ts
+                 FROM (SELECT ts, request_id, kind, payload FROM flight_commands
+                       WHERE flight_id=$1 AND kind != 'ack') c
+                 LEFT JOIN (SELECT request_id, first(ack_ok) ack_ok, min(ack_ts) ack_ts FROM flight_commands
+                            WHERE flight_id=$1 AND kind = 'ack') a ON c.request_id = a.request_id
+                 ORDER BY c.ts",
+                &[&flight_id],
             )
             .await?;
-
         Ok(rows
-            .into_iter()
+            .iter()
             .map(|r| {
-                let fid: String = r.get(0);
-                let ts: DateTime<Utc> = r.get(1);
-                (fid, ts)
+                let payload: String = r.get(3);
+                FlightCommand {
+                    ts: r.get::<_, DateTime<Utc>>(0).to_rfc3339(),
+                    request_id: r.get(1),
+                    kind: r.get(2),
+                    payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+                    ack_ok: r.get(4),
+                    ack_ts: r.get::<_, Option<DateTime<Utc>>>(5).map(|t| t.to_rfc3339()),
+                }
             })
             .collect())
     }
 
-    pub async fn fetch_flight_points(
+    /// Último evento `{"event":"<event>",...}` guardado en `logger_configs`
+    pub async fn latest_logger_event(&self, event: &str) -> Result<Option<(DateTime<Utc>, String)>> {
+        let client = self.reader().await?;
+        let pattern = format!("%\"event\":\"{event}\"%");
+        let row = client
+            .query_opt(
+                "SELECT ts, config_json
+                 FROM logger_configs
+                 WHERE config_json LIKE $1
+                 ORDER BY ts DESC
+                 LIMIT 1",
+                &[&pattern],
+            )
+            .await?;
+        Ok(row.map(|r| (r.get(0), r.get(1))))
+    }
+
+    /// Última config aplicada: las filas de `logger_configs` que no son eventos
+    pub async fn latest_logger_config(&self) -> Result<Option<(DateTime<Utc>, String)>> {
+        let client = self.reader().await?;
+        let row = client
+            .query_opt(
+                "SELECT ts, config_json
+                 FROM logger_configs
+                 WHERE config_json NOT LIKE '%\"event\":%'
+                 ORDER BY ts DESC
+                 LIMIT 1",
+                &[],
+            )
+            .await?;
+        Ok(row.map(|r| (r.get(0), r.get(1))))
+    }
+
+    /// Filas de `logger_configs` (configs y eventos), más recientes primero
+    pub async fn list_logger_configs(
         &self,
-        flight_id: &str,
         from: Option<DateTime<Utc>>,
         to: Option<DateTime<Utc>>,
         limit: i64,
-    ) -> Result<Vec<FlightPoint>> {
-        let client = self.inner.read().await;
-    
-        let rows = match (from, to) {
-            (None, None) => {
-                client.query(
-                    "SELECT ts, payload
-                     FROM flight_logs
-                     WHERE flight_id=$1
-                     ORDER BY ts
-                     LIMIT $2",
-                    &[&flight_id, &limit],
-                ).await?
-            }
-            (Some(f), None) => {
-                client.query(
-                    "SELECT ts, payload
-                     FROM flight_logs
-                     WHERE flight_id=$1 AND ts >= $2
-                     ORDER BY ts
-                     LIMIT $3",
-                    &[&flight_id, &f, &limit],
-                ).await?
-            }
-            (None, Some(t)) => {
-                client.query(
-                    "SELECT ts, payload
-                     FROM flight_logs
-                     WHERE flight_id=$1 AND ts <= $2
-                     ORDER BY ts
-                     LIMIT $3",
-                    &[&flight_id, &t, &limit],
-                ).await?
-            }
-            (Some(f), Some(t)) => {
-                client.query(
-                    "SELECT ts, payload
-                     FROM flight_logs
-                     WHERE flight_id=$1 AND ts >= $2 AND ts <= $3
-                     ORDER BY ts
-                     LIMIT $4",
-                    &[&flight_id, &f, &t, &limit],
-                ).await?
-            }
-        };
-
-        let mut out = Vec::with_capacity(rows.len());
-        for r in rows {
-            let ts: DateTime<Utc> = r.get(0);
-            let payload_str: String = r.get(1);
-            let payload = serde_json::from_str::<serde_json::Value>(&payload_str)
-                .unwrap_or_else(|_| serde_json::json!({ "raw": payload_str }));
-            out.push(FlightPoint { ts, payload });
+    ) -> Result<Vec<(DateTime<Utc>, String)>> {
+        let client = self.reader().await?;
+        let mut conds: Vec<String> = Vec::new();
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+        if let Some(from) = &from {
+            params.push(from);
+            conds.push(format!("ts >= ${}", params.len()));
         }
-        Ok(out)
+        if let Some(to) = &to {
+            params.push(to);
+            conds.push(format!("ts <= ${}", params.len()));
+        }
+        let mut sql = String::from("SELECT ts, config_json FROM logger_configs");
+        if !conds.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conds.join(" AND ")));
+        }
+        sql.push_str(&format!(" ORDER BY ts DESC LIMIT {}", limit.max(0)));
+        let rows = client.query(&sql, &params).await?;
+        Ok(rows.iter().map(|r| (r.get(0), r.get(1))).collect())
     }
-}
 
-/// Conexión opcional (lazy) a QuestDB
-#[derive(Clone)]
-pub struct OptionalDb {
-    inner: Arc<Mutex<Option<QuestDb>>>,
-    config: QuestDbConfig,
-}
+    // ---------- NUEVOS MÉTODOS QUE ESPERA mod.rs ----------
 
-impl OptionalDb {
-    /// Constructor público para usar desde main.rs
-    pub fn new(config: QuestDbConfig) -> Self {
-        Self {
-            inner: Arc::new(Mutex::new(None)),
-            config,
+    /// Página de vuelos (más reciente primero) y total de vuelos, sin contar los borrados
+    pub async fn list_flights(&self, filter: &FlightFilter) -> Result<(Vec<FlightRow>, i64)> {
+        let client = self.reader().await?;
+
+        let mut conds: Vec<String> = Vec::new();
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+        if let Some(from) = &filter.from {
+            params.push(from);
+            conds.push(format!("last_ts >= ${}", params.len()));
+        }
+        if let Some(to) = &filter.to {
+            params.push(to);
+            conds.push(format!("first_ts <= ${}", params.len()));
+        }
+        if let Some(min) = &filter.min_points {
+            params.push(min);
+            conds.push(format!("points >= ${}", params.len()));
+        }
+        let tag_like = filter.tag.as_ref().map(|t| format!("%,{t},%"));
+        if !filter.include_archived {
+            conds.push(format!("flight_id NOT IN (SELECT flight_id FROM ({FLIGHT_META_LATEST}) WHERE archived = true)"));
+        }
+        if let Some(like) = &tag_like {
+            params.push(like);
+            conds.push(format!(
+                "flight_id IN (SELECT flight_id FROM ({FLIGHT_META_LATEST}) WHERE concat(',', tags, ',') LIKE ${})",
+                params.len()
+            ));
         }
-    }
 
-    async fn ensure_connected(&self) -> Result<(), String> {
-        let mut db = self.inner.lock().await;
-        if db.is_none() {
-            match QuestDb::connect(self.config.clone()).await {
-                Ok(new_db) => { *db = Some(new_db); Ok(()) }
-                Err(e) => Err(e.to_string()),
-            }
-        } else {
-            Ok(())
+        // El total cuenta con los filtros pero sin el cursor
+        let where_ = if conds.is_empty() { String::new() } else { format!("WHERE {}", conds.join(" AND ")) };
+        let total: i64 = client
+            .query_one(&format!("SELECT count() FROM ({FLIGHTS_GROUPED}) {where_}"), &params)
+            .await?
+            .get(0);
+
+        if let Some(before) = &filter.before {
+            params.push(before);
+            conds.push(format!("last_ts < ${}", params.len()));
         }
+        let where_ = if conds.is_empty() { String::new() } else { format!("WHERE {}", conds.join(" AND ")) };
+        params.push(&filter.limit);
+        let sql = format!(
+            "SELECT flight_id, first_ts, last_ts, points FROM ({FLIGHTS_GROUPED}) {where_}
+             ORDER BY last_ts DESC
+             LIMIT ${}",
+            params.len()
+        );
+        let rows = client.query(&sql, &params).await?;
+
+        let mut meta: HashMap<String, FlightMeta> = client
+            .query(FLIGHT_META_LATEST, &[])
+            .await?
+            .into_iter()
+            .map(|r| (r.get(0), FlightMeta::from_row(r.get(1), r.get(2), r.get(3))))
+            .collect();
+        let items = rows
+            .into_iter()
+            .map(|r| {
+                let flight_id: String = r.get(0);
+                FlightRow {
+                    meta: meta.remove(&flight_id).unwrap_or_default(),
+                    flight_id,
+                    first_ts: r.get(1),
+                    last_ts: r.get(2),
+                    points: r.get(3),
+                }
+            })
+            .collect();
+        Ok((items, total))
     }
 
-    pub async fn insert_flight_log(&self, flight_id: &str, payload: &str) -> Result<(), String> {
-        self.ensure_connected().await?;
-        let db = self.inner.lock().await;
-        db.as_ref()
-            .unwrap()
-            .insert_flight_log(flight_id, payload)
-            .await
-            .map_err(|e| e.to_string())
+    pub async fn is_flight_deleted(&self, flight_id: &str) -> Result<bool> {
+        let client = self.reader().await?;
+        let row = client
+            .query_opt("SELECT flight_id FROM deleted_flights WHERE flight_id=$1 LIMIT 1", &[&flight_id])
+            .await?;
+        Ok(row.is_some())
     }
 
-    pub async fn insert_logger_config(&self, config: &str) -> Result<(), String> {
-        self.ensure_connected().await?;
-        let db = self.inner.lock().await;
-        db.as_ref()
-            .unwrap()
-            .insert_logger_config(config)
-            .await
-            .map_err(|e| e.to_string())
+    /// Rango, nº de puntos, metadatos y config de inicio de un vuelo, sin leer sus puntos;
+    /// `None` si no existe o está borrado
+    pub async fn flight_info(&self, flight_id: &str) -> Result<Option<FlightInfo>> {
+        if self.is_flight_deleted(flight_id).await? {
+            return Ok(None);
+        }
+        let client = self.reader().await?;
+        let row = client
+            .query_one("SELECT min(ts), max(ts), count() FROM flight_logs WHERE flight_id=$1", &[&flight_id])
+            .await?;
+        let points: i64 = row.get(2);
+        // Sin filas, min/max salen null
+        let (Some(first_ts), Some(last_ts)) = (row.get::<_, Option<DateTime<Utc>>>(0), row.get(1)) else {
+            return Ok(None);
+        };
+
+        drop(client);
+        let meta = self.flight_meta(flight_id).await?;
+        let client = self.reader().await?;
+        let summaries: i64 = client
+            .query_one("SELECT count() FROM flight_summaries WHERE flight_id=$1", &[&flight_id])
+            .await?
+            .get(0);
+        let config = client
+            .query_opt(
+                "SELECT ts, config_json
+                 FROM logger_configs
+                 WHERE ts <= $1
+                   AND (config_json NOT LIKE '%\"event\":%' OR config_json LIKE '%\"event\":\"start\"%')
+                 ORDER BY ts DESC
+                 LIMIT 1",
+                &[&first_ts],
+            )
+            .await?
+            .map(|r| (r.get(0), r.get(1)));
+
+        Ok(Some(FlightInfo { first_ts, last_ts, points, meta, has_summary: summaries > 0, config }))
     }
 
-    // Delegados que usa mod.rs
-    pub async fn list_flights(&self, limit: i64) -> Result<Vec<(String, DateTime<Utc>)>, String> {
-        self.ensure_connected().await?;
-        let db = self.inner.lock().await;
-        db.as_ref().unwrap()
-            .list_flights(limit).await
-            .map_err(|e| e.to_string())
+    /// Marca el vuelo como borrado; sus filas dejan de aparecer en listados y series
+    pub async fn mark_flight_deleted(&self, flight_id: &str) -> Result<()> {
+        let client = self.inner.read().await;
+        client
+            .execute("INSERT INTO deleted_flights (ts, flight_id) VALUES (now(), $1)", &[&flight_id])
+            .await?;
+        info!("🗑️  Vuelo {flight_id} borrado");
+        Ok(())
     }
 
-    pub async fn fetch_flight_points(
+    /// Vuelos (no borrados) cuyo último punto es anterior a `cutoff`, del más antiguo al más nuevo
+    pub async fn flights_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<FlightRow>> {
+        let client = self.reader().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT flight_id, first_ts, last_ts, points FROM ({FLIGHTS_GROUPED})
+                     WHERE last_ts < $1 ORDER BY last_ts"
+                ),
+                &[&cutoff],
+            )
+            .await?;
+        let mut meta: HashMap<String, FlightMeta> = client
+            .query(FLIGHT_META_LATEST, &[])
+            .await?
+            .into_iter()
+            .map(|r| (r.get(0), FlightMeta::from_row(r.get(1), r.get(2), r.get(3))))
+            .collect();
+        Ok(rows
+            .into_iter()
+            .map(|r| {
+                let flight_id: String = r.get(0);
+                FlightRow {
+                    meta: meta.remove(&flight_id).unwrap_or_default(),
+                    flight_id,
+                    first_ts: r.get(1),
+                    last_ts: r.get(2),
+                    points: r.get(3),
+                }
+            })
+            .collect())
+    }
+
+    /// Suelta las particiones diarias de `flight_logs` anteriores a `cutoff` que ya solo
+    /// guardan vuelos borrados (el día del primer punto vivo se conserva entero).
+    /// Devuelve cuántas particiones se han ido
+    pub async fn drop_deleted_partitions(&self, cutoff: DateTime<Utc>) -> Result<i64> {
+        let client = self.inner.read().await;
+        let oldest_live: Option<DateTime<Utc>> = client
+            .query_one(
+                "SELECT min(ts) FROM flight_logs WHERE flight_id NOT IN (SELECT flight_id FROM deleted_flights)",
+                &[],
+            )
+            .await?
+            .get(0);
+        let limit = oldest_live.map_or(cutoff, |t| t.min(cutoff));
+        let Some(day) = limit.date_naive().and_hms_opt(0, 0, 0) else { return Ok(0) };
+        let count = "SELECT count() FROM table_partitions('flight_logs')";
+        let before: i64 = client.query_one(count, &[]).await?.get(0);
+        // ALTER no admite parámetros: la fecha va como literal
+        let sql = format!("ALTER TABLE flight_logs DROP PARTITION WHERE ts < '{}'", day.and_utc().to_rfc3339());
+        if let Err(e) = client.batch_execute(&sql).await {
+            // Sin ninguna partición que cumpla QuestDB también da error
+            debug!("Sin particiones que soltar antes de {day}: {e}");
+            return Ok(0);
+        }
+        let after: i64 = client.query_one(count, &[]).await?.get(0);
+        let dropped = before - after;
+        if dropped > 0 {
+            info!("🧹 {dropped} partición(es) de flight_logs anteriores a {day} eliminadas");
+        }
+        Ok(dropped)
+    }
+
+    /// ¿Hay puntos guardados de este vuelo (y no está borrado)?
+    pub async fn flight_exists(&self, flight_id: &str) -> Result<bool> {
+        Ok(self.count_flight_points(flight_id, None, None).await? > 0)
+    }
+
+    /// Puntos del vuelo entre `from` y `to` con un `count()`, sin leerlos (0 si está borrado)
+    pub async fn count_flight_points(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<i64> {
+        if self.is_flight_deleted(flight_id).await? {
+            return Ok(0);
+        }
+        let client = self.reader().await?;
+        let count = client.prepare_cached(
+            "SELECT count() FROM flight_logs WHERE flight_id=$1 AND ts >= $2 AND ts <= $3",
+        ).await?;
+        let from = from.unwrap_or(DateTime::UNIX_EPOCH);
+        let to = to.unwrap_or(OPEN_END);
+        Ok(client.query_one(&count, &[&flight_id, &from, &to]).await?.get(0))
+    }
+
+    /// Última versión de los metadatos del vuelo (vacíos si nunca se fijaron)
+    pub async fn flight_meta(&self, flight_id: &str) -> Result<FlightMeta> {
+        let client = self.reader().await?;
+        Ok(client
+            .query_opt(&format!("SELECT tags, notes, archived FROM ({FLIGHT_META_LATEST}) WHERE flight_id=$1"), &[&flight_id])
+            .await?
+            .map(|r| FlightMeta::from_row(r.get(0), r.get(1), r.get(2)))
+            .unwrap_or_default())
+    }
+
+    /// Nueva versión de etiquetas/notas/archivado del vuelo
+    pub async fn set_flight_meta(&self, flight_id: &str, meta: &FlightMeta) -> Result<()> {
+        let client = self.inner.read().await;
+        client
+            .execute(
+                "INSERT INTO flight_meta (ts, flight_id, tags, notes, archived) VALUES (now(), $1, $2, $3, $4)",
+                &[&flight_id, &meta.tags.join(","), &meta.notes, &meta.archived],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Nº de puntos y último `ts` del vuelo, para saber si un resumen cacheado sigue valiendo
+    pub async fn flight_span(&self, flight_id: &str) -> Result<(i64, Option<DateTime<Utc>>)> {
+        if self.is_flight_deleted(flight_id).await? {
+            return Ok((0, None));
+        }
+        let client = self.reader().await?;
+        let row = client
+            .query_one("SELECT count(), max(ts) FROM flight_logs WHERE flight_id=$1", &[&flight_id])
+            .await?;
+        Ok((row.get(0), row.get(1)))
+    }
+
+    /// Último resumen guardado del vuelo con esos parámetros
+    pub async fn cached_summary(&self, flight_id: &str, params: &str) -> Result<Option<CachedSummary>> {
+        let client = self.reader().await?;
+        let row = client
+            .query_opt(
+                "SELECT points, last_ts, summary
+                 FROM flight_summaries
+                 WHERE flight_id=$1 AND params=$2
+                 ORDER BY ts DESC
+                 LIMIT 1",
+                &[&flight_id, &params],
+            )
+            .await?;
+        Ok(row.map(|r| CachedSummary { points: r.get(0), last_ts: r.get(1), summary: r.get(2) }))
+    }
+
+    pub async fn store_summary(
+        &self,
+        flight_id: &str,
+        params: &str,
+        points: i64,
+        last_ts: DateTime<Utc>,
+        summary: &str,
+    ) -> Result<()> {
+        let client = self.inner.read().await;
+        client
+            .execute(
+                "INSERT INTO flight_summaries (ts, flight_id, params, points, last_ts, summary)
+                 VALUES (now(), $1, $2, $3, $4, $5)",
+                &[&flight_id, &params, &points, &last_ts, &summary],
+            )
+            .await?;
+        debug!("📋 Resumen de {flight_id} guardado ({points} puntos)");
+        Ok(())
+    }
+
+    pub async fn insert_flight_event(&self, flight_id: &str, label: &str, ts: DateTime<Utc>) -> Result<()> {
+        let client = self.inner.read().await;
+        client
+            .execute(
+                "INSERT INTO flight_events (ts, flight_id, label) VALUES ($1, $2, $3)",
+                &[&ts, &flight_id, &label],
+            )
+            .await?;
+        info!("📍 Marca en {flight_id}: {label}");
+        Ok(())
+    }
+
+    /// Nueva versión de un webhook; `events` separados por comas
+    pub async fn store_webhook(&self, id: &str, url: &str, events: &str, removed: bool) -> Result<()> {
+        let client = self.inner.read().await;
+        client
+            .execute(
+                "INSERT INTO webhooks (ts, id, url, events, removed) VALUES (now(), $1, $2, $3, $4)",
+                &[&id, &url, &events, &removed],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Webhooks vigentes: (id, url, events)
+    pub async fn list_webhooks(&self) -> Result<Vec<(String, String, String)>> {
+        let client = self.reader().await?;
+        let rows = client
+            .query(
+                "SELECT id, url, events FROM (
+                     SELECT ts, id, url, events, removed FROM webhooks LATEST ON ts PARTITION BY id
+                 ) WHERE removed = false",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().map(|r| (r.get(0), r.get(1), r.get(2))).collect())
+    }
+
+    /// Marcas del vuelo en orden de `ts`, opcionalmente dentro de [from, to]
+    pub async fn list_flight_events(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<FlightEvent>> {
+        let client = self.reader().await?;
+        let mut sql = String::from("SELECT ts, label FROM flight_events WHERE flight_id=$1");
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&flight_id];
+        if let Some(from) = &from {
+            params.push(from);
+            sql.push_str(&format!(" AND ts >= ${}", params.len()));
+        }
+        if let Some(to) = &to {
+            params.push(to);
+            sql.push_str(&format!(" AND ts <= ${}", params.len()));
+        }
+        sql.push_str(" ORDER BY ts");
+        let rows = client.query(&sql, &params).await?;
+        Ok(rows
+            .iter()
+            .map(|r| FlightEvent {
+                ts: r.get::<_, DateTime<Utc>>(0).to_rfc3339(),
+                flight_id: flight_id.to_string(),
+                label: r.get(1),
+            })
+            .collect())
+    }
+
+    /// Agregados por intervalo con `SAMPLE BY`, calculados en QuestDB
+    pub async fn aggregate_flight(&self, flight_id: &str, req: &AggRequest) -> Result<Vec<AggRow>> {
+        if self.is_flight_deleted(flight_id).await? {
+            return Ok(Vec::new());
+        }
+        let typed = self.covered(flight_id, &req.fields).await?;
+        let client = self.reader().await?;
+        let rows = client.query(&req.sql(typed), &[&flight_id]).await?;
+        Ok(rows.iter().map(|r| AggRow::from_sql(req, r)).collect())
+    }
+
+    /// `sql` preparada en la conexión de escritura, preparándola la primera vez
+    async fn prepare(&self, client: &Client, sql: &'static str) -> Result<Statement> {
+        if let Some(statement) = self.prepared.lock().unwrap().get(sql) {
+            return Ok(statement.clone());
+        }
+        let statement = client.prepare(sql).await?;
+        self.prepared.lock().unwrap().insert(sql, statement.clone());
+        Ok(statement)
+    }
+
+    /// Conexión del pool de lecturas
+    async fn reader(&self) -> Result<Reader> {
+        Ok(Reader { client: Some(self.reads.get().await?), busy: AtomicBool::new(false) })
+    }
+
+    /// La conexión de fondo ha terminado: este cliente ya no sirve
+    pub async fn is_closed(&self) -> bool {
+        self.inner.read().await.is_closed()
+    }
+
+    /// Consulta mínima para comprobar que la conexión responde
+    pub async fn ping(&self) -> Result<()> {
+        self.inner.read().await.simple_query("SELECT 1").await?;
+        Ok(())
+    }
+
+    /// Como `fetch_flight_points` pero solo con `fields`: si el vuelo los tiene todos en
+    /// `flight_metrics` se leen de ahí (sin parsear JSON), con el mismo formato de punto
+    /// (`{"payload":{campo: valor}}`); si no, del payload crudo
+    pub async fn fetch_field_points(
+        &self,
+        flight_id: &str,
+        fields: &[String],
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<FlightPoint>> {
+        if self.is_flight_deleted(flight_id).await? || !self.covered(flight_id, fields).await? {
+            return self.fetch_flight_points(flight_id, from, to, limit).await;
+        }
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&flight_id];
+        if let Some(from) = &from {
+            params.push(from);
+        }
+        if let Some(to) = &to {
+            params.push(to);
+        }
+        params.push(&limit);
+        let sql = field_points_sql(fields, from.is_some(), to.is_some());
+        let client = self.reader().await?;
+        let rows = client.query(&sql, &params).await?;
+        Ok(rows
+            .iter()
+            .map(|r| typed_point(r.get(0), fields, (1..=fields.len()).map(|i| r.get(i))))
+            .collect())
+    }
+
+    pub async fn fetch_flight_points(
         &self,
         flight_id: &str,
         from: Option<DateTime<Utc>>,
         to: Option<DateTime<Utc>>,
         limit: i64,
-    ) -> Result<Vec<FlightPoint>, String> {
+) -> Result<Vec<FlightPoint>> {
+        if self.is_flight_deleted(flight_id).await? {
+            return Ok(Vec::new());
+        }
+        let client = self.reader().await?;
+        // Una sola sentencia preparada: los extremos que faltan se abren al máximo
+        // (sigue siendo un intervalo sobre `ts`, que QuestDB recorre por particiones)
+        let select = client.prepare_cached(
+            "SELECT ts, payload
+             FROM flight_logs
+             WHERE flight_id=$1 AND ts >= $2 AND ts <= $3
+             ORDER BY ts
+             LIMIT $4",
+        ).await?;
+        let from = from.unwrap_or(DateTime::UNIX_EPOCH);
+        let to = to.unwrap_or(OPEN_END);
+        let rows = client.query(&select, &[&flight_id, &from, &to, &limit]).await?;
+
+        let mut out = Vec::with_capacity(rows.len());
+        for r in rows {
+            let ts: DateTime<Utc> = r.get(0);
+            let payload_str: String = r.get(1);
+            let payload = serde_json::from_str::<serde_json::Value>(&payload_str)
+                .unwrap_or_else(|_| serde_json::json!({ "raw": payload_str }));
+            out.push(FlightPoint { ts, payload });
+        }
+        Ok(out)
+    }
+}
+
+/// Reconexión (PG e ILP): espera inicial tras un fallo, que se dobla hasta `RECONNECT_MAX`
+const RECONNECT_FIRST: Duration = Duration::from_millis(500);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// Tope del intento de conexión PG: con el host caído no se cuelgan las escrituras
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Espera máxima por una conexión libre del pool de lecturas
+const READ_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Espera entre intentos de conexión; mientras dura, los intentos fallan al momento
+#[derive(Debug, Default)]
+struct Backoff {
+    delay: Option<Duration>,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    /// Lo que queda de espera, si queda
+    fn remaining(&self) -> Option<Duration> {
+        self.retry_at.and_then(|at| at.checked_duration_since(Instant::now()))
+    }
+
+    fn failed(&mut self) -> Duration {
+        let delay = self.delay.map_or(RECONNECT_FIRST, |d| (d * 2).min(RECONNECT_MAX));
+        *self = Self { delay: Some(delay), retry_at: Some(Instant::now() + delay) };
+        delay
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Cada cuánto el sondeo de fondo intenta reconectar con el circuito abierto
+const BREAKER_PROBE_EVERY: Duration = Duration::from_secs(5);
+
+/// Circuito de la conexión a QuestDB: `closed` conectado (o sin fallos), `open` dentro de
+/// la espera tras un fallo (todo falla al momento), `half_open` pasada la espera: el
+/// próximo intento decide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    backoff: Backoff,
+    last_error: Option<String>,
+    /// Fallos de conexión seguidos
+    failures: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BreakerSnapshot {
+    pub state: BreakerState,
+    pub failures: u64,
+    pub last_error: Option<String>,
+    /// Lo que falta para el próximo intento (solo en `open`)
+    pub retry_in_ms: Option<u64>,
+}
+
+impl Breaker {
+    fn state(&self) -> BreakerState {
+        match (self.backoff.retry_at, self.backoff.remaining()) {
+            (None, _) => BreakerState::Closed,
+            (Some(_), Some(_)) => BreakerState::Open,
+            (Some(_), None) => BreakerState::HalfOpen,
+        }
+    }
+
+    fn failed(&mut self, error: String) -> Duration {
+        self.failures += 1;
+        self.last_error = Some(error);
+        self.backoff.failed()
+    }
+
+    fn closed(&mut self) {
+        if self.failures > 0 {
+            info!("🔌 QuestDB responde de nuevo tras {} intento(s) fallido(s)", self.failures);
+        }
+        self.backoff.reset();
+        self.failures = 0;
+    }
+
+    fn snapshot(&self) -> BreakerSnapshot {
+        BreakerSnapshot {
+            state: self.state(),
+            failures: self.failures,
+            last_error: self.last_error.clone(),
+            retry_in_ms: self.backoff.remaining().map(|d| d.as_millis() as u64),
+        }
+    }
+}
+
+const ILP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `SELECT` de `fetch_field_points` sobre `flight_metrics`: `$1` el vuelo, luego `from` y
+/// `to` si vienen y el `limit` al final
+fn field_points_sql(fields: &[String], from: bool, to: bool) -> String {
+    let mut conds = vec!["flight_id=$1".to_string()];
+    let mut n = 1;
+    if from {
+        n += 1;
+        conds.push(format!("ts >= ${n}"));
+    }
+    if to {
+        n += 1;
+        conds.push(format!("ts <= ${n}"));
+    }
+    let cols: String = fields.iter().map(|f| format!(", \"{f}\"")).collect();
+    format!("SELECT ts{cols} FROM flight_metrics WHERE {} ORDER BY ts LIMIT ${}", conds.join(" AND "), n + 1)
+}
+
+/// Punto con el mismo formato que los del payload crudo (`{"payload":{campo: valor}}`);
+/// los `NULL` (campo ausente en esa muestra) no aparecen
+fn typed_point(ts: DateTime<Utc>, fields: &[String], values: impl Iterator<Item = Option<f64>>) -> FlightPoint {
+    let values: serde_json::Map<String, serde_json::Value> = fields
+        .iter()
+        .zip(values)
+        .filter_map(|(f, v)| v.map(|v| (f.clone(), v.into())))
+        .collect();
+    FlightPoint { ts, payload: serde_json::json!({ "payload": values }) }
+}
+
+/// Escritor de `flight_logs` por InfluxDB Line Protocol sobre TCP, la vía rápida de
+/// QuestDB para ingesta. Una conexión persistente; tras un fallo no se reintenta hasta
+/// pasada la espera, así los lotes fallan al momento en vez de colgarse
+pub struct IlpWriter {
+    addr: String,
+    conn: Mutex<IlpConn>,
+}
+
+#[derive(Default)]
+struct IlpConn {
+    stream: Option<tokio::net::TcpStream>,
+    backoff: Backoff,
+}
+
+impl IlpWriter {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self { addr: format!("{host}:{port}"), conn: Mutex::new(IlpConn::default()) }
+    }
+
+    /// Manda el lote como líneas `flight_logs,flight_id=<sym> payload="..." <ns>`
+    pub async fn write(&self, rows: &[LogRow]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut buf = String::with_capacity(rows.len() * 128);
+        for row in rows {
+            ilp_line(&mut buf, row);
+        }
+        let mut conn = self.conn.lock().await;
+        if conn.stream.is_none() {
+            if let Some(wait) = conn.backoff.remaining() {
+                anyhow::bail!("ILP {} down, retrying in {wait:?}", self.addr);
+            }
+            match tokio::time::timeout(ILP_CONNECT_TIMEOUT, tokio::net::TcpStream::connect(&self.addr)).await {
+                Ok(Ok(stream)) => {
+                    let _ = stream.set_nodelay(true);
+                    info!("✅ ILP conectado a {}", self.addr);
+                    *conn = IlpConn { stream: Some(stream), ..Default::default() };
+                }
+                Ok(Err(e)) => return Err(conn.failed(&self.addr, e.into())),
+                Err(_) => return Err(conn.failed(&self.addr, anyhow::anyhow!("connect timed out"))),
+            }
+        }
+        let stream = conn.stream.as_mut().unwrap();
+        if let Err(e) = stream.write_all(buf.as_bytes()).await {
+            return Err(conn.failed(&self.addr, e.into()));
+        }
+        trace!("📊 {} logs de vuelo enviados por ILP", rows.len());
+        Ok(())
+    }
+}
+
+impl IlpConn {
+    fn failed(&mut self, addr: &str, e: anyhow::Error) -> anyhow::Error {
+        self.stream = None;
+        let delay = self.backoff.failed();
+        warn!("⚠️  ILP {addr}: {e}; reintento en {delay:?}");
+        e
+    }
+}
+
+/// Una línea ILP; el payload va como campo string (se escapan `\`, `"` y saltos de línea)
+fn ilp_line(buf: &mut String, row: &LogRow) {
+    use std::fmt::Write;
+
+    buf.push_str("flight_logs,flight_id=");
+    buf.push_str(&ilp_tag(&row.flight_id));
+    buf.push_str(" payload=\"");
+    for c in row.payload.chars() {
+        match c {
+            '"' | '\\' => { buf.push('\\'); buf.push(c); }
+            '\n' => buf.push_str("\\\n"),
+            _ => buf.push(c),
+        }
+    }
+    let ns = row.ts.timestamp_nanos_opt().unwrap_or_default();
+    let _ = writeln!(buf, "\" {ns}");
+
+    // Campos tipados: ILP crea las columnas que falten
+    let Some(typed) = &row.typed else { return };
+    let values: Vec<String> = typed.fields.iter().zip(&typed.values)
+        .filter_map(|(f, v)| v.filter(|v| v.is_finite()).map(|v| format!("{f}={v:?}")))
+        .collect();
+    if !values.is_empty() {
+        let _ = writeln!(buf, "flight_metrics,flight_id={} {} {ns}", ilp_tag(&row.flight_id), values.join(","));
+    }
+}
+
+fn ilp_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Tiempo máximo del sondeo de salud (conexión + `SELECT 1`)
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Durante este tiempo se reutiliza el último resultado del sondeo
+const PROBE_CACHE: Duration = Duration::from_secs(5);
+
+/// Error de `OptionalDb`: sin conexión o fallo de la propia consulta
+#[derive(Debug)]
+pub enum DbError {
+    Unavailable(String),
+    Query(String),
+    /// La operación superó su tope (`write_timeout_ms`/`read_timeout_ms`)
+    Timeout(String),
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unavailable(e) => write!(f, "QuestDB unavailable: {e}"),
+            Self::Query(e) => write!(f, "QuestDB query failed: {e}"),
+            Self::Timeout(e) => write!(f, "QuestDB timed out: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<anyhow::Error> for DbError {
+    /// Una conexión cerrada a mitad de consulta cuenta como caída, no como fallo de la consulta
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast_ref::<tokio_postgres::Error>() {
+            Some(pg) if pg.is_closed() => Self::Unavailable(e.to_string()),
+            _ => Self::Query(e.to_string()),
+        }
+    }
+}
+
+/// Conexión del pool de lecturas. Si se suelta con una consulta a medias (timeout de
+/// `OptionalDb` o petición HTTP abandonada, que axum suelta al cerrarse la conexión) se
+/// manda un CancelRequest a QuestDB y la conexión se cierra en vez de volver al pool
+struct Reader {
+    client: Option<deadpool_postgres::Object>,
+    busy: AtomicBool,
+}
+
+impl Reader {
+    async fn run<T>(&self, query: impl Future<Output = Result<T, tokio_postgres::Error>>) -> Result<T, tokio_postgres::Error> {
+        self.busy.store(true, Ordering::Relaxed);
+        let res = query.await;
+        self.busy.store(false, Ordering::Relaxed);
+        res
+    }
+
+    fn client(&self) -> &Client {
+        self.client.as_ref().expect("reader ya soltado")
+    }
+
+    async fn query<T: ?Sized + ToStatement>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, tokio_postgres::Error> {
+        self.run(self.client().query(statement, params)).await
+    }
+
+    async fn query_one<T: ?Sized + ToStatement>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, tokio_postgres::Error> {
+        self.run(self.client().query_one(statement, params)).await
+    }
+
+    /// Preparada una vez por conexión del pool (caché de deadpool)
+    async fn prepare_cached(&self, sql: &str) -> Result<Statement, tokio_postgres::Error> {
+        self.run(self.client.as_ref().expect("reader ya soltado").prepare_cached(sql)).await
+    }
+
+    async fn query_opt<T: ?Sized + ToStatement>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, tokio_postgres::Error> {
+        self.run(self.client().query_opt(statement, params)).await
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        if !self.busy.load(Ordering::Relaxed) {
+            return;
+        }
+        let (Some(client), Ok(rt)) = (self.client.take(), tokio::runtime::Handle::try_current()) else { return };
+        let client = deadpool_postgres::Object::take(client);
+        rt.spawn(async move {
+            match client.cancel_token().cancel_query(NoTls).await {
+                Ok(()) => debug!("Consulta de QuestDB abandonada: cancelada"),
+                Err(e) => debug!("No se pudo cancelar la consulta abandonada: {e}"),
+            }
+            drop(client);
+        });
+    }
+}
+
+/// Conexión opcional (lazy) a QuestDB
+#[derive(Clone)]
+pub struct OptionalDb {
+    inner: Arc<Mutex<Option<QuestDb>>>,
+    config: QuestDbConfig,
+    /// Último sondeo de `probe`: (cuándo, ok)
+    last_probe: Arc<std::sync::Mutex<Option<(Instant, bool)>>>,
+    /// Escrituras correctas/fallidas para `GET /api/stats`
+    pub metrics: Arc<DbMetrics>,
+    /// Con `ilp_port` los lotes de telemetría van por ILP; las lecturas siguen por PG
+    ilp: Option<Arc<IlpWriter>>,
+    /// Circuito de reconexión: tras un fallo, espera creciente sin intentarlo
+    breaker: Arc<std::sync::Mutex<Breaker>>,
+    /// Backend embebido (`ARTHERIS_STORAGE=sqlite:...`): lo que está en `FlightStore` va ahí
+    /// y el resto da `Unavailable` sin intentar conectar a QuestDB
+    embedded: Option<Arc<dyn FlightStore>>,
+}
+
+impl OptionalDb {
+    /// Constructor público para usar desde main.rs
+    pub fn new(config: QuestDbConfig) -> Self {
+        let ilp = config.ilp_port.map(|port| Arc::new(IlpWriter::new(&config.host, port)));
+        Self {
+            inner: Arc::new(Mutex::new(None)),
+            config,
+            last_probe: Default::default(),
+            metrics: Default::default(),
+            ilp,
+            breaker: Default::default(),
+            embedded: None,
+        }
+    }
+
+    /// Con un backend embebido en vez de QuestDB
+    #[cfg(any(test, feature = "sqlite"))]
+    pub fn with_store(config: QuestDbConfig, store: Arc<dyn FlightStore>) -> Self {
+        Self { embedded: Some(store), ilp: None, ..Self::new(config) }
+    }
+
+    /// Backend de las operaciones de `FlightStore`: el embebido o la conexión a QuestDB
+    async fn store(&self) -> Result<Arc<dyn FlightStore>, DbError> {
+        match &self.embedded {
+            Some(store) => Ok(store.clone()),
+            None => Ok(Arc::new(self.db().await?)),
+        }
+    }
+
+    /// Sondeo de salud con tope de tiempo y caché: con la BD caída, muchas consultas
+    /// a `/api/health` no disparan una reconexión cada una
+    pub async fn probe(&self) -> bool {
+        if self.embedded.is_some() {
+            return true;
+        }
+        if let Some((at, ok)) = *self.last_probe.lock().unwrap()
+            && at.elapsed() < PROBE_CACHE
+        {
+            return ok;
+        }
+        let check = async {
+            self.db().await?.ping().await.map_err(DbError::from)
+        };
+        let ok = match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                debug!("Sondeo de QuestDB fallido: {e}");
+                false
+            }
+            Err(_) => {
+                debug!("Sondeo de QuestDB sin respuesta en {PROBE_TIMEOUT:?}");
+                false
+            }
+        };
+        *self.last_probe.lock().unwrap() = Some((Instant::now(), ok));
+        ok
+    }
+
+    /// ¿Hay una conexión establecida y viva? Sin esperar: si está en uso se da por conectada
+    pub async fn is_connected(&self) -> bool {
+        if self.embedded.is_some() {
+            return true;
+        }
+        let Ok(db) = self.inner.try_lock() else { return true };
+        match db.as_ref() {
+            Some(db) => !db.is_closed().await,
+            None => false,
+        }
+    }
+
+    /// Corta `op` a los `ms` milisegundos con `DbError::Timeout`. Soltar el futuro cancela
+    /// la consulta si iba por el pool de lecturas (ver `Reader`)
+    async fn timed<T>(ms: u64, op: impl Future<Output = Result<T, DbError>>) -> Result<T, DbError> {
+        match tokio::time::timeout(Duration::from_millis(ms), op).await {
+            Ok(res) => res,
+            Err(_) => Err(DbError::Timeout(format!("no answer in {ms} ms"))),
+        }
+    }
+
+    /// Lectura con el tope `read_timeout_ms`
+    async fn read<T>(&self, op: impl Future<Output = Result<T, DbError>>) -> Result<T, DbError> {
+        Self::timed(self.config.read_timeout_ms, op).await
+    }
+
+    /// Versión del esquema de la conexión actual (`None` sin conexión)
+    pub async fn schema_version(&self) -> Option<i32> {
+        self.inner.lock().await.as_ref().map(QuestDb::schema_version)
+    }
+
+    /// Cuenta el resultado de una escritura en `metrics`; con el tope `write_timeout_ms`
+    async fn counted<T>(&self, write: impl Future<Output = Result<T, DbError>>) -> Result<T, DbError> {
+        let res = Self::timed(self.config.write_timeout_ms, write).await;
+        self.metrics.record(&res);
+        res
+    }
+
+    /// Cliente conectado. Es un clon (comparte conexión y pool): el candado solo se
+    /// toma para obtenerlo, así las consultas en paralelo no se esperan entre sí
+    async fn db(&self) -> Result<QuestDb, DbError> {
+        if self.embedded.is_some() {
+            return Err(DbError::Unavailable("not supported by the embedded storage backend".into()));
+        }
         self.ensure_connected().await?;
-        let db = self.inner.lock().await;
-        db.as_ref().unwrap()
-            .fetch_flight_points(flight_id, from, to, limit)
+        self.inner.lock().await.clone().ok_or_else(|| DbError::Unavailable("not connected".into()))
+    }
+
+    /// Conecta si no hay cliente o si el que hay se quedó sin conexión (QuestDB reiniciado).
+    /// Con el circuito abierto se devuelve `Unavailable` sin intentarlo: las escrituras
+    /// fallan al momento (al spool) en vez de esperar cada una a su timeout de conexión
+    async fn ensure_connected(&self) -> Result<(), DbError> {
+        self.connect(false).await
+    }
+
+    /// `force` se salta la espera del circuito (lo usa el sondeo de fondo)
+    async fn connect(&self, force: bool) -> Result<(), DbError> {
+        let mut db = self.inner.lock().await;
+        if let Some(current) = db.as_ref() {
+            if !current.is_closed().await {
+                return Ok(());
+            }
+            warn!("🔌 Conexión a QuestDB perdida; se reconectará");
+            *db = None;
+        }
+        if !force && let Some(wait) = self.breaker.lock().unwrap().backoff.remaining() {
+            return Err(DbError::Unavailable(format!("circuit open, next attempt in {} ms", wait.as_millis())));
+        }
+        match QuestDb::connect(self.config.clone()).await {
+            Ok(new_db) => {
+                self.breaker.lock().unwrap().closed();
+                *db = Some(new_db);
+                Ok(())
+            }
+            Err(e) => {
+                let delay = self.breaker.lock().unwrap().failed(e.to_string());
+                debug!("Próximo intento de conexión a QuestDB en {delay:?}");
+                Err(DbError::Unavailable(e.to_string()))
+            }
+        }
+    }
+
+    pub fn breaker(&self) -> BreakerSnapshot {
+        self.breaker.lock().unwrap().snapshot()
+    }
+
+    /// Sondeo de fondo: con el circuito abierto intenta conectar y un `SELECT 1` cada
+    /// `BREAKER_PROBE_EVERY`; si responde, el circuito se cierra sin esperar a que acabe la espera
+    pub async fn run_breaker_probe(self) {
+        let mut tick = tokio::time::interval(BREAKER_PROBE_EVERY);
+        loop {
+            tick.tick().await;
+            if self.breaker.lock().unwrap().state() == BreakerState::Closed {
+                continue;
+            }
+            let check = async {
+                self.connect(true).await?;
+                self.db().await?.ping().await.map_err(DbError::from)
+            };
+            match tokio::time::timeout(CONNECT_TIMEOUT + PROBE_TIMEOUT, check).await {
+                Ok(Ok(())) => *self.last_probe.lock().unwrap() = Some((Instant::now(), true)),
+                Ok(Err(e)) => debug!("Sondeo del circuito de QuestDB fallido: {e}"),
+                Err(_) => debug!("Sondeo del circuito de QuestDB sin respuesta"),
+            }
+        }
+    }
+
+    /// Con `ts` la fila se guarda con ese instante (el de la muestra); sin él, `now()`
+    pub async fn insert_flight_log(&self, flight_id: &str, payload: &str, ts: Option<DateTime<Utc>>) -> Result<(), DbError> {
+        self.counted(async {
+            self.store().await?
+                .insert_flight_log(flight_id, payload, ts)
+                .await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn insert_flight_log_at(&self, flight_id: &str, payload: &str, ts: DateTime<Utc>) -> Result<(), DbError> {
+        self.counted(async {
+            self.store().await?
+                .insert_flight_log(flight_id, payload, Some(ts))
+                .await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    /// Un lote del buffer de escritura; cuenta como tantas inserciones como filas
+    pub async fn insert_flight_logs(&self, rows: &[LogRow]) -> Result<(), DbError> {
+        if let Some(ilp) = &self.ilp {
+            let res = ilp.write(rows).await.map_err(|e| DbError::Unavailable(e.to_string()));
+            self.metrics.record_rows(rows.len() as u64, &res);
+            return res;
+        }
+        let res = Self::timed(self.config.write_timeout_ms, async {
+            self.store().await?
+                .insert_flight_logs(rows).await
+                .map_err(DbError::from)
+        }).await;
+        self.metrics.record_rows(rows.len() as u64, &res);
+        res
+    }
+
+    pub async fn insert_logger_config(&self, config: &str) -> Result<(), DbError> {
+        self.counted(async {
+            self.store().await?
+                .insert_logger_config(config)
+                .await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn insert_command_log(
+        &self,
+        flight_id: Option<&str>,
+        request_id: Option<&str>,
+        direction: &str,
+        payload: &str,
+    ) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .insert_command_log(flight_id, request_id, direction, payload)
+                .await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn insert_flight_command(&self, flight_id: &str, request_id: Option<&str>, payload: &str) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .insert_flight_command(flight_id, request_id, payload).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn insert_command_ack(&self, flight_id: &str, request_id: &str, payload: &str) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .insert_command_ack(flight_id, request_id, payload).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn list_flight_commands(&self, flight_id: &str) -> Result<Vec<FlightCommand>, DbError> {
+        self.read(async {
+            self.db().await?
+                .list_flight_commands(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn latest_logger_config(&self) -> Result<Option<(DateTime<Utc>, String)>, DbError> {
+        self.read(async {
+            self.store().await?
+                .latest_logger_config().await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn latest_logger_event(&self, event: &str) -> Result<Option<(DateTime<Utc>, String)>, DbError> {
+        self.read(async {
+            self.store().await?
+                .latest_logger_event(event).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn list_logger_configs(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<(DateTime<Utc>, String)>, DbError> {
+        self.read(async {
+            self.db().await?
+                .list_logger_configs(from, to, limit).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn flight_info(&self, flight_id: &str) -> Result<Option<FlightInfo>, DbError> {
+        self.read(async {
+            self.store().await?
+                .flight_info(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn mark_flight_deleted(&self, flight_id: &str) -> Result<(), DbError> {
+        self.counted(async {
+            self.store().await?
+                .mark_flight_deleted(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn flights_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<FlightRow>, DbError> {
+        self.read(async {
+            self.db().await?
+                .flights_before(cutoff).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn drop_deleted_partitions(&self, cutoff: DateTime<Utc>) -> Result<i64, DbError> {
+        self.read(async {
+            self.db().await?
+                .drop_deleted_partitions(cutoff).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn flight_exists(&self, flight_id: &str) -> Result<bool, DbError> {
+        self.read(async {
+            self.store().await?
+                .flight_exists(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn count_flight_points(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<i64, DbError> {
+        self.read(async {
+            self.store().await?
+                .count_flight_points(flight_id, from, to).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn flight_meta(&self, flight_id: &str) -> Result<FlightMeta, DbError> {
+        self.read(async {
+            self.db().await?
+                .flight_meta(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn set_flight_meta(&self, flight_id: &str, meta: &FlightMeta) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .set_flight_meta(flight_id, meta).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn flight_span(&self, flight_id: &str) -> Result<(i64, Option<DateTime<Utc>>), DbError> {
+        self.read(async {
+            self.store().await?
+                .flight_span(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn cached_summary(&self, flight_id: &str, params: &str) -> Result<Option<CachedSummary>, DbError> {
+        self.read(async {
+            self.db().await?
+                .cached_summary(flight_id, params).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn store_summary(
+        &self,
+        flight_id: &str,
+        params: &str,
+        points: i64,
+        last_ts: DateTime<Utc>,
+        summary: &str,
+    ) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .store_summary(flight_id, params, points, last_ts, summary).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn insert_flight_event(&self, flight_id: &str, label: &str, ts: DateTime<Utc>) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .insert_flight_event(flight_id, label, ts).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn store_webhook(&self, id: &str, url: &str, events: &str, removed: bool) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .store_webhook(id, url, events, removed).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn list_webhooks(&self) -> Result<Vec<(String, String, String)>, DbError> {
+        self.read(async {
+            self.db().await?
+                .list_webhooks().await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn list_flight_events(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<FlightEvent>, DbError> {
+        self.read(async {
+            self.db().await?
+                .list_flight_events(flight_id, from, to).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn stored_rows(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, String)>, DbError> {
+        self.read(async {
+            self.store().await?
+                .stored_rows(flight_id, from, to).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn metric_timestamps(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<DateTime<Utc>>, DbError> {
+        if self.embedded.is_some() {
+            return Ok(Vec::new());
+        }
+        self.read(async {
+            self.db().await?
+                .metric_timestamps(flight_id, from, to).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    /// Solo `flight_metrics`, siempre por PG (el reimportado; la vía normal es `insert_flight_logs`)
+    pub async fn insert_flight_metrics(&self, rows: &[LogRow]) -> Result<(), DbError> {
+        self.counted(async {
+            self.db().await?
+                .insert_flight_metrics(rows).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn add_metric_columns(&self, flight_id: &str, fields: &[String]) -> Result<Vec<String>, DbError> {
+        self.counted(async {
+            self.db().await?
+                .add_metric_columns(flight_id, fields).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    /// Sin `flight_metrics` en el backend embebido: ningún vuelo tiene campos tipados
+    pub async fn typed_fields(&self, flight_id: &str) -> Result<Vec<String>, DbError> {
+        if self.embedded.is_some() {
+            return Ok(Vec::new());
+        }
+        self.read(async {
+            self.db().await?
+                .typed_fields(flight_id).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn fetch_field_points(
+        &self,
+        flight_id: &str,
+        fields: &[String],
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<FlightPoint>, DbError> {
+        // Sin `flight_metrics` en el embebido: siempre del payload crudo
+        if self.embedded.is_some() {
+            return self.fetch_flight_points(flight_id, from, to, limit).await;
+        }
+        self.read(async {
+            self.db().await?
+                .fetch_field_points(flight_id, fields, from, to, limit).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn aggregate_flight(&self, flight_id: &str, req: &AggRequest) -> Result<Vec<AggRow>, DbError> {
+        self.read(async {
+            self.db().await?
+                .aggregate_flight(flight_id, req).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    // Delegados que usa mod.rs
+    pub async fn list_flights(&self, filter: &FlightFilter) -> Result<(Vec<FlightRow>, i64), DbError> {
+        self.read(async {
+            self.store().await?
+                .list_flights(filter).await
+                .map_err(DbError::from)
+        }).await
+    }
+
+    pub async fn fetch_flight_points(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<FlightPoint>, DbError> {
+        self.read(async {
+            self.store().await?
+                .fetch_flight_points(flight_id, from, to, limit)
+                .await
+                .map_err(DbError::from)
+        }).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[test]
+    fn field_points_sql_numbers_the_optional_bounds() {
+        let fields = ["AngleRoll".to_string(), "AnglePitch".to_string()];
+        assert_eq!(
+            field_points_sql(&fields, false, false),
+            "SELECT ts, \"AngleRoll\", \"AnglePitch\" FROM flight_metrics WHERE flight_id=$1 ORDER BY ts LIMIT $2"
+        );
+        assert_eq!(
+            field_points_sql(&fields[..1], true, true),
+            "SELECT ts, \"AngleRoll\" FROM flight_metrics WHERE flight_id=$1 AND ts >= $2 AND ts <= $3 ORDER BY ts LIMIT $4"
+        );
+        assert!(field_points_sql(&fields[..1], false, true).ends_with("WHERE flight_id=$1 AND ts <= $2 ORDER BY ts LIMIT $3"));
+    }
+
+    #[test]
+    fn typed_point_has_the_raw_payload_shape() {
+        let fields = ["a".to_string(), "b".to_string()];
+        let ts = DateTime::from_timestamp(1, 0).unwrap();
+        let p = typed_point(ts, &fields, [Some(1.5), None].into_iter());
+        assert_eq!(p.ts, ts);
+        assert_eq!(p.payload, serde_json::json!({ "payload": { "a": 1.5 } }));
+    }
+
+    /// QuestDB real para los tests `#[ignore]`: `QUESTDB_HOST`/`QUESTDB_PORT` (localhost:8812)
+    pub(crate) async fn live_db() -> QuestDb {
+        QuestDb::connect(live_config()).await.expect("QuestDB de pruebas no disponible")
+    }
+
+    pub(crate) fn live_config() -> QuestDbConfig {
+        QuestDbConfig {
+            host: std::env::var("QUESTDB_HOST").unwrap_or_else(|_| "localhost".into()),
+            port: std::env::var("QUESTDB_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8812),
+            user: "admin".into(),
+            password: "quest".into(),
+            database: "qdb".into(),
+            pool_size: 2,
+            ilp_port: None,
+            write_timeout_ms: default_write_timeout_ms(),
+            read_timeout_ms: default_read_timeout_ms(),
+            allow_newer_schema: true,
+        }
+    }
+
+    /// Servidor Postgres falso, con lo justo del protocolo para `QuestDb::connect`, `prepare`,
+    /// `execute` y `query`: las sentencias se aceptan sin ejecutarse, cada `SELECT` da una
+    /// fila con un `int4` y un `Parse` con `long_sequence` se queda sin respuesta
+    pub(crate) struct FakePg {
+        pub port: u16,
+        connections: Arc<AtomicUsize>,
+        statements: Arc<std::sync::Mutex<Vec<String>>>,
+        accept: tokio::task::JoinHandle<()>,
+    }
+
+    impl FakePg {
+        pub(crate) async fn start() -> Self {
+            Self::start_on(0).await
+        }
+
+        /// En `port` (0 = uno libre): con el de uno ya parado es un reinicio
+        pub(crate) async fn start_on(port: u16) -> Self {
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let connections = Arc::new(AtomicUsize::new(0));
+            let statements = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let accept = tokio::spawn({
+                let (connections, statements) = (connections.clone(), statements.clone());
+                async move {
+                    // Las sesiones viven en el `JoinSet`: abortar esta tarea las corta todas
+                    let mut sessions = tokio::task::JoinSet::new();
+                    while let Ok((stream, _)) = listener.accept().await {
+                        connections.fetch_add(1, Ordering::SeqCst);
+                        sessions.spawn(fake_pg_session(stream, statements.clone()));
+                    }
+                }
+            });
+            Self { port, connections, statements, accept }
+        }
+
+        pub(crate) fn config(&self) -> QuestDbConfig {
+            QuestDbConfig { host: "127.0.0.1".into(), port: self.port, ..live_config() }
+        }
+
+        /// Conexiones aceptadas (las de cancelación incluidas)
+        pub(crate) fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+
+        /// SQL recibido (`Query` y `Parse`), en orden
+        pub(crate) fn statements(&self) -> Vec<String> {
+            self.statements.lock().unwrap().clone()
+        }
+
+        /// Cierra el puerto y corta las conexiones abiertas, como un QuestDB que se cae
+        pub(crate) async fn stop(self) {
+            self.accept.abort();
+            let _ = self.accept.await;
+        }
+    }
+
+    async fn fake_pg_session(mut stream: tokio::net::TcpStream, statements: Arc<std::sync::Mutex<Vec<String>>>) -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Arranque: SSL/GSSENC se rechazan con 'N'; una petición de cancelación no pide respuesta
+        loop {
+            let len = stream.read_i32().await? as usize;
+            let mut body = vec![0; len.saturating_sub(4)];
+            stream.read_exact(&mut body).await?;
+            match i32::from_be_bytes(body[..4].try_into().unwrap()) {
+                80877103 | 80877104 => stream.write_all(b"N").await?,
+                80877102 => return Ok(()),
+                _ => break,
+            }
+        }
+        let mut out = Vec::new();
+        pg_message(&mut out, b'R', &0i32.to_be_bytes());
+        pg_message(&mut out, b'K', &[0; 8]);
+        pg_message(&mut out, b'Z', b"I");
+        stream.write_all(&out).await?;
+
+        let mut prepared: HashMap<String, String> = HashMap::new();
+        let mut portals: HashMap<String, String> = HashMap::new();
+        loop {
+            let tag = stream.read_u8().await?;
+            let len = stream.read_i32().await? as usize;
+            let mut body = vec![0; len.saturating_sub(4)];
+            stream.read_exact(&mut body).await?;
+            let mut at = 0;
+            let mut out = Vec::new();
+            match tag {
+                b'Q' => {
+                    statements.lock().unwrap().push(cstr(&body, &mut at));
+                    pg_message(&mut out, b'C', b"SELECT 0\0");
+                    pg_message(&mut out, b'Z', b"I");
+                }
+                b'P' => {
+                    let name = cstr(&body, &mut at);
+                    let sql = cstr(&body, &mut at);
+                    statements.lock().unwrap().push(sql.clone());
+                    if sql.contains("long_sequence") {
+                        std::future::pending::<()>().await;
+                    }
+                    prepared.insert(name, sql);
+                    pg_message(&mut out, b'1', &[]);
+                }
+                b'D' => {
+                    at = 1;
+                    let sql = prepared.get(&cstr(&body, &mut at)).cloned().unwrap_or_default();
+                    // Todos los parámetros como `text` (OID 25)
+                    let params = sql.split('$').skip(1)
+                        .filter_map(|s| s.split(|c: char| !c.is_ascii_digit()).next()?.parse::<i16>().ok())
+                        .max()
+                        .unwrap_or(0);
+                    let mut desc = params.to_be_bytes().to_vec();
+                    for _ in 0..params {
+                        desc.extend(25i32.to_be_bytes());
+                    }
+                    pg_message(&mut out, b't', &desc);
+                    if is_select(&sql) {
+                        // Una columna `int4` (OID 23)
+                        let mut row = 1i16.to_be_bytes().to_vec();
+                        row.extend(b"x\0");
+                        row.extend(0i32.to_be_bytes());
+                        row.extend(0i16.to_be_bytes());
+                        row.extend(23i32.to_be_bytes());
+                        row.extend(4i16.to_be_bytes());
+                        row.extend((-1i32).to_be_bytes());
+                        row.extend(0i16.to_be_bytes());
+                        pg_message(&mut out, b'T', &row);
+                    } else {
+                        pg_message(&mut out, b'n', &[]);
+                    }
+                }
+                b'B' => {
+                    let portal = cstr(&body, &mut at);
+                    let statement = cstr(&body, &mut at);
+                    portals.insert(portal, prepared.get(&statement).cloned().unwrap_or_default());
+                    pg_message(&mut out, b'2', &[]);
+                }
+                b'E' => {
+                    let sql = portals.get(&cstr(&body, &mut at)).cloned().unwrap_or_default();
+                    if is_select(&sql) {
+                        // `max(version)` al día: `migrate` no tiene nada que aplicar
+                        let value = if sql.contains("max(version)") { MIGRATIONS.len() as i32 } else { 1 };
+                        let mut row = 1i16.to_be_bytes().to_vec();
+                        row.extend(4i32.to_be_bytes());
+                        row.extend(value.to_be_bytes());
+                        pg_message(&mut out, b'D', &row);
+                        pg_message(&mut out, b'C', b"SELECT 1\0");
+                    } else {
+                        pg_message(&mut out, b'C', b"INSERT 0 1\0");
+                    }
+                }
+                b'C' => pg_message(&mut out, b'3', &[]),
+                b'S' => pg_message(&mut out, b'Z', b"I"),
+                b'X' => return Ok(()),
+                _ => {}
+            }
+            stream.write_all(&out).await?;
+        }
+    }
+
+    fn pg_message(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+        out.push(tag);
+        out.extend((body.len() as i32 + 4).to_be_bytes());
+        out.extend(body);
+    }
+
+    /// Cadena terminada en 0 desde `at`, que queda tras el terminador
+    fn cstr(body: &[u8], at: &mut usize) -> String {
+        let end = body[*at..].iter().position(|b| *b == 0).map_or(body.len(), |n| *at + n);
+        let s = String::from_utf8_lossy(&body[*at..end]).into_owned();
+        *at = (end + 1).min(body.len());
+        s
+    }
+
+    fn is_select(sql: &str) -> bool {
+        sql.trim_start().get(..6).is_some_and(|s| s.eq_ignore_ascii_case("SELECT"))
+    }
+
+    /// Las tablas WAL se ven con algo de retraso tras el `INSERT`
+    async fn eventually<T, F: Future<Output = Result<T>>>(mut f: impl FnMut() -> F, ok: impl Fn(&T) -> bool) -> T {
+        for _ in 0..50 {
+            if let Ok(v) = f().await
+                && ok(&v)
+            {
+                return v;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("QuestDB no llegó al estado esperado");
+    }
+
+    #[tokio::test]
+    #[ignore = "necesita QuestDB (QUESTDB_HOST/QUESTDB_PORT)"]
+    async fn fetch_field_points_reads_typed_columns() {
+        let db = live_db().await;
+        let fid = format!("test_typed_{}", uuid::Uuid::new_v4().simple());
+        let fields = db.add_metric_columns(&fid, &["AngleRoll".to_string()]).await.unwrap();
+        assert_eq!(fields, ["AngleRoll"]);
+        let fields: Arc<[String]> = fields.into();
+        let rows: Vec<LogRow> = (0..3)
+            .map(|i| LogRow {
+                ts: DateTime::from_timestamp(1_700_000_000 + i, 0).unwrap(),
+                flight_id: fid.clone(),
+                payload: serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": i as f64, "other": i } }).to_string(),
+                typed: Some(TypedValues { fields: fields.clone(), values: vec![Some(i as f64)] }),
+            })
+            .collect();
+        db.insert_flight_logs(&rows).await.unwrap();
+
+        let roll = ["AngleRoll".to_string()];
+        let typed = eventually(|| db.fetch_field_points(&fid, &roll, None, None, 100), |p| p.len() == 3).await;
+        // De `flight_metrics`: solo el campo pedido, sin el resto del payload
+        assert_eq!(typed[2].payload, serde_json::json!({ "payload": { "AngleRoll": 2.0 } }));
+        assert_eq!(typed[0].ts, rows[0].ts);
+
+        // Campo sin columna: del payload crudo
+        let raw = db.fetch_field_points(&fid, &["other".to_string()], None, None, 100).await.unwrap();
+        assert_eq!(raw.len(), 3);
+        assert_eq!(raw[1].payload["payload"]["other"], 1);
+
+        let bounded = db
+            .fetch_field_points(&fid, &roll, Some(rows[1].ts), Some(rows[1].ts), 100)
             .await
-            .map_err(|e| e.to_string())
+            .unwrap();
+        assert_eq!(bounded.len(), 1);
+        db.mark_flight_deleted(&fid).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_read_does_not_delay_inserts() {
+        let fake = FakePg::start().await;
+        let db = Arc::new(QuestDb::connect(fake.config()).await.unwrap());
+        // El servidor falso no contesta nunca a esta lectura: ocupa su conexión del pool
+        let slow = tokio::spawn({
+            let db = db.clone();
+            async move {
+                let reader = db.reader().await.unwrap();
+                reader.query("SELECT sum(rnd_double()) FROM long_sequence(2000000000)", &[]).await.map(|_| ())
+            }
+        });
+        for _ in 0..50 {
+            if fake.statements().iter().any(|sql| sql.contains("long_sequence")) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(fake.statements().iter().any(|sql| sql.contains("long_sequence")), "la lectura no llegó al servidor");
+
+        let started = Instant::now();
+        for i in 0..10 {
+            db.insert_flight_log("f", &format!(r#"{{"seq":{i}}}"#)).await.unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+        assert!(!slow.is_finished(), "la lectura lenta terminó antes de tiempo");
+        // Escritura y lectura, cada una en su conexión
+        assert_eq!(fake.connections(), 2);
+        assert_eq!(fake.statements().iter().filter(|sql| sql.as_str() == INSERT_LOG_NOW).count(), 1);
+
+        slow.abort();
+        fake.stop().await;
+    }
+
+    /// Vuelo de 5 puntos (t = 0..4 s) junto a otro de 2 que no debe contarse
+    fn fixture_rows(fid: &str) -> Vec<LogRow> {
+        let row = |flight_id: &str, secs: i64| LogRow {
+            ts: DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+            flight_id: flight_id.to_string(),
+            payload: serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": secs } }).to_string(),
+            typed: None,
+        };
+        let other = format!("{fid}_other");
+        (0..5).map(|s| row(fid, s)).chain((0..2).map(|s| row(&other, s))).collect()
+    }
+
+    async fn counts_match_the_fixture(db: &OptionalDb, fid: &str) {
+        let at = |secs: i64| DateTime::from_timestamp(1_700_000_000 + secs, 0);
+        assert_eq!(db.count_flight_points(fid, None, None).await.unwrap(), 5);
+        // Límites inclusivos, como en `fetch_flight_points`
+        assert_eq!(db.count_flight_points(fid, at(1), at(3)).await.unwrap(), 3);
+        assert_eq!(db.count_flight_points(fid, at(3), None).await.unwrap(), 2);
+        assert_eq!(db.count_flight_points(fid, None, at(-1)).await.unwrap(), 0);
+        assert!(db.flight_exists(fid).await.unwrap());
+        assert!(!db.flight_exists(&format!("{fid}_missing")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn point_counts_on_the_embedded_store() {
+        let store = crate::ws_server::sqlite::SqliteStore::open(":memory:").unwrap();
+        let db = OptionalDb::with_store(crate::ws_server::server::test_db_config(), Arc::new(store));
+        db.insert_flight_logs(&fixture_rows("fixture")).await.unwrap();
+        counts_match_the_fixture(&db, "fixture").await;
+
+        db.mark_flight_deleted("fixture").await.unwrap();
+        assert_eq!(db.count_flight_points("fixture", None, None).await.unwrap(), 0);
+        assert!(!db.flight_exists("fixture").await.unwrap());
+        assert_eq!(db.count_flight_points("fixture_other", None, None).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "necesita QuestDB (QUESTDB_HOST/QUESTDB_PORT)"]
+    async fn point_counts_on_questdb() {
+        let live = live_db().await;
+        let fid = format!("test_count_{}", uuid::Uuid::new_v4().simple());
+        live.insert_flight_logs(&fixture_rows(&fid)).await.unwrap();
+        eventually(|| live.count_flight_points(&fid, None, None), |n| *n == 5).await;
+        counts_match_the_fixture(&OptionalDb::new(live_config()), &fid).await;
+
+        live.mark_flight_deleted(&fid).await.unwrap();
+        eventually(|| live.flight_exists(&fid), |exists| !exists).await;
+        assert_eq!(live.count_flight_points(&format!("{fid}_other"), None, None).await.unwrap(), 2);
     }
 }
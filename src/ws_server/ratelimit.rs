@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::api_error::ApiError;
+use super::server::WsContext;
+
+/// Límites por conexión WS en mensajes/s (0 = sin límite). La ráfaga admitida es 1 s.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct RateLimits {
+    /// Comandos hacia el ESP32 (mode, motors, leds, passthrough…)
+    pub command_per_sec: f64,
+    /// `type: telemetry` y `Command::Data`
+    pub data_per_sec: f64,
+}
+
+/// Límites vigentes; cambiables en caliente por HTTP
+pub struct RateLimitConfig(RwLock<RateLimits>);
+
+impl RateLimitConfig {
+    pub fn new(limits: RateLimits) -> Self {
+        Self(RwLock::new(limits))
+    }
+
+    pub fn get(&self) -> RateLimits {
+        *self.0.read().unwrap()
+    }
+
+    pub fn set(&self, limits: RateLimits) {
+        *self.0.write().unwrap() = limits;
+    }
+}
+
+/// Token bucket de una conexión; la tasa se lee en cada intento
+#[derive(Default)]
+pub struct TokenBucket {
+    tokens: Option<f64>,
+    last: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn try_take(&mut self, per_sec: f64) -> bool {
+        if per_sec <= 0.0 {
+            return true;
+        }
+        let capacity = per_sec.max(1.0);
+        let now = Instant::now();
+        let elapsed = self.last.map(|t| now.duration_since(t).as_secs_f64()).unwrap_or(0.0);
+        self.last = Some(now);
+        let tokens = self.tokens.map_or(capacity, |t| (t + elapsed * per_sec).min(capacity));
+        if tokens >= 1.0 {
+            self.tokens = Some(tokens - 1.0);
+            true
+        } else {
+            self.tokens = Some(tokens);
+            false
+        }
+    }
+
+    /// Segundos hasta el próximo token tras un `try_take` fallido
+    pub fn retry_after(&self, per_sec: f64) -> f64 {
+        if per_sec <= 0.0 {
+            return 0.0;
+        }
+        (1.0 - self.tokens.unwrap_or(0.0)).max(0.0) / per_sec
+    }
+}
+
+/// Límites HTTP por IP en peticiones/s (0 = sin límite), con ráfaga de 1 s
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct HttpRateLimits {
+    /// GET de vuelos y series (consultas a QuestDB)
+    pub read_per_sec: f64,
+    /// POST/PUT/DELETE
+    pub write_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum HttpClass {
+    Read,
+    Write,
+}
+
+/// Entradas a partir de las cuales se limpian los buckets sin uso
+const HTTP_BUCKETS_PRUNE: usize = 1024;
+const HTTP_BUCKET_IDLE: Duration = Duration::from_secs(60);
+
+/// Un token bucket por IP y clase de petición
+pub struct HttpRateLimiter {
+    limits: HttpRateLimits,
+    buckets: Mutex<HashMap<(IpAddr, HttpClass), (TokenBucket, Instant)>>,
+}
+
+impl HttpRateLimiter {
+    pub fn new(limits: HttpRateLimits) -> Self {
+        Self { limits, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Las lecturas caras (todo lo que consulta QuestDB) y cualquier escritura;
+    /// health, stats, telemetría en vivo, WS/SSE y la documentación quedan fuera
+    fn classify(method: &Method, path: &str) -> Option<HttpClass> {
+        match *method {
+            Method::OPTIONS => None,
+            Method::GET | Method::HEAD => {
+                let expensive = path.starts_with("/api/flights")
+                    || path.starts_with("/api/series")
+                    || path.starts_with("/api/logger/config/history");
+                expensive.then_some(HttpClass::Read)
+            }
+            _ => Some(HttpClass::Write),
+        }
+    }
+
+    /// `Err(segundos)` si la IP ha agotado su cupo
+    fn check(&self, ip: IpAddr, class: HttpClass) -> Result<(), f64> {
+        let per_sec = match class {
+            HttpClass::Read => self.limits.read_per_sec,
+            HttpClass::Write => self.limits.write_per_sec,
+        };
+        if per_sec <= 0.0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= HTTP_BUCKETS_PRUNE {
+            buckets.retain(|_, (_, used)| now.duration_since(*used) < HTTP_BUCKET_IDLE);
+        }
+        let (bucket, used) = buckets.entry((ip, class)).or_insert_with(|| (TokenBucket::default(), now));
+        *used = now;
+        if bucket.try_take(per_sec) { Ok(()) } else { Err(bucket.retry_after(per_sec)) }
+    }
+}
+
+/// Middleware HTTP: 429 con `Retry-After` cuando una IP supera su cupo
+pub async fn limit_http(
+    State(ctx): State<WsContext>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let ip = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip());
+    let class = HttpRateLimiter::classify(req.method(), req.uri().path());
+    if let (Some(ip), Some(class)) = (ip, class)
+        && let Err(wait) = ctx.http_limiter.check(ip, class)
+    {
+        return Err(ApiError::RateLimited { retry_after_secs: wait.ceil().max(1.0) as u64 });
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_requests_share_one_bucket_per_ip_and_class() {
+        // 10/s: ráfaga de 10 y un token cada 100 ms
+        let limiter = Arc::new(HttpRateLimiter::new(HttpRateLimits { read_per_sec: 10.0, write_per_sec: 3.0 }));
+        let ips: [IpAddr; 2] = ["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let tasks: Vec<_> = (0..32).map(|i| {
+            let limiter = limiter.clone();
+            let ip = ips[i % 2];
+            tokio::spawn(async move {
+                let mut ok = [0usize; 2];
+                for _ in 0..4 {
+                    ok[0] += limiter.check(ip, HttpClass::Read).is_ok() as usize;
+                    ok[1] += limiter.check(ip, HttpClass::Write).is_ok() as usize;
+                }
+                (ip, ok)
+            })
+        }).collect();
+
+        let mut passed: HashMap<IpAddr, [usize; 2]> = HashMap::new();
+        for task in tasks {
+            let (ip, ok) = task.await.unwrap();
+            let total = passed.entry(ip).or_default();
+            total[0] += ok[0];
+            total[1] += ok[1];
+        }
+        // 64 intentos por IP y clase; pasa exactamente la ráfaga de cada bucket
+        for ip in ips {
+            assert_eq!(passed[&ip], [10, 3], "{ip}");
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 4);
+        let wait = limiter.check(ips[0], HttpClass::Write).unwrap_err();
+        assert!(wait > 0.0 && wait <= 1.0 / 3.0, "{wait}");
+    }
+}
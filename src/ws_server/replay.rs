@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::questdb::FlightPoint;
+use super::server::WsContext;
+
+/// Reproducción de un vuelo grabado sobre el canal broadcast (una a la vez)
+#[derive(Default)]
+pub struct Replay {
+    /// (flight_id, cancelación, generación)
+    current: Mutex<Option<(String, CancellationToken, u64)>>,
+    generation: AtomicU64,
+}
+
+impl Replay {
+    pub fn active(&self) -> Option<String> {
+        self.current.lock().unwrap().as_ref().map(|(fid, _, _)| fid.clone())
+    }
+
+    /// Lanza la reproducción; `false` si ya hay una en curso
+    pub fn start(&self, ctx: WsContext, flight_id: String, points: Vec<FlightPoint>, speed: f64) -> bool {
+        let mut current = self.current.lock().unwrap();
+        if current.is_some() {
+            return false;
+        }
+        let cancel = CancellationToken::new();
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        *current = Some((flight_id.clone(), cancel.clone(), generation));
+        tokio::spawn(run(ctx, flight_id, points, speed, cancel, generation));
+        true
+    }
+
+    pub fn stop(&self) -> bool {
+        match self.current.lock().unwrap().take() {
+            Some((_, cancel, _)) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finished(&self, generation: u64) {
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref().is_some_and(|(_, _, g)| *g == generation) {
+            *current = None;
+        }
+    }
+}
+
+/// Reemite cada punto respetando los intervalos originales / `speed`.
+/// Va directo al canal (sin caché de últimos valores ni persistencia).
+async fn run(
+    ctx: WsContext,
+    flight_id: String,
+    points: Vec<FlightPoint>,
+    speed: f64,
+    cancel: CancellationToken,
+    generation: u64,
+) {
+    info!("⏯️  Replay de {flight_id}: {} puntos a x{speed}", points.len());
+    let event = |state: &str| serde_json::json!({ "type": "replay", "state": state, "flight_id": &flight_id }).to_string();
+    let _ = ctx.publish(event("started"));
+
+    let mut prev_ts = None;
+    let mut stopped = false;
+    for p in points {
+        if let Some(prev) = prev_ts {
+            let gap = p.ts.signed_duration_since(prev).to_std().unwrap_or_default().div_f64(speed);
+            // Huecos largos (pausas en la grabación) se recortan a 60 s
+            tokio::select! {
+                _ = tokio::time::sleep(gap.min(Duration::from_secs(60))) => {}
+                _ = cancel.cancelled() => { stopped = true; break; }
+            }
+        } else if cancel.is_cancelled() {
+            stopped = true;
+            break;
+        }
+        prev_ts = Some(p.ts);
+
+        let mut msg = p.payload;
+        if let Some(obj) = msg.as_object_mut() {
+            obj.insert("replay".into(), true.into());
+            obj.insert("ts".into(), p.ts.to_rfc3339().into());
+            obj.insert("flight_id".into(), flight_id.clone().into());
+            let _ = ctx.publish(msg.to_string());
+        }
+    }
+
+    let _ = ctx.publish(event(if stopped { "stopped" } else { "finished" }));
+    info!("⏹️  Replay de {flight_id} {}", if stopped { "detenido" } else { "terminado" });
+    ctx.replay.finished(generation);
+}
@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use super::server::WsContext;
+
+/// La pasada de retención va cada cuarto del TTL, entre 1 min y 1 h
+const MIN_EVERY: Duration = Duration::from_secs(60);
+const MAX_EVERY: Duration = Duration::from_secs(3600);
+
+/// Tarea de retención de la config del logger (`retention: {"mode":"ttl","seconds":N}`):
+/// borra los vuelos que terminaron hace más de N s y suelta las particiones diarias que
+/// ya solo tienen vuelos borrados. `infinite` la para
+#[derive(Default)]
+pub struct Retention {
+    task: Mutex<Option<(u64, JoinHandle<()>)>>,
+    runs: AtomicU64,
+    flights_deleted: AtomicU64,
+    partitions_dropped: AtomicU64,
+    last_run: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RetentionSnapshot {
+    /// TTL vigente; `null` = sin retención (`infinite`)
+    pub ttl_s: Option<u64>,
+    pub runs: u64,
+    pub flights_deleted: u64,
+    pub partitions_dropped: u64,
+    pub last_run: Option<String>,
+}
+
+impl Retention {
+    /// Arranca, reprograma (si cambia el TTL) o para la tarea según la config aplicada
+    pub fn apply(&self, ctx: &WsContext, ttl_s: Option<u64>) {
+        let mut task = self.task.lock().unwrap();
+        if task.as_ref().map(|(ttl, _)| *ttl) == ttl_s {
+            return;
+        }
+        if let Some((_, handle)) = task.take() {
+            handle.abort();
+        }
+        match ttl_s {
+            Some(ttl) if ttl > 0 => {
+                info!("🧹 Retención activa: vuelos de más de {ttl} s");
+                *task = Some((ttl, tokio::spawn(run(ctx.clone(), ttl))));
+            }
+            _ => info!("🧹 Retención desactivada"),
+        }
+    }
+
+    pub fn snapshot(&self) -> RetentionSnapshot {
+        RetentionSnapshot {
+            ttl_s: self.task.lock().unwrap().as_ref().map(|(ttl, _)| *ttl),
+            runs: self.runs.load(Ordering::Relaxed),
+            flights_deleted: self.flights_deleted.load(Ordering::Relaxed),
+            partitions_dropped: self.partitions_dropped.load(Ordering::Relaxed),
+            last_run: self.last_run.lock().unwrap().clone(),
+        }
+    }
+}
+
+async fn run(ctx: WsContext, ttl_s: u64) {
+    let every = Duration::from_secs(ttl_s / 4).clamp(MIN_EVERY, MAX_EVERY);
+    let mut tick = tokio::time::interval(every);
+    loop {
+        tick.tick().await;
+        if let Err(e) = pass(&ctx, ttl_s).await {
+            warn!("⚠️  Retención: pasada fallida: {e}");
+        }
+    }
+}
+
+/// Una pasada: tombstone a los vuelos caducados (nunca al que se graba) y particiones fuera
+async fn pass(ctx: &WsContext, ttl_s: u64) -> Result<(), super::questdb::DbError> {
+    let cutoff = Utc::now() - chrono::Duration::seconds(ttl_s as i64);
+    let active = ctx.flight_id.read().await.clone();
+    let expired: Vec<String> = ctx.questdb.flights_before(cutoff).await?
+        .into_iter()
+        .map(|f| f.flight_id)
+        .filter(|fid| active.as_deref() != Some(fid.as_str()))
+        .collect();
+    for fid in &expired {
+        ctx.questdb.mark_flight_deleted(fid).await?;
+    }
+    let partitions = ctx.questdb.drop_deleted_partitions(cutoff).await?;
+
+    let stats = &ctx.retention;
+    stats.runs.fetch_add(1, Ordering::Relaxed);
+    stats.flights_deleted.fetch_add(expired.len() as u64, Ordering::Relaxed);
+    stats.partitions_dropped.fetch_add(partitions.max(0) as u64, Ordering::Relaxed);
+    *stats.last_run.lock().unwrap() = Some(Utc::now().to_rfc3339());
+
+    if expired.is_empty() && partitions == 0 {
+        return Ok(());
+    }
+    info!("🧹 Retención: {} vuelo(s) borrados, {partitions} partición(es) fuera", expired.len());
+    let event = serde_json::json!({
+        "event": "retention",
+        "ttl_s": ttl_s,
+        "cutoff": cutoff.to_rfc3339(),
+        "deleted": expired,
+        "partitions_dropped": partitions,
+    })
+    .to_string();
+    if let Err(e) = ctx.questdb.insert_logger_config(&event).await {
+        warn!("⚠️  Evento no guardado en logger_configs: {e}");
+    }
+    Ok(())
+}
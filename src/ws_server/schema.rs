@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// flight_id donde van los paquetes que no pasan la validación
+pub const QUARANTINE_FLIGHT_ID: &str = "quarantine";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Number,
+    String,
+    Bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FieldRule {
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Qué hacer con un campo numérico fuera de rango (los tipos erróneos siempre se quitan)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    #[default]
+    Strip,
+    Clamp,
+}
+
+/// Cuerpo de `POST /api/telemetry/schema`; `fields` vacío = sin validación
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct TelemetrySchema {
+    #[serde(default)]
+    pub fields: HashMap<String, FieldRule>,
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Ningún campo del esquema presente era válido → a `quarantine`
+    Quarantine,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SchemaState {
+    pub schema: TelemetrySchema,
+    pub violations: HashMap<String, u64>,
+    pub quarantined: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SchemaValidator {
+    schema: RwLock<TelemetrySchema>,
+    violations: Mutex<HashMap<String, u64>>,
+    quarantined: AtomicU64,
+}
+
+impl SchemaValidator {
+    pub fn set(&self, schema: TelemetrySchema) {
+        *self.schema.write().unwrap() = schema;
+        self.violations.lock().unwrap().clear();
+        self.quarantined.store(0, Ordering::Relaxed);
+    }
+
+    pub fn state(&self) -> SchemaState {
+        SchemaState {
+            schema: self.schema.read().unwrap().clone(),
+            violations: self.violations.lock().unwrap().clone(),
+            quarantined: self.quarantined.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Valida (y corrige según la política) el objeto `payload` de un paquete de telemetría
+    pub fn validate(&self, payload: &mut Value) -> Verdict {
+        let schema = self.schema.read().unwrap();
+        if schema.fields.is_empty() {
+            return Verdict::Pass;
+        }
+        let Some(obj) = payload.as_object_mut() else {
+            self.quarantined.fetch_add(1, Ordering::Relaxed);
+            return Verdict::Quarantine;
+        };
+
+        let mut checked = 0usize;
+        let mut bad = Vec::new();
+        for (name, rule) in &schema.fields {
+            let Some(val) = obj.get_mut(name) else { continue };
+            checked += 1;
+            match check_field(val, rule, schema.policy) {
+                FieldCheck::Ok => {}
+                FieldCheck::Clamped => bad.push((name.clone(), false)),
+                FieldCheck::Strip => bad.push((name.clone(), true)),
+            }
+        }
+        if bad.is_empty() {
+            return Verdict::Pass;
+        }
+
+        let stripped = bad.iter().filter(|(_, strip)| *strip).count();
+        {
+            let mut violations = self.violations.lock().unwrap();
+            for (name, strip) in bad {
+                *violations.entry(name.clone()).or_insert(0) += 1;
+                if strip {
+                    obj.remove(&name);
+                }
+            }
+        }
+
+        if stripped == checked {
+            self.quarantined.fetch_add(1, Ordering::Relaxed);
+            Verdict::Quarantine
+        } else {
+            Verdict::Pass
+        }
+    }
+}
+
+enum FieldCheck {
+    Ok,
+    Clamped,
+    Strip,
+}
+
+fn check_field(val: &mut Value, rule: &FieldRule, policy: Policy) -> FieldCheck {
+    match rule.kind {
+        FieldType::String => if val.is_string() { FieldCheck::Ok } else { FieldCheck::Strip },
+        FieldType::Bool => if val.is_boolean() { FieldCheck::Ok } else { FieldCheck::Strip },
+        FieldType::Number => {
+            let Some(x) = val.as_f64().filter(|x| x.is_finite()) else { return FieldCheck::Strip };
+            let lo = rule.min.unwrap_or(f64::NEG_INFINITY);
+            let hi = rule.max.unwrap_or(f64::INFINITY);
+            if (lo..=hi).contains(&x) {
+                FieldCheck::Ok
+            } else if policy == Policy::Clamp {
+                *val = x.clamp(lo, hi).into();
+                FieldCheck::Clamped
+            } else {
+                FieldCheck::Strip
+            }
+        }
+    }
+}
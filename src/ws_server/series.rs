@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use super::questdb::FlightPoint;
+
+/// Campos que se devuelven si la consulta no pide ninguno
+const DEFAULT_FIELDS: &[&str] = &["AngleRoll", "AnglePitch", "InputThrottle"];
+/// Límite de puntos por defecto de una serie
+pub const DEFAULT_LIMIT: i64 = 50_000;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SeriesPoint {
+    pub ts: String,
+    pub values: HashMap<String, f64>,
+}
+
+pub fn default_fields() -> Vec<String> {
+    DEFAULT_FIELDS.iter().map(|f| f.to_string()).collect()
+}
+
+/// `fields=AngleRoll,AnglePitch` → lista sin vacíos
+pub fn split_fields(csv: &str) -> Vec<String> {
+    csv.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
+/// Fecha RFC 3339 de los parámetros `from`/`to`; inválida = sin filtro
+pub fn parse_ts(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.with_timezone(&Utc))
+}
+
+/// payload → {"type":"telemetry","payload":{ ...pares clave:valor... }}; solo campos numéricos
+pub fn extract(p: &FlightPoint, fields: &[String]) -> SeriesPoint {
+    SeriesPoint { ts: p.ts.to_rfc3339(), values: extract_values(p, fields) }
+}
+
+pub fn extract_values(p: &FlightPoint, fields: &[String]) -> HashMap<String, f64> {
+    let Some(obj) = payload_obj(p) else { return HashMap::new() };
+    fields.iter().filter_map(|f| Some((f.clone(), field_value(obj, f)?))).collect()
+}
+
+/// Pares clave:valor de la muestra (`{"type":"telemetry","payload":{...}}`).
+/// Series, resumen y exportaciones leen los campos solo a través de aquí
+pub fn payload_obj(p: &FlightPoint) -> Option<&Map<String, Value>> {
+    p.payload.get("payload").and_then(|v| v.as_object())
+}
+
+/// Valor numérico de un campo (f64, i64 o u64); cualquier otra cosa → `None`
+pub fn field_value(obj: &Map<String, Value>, field: &str) -> Option<f64> {
+    let val = obj.get(field)?;
+    val.as_f64().or_else(|| val.as_i64().map(|x| x as f64)).or_else(|| val.as_u64().map(|x| x as f64))
+}
+
+/// Método de reducción cuando la serie supera `max_points`
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Downsample {
+    /// Media por intervalo de tiempo
+    #[default]
+    Avg,
+    /// Largest-Triangle-Three-Buckets, por campo
+    Lttb,
+}
+
+/// Serie con `max_points`: se indica si se redujo y el tamaño de bucket usado
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Downsampled {
+    pub points: Vec<SeriesPoint>,
+    pub downsampled: bool,
+    /// `avg`: anchura de cada bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_ms: Option<f64>,
+    /// `lttb`: muestras por bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_points: Option<f64>,
+}
+
+/// Muestra con tiempo en µs para operar
+pub struct Sample {
+    pub t: i64,
+    pub values: HashMap<String, f64>,
+}
+
+impl Sample {
+    fn into_point(self) -> SeriesPoint {
+        let ts = DateTime::<Utc>::from_timestamp_micros(self.t).unwrap_or_default();
+        SeriesPoint { ts: ts.to_rfc3339(), values: self.values }
+    }
+}
+
+/// Muestras ya extraídas (y reducidas si hizo falta), antes de darles formato de salida
+pub struct Reduced {
+    pub samples: Vec<Sample>,
+    pub downsampled: bool,
+    pub bucket_ms: Option<f64>,
+    pub bucket_points: Option<f64>,
+}
+
+/// Todas las muestras, sin reducir
+pub fn samples(points: &[FlightPoint], fields: &[String]) -> Reduced {
+    let samples = points
+        .iter()
+        .map(|p| Sample { t: p.ts.timestamp_micros(), values: extract_values(p, fields) })
+        .collect();
+    Reduced { samples, downsampled: false, bucket_ms: None, bucket_points: None }
+}
+
+/// Reduce la serie a unos `max_points` (mín. 3) conservando el primer y el
+/// último punto tal cual; los campos ausentes en una muestra no cuentan
+pub fn reduce(points: &[FlightPoint], fields: &[String], max_points: usize, method: Downsample) -> Reduced {
+    let max_points = max_points.max(3);
+    let all = samples(points, fields);
+    if points.len() <= max_points {
+        return all;
+    }
+    match method {
+        Downsample::Avg => {
+            let (out, width_us) = bucket_avg(&all.samples, max_points - 2);
+            Reduced { samples: out, downsampled: true, bucket_ms: Some(width_us / 1000.0), bucket_points: None }
+        }
+        Downsample::Lttb => {
+            let per_bucket = (all.samples.len() - 2) as f64 / (max_points - 2) as f64;
+            let out = lttb_fields(all.samples, fields, max_points);
+            Reduced { samples: out, downsampled: true, bucket_ms: None, bucket_points: Some(per_bucket) }
+        }
+    }
+}
+
+pub fn downsample(points: &[FlightPoint], fields: &[String], max_points: usize, method: Downsample) -> Downsampled {
+    reduce(points, fields, max_points, method).into_points()
+}
+
+impl Reduced {
+    pub fn into_points(self) -> Downsampled {
+        Downsampled {
+            points: self.samples.into_iter().map(Sample::into_point).collect(),
+            downsampled: self.downsampled,
+            bucket_ms: self.bucket_ms,
+            bucket_points: self.bucket_points,
+        }
+    }
+
+    /// Arrays paralelos: un `ts` por muestra y, por campo, su valor o `null`
+    pub fn into_columns(self, fields: &[String], ts_format: TsFormat) -> Columns {
+        let ts = match ts_format {
+            TsFormat::Rfc3339 => Timestamps::Rfc3339(
+                self.samples.iter()
+                    .map(|s| DateTime::<Utc>::from_timestamp_micros(s.t).unwrap_or_default().to_rfc3339())
+                    .collect(),
+            ),
+            TsFormat::EpochMs => Timestamps::EpochMs(self.samples.iter().map(|s| s.t.div_euclid(1000)).collect()),
+        };
+        let columns = fields.iter()
+            .map(|f| (f.clone(), self.samples.iter().map(|s| s.values.get(f).copied()).collect()))
+            .collect();
+        Columns {
+            ts,
+            fields: columns,
+            downsampled: self.downsampled,
+            bucket_ms: self.bucket_ms,
+            bucket_points: self.bucket_points,
+        }
+    }
+}
+
+/// Forma de la respuesta de una serie
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SeriesFormat {
+    /// `[{ts, values:{...}}]`
+    #[default]
+    Points,
+    /// `{ts:[...], fields:{campo:[...]}}`
+    Columns,
+}
+
+/// Formato de los `ts` en `format=columns`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TsFormat {
+    #[default]
+    Rfc3339,
+    /// Milisegundos desde epoch, sin formatear fechas
+    EpochMs,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Timestamps {
+    Rfc3339(Vec<String>),
+    EpochMs(Vec<i64>),
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Columns {
+    pub ts: Timestamps,
+    /// Mismo largo que `ts`; `null` donde la muestra no traía el campo
+    pub fields: BTreeMap<String, Vec<Option<f64>>>,
+    pub downsampled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_points: Option<f64>,
+}
+
+/// Media por campo en `buckets` intervalos iguales entre el primer y el último punto
+fn bucket_avg(samples: &[Sample], buckets: usize) -> (Vec<Sample>, f64) {
+    let (first, last) = (&samples[0], &samples[samples.len() - 1]);
+    let width = (last.t - first.t) as f64 / buckets as f64;
+
+    // (suma, n) por campo; `None` = bucket sin muestras
+    let mut acc: Vec<Option<HashMap<&str, (f64, usize)>>> = (0..buckets).map(|_| None).collect();
+    for s in &samples[1..samples.len() - 1] {
+        let i = if width > 0.0 { (((s.t - first.t) as f64 / width) as usize).min(buckets - 1) } else { 0 };
+        let bucket = acc[i].get_or_insert_with(HashMap::new);
+        for (k, v) in &s.values {
+            let e = bucket.entry(k.as_str()).or_insert((0.0, 0));
+            e.0 += v;
+            e.1 += 1;
+        }
+    }
+
+    let mut out = vec![Sample { t: first.t, values: first.values.clone() }];
+    for (i, bucket) in acc.into_iter().enumerate() {
+        let Some(bucket) = bucket else { continue };
+        out.push(Sample {
+            t: first.t + (width * (i as f64 + 0.5)) as i64,
+            values: bucket.into_iter().map(|(k, (sum, n))| (k.to_string(), sum / n as f64)).collect(),
+        });
+    }
+    out.push(Sample { t: last.t, values: last.values.clone() });
+    (out, width)
+}
+
+/// LTTB por campo sobre las muestras que lo tienen; las filas resultantes son la
+/// unión de los índices elegidos por cada campo, más el primero y el último
+fn lttb_fields(samples: Vec<Sample>, fields: &[String], threshold: usize) -> Vec<Sample> {
+    let last = samples.len() - 1;
+    let mut rows: BTreeMap<usize, HashMap<String, f64>> = BTreeMap::new();
+    rows.insert(0, samples[0].values.clone());
+    rows.insert(last, samples[last].values.clone());
+
+    for f in fields {
+        let (idx, xy): (Vec<usize>, Vec<(f64, f64)>) = samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.values.get(f).map(|v| (i, (s.t as f64, *v))))
+            .unzip();
+        for j in lttb(&xy, threshold) {
+            rows.entry(idx[j]).or_default().insert(f.clone(), xy[j].1);
+        }
+    }
+    rows.into_iter().map(|(i, values)| Sample { t: samples[i].t, values }).collect()
+}
+
+/// Índices elegidos por LTTB (Steinarsson, 2013); incluye siempre el primero y el último
+fn lttb(data: &[(f64, f64)], threshold: usize) -> Vec<usize> {
+    let n = data.len();
+    if n <= threshold || threshold < 3 {
+        return (0..n).collect();
+    }
+    let every = (n - 2) as f64 / (threshold - 2) as f64;
+    let mut out = Vec::with_capacity(threshold);
+    let mut a = 0;
+    out.push(0);
+    for i in 0..threshold - 2 {
+        // Media del bucket siguiente (el último punto para el último bucket)
+        let next_start = ((i + 1) as f64 * every) as usize + 1;
+        let next_end = (((i + 2) as f64 * every) as usize + 1).min(n);
+        let (avg_x, avg_y) = if next_start < next_end {
+            let len = (next_end - next_start) as f64;
+            let (sx, sy) = data[next_start..next_end].iter().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+            (sx / len, sy / len)
+        } else {
+            data[n - 1]
+        };
+
+        let start = (i as f64 * every) as usize + 1;
+        let end = next_start.min(n - 1);
+        let (ax, ay) = data[a];
+        let mut best = start;
+        let mut best_area = -1.0;
+        for (j, (x, y)) in data.iter().enumerate().take(end).skip(start) {
+            let area = ((ax - avg_x) * (y - ay) - (ax - x) * (avg_y - ay)).abs();
+            if area > best_area {
+                best_area = area;
+                best = j;
+            }
+        }
+        out.push(best);
+        a = best;
+    }
+    out.push(n - 1);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10 muestras a 1 Hz: `AngleRoll = i` siempre, `AnglePitch = 10·i` solo en las pares
+    fn fixture() -> Vec<FlightPoint> {
+        (0..10)
+            .map(|i| {
+                let mut payload = serde_json::json!({ "AngleRoll": i });
+                if i % 2 == 0 {
+                    payload["AnglePitch"] = serde_json::json!(10 * i);
+                }
+                FlightPoint {
+                    ts: DateTime::from_timestamp(1_700_000_000 + i, 0).unwrap(),
+                    payload: serde_json::json!({ "type": "telemetry", "payload": payload }),
+                }
+            })
+            .collect()
+    }
+
+    fn fields() -> Vec<String> {
+        vec!["AngleRoll".into(), "AnglePitch".into()]
+    }
+
+    #[test]
+    fn avg_buckets_match_the_hand_computed_fixture() {
+        let out = downsample(&fixture(), &fields(), 5, Downsample::Avg);
+        assert!(out.downsampled);
+        // 3 buckets de 3 s entre t=0 y t=9: {1,2}, {3,4,5}, {6,7,8}
+        assert_eq!(out.bucket_ms, Some(3000.0));
+        let got: Vec<_> = out.points.iter()
+            .map(|p| (p.ts.as_str(), p.values.get("AngleRoll").copied(), p.values.get("AnglePitch").copied()))
+            .collect();
+        assert_eq!(got, [
+            ("2023-11-14T22:13:20+00:00", Some(0.0), Some(0.0)),
+            ("2023-11-14T22:13:21.500+00:00", Some(1.5), Some(20.0)),
+            ("2023-11-14T22:13:24.500+00:00", Some(4.0), Some(40.0)),
+            ("2023-11-14T22:13:27.500+00:00", Some(7.0), Some(70.0)),
+            ("2023-11-14T22:13:29+00:00", Some(9.0), None),
+        ]);
+    }
+
+    #[test]
+    fn lttb_keeps_the_ends_and_short_series_pass_through() {
+        let points = fixture();
+        let out = downsample(&points, &fields(), 4, Downsample::Lttb);
+        assert!(out.downsampled);
+        assert_eq!(out.bucket_points, Some(4.0));
+        let (first, last) = (&out.points[0], &out.points[out.points.len() - 1]);
+        assert_eq!((first.values["AngleRoll"], first.values["AnglePitch"]), (0.0, 0.0));
+        assert_eq!((last.values["AngleRoll"], last.values.get("AnglePitch")), (9.0, None));
+        // Cada campo aporta como mucho `max_points` valores
+        for f in fields() {
+            assert!(out.points.iter().filter(|p| p.values.contains_key(&f)).count() <= 4, "{f}");
+        }
+
+        let all = downsample(&points, &fields(), 10, Downsample::Avg);
+        assert!(!all.downsampled && all.bucket_ms.is_none());
+        assert_eq!(all.points.len(), 10);
+    }
+}
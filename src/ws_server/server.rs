@@ -1,17 +1,65 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde_json::{self, Value};
-use tokio::net::{TcpListener, UdpSocket};
-use tokio::sync::{broadcast, RwLock};
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_tungstenite::{accept_hdr_async, WebSocketStream};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
-use crate::config::function::{set_led_all, set_led_many, set_led_one, set_motors_state, set_mode};
+use crate::config::function::{
+    set_led_all, set_led_many, set_led_one, set_mode, set_motor_one_speed, set_motors_all_speed,
+    set_motors_many_speed, set_motors_state,
+};
+use super::acks::{AckRoutes, ReplyWaiters};
+use super::metrics::Metrics;
+use super::webhooks::Webhooks;
+use super::jobs::Jobs;
+use super::writer::TelemetryWriter;
+use super::spool::Spool;
+use super::retention::Retention;
+use super::bus::{Bus, Class};
+use super::auth::{AuthConfig, CommandPolicy, WS_AUTH_TIMEOUT};
 use super::questdb::OptionalDb;
+use super::capture::Capture;
+use super::events;
+use super::cors::CorsOrigins;
+use super::deflate::{self, DeflateStats, DeflateStream};
+use super::commands;
+use super::clients::{ClientInfo, ClientRegistry, KeepaliveConfig};
+use super::last_values::LastValues;
+use super::logger_config::AppliedConfig;
+use super::ratelimit::{HttpRateLimiter, RateLimitConfig, TokenBucket};
+use super::replay::Replay;
+use super::clock::ClockSync;
+use super::failover::RemoteFailover;
+use super::schema::SchemaValidator;
+use super::params;
+use super::series;
+use super::status::StatusReporter;
+use super::transport::TelemetryTransport;
+use super::udp::{CsvMapping, StreamRate, UdpStats};
+use super::ws_stats::BroadcastStats;
+
+/// Tiempo que se espera a que los clientes WS cierren al apagar
+const WS_SHUTDOWN_DRAIN: std::time::Duration = std::time::Duration::from_secs(2);
 
 /// Estructuras para decodificar comandos de alto nivel
 #[derive(Debug, Deserialize)]
@@ -26,6 +74,22 @@ struct LedMany {
     state: bool,
 }
 
+/// `{"motor":{"id":N,"speed":US}}` o `{"motor":{"id":N,"state":false}}`
+#[derive(Debug, Deserialize)]
+struct MotorOne {
+    id: u32,
+    speed: Option<u32>,
+    #[allow(dead_code)] // solo se valida el tipo; el firmware lo recibe tal cual
+    state: Option<bool>,
+}
+
+/// `{"motors":{"ids":[..],"speed":US}}` o `{"motors":{"speed":US}}` (todos)
+#[derive(Debug, Deserialize)]
+struct MotorsSpeed {
+    ids: Option<Vec<u32>>,
+    speed: Option<u32>,
+}
+
 #[derive(Debug, Deserialize)]
 struct Payload {
     mode: Option<i32>,
@@ -51,131 +115,1403 @@ enum Command {
     Data { flight_id: String, payload: String },
 }
 
+/// Control de suscripciones por cliente:
+/// `{"type":"subscribe","topics":[...]}` / `{"type":"unsubscribe","topics":[...]}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Subscription {
+    Subscribe { topics: Vec<String> },
+    Unsubscribe { topics: Option<Vec<String>> },
+}
+
+/// Campos de telemetría que quiere este cliente: `{"type":"filter","fields":[...]}`
+/// (`null` o lista vacía = payload completo)
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum FieldFilterMsg {
+    Filter { fields: Option<Vec<String>> },
+}
+
+/// Codificación de los frames de este cliente: `{"type":"format","encoding":"msgpack"|"json"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum FormatMsg {
+    Format { encoding: String },
+}
+
+/// Tope de telemetría para este cliente: `{"type":"max_rate","hz":5}` (0 u omitido = sin tope)
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MaxRateMsg {
+    MaxRate {
+        #[serde(default)]
+        hz: Option<f64>,
+    },
+}
+
+/// Agrupado de telemetría: `{"type":"batch","interval_ms":50}` (0 u omitido = desactivado)
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BatchMsg {
+    Batch {
+        #[serde(default)]
+        interval_ms: Option<u64>,
+    },
+}
+
+/// Marca en el vuelo activo: `{"type":"mark","label":"..."}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum MarkMsg {
+    Mark { label: String },
+}
+
+/// Consulta histórica sobre el WS (misma lógica que `GET /api/flights/:id/series`)
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum QueryMsg {
+    Query(WsQuery),
+}
+
+#[derive(Debug, Deserialize)]
+struct WsQuery {
+    request_id: Option<String>,
+    query: String,
+    flight_id: Option<String>,
+    fields: Option<Vec<String>>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Ejecuta la consulta y devuelve el resultado solo a este cliente, en trozos de
+/// `ctx.query_chunk` puntos para no acaparar su cola de salida
+async fn run_query(q: WsQuery, ctx: WsContext, out: ClientOut, msgpack: Arc<AtomicBool>) {
+    let rid = q.request_id.clone();
+    let send = |v: Value| {
+        let out = out.clone();
+        let frame = encode_frame(v.to_string(), msgpack.load(Ordering::Relaxed));
+        async move { out.send(frame).await }
+    };
+    let error = |reason: String| serde_json::json!({ "type": "query_error", "request_id": &rid, "reason": reason });
+
+    if q.query != "series" {
+        send(error(format!("unknown query '{}'", q.query))).await;
+        return;
+    }
+    let Some(flight_id) = q.flight_id else {
+        send(error("missing 'flight_id'".into())).await;
+        return;
+    };
+    // Mismas reglas que los parámetros de `GET /api/flights/:id/series`
+    let checked = params::range(q.from.as_deref(), q.to.as_deref())
+        .and_then(|range| Ok((range, params::limit(q.limit, series::DEFAULT_LIMIT, ctx.max_query_limit)?)));
+    let ((from, to), limit) = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            send(error(e.message().into_owned())).await;
+            return;
+        }
+    };
+    let fields = q.fields.filter(|f| !f.is_empty()).unwrap_or_else(series::default_fields);
+
+    let points = match ctx.questdb.fetch_flight_points(&flight_id, from, to, limit).await {
+        Ok(points) => points,
+        Err(e) => {
+            warn!("⚠️  Consulta WS de {flight_id} fallida: {e}");
+            send(error(format!("database error: {e}"))).await;
+            return;
+        }
+    };
+    if points.is_empty() && from.is_none() && to.is_none() {
+        send(error(format!("unknown flight '{flight_id}'"))).await;
+        return;
+    }
+
+    let mut chunks = points.chunks(ctx.query_chunk).peekable();
+    if chunks.peek().is_none() {
+        send(serde_json::json!({ "type": "query_result", "request_id": &rid, "done": true, "points": [] })).await;
+    }
+    while let Some(chunk) = chunks.next() {
+        let points: Vec<_> = chunk.iter().map(|p| series::extract(p, &fields)).collect();
+        let done = chunks.peek().is_none();
+        let msg = serde_json::json!({ "type": "query_result", "request_id": &rid, "done": done, "points": points });
+        if !send(msg).await {
+            break;
+        }
+    }
+}
+
+/// Autenticación en el primer mensaje: `{"type":"auth","token":"..."}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum AuthMsg {
+    Auth { token: String },
+}
+
+/// Versión del protocolo WS que habla este servidor
+pub const WS_PROTO_VERSION: u32 = 1;
+/// Capacidades anunciadas en el `hello`; cada función nueva del protocolo se añade aquí
+pub const WS_FEATURES: &[&str] = &[
+    "ack", "auth", "topics", "fields", "msgpack", "snapshot", "rate_limit", "replay", "errors", "max_rate", "batch", "query",
+];
+
+/// Saludo inicial en ambos sentidos: `{"type":"hello","proto":N}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum HelloMsg {
+    Hello { proto: u32 },
+}
+
+fn hello_frame() -> String {
+    serde_json::json!({
+        "type": "hello",
+        "server": "artheris",
+        "proto": WS_PROTO_VERSION,
+        "features": WS_FEATURES,
+    })
+    .to_string()
+}
+
+/// Filtro por `type` de los mensajes difundidos; `None` = todo (clientes que nunca se suscriben).
+/// Mensajes sin `type` (o no JSON) cuentan como el tópico `raw`.
+#[derive(Default)]
+struct TopicFilter(std::sync::RwLock<Option<BTreeSet<String>>>);
+
+impl TopicFilter {
+    fn allows(&self, kind: Option<&str>) -> bool {
+        let guard = self.0.read().unwrap();
+        let Some(topics) = guard.as_ref() else { return true };
+        topics.contains(kind.unwrap_or("raw"))
+    }
+
+    /// Aplica el cambio y devuelve la confirmación para el cliente
+    fn apply(&self, sub: Subscription) -> String {
+        let mut guard = self.0.write().unwrap();
+        match sub {
+            Subscription::Subscribe { topics } => guard.get_or_insert_with(BTreeSet::new).extend(topics),
+            Subscription::Unsubscribe { topics: Some(topics) } => {
+                if let Some(set) = guard.as_mut() {
+                    for t in &topics {
+                        set.remove(t);
+                    }
+                }
+            }
+            // Sin lista: vuelve a recibir todo
+            Subscription::Unsubscribe { topics: None } => *guard = None,
+        }
+        serde_json::json!({ "type": "subscriptions", "topics": guard.as_ref() }).to_string()
+    }
+
+    fn topics(&self) -> Option<Vec<String>> {
+        self.0.read().unwrap().as_ref().map(|t| t.iter().cloned().collect())
+    }
+}
+
+/// Recorta el `payload` de la telemetría a los campos pedidos por el cliente
+#[derive(Default)]
+struct FieldFilter(std::sync::RwLock<Option<Vec<String>>>);
+
+impl FieldFilter {
+    /// Sin filtro devuelve el mismo `String` (no se parsea nada)
+    fn project(&self, text: String) -> String {
+        let guard = self.0.read().unwrap();
+        let Some(fields) = guard.as_ref() else { return text };
+        let Ok(mut msg) = serde_json::from_str::<Value>(&text) else { return text };
+        if msg.get("type").and_then(|t| t.as_str()) != Some("telemetry") {
+            return text;
+        }
+        match msg.get_mut("payload").and_then(|p| p.as_object_mut()) {
+            Some(payload) => payload.retain(|k, _| fields.iter().any(|f| f == k)),
+            None => return text,
+        }
+        msg.to_string()
+    }
+
+    fn apply(&self, FieldFilterMsg::Filter { fields }: FieldFilterMsg) -> String {
+        let fields = fields.filter(|f| !f.is_empty());
+        let reply = serde_json::json!({ "type": "filter", "fields": &fields }).to_string();
+        *self.0.write().unwrap() = fields;
+        reply
+    }
+}
+
+/// Capacidad de la cola de salida de cada cliente WS
+const WS_CLIENT_QUEUE: usize = 256;
+/// Tiempo para vaciar la cola de salida al cerrar una conexión
+const WS_WRITER_FLUSH: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Lo que recibe el writer: frames ya codificados, o telemetría en texto que se
+/// codifica al escribir (después de `max_rate` y del agrupado `batch`)
+enum Outgoing {
+    Frame(Message),
+    Telemetry(String),
+}
+
+/// Cola de salida de un cliente WS; una task dedicada la escribe en el socket
+#[derive(Clone)]
+struct ClientOut {
+    tx: mpsc::Sender<Outgoing>,
+    client: Arc<ClientInfo>,
+}
+
+/// Motivo por el que el servidor cierra una conexión; viaja en el Close frame
+/// para que el cliente no vea un 1006 sin explicación
+enum CloseReason {
+    Shutdown,
+    Kicked,
+    AuthTimeout,
+    IdleTimeout,
+    UnsupportedProto(u32),
+}
+
+impl CloseReason {
+    fn frame(&self) -> CloseFrame<'static> {
+        let (code, reason) = match self {
+            Self::Shutdown => (CloseCode::Away, "server shutdown".to_string()),
+            Self::Kicked => (CloseCode::Policy, "kicked by admin".to_string()),
+            Self::AuthTimeout => (CloseCode::Policy, "auth timeout".to_string()),
+            Self::IdleTimeout => (CloseCode::Normal, "idle timeout".to_string()),
+            Self::UnsupportedProto(proto) => {
+                (CloseCode::Protocol, format!("unsupported proto {proto}, max {WS_PROTO_VERSION}"))
+            }
+        };
+        CloseFrame { code, reason: reason.into() }
+    }
+}
+
+/// Máximo de muestras en un `telemetry_batch` antes de enviarlo sin esperar al intervalo
+const WS_BATCH_MAX: usize = 64;
+
+/// Estado de la task que escribe en el socket de un cliente
+struct Writer<S> {
+    sink: S,
+    client: Arc<ClientInfo>,
+    msgpack: Arc<AtomicBool>,
+    /// `max_rate`: telemetría más reciente retenida y cuándo salió la anterior
+    pending: Option<String>,
+    last_telemetry: Option<tokio::time::Instant>,
+    /// `batch`: telemetría acumulada desde `batch_since`
+    batch: Vec<String>,
+    batch_since: Option<tokio::time::Instant>,
+}
+
+impl<S> Writer<S>
+where
+    S: futures_util::Sink<Message> + Unpin,
+{
+    async fn run(mut self, mut rx: mpsc::Receiver<Outgoing>) {
+        loop {
+            let deadline = self.pending_due().into_iter().chain(self.batch_due()).min();
+            let ok = tokio::select! {
+                item = rx.recv() => match item {
+                    Some(Outgoing::Telemetry(text)) => self.telemetry(text).await,
+                    // Lo que no es telemetría sale detrás del lote en curso: se respeta el orden
+                    Some(Outgoing::Frame(msg)) => self.flush_batch().await && self.write(msg).await,
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                    self.on_deadline().await
+                }
+            };
+            if !ok {
+                break;
+            }
+        }
+    }
+
+    /// Escribe un frame; `false` si hay que terminar (error de socket o Close enviado)
+    async fn write(&mut self, msg: Message) -> bool {
+        let is_close = matches!(msg, Message::Close(_));
+        let is_data = matches!(msg, Message::Text(_) | Message::Binary(_));
+        if self.sink.send(msg).await.is_err() || is_close {
+            return false;
+        }
+        if is_data {
+            self.client.record_sent();
+        }
+        true
+    }
+
+    async fn write_text(&mut self, text: String) -> bool {
+        let frame = encode_frame(text, self.msgpack.load(Ordering::Relaxed));
+        self.write(frame).await
+    }
+
+    /// Con `max_rate` solo se guarda la telemetría más reciente de cada intervalo
+    async fn telemetry(&mut self, text: String) -> bool {
+        if let Some(iv) = self.client.min_interval() {
+            let now = tokio::time::Instant::now();
+            if self.last_telemetry.is_some_and(|last| now < last + iv) {
+                self.pending = Some(text);
+                return true;
+            }
+            self.last_telemetry = Some(now);
+        }
+        self.emit(text).await
+    }
+
+    /// Telemetría que ya pasó `max_rate`: al lote si el cliente pidió `batch`, si no directa
+    async fn emit(&mut self, text: String) -> bool {
+        if self.client.batch_interval().is_none() {
+            return self.write_text(text).await;
+        }
+        self.batch_since.get_or_insert_with(tokio::time::Instant::now);
+        self.batch.push(text);
+        if self.batch.len() >= WS_BATCH_MAX {
+            return self.flush_batch().await;
+        }
+        true
+    }
+
+    async fn flush_batch(&mut self) -> bool {
+        self.batch_since = None;
+        if self.batch.is_empty() {
+            return true;
+        }
+        // Cada muestra ya es JSON: se concatenan sin re-parsear
+        let text = format!(r#"{{"type":"telemetry_batch","items":[{}]}}"#, self.batch.join(","));
+        self.batch.clear();
+        self.write_text(text).await
+    }
+
+    fn pending_due(&self) -> Option<tokio::time::Instant> {
+        self.pending.as_ref()?;
+        match (self.client.min_interval(), self.last_telemetry) {
+            (Some(iv), Some(last)) => Some(last + iv),
+            // Se quitó el tope con algo retenido: sale ya
+            _ => Some(tokio::time::Instant::now()),
+        }
+    }
+
+    fn batch_due(&self) -> Option<tokio::time::Instant> {
+        Some(self.batch_since? + self.client.batch_interval().unwrap_or_default())
+    }
+
+    async fn on_deadline(&mut self) -> bool {
+        let now = tokio::time::Instant::now();
+        if self.pending_due().is_some_and(|due| due <= now)
+            && let Some(text) = self.pending.take()
+        {
+            self.last_telemetry = Some(now);
+            if !self.emit(text).await {
+                return false;
+            }
+        }
+        if self.batch_due().is_some_and(|due| due <= now) {
+            return self.flush_batch().await;
+        }
+        true
+    }
+}
+
+impl ClientOut {
+    fn spawn<S>(sink: S, client: Arc<ClientInfo>, msgpack: Arc<AtomicBool>) -> (Self, tokio::task::JoinHandle<()>)
+    where
+        S: futures_util::Sink<Message> + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<Outgoing>(WS_CLIENT_QUEUE);
+        let writer = Writer {
+            sink,
+            client: client.clone(),
+            msgpack,
+            pending: None,
+            last_telemetry: None,
+            batch: Vec::new(),
+            batch_since: None,
+        };
+        (Self { tx, client }, tokio::spawn(writer.run(rx)))
+    }
+
+    /// Encola esperando hueco (mensajes de control); `false` si la conexión se cerró
+    async fn send(&self, msg: Message) -> bool {
+        self.tx.send(Outgoing::Frame(msg)).await.is_ok()
+    }
+
+    /// Encola el Close frame; el writer lo envía tras lo pendiente y suelta el socket
+    async fn close(&self, reason: CloseReason) {
+        let _ = self.send(Message::Close(Some(reason.frame()))).await;
+    }
+
+    /// Telemetría: sin esperar; con la cola llena se descarta y se cuenta
+    fn try_send_telemetry(&self, text: String) -> bool {
+        match self.tx.try_send(Outgoing::Telemetry(text)) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.client.record_dropped();
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    async fn send_telemetry(&self, text: String) -> bool {
+        self.tx.send(Outgoing::Telemetry(text)).await.is_ok()
+    }
+
+    fn is_full(&self) -> bool {
+        self.tx.capacity() == 0
+    }
+}
+
+/// Frame de salida: texto JSON, o MessagePack binario si el cliente lo pidió
+fn encode_frame(text: String, msgpack: bool) -> Message {
+    if !msgpack {
+        return Message::Text(text);
+    }
+    let value = serde_json::from_str::<Value>(&text).unwrap_or(Value::String(text));
+    match rmp_serde::to_vec_named(&value) {
+        Ok(bytes) => Message::Binary(bytes),
+        Err(_) => Message::Text(value.to_string()),
+    }
+}
+
 /// Contexto compartido para WS/HTTP
 #[derive(Clone)]
 pub struct WsContext {
+    /// Canal de texto anterior; solo para consumidores sin migrar a `bus`
     pub tx: broadcast::Sender<String>,
-    pub esp32_socket: Option<Arc<UdpSocket>>,
-    pub remote_addr: SocketAddr,
+    /// Difusión hacia WS por clase de mensaje (telemetría, acks, estado, sistema)
+    pub bus: Arc<Bus>,
+    /// Enlace de comandos con el ESP32 (UDP o serie)
+    pub esp32: Option<Arc<dyn TelemetryTransport>>,
+    /// Lista priorizada de direcciones del ESP32 (solo con enlace UDP)
+    pub esp32_failover: Option<Arc<RemoteFailover>>,
     pub questdb: OptionalDb,
     pub flight_id: Arc<RwLock<Option<String>>>,
-    pub last_config: Arc<RwLock<Option<Value>>>,
+    pub last_config: Arc<RwLock<Option<AppliedConfig>>>,
+    pub udp_stats: Arc<UdpStats>,
+    pub stream_rate: Arc<StreamRate>,
+    pub capture: Arc<Capture>,
+    pub schema: Arc<SchemaValidator>,
+    pub csv_map: Arc<CsvMapping>,
+    pub clock: Arc<ClockSync>,
+    pub auth: Arc<AuthConfig>,
+    /// Mensajes perdidos por clientes WS lentos (lag del canal broadcast)
+    pub ws_stats: Arc<BroadcastStats>,
+    pub acks: Arc<AckRoutes>,
+    /// Envíos crudos por HTTP esperando ack/eco
+    pub esp32_replies: Arc<ReplyWaiters>,
+    pub clients: Arc<ClientRegistry>,
+    /// Último mensaje por tipo, reenviado a los clientes nuevos
+    pub last_values: Arc<LastValues>,
+    pub keepalive: KeepaliveConfig,
+    /// Negociar permessage-deflate con los clientes que lo ofrecen (`ARTHERIS_WS_DEFLATE=0` lo apaga)
+    pub ws_deflate: bool,
+    /// wss:// y https:// si se configuró certificado
+    pub tls: Option<TlsAcceptor>,
+    /// Orígenes con acceso CORS al router HTTP
+    pub cors: CorsOrigins,
+    /// Límite de mensajes por cliente WS
+    pub rate_limits: Arc<RateLimitConfig>,
+    /// Cupo por IP de la API HTTP
+    pub http_limiter: Arc<HttpRateLimiter>,
+    /// Dirección de escucha del servidor WS (`ARTHERIS_WS_ADDR`)
+    /// Listener WS propio (9001); `None` = solo `GET /ws` en el puerto HTTP
+    pub ws_addr: Option<SocketAddr>,
+    /// Bundle del panel web servido en `/` (`ARTHERIS_STATIC_DIR`)
+    pub static_dir: Option<Arc<std::path::Path>>,
+    /// Apagado ordenado (Ctrl-C / `exit`): cierra también las sesiones de `/ws`
+    pub shutdown: CancellationToken,
+    pub replay: Arc<Replay>,
+    /// Lista global de comandos permitidos
+    pub commands: Arc<CommandPolicy>,
+    /// Contadores de comandos y del proceso (`GET /api/stats`)
+    pub metrics: Arc<Metrics>,
+    /// Webhooks de grabación, enlace y alertas
+    pub webhooks: Arc<Webhooks>,
+    /// Trabajos largos en segundo plano (`GET /api/jobs/:id`)
+    pub jobs: Arc<Jobs>,
+    /// Escritura diferida y por lotes de la telemetría UDP en `flight_logs`
+    pub telemetry_writer: Arc<TelemetryWriter>,
+    /// Telemetría en disco cuando QuestDB no la acepta (`POST /api/spool/replay`)
+    pub spool: Arc<Spool>,
+    /// Retención por TTL de la config del logger
+    pub retention: Arc<Retention>,
+    /// Puntos por mensaje `query_result` en las consultas por WS
+    pub query_chunk: usize,
+    /// Tope de `limit` en las consultas HTTP (más → 400)
+    pub max_query_limit: i64,
+    pub status: Arc<StatusReporter>,
+}
+
+impl WsContext {
+    /// Difunde a todos los clientes WS y actualiza la caché de últimos valores.
+    /// Devuelve los receptores alcanzados (`None` = ninguno)
+    pub fn broadcast(&self, text: String) -> Option<usize> {
+        self.last_values.update(&text);
+        self.publish(text)
+    }
+
+    /// Ack para `request_id`: solo al cliente WS que lo pidió; si no se sabe quién
+    /// (HTTP, consola, caducado) se difunde como antes
+    pub fn send_ack(&self, request_id: &str, text: String) {
+        if let Some(id) = self.acks.client_for(request_id)
+            && self.clients.send_to(id, text.clone())
+        {
+            return;
+        }
+        let _ = self.publish(text);
+    }
+
+    /// Publica en el `Bus` (contabilizado), sin pasar por la caché de últimos valores
+    pub fn publish(&self, text: String) -> Option<usize> {
+        if self.tx.receiver_count() > 0 {
+            let _ = self.tx.send(text.clone());
+        }
+        let res = self.bus.publish(text);
+        self.ws_stats.record_send(res);
+        res
+    }
+
+    /// Registra un comando saliente / ack entrante en `command_logs` (y, con un vuelo
+    /// grabándose, en `flight_commands`) sin bloquear al llamador (los fallos de BD solo se loguean)
+    pub fn log_command(&self, request_id: Option<&str>, direction: &'static str, payload: &str) {
+        match direction {
+            "out" => self.metrics.commands.record_sent(request_id),
+            "ack" => self.metrics.commands.record_ack(request_id),
+            _ => {}
+        }
+        let db = self.questdb.clone();
+        let flight_id = self.flight_id.clone();
+        let request_id = request_id.map(str::to_owned);
+        let payload = payload.to_owned();
+        tokio::spawn(async move {
+            let fid = flight_id.read().await.clone();
+            if let Err(e) = db.insert_command_log(fid.as_deref(), request_id.as_deref(), direction, &payload).await {
+                debug!("⚠️  command_logs: {e}");
+            }
+            let Some(fid) = fid else { return };
+            let res = match (direction, request_id.as_deref()) {
+                ("out", rid) => db.insert_flight_command(&fid, rid, &payload).await,
+                ("ack", Some(rid)) => db.insert_command_ack(&fid, rid, &payload).await,
+                _ => return,
+            };
+            if let Err(e) = res {
+                debug!("⚠️  flight_commands: {e}");
+            }
+        });
+    }
 }
 
+/// Lanza el servidor WS en `ctx.ws_addr` hasta que se cancela `shutdown`; entonces cierra
+/// cada conexión con 1001 y espera brevemente a que terminen
 pub async fn start_ws_server(ctx: WsContext) -> Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:9001").await?;
-    info!("🌐 WebSocket server escuchando en ws://0.0.0.0:9001");
+    let shutdown = ctx.shutdown.clone();
+    let Some(ws_addr) = ctx.ws_addr else {
+        info!("🌐 Listener WS propio desactivado; WebSocket solo en GET /ws");
+        shutdown.cancelled().await;
+        close_clients(&ctx).await;
+        return Ok(());
+    };
+    let listener = TcpListener::bind(ws_addr)
+        .await
+        .with_context(|| format!("no se pudo enlazar el servidor WebSocket en {ws_addr}"))?;
+    let scheme = if ctx.tls.is_some() { "wss" } else { "ws" };
+    info!("🌐 WebSocket server escuchando en {scheme}://{ws_addr}");
 
     loop {
-        let (stream, _addr) = listener.accept().await?;
-        let mut rx = ctx.tx.subscribe();
-        let ctx_clone = ctx.clone();
+        let (stream, addr) = tokio::select! {
+            res = listener.accept() => res?,
+            _ = shutdown.cancelled() => break,
+        };
+        // Acks y pongs son frames pequeños: sin Nagle no esperan al ACK de TCP del cliente
+        let _ = stream.set_nodelay(true);
+        let conn_cancel = shutdown.child_token();
+        let ctx = ctx.clone();
+        let tls = ctx.tls.clone();
 
         tokio::spawn(async move {
-            let ws = match accept_async(stream).await {
-                Ok(ws) => ws,
-                Err(e) => {
-                    error!("❌ Error aceptando WS: {}", e);
-                    return;
+            match tls {
+                // Con TLS configurado las conexiones en claro fallan en el handshake
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve_connection(stream, addr, ctx, conn_cancel).await,
+                    Err(e) => debug!("🔒 Handshake TLS fallido desde {addr}: {e}"),
+                },
+                None => serve_connection(stream, addr, ctx, conn_cancel).await,
+            }
+        });
+    }
+
+    close_clients(&ctx).await;
+    Ok(())
+}
+
+/// Cierra todas las sesiones WS (de ambos listeners) y espera a que terminen
+async fn close_clients(ctx: &WsContext) {
+    let open = ctx.clients.len();
+    info!("🛑 Cerrando servidor WS ({open} clientes)");
+    ctx.clients.close_all();
+    if !ctx.clients.drain(WS_SHUTDOWN_DRAIN).await {
+        warn!("⚠️  {} clientes WS no cerraron a tiempo", ctx.clients.len());
+    }
+}
+
+/// Opciones de la URL de conexión
+pub struct ConnParams {
+    url_token_ok: bool,
+    want_snapshot: bool,
+    viewer: bool,
+    /// Contadores de permessage-deflate si se negoció
+    deflate: Option<Arc<DeflateStats>>,
+}
+
+impl ConnParams {
+    // Token opcional en la URL: ws://host:9001/?token=...
+    // `?snapshot=0` desactiva el reenvío de últimos valores al conectar
+    // `?role=viewer` solo recibe (nunca se reenvía nada al ESP32)
+    pub fn from_query(query: Option<&str>, auth: &AuthConfig) -> Self {
+        Self {
+            url_token_ok: query_param(query, "token").is_some_and(|t| auth.check(t)),
+            want_snapshot: !matches!(query_param(query, "snapshot"), Some("0" | "false")),
+            viewer: query_param(query, "role") == Some("viewer"),
+            deflate: None,
+        }
+    }
+}
+
+/// permessage-deflate aceptado para este upgrade (`None` si está apagado o el cliente no lo ofrece)
+fn negotiate_deflate(ctx: &WsContext, headers: &HeaderMap) -> Option<deflate::Agreed> {
+    if !ctx.ws_deflate {
+        return None;
+    }
+    deflate::negotiate(headers.get_all(header::SEC_WEBSOCKET_EXTENSIONS).iter().filter_map(|v| v.to_str().ok()))
+}
+
+/// Handshake WS sobre una conexión del listener propio (TCP en claro o TLS)
+async fn serve_connection<S>(stream: S, addr: SocketAddr, ctx: WsContext, conn_cancel: CancellationToken)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut params = None;
+    let mut agreed = None;
+    // El tipo de error lo impone tungstenite
+    #[allow(clippy::result_large_err)]
+    let check_url = |req: &Request, mut resp: Response| {
+        let origin = req.headers().get(header::ORIGIN).and_then(|v| v.to_str().ok());
+        if !ctx.auth.origin_allowed(origin) {
+            warn!("🚫 Upgrade WS desde {addr} rechazado: Origin {origin:?} no permitido");
+            let mut resp = ErrorResponse::new(Some("Origin not allowed".to_string()));
+            *resp.status_mut() = StatusCode::FORBIDDEN;
+            return Err(resp);
+        }
+        params = Some(ConnParams::from_query(req.uri().query(), &ctx.auth));
+        agreed = negotiate_deflate(&ctx, req.headers());
+        if let Some(a) = &agreed
+            && let Ok(v) = HeaderValue::from_str(&a.response())
+        {
+            resp.headers_mut().insert(header::SEC_WEBSOCKET_EXTENSIONS, v);
+        }
+        Ok(resp)
+    };
+    // La compresión va por debajo de tungstenite y se activa tras el 101
+    let mut ws = match accept_hdr_async(DeflateStream::before_handshake(stream), check_url).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            error!("❌ Error aceptando WS: {}", e);
+            return;
+        }
+    };
+    let Some(mut params) = params else { return };
+    if let Some(agreed) = agreed {
+        let stats = Arc::new(DeflateStats::default());
+        ws.get_mut().enable(agreed, stats.clone());
+        params.deflate = Some(stats);
+    }
+    run_session(ws, addr, params, ctx, conn_cancel).await;
+}
+
+/// `GET /ws` en el router HTTP: mismo comportamiento que el listener propio, detrás
+/// de CORS, TLS y el proxy inverso del puerto HTTP.
+/// (El extractor `WebSocketUpgrade` de axum 0.7 trae otra versión de tungstenite;
+/// el upgrade se hace a mano para compartir `run_session` tal cual.)
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "ws",
+    responses(
+        (status = 101, description = "Upgrade a WebSocket"),
+        (status = 400, description = "No es un upgrade WebSocket válido", body = String, content_type = "text/plain"),
+        (status = 403, description = "Origin no permitido", body = String, content_type = "text/plain"),
+    )
+)]
+pub async fn ws_upgrade(
+    State(ctx): State<WsContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut req: axum::extract::Request,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let headers = req.headers();
+    let is_upgrade = headers.get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let version_ok = headers.get(header::SEC_WEBSOCKET_VERSION).is_some_and(|v| v == "13");
+    let key = headers.get(header::SEC_WEBSOCKET_KEY).filter(|_| is_upgrade && version_ok)
+        .ok_or((StatusCode::BAD_REQUEST, "Expected a WebSocket upgrade request".to_string()))?;
+    let origin = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok());
+    if !ctx.auth.origin_allowed(origin) {
+        warn!("🚫 Upgrade WS desde {addr} rechazado: Origin {origin:?} no permitido");
+        return Err((StatusCode::FORBIDDEN, "Origin not allowed".to_string()));
+    }
+    let accept = derive_accept_key(key.as_bytes());
+    let mut params = ConnParams::from_query(req.uri().query(), &ctx.auth);
+    let agreed = negotiate_deflate(&ctx, req.headers());
+    let on_upgrade = hyper::upgrade::on(&mut req);
+
+    let mut response = axum::response::Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept);
+    if let Some(a) = &agreed {
+        response = response.header(header::SEC_WEBSOCKET_EXTENSIONS, a.response());
+    }
+
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                let mut io = DeflateStream::new(hyper_util::rt::TokioIo::new(upgraded));
+                if let Some(agreed) = agreed {
+                    let stats = Arc::new(DeflateStats::default());
+                    io.enable(agreed, stats.clone());
+                    params.deflate = Some(stats);
                 }
-            };
+                let ws = WebSocketStream::from_raw_socket(io, Role::Server, None).await;
+                let cancel = ctx.shutdown.child_token();
+                run_session(ws, addr, params, ctx, cancel).await;
+            }
+            Err(e) => error!("❌ Error en upgrade WS desde {addr}: {e}"),
+        }
+    });
+
+    response
+        .body(axum::body::Body::empty())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Sesión WS ya establecida, venga del listener propio o de `GET /ws`
+pub async fn run_session<S>(
+    ws: WebSocketStream<S>,
+    addr: SocketAddr,
+    params: ConnParams,
+    ctx_clone: WsContext,
+    conn_cancel: CancellationToken,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut rx = ctx_clone.bus.subscribe();
+    let ConnParams { url_token_ok, want_snapshot, viewer, deflate } = params;
+    let auth = ctx_clone.auth.clone();
+
+    // Se da de baja del registro al terminar cualquiera de las dos tasks
+    let (client_guard, mut direct) = ctx_clone.clients.register(addr, conn_cancel.clone());
+    let client = client_guard.info().clone();
+    if let Some(stats) = deflate {
+        client.set_deflate(stats);
+    }
+    let role = move |authed: bool| if viewer { "viewer" } else if authed { "operator" } else { "unauthenticated" };
+    let (ws_sender, mut ws_receiver) = ws.split();
+    let msgpack = Arc::new(AtomicBool::new(false));
+    let (out, mut writer) = ClientOut::spawn(ws_sender, client.clone(), msgpack.clone());
+    let filter = Arc::new(TopicFilter::default());
+    let fields = Arc::new(FieldFilter::default());
+    let authed = Arc::new(AtomicBool::new(!auth.enabled() || url_token_ok));
+    let read_only = auth.ws_read_only;
+    let keepalive = ctx_clone.keepalive;
+    client.set_role(role(authed.load(Ordering::Relaxed)));
 
-            let (ws_sender, mut ws_receiver) = ws.split();
-            let ws_sender = Arc::new(tokio::sync::Mutex::new(ws_sender));
+    // Lo primero que recibe el cliente es la versión y las capacidades del servidor
+    let _ = out.send(Message::Text(hello_frame())).await;
 
-            // Task 1: broadcast -> cliente
-            let mut rx_task = {
-                let ws_sender = Arc::clone(&ws_sender);
-                tokio::spawn(async move {
-                    while let Ok(text) = rx.recv().await {
-                        if ws_sender.lock().await.send(Message::Text(text)).await.is_err() {
+    if want_snapshot && (read_only || authed.load(Ordering::Relaxed)) {
+        for text in ctx_clone.last_values.snapshot() {
+            if !out.send(Message::Text(text)).await {
+                break;
+            }
+        }
+    }
+
+    // Task 1: broadcast -> cliente (filtrado por suscripción)
+    let mut rx_task = {
+        let out = out.clone();
+        let filter = Arc::clone(&filter);
+        let authed = Arc::clone(&authed);
+        let fields = Arc::clone(&fields);
+        let msgpack = Arc::clone(&msgpack);
+        let ws_stats = Arc::clone(&ctx_clone.ws_stats);
+        tokio::spawn(async move {
+            loop {
+                let recv = tokio::select! {
+                    // Respuestas dirigidas solo a este cliente (acks): sin filtros de tópico
+                    Some(text) = direct.recv() => {
+                        if !out.send(encode_frame(text, msgpack.load(Ordering::Relaxed))).await {
                             break;
                         }
+                        continue;
                     }
-                })
-            };
+                    recv = rx.recv() => recv,
+                };
+                let frame = match recv {
+                    Ok(frame) => frame,
+                    // Cliente lento: se avisa (sin pasar por filtros) y se sigue desde lo más reciente
+                    Err(RecvError::Lagged(n)) => {
+                        ws_stats.record_lagged(n);
+                        debug!("Cliente WS atrasado, {n} mensajes descartados");
+                        let warning = serde_json::json!({ "type": "warning", "dropped": n }).to_string();
+                        let frame = encode_frame(warning, msgpack.load(Ordering::Relaxed));
+                        if !out.send(frame).await {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if !(read_only || authed.load(Ordering::Relaxed)) || !filter.allows(frame.kind.as_deref()) {
+                    continue;
+                }
+                let text = fields.project(frame.text.clone());
+                // Con la cola llena la telemetría se descarta; el resto (acks, eventos) espera hueco
+                let ok = match (frame.class == Class::Telemetry, out.is_full()) {
+                    (true, true) => out.try_send_telemetry(text),
+                    (true, false) => out.send_telemetry(text).await,
+                    (false, _) => out.send(encode_frame(text, msgpack.load(Ordering::Relaxed))).await,
+                };
+                if !ok {
+                    break;
+                }
+            }
+        })
+    };
 
-            // Task 2: cliente -> router/UDP/DB
-            let mut recv_task = {
-                let ws_sender = Arc::clone(&ws_sender);
-                tokio::spawn(async move {
-                    while let Some(msg) = ws_receiver.next().await {
-                        match msg {
-                            Ok(Message::Text(text)) => {
-                                debug!("📨 WS: {text}");
-
-                                // Reenvía a ESP32 si está conectado
-                                if let Some(sock) = &ctx_clone.esp32_socket {
-                                    if let Err(e) = sock.send_to(text.as_bytes(), ctx_clone.remote_addr).await {
-                                        error!("❌ Error enviando a ESP32: {e}");
-                                    }
-                                }
-
-                                // Persistencia si es Command::Data
-                                if let Ok(Command::Data { flight_id, payload }) =
-                                    serde_json::from_str::<Command>(&text)
-                                {
-                                    if let Err(e) = ctx_clone.questdb.insert_flight_log(&flight_id, &payload).await {
-                                        warn!("⚠️  {}", e);
-                                    }
-                                    // Reenvía a todos los clientes WebSocket
-                                    if let Err(e) = ctx_clone.tx.send(text.clone()) {
-                                        error!("❌ Error enviando broadcast: {e}");
-                                    }
-                                } else {
-                                    // Si no es Command::Data, igual lo publicamos a clientes
-                                    let _ = ctx_clone.tx.send(text);
-                                }
+    // Task 2: cliente -> router/UDP/DB
+    let mut recv_task = {
+        let out = out.clone();
+        let client = Arc::clone(&client);
+        tokio::spawn(async move {
+            let deadline = tokio::time::Instant::now() + WS_AUTH_TIMEOUT;
+            let mut command_bucket = TokenBucket::default();
+            let mut data_bucket = TokenBucket::default();
+            loop {
+                let next = if read_only || authed.load(Ordering::Relaxed) {
+                    ws_receiver.next().await
+                } else {
+                    match tokio::time::timeout_at(deadline, ws_receiver.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            warn!("🔒 Cliente WS sin token tras {WS_AUTH_TIMEOUT:?}, cerrando");
+                            out.close(CloseReason::AuthTimeout).await;
+                            break;
+                        }
+                    }
+                };
+                let Some(msg) = next else { break };
+                client.record_received();
+                // Clientes MessagePack: los binarios se pasan a JSON antes del router
+                let msg = match msg {
+                    Ok(Message::Binary(bytes)) if msgpack.load(Ordering::Relaxed) => {
+                        match rmp_serde::from_slice::<Value>(&bytes) {
+                            Ok(v) => Ok(Message::Text(v.to_string())),
+                            Err(e) => {
+                                warn!("⚠️  Frame MessagePack inválido desde {addr}: {e}");
+                                continue;
                             }
-                            Ok(Message::Ping(p)) => {
-                                let _ = ws_sender.lock().await.send(Message::Pong(p)).await;
+                        }
+                    }
+                    other => other,
+                };
+                match msg {
+                    Ok(Message::Text(text)) => {
+                        debug!("📨 WS: {text}");
+
+                        if let Ok(HelloMsg::Hello { proto }) = serde_json::from_str::<HelloMsg>(&text) {
+                            client.set_proto(proto);
+                            if proto > WS_PROTO_VERSION {
+                                warn!("🚫 Cliente WS {} pide protocolo {proto} (máx. {WS_PROTO_VERSION})", client.id);
+                                out.close(CloseReason::UnsupportedProto(proto)).await;
+                                break;
+                            }
+                            continue;
+                        }
+
+                        if let Ok(AuthMsg::Auth { token }) = serde_json::from_str::<AuthMsg>(&text) {
+                            let ok = auth.check(&token);
+                            if ok {
+                                authed.store(true, Ordering::Relaxed);
+                                client.set_role(role(true));
+                            }
+                            let reply = serde_json::json!({ "type": "auth", "ok": ok }).to_string();
+                            let _ = out.send(Message::Text(reply)).await;
+                            continue;
+                        }
+
+                        // Suscripciones: solo afectan a este cliente
+                        if let Ok(sub) = serde_json::from_str::<Subscription>(&text) {
+                            let ack = filter.apply(sub);
+                            client.set_topics(filter.topics());
+                            let _ = out.send(Message::Text(ack)).await;
+                            continue;
+                        }
+                        if let Ok(FormatMsg::Format { encoding }) = serde_json::from_str::<FormatMsg>(&text) {
+                            let use_msgpack = encoding.eq_ignore_ascii_case("msgpack");
+                            msgpack.store(use_msgpack, Ordering::Relaxed);
+                            let reply = serde_json::json!({ "type": "format", "encoding": if use_msgpack { "msgpack" } else { "json" } });
+                            let _ = out.send(encode_frame(reply.to_string(), use_msgpack)).await;
+                            continue;
+                        }
+                        if let Ok(MaxRateMsg::MaxRate { hz }) = serde_json::from_str::<MaxRateMsg>(&text) {
+                            client.set_max_hz(hz.unwrap_or(0.0));
+                            let reply = serde_json::json!({ "type": "max_rate", "hz": client.max_hz() });
+                            let _ = out.send(encode_frame(reply.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                            continue;
+                        }
+                        if let Ok(BatchMsg::Batch { interval_ms }) = serde_json::from_str::<BatchMsg>(&text) {
+                            client.set_batch_ms(interval_ms.unwrap_or(0));
+                            let reply = serde_json::json!({ "type": "batch", "interval_ms": client.batch_ms() });
+                            let _ = out.send(encode_frame(reply.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                            continue;
+                        }
+                        if let Ok(QueryMsg::Query(q)) = serde_json::from_str::<QueryMsg>(&text)
+                            && (read_only || authed.load(Ordering::Relaxed))
+                        {
+                            tokio::spawn(run_query(q, ctx_clone.clone(), out.clone(), msgpack.clone()));
+                            continue;
+                        }
+                        if let Ok(f) = serde_json::from_str::<FieldFilterMsg>(&text) {
+                            let ack = fields.apply(f);
+                            let _ = out.send(Message::Text(ack)).await;
+                            continue;
+                        }
+
+                        // Sin token (modo solo lectura): ningún comando pasa
+                        if !authed.load(Ordering::Relaxed) {
+                            client.record_rejected();
+                            let err = serde_json::json!({ "type": "error", "error": "unauthorized" }).to_string();
+                            let _ = out.send(Message::Text(err)).await;
+                            continue;
+                        }
+
+                        let root = serde_json::from_str::<Value>(&text).ok();
+                        let rid = root.as_ref().and_then(extract_request_id);
+
+                        // Espectadores: nada de lo que envían llega al ESP32
+                        if viewer {
+                            client.record_rejected();
+                            let err = serde_json::json!({ "type": "error", "code": "forbidden", "request_id": rid });
+                            let _ = out.send(encode_frame(err.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                            continue;
+                        }
+
+                        // Token bucket por conexión: datos/telemetría y comandos por separado
+                        let kind = root.as_ref().and_then(|r| r.get("type")).and_then(|t| t.as_str());
+                        let limits = ctx_clone.rate_limits.get();
+                        let allowed = if matches!(kind, Some("data" | "telemetry")) {
+                            data_bucket.try_take(limits.data_per_sec)
+                        } else {
+                            command_bucket.try_take(limits.command_per_sec)
+                        };
+                        if !allowed {
+                            debug!("🚦 Cliente WS {} limitado ({kind:?})", client.id);
+                            let err = serde_json::json!({ "type": "error", "code": "rate_limited", "request_id": rid });
+                            let _ = out.send(encode_frame(err.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                            continue;
+                        }
+
+                        // Marca: se guarda y se difunde; aquí solo se responde si falla
+                        if let Ok(MarkMsg::Mark { label }) = serde_json::from_str::<MarkMsg>(&text) {
+                            if let Err(e) = events::mark_active(&ctx_clone, &label).await {
+                                let err = serde_json::json!({ "type": "error", "code": "mark_failed", "request_id": rid, "reason": e.to_string() });
+                                let _ = out.send(encode_frame(err.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                            }
+                            continue;
+                        }
+
+                        // Los acks de este request_id (locales y del ESP32) vuelven solo a este cliente
+                        if let Some(rid) = rid {
+                            ctx_clone.acks.track(rid, client.id);
+                        }
+
+                        // Comandos de alto nivel (mode/motors/leds) van por el router
+                        match handle_incoming(&text, &ctx_clone).await {
+                            Ok(Routed::Handled) => continue,
+                            Ok(Routed::Unrecognized) => {}
+                            Ok(Routed::Rejected(cmd)) => {
+                                client.record_rejected();
+                                let err = serde_json::json!({ "type": "error", "code": "command_not_allowed", "command": cmd, "request_id": rid });
+                                let _ = out.send(encode_frame(err.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                                continue;
+                            }
+                            Ok(Routed::Malformed { reason, got }) => {
+                                debug!("Comando WS mal formado de {}: {reason}", client.id);
+                                let err = serde_json::json!({ "type": "error", "code": "malformed_command", "request_id": rid, "reason": reason, "got": got });
+                                let _ = out.send(encode_frame(err.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                                continue;
                             }
-                            Ok(Message::Pong(_)) => {}
-                            Ok(Message::Binary(_)) => {}
-                            Ok(Message::Close(_)) => break,
-                            Ok(Message::Frame(_)) => {}
                             Err(e) => {
-                                error!("❌ Error recibiendo WS: {}", e);
-                                break;
+                                error!("❌ Error procesando comando WS: {e}");
+                                continue;
                             }
                         }
+
+                        // El reenvío crudo (salvo datos) también pasa por la lista permitida
+                        if !matches!(kind, Some("data" | "telemetry")) && !ctx_clone.commands.allows("raw") {
+                            client.record_rejected();
+                            let err = serde_json::json!({ "type": "error", "code": "command_not_allowed", "command": "raw", "request_id": rid });
+                            let _ = out.send(encode_frame(err.to_string(), msgpack.load(Ordering::Relaxed))).await;
+                            continue;
+                        }
+
+                        // Resto: reenvío crudo al ESP32 si está conectado
+                        if let Err(e) = passthrough(&ctx_clone, &text, rid).await {
+                            error!("❌ Error enviando a ESP32: {e}");
+                        }
+
+                        // Persistencia si es Command::Data
+                        if let Ok(Command::Data { flight_id, payload }) =
+                            serde_json::from_str::<Command>(&text)
+                        {
+                            // Con timestamp propio en el payload se guarda ese; si no, el de llegada
+                            let ts = serde_json::from_str::<Value>(&payload).ok()
+                                .and_then(|v| ctx_clone.clock.own_timestamp(&v));
+                            if let Err(e) = ctx_clone.questdb.insert_flight_log(&flight_id, &payload, ts).await {
+                                warn!("⚠️  {}", e);
+                            }
+                            // Reenvía a todos los clientes WebSocket
+                            let _ = ctx_clone.broadcast(text.clone());
+                        } else {
+                            // Si no es Command::Data, igual lo publicamos a clientes
+                            let _ = ctx_clone.broadcast(text);
+                        }
                     }
-                })
-            };
+                    Ok(Message::Ping(p)) => {
+                        let _ = out.send(Message::Pong(p)).await;
+                    }
+                    // La actividad (last seen) ya quedó registrada arriba
+                    Ok(Message::Pong(_)) => {}
+                    Ok(Message::Binary(_)) => {}
+                    Ok(Message::Close(_)) => break,
+                    Ok(Message::Frame(_)) => {}
+                    Err(e) => {
+                        error!("❌ Error recibiendo WS: {}", e);
+                        break;
+                    }
+                }
+            }
+        })
+    };
 
-            // Espera a que una de las tasks termine
-            tokio::select! {
-                _ = &mut rx_task => recv_task.abort(),
-                _ = &mut recv_task => rx_task.abort(),
+    // Task 3: ping periódico y cierre de conexiones zombi
+    let mut ping_task = {
+        let out = out.clone();
+        let client = Arc::clone(&client);
+        tokio::spawn(async move {
+            let Some(every) = keepalive.ping_every else {
+                return std::future::pending().await;
+            };
+            let mut tick = tokio::time::interval(every);
+            tick.tick().await;
+            loop {
+                tick.tick().await;
+                let idle = client.idle();
+                if idle > keepalive.idle_timeout {
+                    warn!("💤 Cliente WS {} ({}) inactivo {:.1}s, cerrando", client.id, addr, idle.as_secs_f64());
+                    out.close(CloseReason::IdleTimeout).await;
+                    break;
+                }
+                if !out.send(Message::Ping(Vec::new())).await {
+                    break;
+                }
             }
-        });
+        })
+    };
+
+    // Espera a que una de las tasks termine (o al cierre del servidor)
+    tokio::select! {
+        _ = &mut rx_task => {},
+        _ = &mut recv_task => {},
+        _ = &mut ping_task => {},
+        _ = conn_cancel.cancelled() => {
+            rx_task.abort();
+            recv_task.abort();
+            let reason = if client.kicked() { CloseReason::Kicked } else { CloseReason::Shutdown };
+            // Con la cola llena y el socket atascado no se espera indefinidamente
+            let _ = tokio::time::timeout(WS_WRITER_FLUSH, out.close(reason)).await;
+        }
     }
+    rx_task.abort();
+    recv_task.abort();
+    ping_task.abort();
+    // El writer termina al enviar el Close o al soltarse todos los emisores
+    drop(out);
+    if tokio::time::timeout(WS_WRITER_FLUSH, &mut writer).await.is_err() {
+        writer.abort();
+    }
+    drop(client_guard);
 }
 
-async fn handle_incoming(
-    text: &str,
-    esp32_socket: Option<Arc<UdpSocket>>,
-    remote_addr: SocketAddr,
-    ws_tx: &broadcast::Sender<String>,
-) -> anyhow::Result<()> {
-    let root: serde_json::Value = match serde_json::from_str(text) {
-        Ok(v) => v,
-        Err(_) => {
-            // No es JSON → re-publica y listo
-            let _ = ws_tx.send(text.to_string());
-            return Ok(());
+#[cfg(any(test, feature = "bench"))]
+impl WsContext {
+    /// Contexto para tests (y benches): sin token, sin QuestDB (puerto cerrado: todo da
+    /// `Unavailable`) y con spool y capturas en un directorio temporal propio
+    pub fn for_tests(esp32: Option<Arc<dyn TelemetryTransport>>) -> Self {
+        use std::time::Duration;
+        use super::ratelimit::{HttpRateLimits, RateLimits};
+        use super::writer::WriterConfig;
+
+        let dir = std::env::temp_dir().join(format!("artheris-test-{}", uuid::Uuid::new_v4()));
+        let db = OptionalDb::new(test_db_config());
+        let (tx, _) = broadcast::channel(100);
+        let spool = Arc::new(Spool::new(dir.join("spool"), u64::MAX));
+        Self {
+            tx,
+            bus: Arc::new(Bus::new(100)),
+            esp32,
+            esp32_failover: None,
+            questdb: db,
+            flight_id: Default::default(),
+            last_config: Default::default(),
+            udp_stats: Arc::new(UdpStats::new(Duration::from_secs(1))),
+            stream_rate: Arc::new(StreamRate::new(0)),
+            capture: Arc::new(Capture::new(dir.join("captures"), 1024 * 1024)),
+            schema: Default::default(),
+            csv_map: Default::default(),
+            clock: Arc::new(ClockSync::new(None)),
+            auth: Default::default(),
+            ws_stats: Arc::new(BroadcastStats::new(100)),
+            acks: Default::default(),
+            esp32_replies: Default::default(),
+            clients: Default::default(),
+            last_values: Default::default(),
+            keepalive: KeepaliveConfig { ping_every: None, idle_timeout: Duration::from_secs(60) },
+            ws_deflate: true,
+            tls: None,
+            cors: CorsOrigins::default(),
+            rate_limits: Arc::new(RateLimitConfig::new(RateLimits { command_per_sec: 0.0, data_per_sec: 0.0 })),
+            http_limiter: Arc::new(HttpRateLimiter::new(HttpRateLimits { read_per_sec: 0.0, write_per_sec: 0.0 })),
+            ws_addr: None,
+            static_dir: None,
+            shutdown: CancellationToken::new(),
+            replay: Default::default(),
+            commands: Default::default(),
+            metrics: Default::default(),
+            webhooks: Default::default(),
+            jobs: Default::default(),
+            telemetry_writer: Arc::new(TelemetryWriter::new(WriterConfig::default(), Some(spool.clone()))),
+            spool,
+            retention: Default::default(),
+            query_chunk: 500,
+            max_query_limit: super::params::DEFAULT_MAX_LIMIT,
+            status: Default::default(),
         }
-    };
+    }
 
-    let kind = root.get("type").and_then(|v| v.as_str());
+    /// Como `for_tests`, con un SQLite en memoria como almacenamiento
+    #[cfg(any(test, feature = "sqlite"))]
+    pub fn for_tests_sqlite() -> Self {
+        let mut ctx = Self::for_tests(None);
+        let store = super::sqlite::SqliteStore::open(":memory:").expect("SQLite en memoria");
+        ctx.questdb = OptionalDb::with_store(test_db_config(), Arc::new(store));
+        ctx
+    }
+}
+
+/// QuestDB en un puerto cerrado: sin conectar nunca, cada operación da `Unavailable`
+#[cfg(any(test, feature = "bench"))]
+pub fn test_db_config() -> super::questdb::QuestDbConfig {
+    super::questdb::QuestDbConfig {
+        host: "127.0.0.1".into(),
+        port: 1,
+        user: "admin".into(),
+        password: "quest".into(),
+        database: "qdb".into(),
+        pool_size: 2,
+        ilp_port: None,
+        write_timeout_ms: 500,
+        read_timeout_ms: 500,
+        allow_newer_schema: false,
+    }
+}
+
+/// Valor de `key` en la query de la URL de upgrade (`a=1&b=2`)
+pub fn query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?.split('&').find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='))
+}
 
-    // request_id top-level o dentro de payload
+/// request_id top-level o dentro de payload
+pub fn extract_request_id(root: &Value) -> Option<&str> {
     let req_id_top = root.get("request_id").and_then(|v| v.as_str());
     let req_id_in_payload = root
         .get("payload")
         .and_then(|p| p.get("request_id"))
         .and_then(|v| v.as_str());
-    let req_id = req_id_top.or(req_id_in_payload);
+    req_id_top.or(req_id_in_payload)
+}
+
+/// Reenvío crudo al ESP32 (registrado en `command_logs`)
+async fn passthrough(ctx: &WsContext, text: &str, req_id: Option<&str>) -> anyhow::Result<()> {
+    ctx.log_command(req_id, "out", text);
+    if let Some(link) = &ctx.esp32 {
+        link.send(text.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Resultado del router de comandos
+enum Routed {
+    /// Comando de alto nivel: ya se envió al ESP32 y se difundió el evento normalizado
+    Handled,
+    /// No es un comando conocido → reenvío crudo
+    Unrecognized,
+    /// Tipo de comando fuera de la lista permitida (`POST /api/security/commands`)
+    Rejected(&'static str),
+    /// `type: "command"` con una forma que no encaja en ninguno de los formatos
+    Malformed { reason: String, got: Value },
+}
+
+/// Comando de alto nivel ya interpretado
+#[derive(Debug, PartialEq)]
+enum HighLevel {
+    LedMany(Vec<u32>, bool),
+    LedAll(bool),
+    LedOne(u32, bool),
+    Mode(String),
+    Motors(bool),
+    MotorSpeed(u32, u32),
+    MotorsSpeed(Vec<u32>, u32),
+    MotorsAllSpeed(u32),
+}
+
+impl HighLevel {
+    /// Nombre usado en la lista de comandos permitidos
+    fn kind(&self) -> &'static str {
+        match self {
+            HighLevel::LedMany(..) | HighLevel::LedAll(_) | HighLevel::LedOne(..) => "led",
+            HighLevel::Mode(_) => "mode",
+            HighLevel::Motors(_) | HighLevel::MotorSpeed(..) | HighLevel::MotorsSpeed(..) | HighLevel::MotorsAllSpeed(_) => "motors",
+        }
+    }
+
+    /// Velocidad pedida a los ESC, si el comando lleva una
+    fn speed(&self) -> Option<u32> {
+        match self {
+            HighLevel::MotorSpeed(_, us) | HighLevel::MotorsSpeed(_, us) | HighLevel::MotorsAllSpeed(us) => Some(*us),
+            _ => None,
+        }
+    }
+
+    /// `true` si el envío UDP al ESP32 salió bien
+    async fn execute(self, ctx: &WsContext, req_id: Option<&str>) -> bool {
+        match self {
+            HighLevel::LedMany(ids, state) => set_led_many(&ids, state, ctx, req_id).await,
+            HighLevel::LedAll(on) => set_led_all(on, ctx, req_id).await,
+            HighLevel::LedOne(id, on) => set_led_one(id, on, ctx, req_id).await,
+            HighLevel::Mode(m) => set_mode(&m, ctx, req_id).await,
+            HighLevel::Motors(on) => set_motors_state(on, ctx, req_id).await,
+            HighLevel::MotorSpeed(id, us) => set_motor_one_speed(id, us, ctx, req_id).await,
+            HighLevel::MotorsSpeed(ids, us) => set_motors_many_speed(&ids, us, ctx, req_id).await,
+            HighLevel::MotorsAllSpeed(us) => set_motors_all_speed(us, ctx, req_id).await,
+        }
+    }
+}
+
+/// Qué hacer con un texto WS antes del reenvío crudo
+#[derive(Debug)]
+enum Classified {
+    /// Comando de alto nivel (con el mensaje, para su `request_id`)
+    Command(HighLevel, Value),
+    /// Cualquier otra cosa: reenvío crudo (incluidos los `type: "command"` que entiende
+    /// el firmware pero no el router, p. ej. `{"command":"calibrate"}` o `{"motor":{"id":1,"state":false}}`)
+    Passthrough,
+    /// JSON roto o comando con tipos incorrectos: error al cliente, nada al ESP32
+    Malformed { reason: String, got: Value },
+}
+
+fn classify(text: &str) -> Classified {
+    let root = match serde_json::from_str::<Value>(text) {
+        Ok(root) => root,
+        // Parece JSON pero no lo es: se avisa en vez de mandar basura al ESP32
+        Err(e) if text.trim_start().starts_with('{') => {
+            return Classified::Malformed { reason: format!("invalid JSON: {e}"), got: Value::String(text.to_owned()) };
+        }
+        Err(_) => return Classified::Passthrough,
+    };
+    if let Some(cmd) = parse_command(&root) {
+        // Mismo rango que `POST /api/command/motor-speed`
+        if let Some(Err(reason)) = cmd.speed().map(commands::check_motor_us) {
+            return Classified::Malformed { reason, got: root };
+        }
+        return Classified::Command(cmd, root);
+    }
+    if root.get("type").and_then(|t| t.as_str()) == Some("command")
+        && let Some(reason) = malformed_reason(&root)
+    {
+        return Classified::Malformed { reason, got: root };
+    }
+    Classified::Passthrough
+}
+
+async fn handle_incoming(
+    text: &str,
+    ctx: &WsContext,
+) -> anyhow::Result<Routed> {
+    let (cmd, root) = match classify(text) {
+        Classified::Command(cmd, root) => (cmd, root),
+        Classified::Passthrough => return Ok(Routed::Unrecognized),
+        Classified::Malformed { reason, got } => return Ok(Routed::Malformed { reason, got }),
+    };
+    if !ctx.commands.allows(cmd.kind()) {
+        return Ok(Routed::Rejected(cmd.kind()));
+    }
+    cmd.execute(ctx, extract_request_id(&root)).await;
+    Ok(Routed::Handled)
+}
+
+/// Por qué un `type: "command"` no reconocido es inválido; `None` si solo es desconocido
+/// (campos que el router no interpreta pero el firmware sí) y puede reenviarse
+fn malformed_reason(root: &Value) -> Option<String> {
+    let payload_top = root.get("payload");
+    let Some(cmd) = payload_top.and_then(|p| p.get("payload")).or(payload_top) else {
+        return Some("missing 'payload'".into());
+    };
+    if !cmd.is_object() {
+        return Some("'payload' must be an object".into());
+    }
+    if let Some(leds) = cmd.get("leds")
+        && let Err(e) = serde_json::from_value::<LedMany>(leds.clone())
+    {
+        return Some(format!("invalid 'leds' (expected {{ids:[u32], state:bool}}): {e}"));
+    }
+    if let Some(led) = cmd.get("led")
+        && !led.is_boolean()
+        && let Err(e) = serde_json::from_value::<LedOne>(led.clone())
+    {
+        return Some(format!("invalid 'led' (expected bool or {{id:u32, state:bool}}): {e}"));
+    }
+    if let Some(mode) = cmd.get("mode")
+        && !mode.is_i64()
+    {
+        return Some(format!("invalid 'mode' (expected integer), got {mode}"));
+    }
+    if let Some(motors) = cmd.get("motors")
+        && !motors.is_boolean()
+        && let Err(e) = serde_json::from_value::<MotorsSpeed>(motors.clone())
+    {
+        return Some(format!("invalid 'motors' (expected bool or {{ids?:[u32], speed:u32}}): {e}"));
+    }
+    if let Some(motor) = cmd.get("motor")
+        && let Err(e) = serde_json::from_value::<MotorOne>(motor.clone())
+    {
+        return Some(format!("invalid 'motor' (expected {{id:u32, speed?:u32, state?:bool}}): {e}"));
+    }
+    if let Some(command) = cmd.get("command")
+        && !command.is_string()
+    {
+        return Some(format!("invalid 'command' (expected string), got {command}"));
+    }
+    None
+}
+
+/// Reconoce los formatos de comando que acepta el WS
+fn parse_command(root: &Value) -> Option<HighLevel> {
+    let kind = root.get("type").and_then(|v| v.as_str());
 
     // Comando puede estar en root.payload o root.payload.payload
     let payload_top = root.get("payload");
@@ -185,97 +1521,547 @@ async fn handle_incoming(
     let env = serde_json::from_value::<Envelope>(root.clone()).ok();
 
     // A) type: "command"
-    if matches!(kind, Some("command")) {
-        if let Some(cmd) = command_node {
-            // leds many
-            if let Some(leds_node) = cmd.get("leds") {
-                if let Ok(many) = serde_json::from_value::<LedMany>(leds_node.clone()) {
-                    set_led_many(&many.ids, many.state, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                    return Ok(());
-                }
-            }
-            // led all / one
-            if let Some(led_node) = cmd.get("led") {
-                if let Some(all) = led_node.as_bool() {
-                    set_led_all(all, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                    return Ok(());
-                }
-                if let Ok(one) = serde_json::from_value::<LedOne>(led_node.clone()) {
-                    set_led_one(one.id, one.state, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                    return Ok(());
-                }
+    if matches!(kind, Some("command"))
+        && let Some(cmd) = command_node
+    {
+        // leds many
+        if let Some(leds_node) = cmd.get("leds")
+            && let Ok(many) = serde_json::from_value::<LedMany>(leds_node.clone())
+        {
+            return Some(HighLevel::LedMany(many.ids, many.state));
+        }
+        // led all / one
+        if let Some(led_node) = cmd.get("led") {
+            if let Some(all) = led_node.as_bool() {
+                return Some(HighLevel::LedAll(all));
             }
-            // mode
-            if let Some(m) = cmd.get("mode").and_then(|v| v.as_i64()) {
-                set_mode(&m.to_string(), esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                return Ok(());
+            if let Ok(one) = serde_json::from_value::<LedOne>(led_node.clone()) {
+                return Some(HighLevel::LedOne(one.id, one.state));
             }
-            // motors
-            if let Some(motors) = cmd.get("motors").and_then(|v| v.as_bool()) {
-                set_motors_state(motors, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                return Ok(());
+        }
+        // mode
+        if let Some(m) = cmd.get("mode").and_then(|v| v.as_i64()) {
+            return Some(HighLevel::Mode(m.to_string()));
+        }
+        // motors on/off o velocidad (de algunos o de todos)
+        if let Some(motors) = cmd.get("motors") {
+            if let Some(on) = motors.as_bool() {
+                return Some(HighLevel::Motors(on));
             }
-            // passthrough prudente
-            if let Some(sock) = &esp32_socket {
-                sock.send_to(text.as_bytes(), remote_addr).await?;
+            if let Ok(MotorsSpeed { ids, speed: Some(us) }) = serde_json::from_value::<MotorsSpeed>(motors.clone()) {
+                return Some(match ids {
+                    Some(ids) => HighLevel::MotorsSpeed(ids, us),
+                    None => HighLevel::MotorsAllSpeed(us),
+                });
             }
-            return Ok(());
         }
+        // velocidad de un motor (`state` va tal cual al firmware)
+        if let Some(motor) = cmd.get("motor")
+            && let Ok(MotorOne { id, speed: Some(us), .. }) = serde_json::from_value::<MotorOne>(motor.clone())
+        {
+            return Some(HighLevel::MotorSpeed(id, us));
+        }
+        // passthrough prudente
+        return None;
     }
 
     // B) Formatos alternativos (Envelope)
-    if let Some(env) = env {
-        if matches!(env.kind.as_deref(), Some("command")) {
-            if let Some(p) = env.payload {
-                if let Some(m) = p.mode {
-                    set_mode(&m.to_string(), esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                    return Ok(());
-                }
-                if let Some(motors) = p.motors {
-                    set_motors_state(motors, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                    return Ok(());
-                }
-                if let Some(many) = p.leds {
-                    set_led_many(&many.ids, many.state, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                    return Ok(());
-                }
-                if let Some(led_val) = p.led {
-                    if let Some(all) = led_val.as_bool() {
-                        set_led_all(all, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                        return Ok(());
-                    }
-                    if let Ok(one) = serde_json::from_value::<LedOne>(led_val) {
-                        set_led_one(one.id, one.state, esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-                        return Ok(());
-                    }
-                }
+    let env = env?;
+    if matches!(env.kind.as_deref(), Some("command"))
+        && let Some(p) = env.payload
+    {
+        if let Some(m) = p.mode {
+            return Some(HighLevel::Mode(m.to_string()));
+        }
+        if let Some(motors) = p.motors {
+            return Some(HighLevel::Motors(motors));
+        }
+        if let Some(many) = p.leds {
+            return Some(HighLevel::LedMany(many.ids, many.state));
+        }
+        if let Some(led_val) = p.led {
+            if let Some(all) = led_val.as_bool() {
+                return Some(HighLevel::LedAll(all));
+            }
+            if let Ok(one) = serde_json::from_value::<LedOne>(led_val) {
+                return Some(HighLevel::LedOne(one.id, one.state));
             }
         }
+    }
+
+    if let Some(m) = env.mode {
+        return Some(HighLevel::Mode(m.to_string()));
+    }
+
+    match env.command.as_deref()? {
+        "ON_LED"     => Some(HighLevel::LedAll(true)),
+        "OFF_LED"    => Some(HighLevel::LedAll(false)),
+        "ON_MOTORS"  => Some(HighLevel::Motors(true)),
+        "OFF_MOTORS" => Some(HighLevel::Motors(false)),
+        // JSON válido pero no reconocido → passthrough
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio_tungstenite::client_async;
+    use super::super::transport::UdpTransport;
+    use super::super::udp::run_receiver;
 
-        if let Some(m) = env.mode {
-            set_mode(&m.to_string(), esp32_socket.clone(), remote_addr, ws_tx, req_id).await;
-            return Ok(());
+    type Client = WebSocketStream<tokio::io::DuplexStream>;
+
+    /// Sesión WS completa (handshake incluido) sobre un `duplex` en memoria
+    pub(crate) async fn connect(ctx: &WsContext, query: &str) -> Client {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        tokio::spawn(serve_connection(server, addr, ctx.clone(), ctx.shutdown.child_token()));
+        let (mut ws, _) = client_async(format!("ws://localhost/{query}"), client).await.unwrap();
+        let hello = next_json(&mut ws).await;
+        assert_eq!(hello["type"], "hello");
+        ws
+    }
+
+    /// Siguiente frame de texto como JSON (falla tras 2 s sin nada)
+    pub(crate) async fn next_json(ws: &mut Client) -> Value {
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(2), ws.next()).await
+                .expect("sin frames del servidor").expect("WS cerrado").unwrap();
+            if let Message::Text(text) = msg {
+                return serde_json::from_str(&text).unwrap();
+            }
         }
+    }
 
-        if let Some(cmd) = env.command.as_deref() {
-            match cmd {
-                "ON_LED"     => set_led_all(true,  esp32_socket.clone(), remote_addr, ws_tx, req_id).await,
-                "OFF_LED"    => set_led_all(false, esp32_socket.clone(), remote_addr, ws_tx, req_id).await,
-                "ON_MOTORS"  => set_motors_state(true,  esp32_socket.clone(), remote_addr, ws_tx, req_id).await,
-                "OFF_MOTORS" => set_motors_state(false, esp32_socket.clone(), remote_addr, ws_tx, req_id).await,
-                _ => {
-                    if let Some(sock) = &esp32_socket {
-                        sock.send_to(text.as_bytes(), remote_addr).await?;
-                    }
+    /// Primer frame de tipo `kind`, saltando el resto (eventos, snapshots...)
+    async fn next_of(ws: &mut Client, kind: &str) -> Value {
+        loop {
+            let v = next_json(ws).await;
+            if v["type"] == kind {
+                return v;
+            }
+        }
+    }
+
+    /// ESP32 falso en un socket UDP local, con el puente recibiendo en otro
+    async fn esp32_link() -> (UdpSocket, WsContext) {
+        let esp32 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bridge = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let link: Arc<dyn TelemetryTransport> = Arc::new(UdpTransport::new(bridge, esp32.local_addr().unwrap()).unwrap());
+        let ctx = WsContext::for_tests(Some(link.clone()));
+        tokio::spawn(run_receiver(link, ctx.clone()));
+        (esp32, ctx)
+    }
+
+    async fn datagram(esp32: &UdpSocket) -> (Value, SocketAddr) {
+        let mut buf = [0u8; 2048];
+        let (n, from) = tokio::time::timeout(Duration::from_secs(2), esp32.recv_from(&mut buf)).await
+            .expect("el ESP32 no recibió nada").unwrap();
+        (serde_json::from_slice(&buf[..n]).unwrap(), from)
+    }
+
+    #[tokio::test]
+    async fn ws_command_reaches_esp32_and_ack_returns_to_sender() {
+        let (esp32, ctx) = esp32_link().await;
+        let mut sender = connect(&ctx, "").await;
+        let mut other = connect(&ctx, "").await;
+
+        let cmd = r#"{"type":"command","payload":{"command":"calibrate","target":"mpu"},"request_id":"r-1"}"#;
+        sender.send(Message::Text(cmd.into())).await.unwrap();
+        let (got, bridge) = datagram(&esp32).await;
+        assert_eq!(got, serde_json::from_str::<Value>(cmd).unwrap());
+
+        esp32.send_to(br#"{"type":"ack","request_id":"r-1","ok":true}"#, bridge).await.unwrap();
+        let ack = next_of(&mut sender, "ack").await;
+        assert_eq!(ack["request_id"], "r-1");
+        assert_eq!(ack["ok"], true);
+        // El ack va solo a quien mandó el comando
+        assert!(tokio::time::timeout(Duration::from_millis(300), next_of(&mut other, "ack")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ws_high_level_commands_produce_datagrams_and_events() {
+        let (esp32, ctx) = esp32_link().await;
+        let mut ws = connect(&ctx, "").await;
+        let cases = [
+            (r#"{"type":"command","payload":{"mode":2},"request_id":"m"}"#, serde_json::json!({"mode": 2}), "modo"),
+            (r#"{"type":"command","payload":{"motors":true},"request_id":"a"}"#, serde_json::json!({"motors": true}), "motors"),
+            (r#"{"type":"command","payload":{"led":{"id":3,"state":true}},"request_id":"l"}"#, serde_json::json!({"led": {"id": 3, "state": true}}), "led"),
+            (r#"{"type":"command","payload":{"motor":{"id":2,"speed":1300}},"request_id":"s"}"#, serde_json::json!({"motor": {"id": 2, "speed": 1300}}), "ack"),
+            (r#"{"command":"OFF_LED","request_id":"o"}"#, serde_json::json!({"led": false}), "led"),
+        ];
+        for (text, payload, event) in cases {
+            ws.send(Message::Text(text.into())).await.unwrap();
+            let (got, _) = datagram(&esp32).await;
+            assert_eq!(got, serde_json::json!({"type": "command", "payload": payload}), "{text}");
+            next_of(&mut ws, event).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_command_is_answered_and_not_forwarded() {
+        let (esp32, ctx) = esp32_link().await;
+        let mut ws = connect(&ctx, "").await;
+        ws.send(Message::Text(r#"{"type":"command","payload":{"mode":"fast"},"request_id":"x"}"#.into())).await.unwrap();
+        let err = next_of(&mut ws, "error").await;
+        assert_eq!(err["code"], "malformed_command");
+        assert_eq!(err["request_id"], "x");
+        let mut buf = [0u8; 64];
+        assert!(tokio::time::timeout(Duration::from_millis(300), esp32.recv_from(&mut buf)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn broadcast_counters_hold_under_10k_messages() {
+        const N: u64 = 10_000;
+        let ctx = WsContext::for_tests(None);
+        let telemetry = |i: u64| serde_json::json!({ "type": "telemetry", "payload": { "seq": i } }).to_string();
+
+        // Sin nadie suscrito nada cuenta como enviado
+        for i in 0..N {
+            assert_eq!(ctx.publish(telemetry(i)), None);
+        }
+        let s = ctx.ws_stats.snapshot();
+        assert_eq!((s.sent, s.no_receivers, s.lagged), (0, N, 0));
+
+        // Un cliente que no lee mientras se publica de golpe: se queda solo con lo que
+        // cabe en el canal y el resto consta como perdido, en un único aviso
+        let mut ws = connect(&ctx, "").await;
+        for i in 0..N {
+            assert!(ctx.publish(telemetry(i)).is_some());
+        }
+        let warning = next_of(&mut ws, "warning").await;
+        // tokio redondea la capacidad del canal a potencia de dos
+        let capacity = s.capacity.next_power_of_two() as u64;
+        assert_eq!(warning["dropped"], N - capacity);
+
+        let s = ctx.ws_stats.snapshot();
+        assert_eq!((s.sent, s.no_receivers), (N, N));
+        assert_eq!((s.lagged, s.lagged_events), (N - capacity, 1));
+        assert_eq!(ctx.ws_stats.dropped(), N - capacity);
+    }
+
+    /// Primer frame de cierre; el socket tiene que terminar justo después
+    async fn close_frame(ws: &mut Client) -> (CloseCode, String) {
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(2), ws.next()).await
+                .expect("sin cierre del servidor").expect("socket cerrado sin frame de cierre").unwrap();
+            if let Message::Close(frame) = msg {
+                let frame = frame.expect("cierre sin código");
+                assert!(matches!(ws.next().await, None | Some(Err(_))));
+                return (frame.code, frame.reason.into_owned());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn server_side_disconnects_send_a_close_code_and_reason() {
+        let ctx = WsContext::for_tests(None);
+        let mut kicked = connect(&ctx, "").await;
+        let mut old = connect(&ctx, "").await;
+        let mut rest = connect(&ctx, "").await;
+
+        let id = ctx.clients.list()[0].id;
+        assert!(ctx.clients.disconnect(id));
+        assert_eq!(close_frame(&mut kicked).await, (CloseCode::Policy, "kicked by admin".into()));
+
+        old.send(Message::Text(format!(r#"{{"type":"hello","proto":{}}}"#, WS_PROTO_VERSION + 1))).await.unwrap();
+        let (code, reason) = close_frame(&mut old).await;
+        assert_eq!(code, CloseCode::Protocol);
+        assert!(reason.starts_with("unsupported proto"), "{reason}");
+
+        ctx.shutdown.cancel();
+        assert_eq!(close_frame(&mut rest).await, (CloseCode::Away, "server shutdown".into()));
+        assert!(ctx.clients.drain(Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn msgpack_client_round_trips_telemetry_and_commands() {
+        async fn next_binary(ws: &mut Client) -> Value {
+            loop {
+                let msg = tokio::time::timeout(Duration::from_secs(2), ws.next()).await
+                    .expect("sin frames del servidor").expect("WS cerrado").unwrap();
+                match msg {
+                    Message::Binary(bytes) => return rmp_serde::from_slice(&bytes).unwrap(),
+                    Message::Text(text) => panic!("frame de texto tras pedir msgpack: {text}"),
+                    _ => {}
                 }
             }
-            return Ok(());
         }
+        let (esp32, ctx) = esp32_link().await;
+        let mut ws = connect(&ctx, "").await;
+        let mut plain = connect(&ctx, "").await;
+        ws.send(Message::Text(r#"{"type":"format","encoding":"msgpack"}"#.into())).await.unwrap();
+        assert_eq!(next_binary(&mut ws).await, serde_json::json!({ "type": "format", "encoding": "msgpack" }));
+
+        let telemetry = serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": -1.25, "seq": 7, "ok": true } });
+        ctx.publish(telemetry.to_string());
+        assert_eq!(next_binary(&mut ws).await, telemetry);
+        // El resto de clientes sigue en JSON
+        assert_eq!(next_of(&mut plain, "telemetry").await, telemetry);
+
+        let cmd = serde_json::json!({ "type": "command", "payload": { "command": "calibrate", "target": "mpu" }, "request_id": "mp-1" });
+        ws.send(Message::Binary(rmp_serde::to_vec_named(&cmd).unwrap())).await.unwrap();
+        assert_eq!(datagram(&esp32).await.0, cmd);
     }
 
-    // JSON válido pero no reconocido → passthrough
-    if let Some(sock) = &esp32_socket {
-        sock.send_to(text.as_bytes(), remote_addr).await?;
+    #[tokio::test]
+    async fn lagged_client_gets_a_warning_and_keeps_streaming() {
+        let ctx = WsContext::for_tests(None);
+        let telemetry = |i: u64| serde_json::json!({ "type": "telemetry", "payload": { "seq": i } }).to_string();
+        let mut ws = connect(&ctx, "").await;
+        let burst = 4 * ctx.ws_stats.snapshot().capacity as u64;
+        for i in 0..burst {
+            ctx.publish(telemetry(i));
+        }
+        assert!(next_of(&mut ws, "warning").await["dropped"].as_u64().unwrap() > 0);
+
+        // Tras el aviso sigue llegando lo más reciente, en orden y hasta el final
+        let mut last = None;
+        while last != Some(burst - 1) {
+            let seq = next_of(&mut ws, "telemetry").await["payload"]["seq"].as_u64().unwrap();
+            assert!(last.is_none_or(|l| seq > l), "{seq} tras {last:?}");
+            last = Some(seq);
+        }
+        ctx.publish(telemetry(burst));
+        assert_eq!(next_of(&mut ws, "telemetry").await["payload"]["seq"], burst);
+        assert_eq!(ctx.ws_stats.snapshot().lagged_events, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_clients_get_their_own_buckets_and_registry_entries() {
+        const CLIENTS: usize = 8;
+        const SENT: usize = 10;
+        // 2 comandos/s: ráfaga de 2 y un token cada 500 ms, mucho más de lo que tarda la prueba
+        const BURST: usize = 2;
+        let (esp32, ctx) = esp32_link().await;
+        ctx.rate_limits.set(super::super::ratelimit::RateLimits { command_per_sec: BURST as f64, data_per_sec: 0.0 });
+
+        let ready = Arc::new(tokio::sync::Barrier::new(CLIENTS + 1));
+        let tasks: Vec<_> = (0..CLIENTS).map(|c| {
+            let (ctx, ready) = (ctx.clone(), ready.clone());
+            tokio::spawn(async move {
+                let mut ws = connect(&ctx, "").await;
+                ready.wait().await;
+                ready.wait().await;
+                for i in 0..SENT {
+                    let cmd = format!(r#"{{"type":"command","payload":{{"command":"calibrate"}},"request_id":"c{c}-{i}"}}"#);
+                    ws.send(Message::Text(cmd)).await.unwrap();
+                }
+                let mut limited = 0;
+                while limited < SENT - BURST {
+                    let err = next_of(&mut ws, "error").await;
+                    assert_eq!(err["code"], "rate_limited");
+                    assert!(err["request_id"].as_str().unwrap().starts_with(&format!("c{c}-")), "{err}");
+                    limited += 1;
+                }
+                ws
+            })
+        }).collect();
+
+        // Todos conectados a la vez: una entrada por cliente, con ids distintos
+        ready.wait().await;
+        let ids: std::collections::HashSet<_> = ctx.clients.list().iter().map(|c| c.id).collect();
+        assert_eq!((ctx.clients.len(), ids.len()), (CLIENTS, CLIENTS));
+        ready.wait().await;
+
+        let sockets: Vec<_> = futures_util::future::join_all(tasks).await.into_iter().map(Result::unwrap).collect();
+        // Al ESP32 solo llega la ráfaga de cada conexión
+        for _ in 0..CLIENTS * BURST {
+            datagram(&esp32).await;
+        }
+        let mut buf = [0u8; 2048];
+        assert!(tokio::time::timeout(Duration::from_millis(300), esp32.recv_from(&mut buf)).await.is_err());
+
+        drop(sockets);
+        assert!(ctx.clients.drain(Duration::from_secs(2)).await);
+        assert!(ctx.clients.is_empty());
+    }
+
+    /// Upgrade a mano (ofreciendo permessage-deflate) para leer las cabeceras del 101
+    /// antes de que llegue ningún frame
+    async fn connect_deflate(ctx: &WsContext) -> (WebSocketStream<DeflateStream<tokio::io::DuplexStream>>, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let addr: SocketAddr = "127.0.0.1:50001".parse().unwrap();
+        tokio::spawn(serve_connection(server, addr, ctx.clone(), ctx.shutdown.child_token()));
+        client.write_all(concat!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n",
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n",
+            "Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n",
+        ).as_bytes()).await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        let mut io = DeflateStream::new(client);
+        if head.contains("permessage-deflate") {
+            let agreed = deflate::negotiate(["permessage-deflate"]).unwrap();
+            io.enable(agreed, Default::default());
+        }
+        (WebSocketStream::from_raw_socket(io, Role::Client, None).await, head)
+    }
+
+    /// Línea de estado de un upgrade a mano con `Origin` opcional
+    async fn upgrade_status(ctx: &WsContext, origin: Option<&str>) -> String {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let addr: SocketAddr = "127.0.0.1:50002".parse().unwrap();
+        tokio::spawn(serve_connection(server, addr, ctx.clone(), ctx.shutdown.child_token()));
+        let origin = origin.map(|o| format!("Origin: {o}\r\n")).unwrap_or_default();
+        let mut client = tokio::io::BufReader::new(client);
+        client.get_mut().write_all(format!(concat!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n",
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+        ), origin).as_bytes()).await.unwrap();
+        let mut status = String::new();
+        client.read_line(&mut status).await.unwrap();
+        status.trim_end().to_string()
+    }
+
+    #[tokio::test]
+    async fn upgrade_is_refused_for_origins_outside_the_allowlist() {
+        let mut ctx = WsContext::for_tests(None);
+        ctx.auth = Arc::new(AuthConfig {
+            allowed_origins: Some(vec!["http://groundstation:5173".into()]),
+            ..(*ctx.auth).clone()
+        });
+        assert_eq!(upgrade_status(&ctx, Some("http://groundstation:5173")).await, "HTTP/1.1 101 Switching Protocols");
+        assert_eq!(upgrade_status(&ctx, Some("http://evil.lan")).await, "HTTP/1.1 403 Forbidden");
+        assert_eq!(upgrade_status(&ctx, None).await, "HTTP/1.1 101 Switching Protocols");
+
+        ctx.auth = Arc::new(AuthConfig { require_origin: true, ..(*ctx.auth).clone() });
+        assert_eq!(upgrade_status(&ctx, None).await, "HTTP/1.1 403 Forbidden");
+    }
+
+    #[tokio::test]
+    async fn deflate_is_negotiated_and_reported_per_client() {
+        let ctx = WsContext::for_tests(None);
+        let (mut ws, head) = connect_deflate(&ctx).await;
+        assert!(head.to_ascii_lowercase().contains("sec-websocket-extensions: permessage-deflate\r\n"), "{head}");
+        let Some(Ok(Message::Text(hello))) = ws.next().await else { panic!("sin hello") };
+        assert_eq!(serde_json::from_str::<Value>(&hello).unwrap()["type"], "hello");
+
+        // Un cliente sin la extensión sigue igual que antes
+        let _plain = connect(&ctx, "").await;
+        let clients = ctx.clients.list();
+        assert_eq!(clients.len(), 2);
+        let ratio = clients[0].compression.as_ref().expect("sin compresión negociada").ratio;
+        assert!(ratio.is_some_and(|r| r > 1.0), "{ratio:?}");
+        assert!(clients[1].compression.is_none());
+
+        // Los comandos del cliente también pueden ir comprimidos
+        ws.send(Message::Text(r#"{"type":"subscribe","topics":["telemetry"]}"#.into())).await.unwrap();
+        let Some(Ok(Message::Text(reply))) = ws.next().await else { panic!("sin respuesta") };
+        assert_eq!(serde_json::from_str::<Value>(&reply).unwrap()["type"], "subscriptions");
+    }
+
+    #[tokio::test]
+    async fn deflate_can_be_switched_off() {
+        let mut ctx = WsContext::for_tests(None);
+        ctx.ws_deflate = false;
+        let (mut ws, head) = connect_deflate(&ctx).await;
+        assert!(!head.to_ascii_lowercase().contains("sec-websocket-extensions"), "{head}");
+        let Some(Ok(Message::Text(hello))) = ws.next().await else { panic!("sin hello") };
+        assert_eq!(serde_json::from_str::<Value>(&hello).unwrap()["type"], "hello");
+        assert!(ctx.clients.list()[0].compression.is_none());
+    }
+
+    #[tokio::test]
+    async fn query_rejects_bad_range_and_limit_like_the_http_api() {
+        let mut ctx = WsContext::for_tests_sqlite();
+        ctx.max_query_limit = 100;
+        let mut ws = connect(&ctx, "").await;
+        let cases = [
+            (r#""from":"ayer""#, "Invalid 'from'"),
+            (r#""from":"2024-05-02T00:00:00Z","to":"2024-05-01T00:00:00Z""#, "'from' must not be after 'to'"),
+            (r#""limit":0"#, "'limit' must be positive"),
+            (r#""limit":101"#, "'limit' must be at most 100"),
+        ];
+        for (extra, reason) in cases {
+            let q = format!(r#"{{"type":"query","request_id":"q","query":"series","flight_id":"f",{extra}}}"#);
+            ws.send(Message::Text(q)).await.unwrap();
+            let err = next_of(&mut ws, "query_error").await;
+            assert_eq!(err["request_id"], "q");
+            assert!(err["reason"].as_str().unwrap().starts_with(reason), "{extra} → {err}");
+        }
+
+        // Con el intervalo bien formado la consulta llega a la BD
+        let q = r#"{"type":"query","request_id":"ok","query":"series","flight_id":"f","from":"2024-05-01T00:00:00Z","limit":100}"#;
+        ws.send(Message::Text(q.into())).await.unwrap();
+        let done = next_of(&mut ws, "query_result").await;
+        assert_eq!((done["request_id"].as_str(), done["done"].as_bool()), (Some("ok"), Some(true)));
+    }
+
+    fn malformed(text: &str) -> String {
+        match classify(text) {
+            Classified::Malformed { reason, .. } => reason,
+            other => panic!("{text} → {other:?}, se esperaba malformed_command"),
+        }
+    }
+
+    fn command(text: &str) -> HighLevel {
+        match classify(text) {
+            Classified::Command(cmd, _) => cmd,
+            other => panic!("{text} → {other:?}, se esperaba un comando"),
+        }
+    }
+
+    fn passthrough(text: &str) {
+        assert!(matches!(classify(text), Classified::Passthrough), "{text} debería reenviarse tal cual");
+    }
+
+    #[test]
+    fn malformed_shapes_are_rejected() {
+        assert!(malformed(r#"{"type":"command","payload":{"mode":2}"#).starts_with("invalid JSON"));
+        assert_eq!(malformed(r#"{"type":"command"}"#), "missing 'payload'");
+        assert_eq!(malformed(r#"{"type":"command","payload":5}"#), "'payload' must be an object");
+        assert!(malformed(r#"{"type":"command","payload":{"leds":{"ids":"1,2","state":true}}}"#).starts_with("invalid 'leds'"));
+        assert!(malformed(r#"{"type":"command","payload":{"led":"on"}}"#).starts_with("invalid 'led'"));
+        assert!(malformed(r#"{"type":"command","payload":{"mode":"fast"}}"#).starts_with("invalid 'mode'"));
+        assert!(malformed(r#"{"type":"command","payload":{"motors":{"speed":"max"}}}"#).starts_with("invalid 'motors'"));
+        assert!(malformed(r#"{"type":"command","payload":{"motor":{"speed":1200}}}"#).starts_with("invalid 'motor'"));
+        assert!(malformed(r#"{"type":"command","payload":{"command":7}}"#).starts_with("invalid 'command'"));
+    }
+
+    #[test]
+    fn malformed_echoes_the_message() {
+        let Classified::Malformed { got, .. } = classify(r#"{"type":"command","payload":{"mode":"x"}}"#) else { panic!() };
+        assert_eq!(got["payload"]["mode"], "x");
+        let Classified::Malformed { got, .. } = classify("{not json") else { panic!() };
+        assert_eq!(got, "{not json");
+    }
+
+    #[test]
+    fn motor_speed_out_of_range_is_malformed_like_over_http() {
+        let expected = commands::check_motor_us(2500).unwrap_err();
+        assert_eq!(malformed(r#"{"type":"command","payload":{"motor":{"id":2,"speed":2500}}}"#), expected);
+        assert_eq!(malformed(r#"{"type":"command","payload":{"motors":{"ids":[1,3],"speed":2500}}}"#), expected);
+        assert!(malformed(r#"{"type":"command","payload":{"motors":{"speed":999}}}"#).starts_with("Speed 999 µs out of range"));
+        assert_eq!(command(r#"{"type":"command","payload":{"motors":{"speed":2000}}}"#), HighLevel::MotorsAllSpeed(2000));
+    }
+
+    #[test]
+    fn dashboard_motor_commands_are_routed() {
+        assert_eq!(command(r#"{"type":"command","payload":{"motor":{"id":2,"speed":1300}}}"#), HighLevel::MotorSpeed(2, 1300));
+        assert_eq!(command(r#"{"type":"command","payload":{"motors":{"ids":[1,3],"speed":1250}}}"#), HighLevel::MotorsSpeed(vec![1, 3], 1250));
+        assert_eq!(command(r#"{"type":"command","payload":{"motors":{"speed":1100}}}"#), HighLevel::MotorsAllSpeed(1100));
+        assert_eq!(command(r#"{"type":"command","payload":{"motors":true}}"#), HighLevel::Motors(true));
+        assert_eq!(command(r#"{"type":"command","payload":{"payload":{"mode":2}}}"#), HighLevel::Mode("2".into()));
+        assert_eq!(command(r#"{"type":"command","payload":{"leds":{"ids":[1],"state":false}}}"#), HighLevel::LedMany(vec![1], false));
+        assert_eq!(command(r#"{"command":"ON_MOTORS"}"#), HighLevel::Motors(true));
+    }
+
+    #[test]
+    fn firmware_envelope_commands_pass_through() {
+        passthrough(r#"{"type":"command","payload":{"motor":{"id":1,"state":false}},"request_id":"1"}"#);
+        passthrough(r#"{"type":"command","payload":{"command":"calibrate","target":"mpu","state":"on"}}"#);
+        passthrough(r#"{"type":"command","payload":{"command":"orient","direction":"north"}}"#);
+        passthrough(r#"{"type":"telemetry","payload":{"AngleRoll":1.5}}"#);
+        passthrough(r#"{"command":"REBOOT"}"#);
+        passthrough("PING");
     }
-    Ok(())
 }
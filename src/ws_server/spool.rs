@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use super::questdb::{DbError, LogRow, OptionalDb, TypedValues};
+use super::series::field_value;
+
+/// Filas por `INSERT` al reimportar
+const REPLAY_BATCH: usize = 500;
+/// Cada cuánto mira el importador de fondo si hay algo que reimportar
+const REPLAY_EVERY: Duration = Duration::from_secs(30);
+
+/// Una fila de telemetría en el spool: el payload va tal cual se iba a guardar
+#[derive(Serialize, Deserialize)]
+struct SpoolLine {
+    flight_id: String,
+    ts: DateTime<Utc>,
+    payload: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SpoolSnapshot {
+    /// Bytes pendientes de reimportar en `dir`
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub spilled_rows: u64,
+    pub replayed_rows: u64,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ReplayReport {
+    /// Ficheros terminados (movidos a `done/`)
+    pub files: usize,
+    pub rows: u64,
+    /// Filas que ya estaban en la BD (de un lote que falló a medias o por timeout): no se repiten
+    pub already_stored: u64,
+    /// Líneas que no se pudieron leer (se saltan)
+    pub skipped: u64,
+}
+
+/// Respaldo en disco de la telemetría que QuestDB no acepta: JSONL de solo-añadir por
+/// vuelo en `dir` (`<flight_id>.jsonl`). Al reimportar, cada fichero se renombra primero
+/// (`<flight_id>@<ms>.jsonl`) para que lo nuevo vaya a otro, y su avance se guarda en
+/// `<fichero>.offset` tras cada lote: un reimportado a medias sigue donde iba sin duplicar
+/// filas. Los terminados pasan a `done/`.
+///
+/// Un lote que llega aquí puede estar escrito en parte (falló un tramo posterior del
+/// `INSERT` o el timeout cortó uno ya confirmado), así que al reimportar se salta lo que
+/// ya esté en `flight_logs` / `flight_metrics` (ver `missing_rows`)
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Escrituras y renombrados del directorio, de uno en uno
+    files: tokio::sync::Mutex<()>,
+    /// Un solo reimportado a la vez (el de fondo o el de `POST /api/spool/replay`)
+    replaying: tokio::sync::Mutex<()>,
+    bytes: AtomicU64,
+    over_cap: AtomicBool,
+    spilled_rows: AtomicU64,
+    replayed_rows: AtomicU64,
+}
+
+impl Spool {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        let dir = dir.into();
+        let bytes = pending_bytes(&dir);
+        if bytes > 0 {
+            info!("💾 Spool con {bytes} bytes pendientes en {}", dir.display());
+        }
+        Self {
+            dir,
+            max_bytes,
+            files: Default::default(),
+            replaying: Default::default(),
+            bytes: AtomicU64::new(bytes),
+            over_cap: AtomicBool::new(false),
+            spilled_rows: AtomicU64::new(0),
+            replayed_rows: AtomicU64::new(0),
+        }
+    }
+
+    pub fn snapshot(&self) -> SpoolSnapshot {
+        SpoolSnapshot {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            max_bytes: self.max_bytes,
+            spilled_rows: self.spilled_rows.load(Ordering::Relaxed),
+            replayed_rows: self.replayed_rows.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Añade las filas al fichero de su vuelo
+    pub async fn spill(&self, rows: &[LogRow]) -> std::io::Result<()> {
+        let mut by_flight: HashMap<&str, String> = HashMap::new();
+        for row in rows {
+            let line = SpoolLine { flight_id: row.flight_id.clone(), ts: row.ts, payload: row.payload.clone() };
+            let buf = by_flight.entry(row.flight_id.as_str()).or_default();
+            buf.push_str(&serde_json::to_string(&line).unwrap_or_default());
+            buf.push('\n');
+        }
+        let _files = self.files.lock().await;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        for (flight_id, buf) in by_flight {
+            let path = self.dir.join(format!("{}.jsonl", file_stem(flight_id)));
+            let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+            file.write_all(buf.as_bytes()).await?;
+            file.flush().await?;
+            self.bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        }
+        self.spilled_rows.fetch_add(rows.len() as u64, Ordering::Relaxed);
+        self.check_cap();
+        Ok(())
+    }
+
+    /// Avisa una vez al pasar de `max_bytes` (y otra si vuelve a pasar tras bajar)
+    fn check_cap(&self) {
+        let over = self.bytes.load(Ordering::Relaxed) > self.max_bytes;
+        if over && !self.over_cap.swap(true, Ordering::Relaxed) {
+            warn!("⚠️  El spool de {} supera {} bytes: QuestDB lleva tiempo sin aceptar telemetría", self.dir.display(), self.max_bytes);
+        } else if !over {
+            self.over_cap.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Reimporta todo lo pendiente con sus timestamps originales. Si QuestDB falla a mitad,
+    /// lo hecho queda anotado en el `.offset` y el error se devuelve
+    pub async fn replay(&self, db: &OptionalDb) -> Result<ReplayReport, DbError> {
+        let _replaying = self.replaying.lock().await;
+        let io = |e: std::io::Error| DbError::Query(format!("spool: {e}"));
+        self.claim().await.map_err(io)?;
+
+        let mut report = ReplayReport::default();
+        let mut claimed = Vec::new();
+        if let Ok(mut dir) = tokio::fs::read_dir(&self.dir).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.contains('@') && name.ends_with(".jsonl") {
+                    claimed.push(entry.path());
+                }
+            }
+        }
+        claimed.sort();
+        let mut typed: HashMap<String, Option<Arc<[String]>>> = HashMap::new();
+        for path in claimed {
+            self.replay_file(db, &path, &mut typed, &mut report).await?;
+            report.files += 1;
+        }
+        self.bytes.store(pending_bytes(&self.dir), Ordering::Relaxed);
+        self.check_cap();
+        if report.rows > 0 {
+            info!("💾 Spool reimportado: {} filas de {} fichero(s)", report.rows, report.files);
+        }
+        Ok(report)
+    }
+
+    /// Renombra los ficheros en uso para que las filas nuevas vayan a otros
+    async fn claim(&self) -> std::io::Result<()> {
+        let _files = self.files.lock().await;
+        let Ok(mut dir) = tokio::fs::read_dir(&self.dir).await else { return Ok(()) };
+        let now = Utc::now().timestamp_millis();
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(stem) = name.strip_suffix(".jsonl")
+                && !stem.contains('@')
+            {
+                tokio::fs::rename(entry.path(), self.dir.join(format!("{stem}@{now}.jsonl"))).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn replay_file(
+        &self,
+        db: &OptionalDb,
+        path: &Path,
+        typed: &mut HashMap<String, Option<Arc<[String]>>>,
+        report: &mut ReplayReport,
+    ) -> Result<(), DbError> {
+        let io = |e: std::io::Error| DbError::Query(format!("spool {}: {e}", path.display()));
+        let offset_path = path.with_extension("jsonl.offset");
+        let mut offset: u64 = tokio::fs::read_to_string(&offset_path).await.ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0);
+        let mut file = tokio::fs::File::open(path).await.map_err(io)?;
+        file.seek(std::io::SeekFrom::Start(offset)).await.map_err(io)?;
+        let mut lines = tokio::io::BufReader::new(file);
+
+        loop {
+            let mut batch = Vec::with_capacity(REPLAY_BATCH);
+            let mut read = 0u64;
+            let mut line = String::new();
+            while batch.len() < REPLAY_BATCH {
+                line.clear();
+                let n = lines.read_line(&mut line).await.map_err(io)?;
+                // Una línea sin `\n` final es una escritura a medias: se deja para la próxima
+                if n == 0 || !line.ends_with('\n') {
+                    break;
+                }
+                read += n as u64;
+                match serde_json::from_str::<SpoolLine>(&line) {
+                    Ok(l) => batch.push(l),
+                    Err(_) => report.skipped += 1,
+                }
+            }
+            if read == 0 {
+                break;
+            }
+            let mut rows = Vec::with_capacity(batch.len());
+            for l in batch {
+                if !typed.contains_key(&l.flight_id) {
+                    let fields = db.typed_fields(&l.flight_id).await?;
+                    typed.insert(l.flight_id.clone(), (!fields.is_empty()).then(|| fields.into()));
+                }
+                rows.push(to_row(l, typed));
+            }
+            let total = rows.len() as u64;
+            let inserted = insert_missing(db, rows).await? as u64;
+            offset += read;
+            tokio::fs::write(&offset_path, offset.to_string()).await.map_err(io)?;
+            report.rows += inserted;
+            report.already_stored += total - inserted;
+            self.replayed_rows.fetch_add(inserted, Ordering::Relaxed);
+        }
+
+        // Terminado: a `done/` (salvo que acabe en una línea a medias, que no debería pasar
+        // en un fichero ya renombrado)
+        let done = self.dir.join("done");
+        tokio::fs::create_dir_all(&done).await.map_err(io)?;
+        if let Some(name) = path.file_name() {
+            tokio::fs::rename(path, done.join(name)).await.map_err(io)?;
+        }
+        let _ = tokio::fs::remove_file(&offset_path).await;
+        Ok(())
+    }
+}
+
+/// Inserta lo que de `rows` no esté ya guardado. Devuelve las filas de `flight_logs` que faltaban
+async fn insert_missing(db: &OptionalDb, rows: Vec<LogRow>) -> Result<usize, DbError> {
+    let mut by_flight: HashMap<String, Vec<LogRow>> = HashMap::new();
+    for row in rows {
+        by_flight.entry(row.flight_id.clone()).or_default().push(row);
+    }
+    let mut inserted = 0;
+    for (flight_id, rows) in by_flight {
+        let from = rows.iter().map(|r| r.ts).min().unwrap_or_default();
+        let to = rows.iter().map(|r| r.ts).max().unwrap_or_default();
+        let stored = db.stored_rows(&flight_id, from, to).await?;
+        let metric_ts = if rows.iter().any(|r| r.typed.is_some()) {
+            db.metric_timestamps(&flight_id, from, to).await?
+        } else {
+            Vec::new()
+        };
+        let (logs, metrics) = missing_rows(rows, stored, metric_ts);
+        if !logs.is_empty() {
+            db.insert_flight_logs(&logs).await?;
+        }
+        if !metrics.is_empty() {
+            db.insert_flight_metrics(&metrics).await?;
+        }
+        inserted += logs.len();
+    }
+    Ok(inserted)
+}
+
+/// Separa `rows` en lo que falta en `flight_logs` (sin valores tipados) y lo que falta en
+/// `flight_metrics`, descontando lo ya guardado. Se compara por `ts` en µs (la precisión
+/// de la BD) y payload; cada fila guardada cubre una sola de `rows`, así que dos muestras
+/// idénticas en el mismo instante se conservan las dos
+fn missing_rows(
+    rows: Vec<LogRow>,
+    stored: Vec<(DateTime<Utc>, String)>,
+    metric_ts: Vec<DateTime<Utc>>,
+) -> (Vec<LogRow>, Vec<LogRow>) {
+    let mut stored_logs: HashMap<(i64, String), usize> = HashMap::new();
+    for (ts, payload) in stored {
+        *stored_logs.entry((ts.timestamp_micros(), payload)).or_default() += 1;
+    }
+    let mut stored_metrics: HashMap<i64, usize> = HashMap::new();
+    for ts in metric_ts {
+        *stored_metrics.entry(ts.timestamp_micros()).or_default() += 1;
+    }
+    let take = |n: Option<&mut usize>| match n {
+        Some(n) if *n > 0 => {
+            *n -= 1;
+            true
+        }
+        _ => false,
+    };
+
+    let (mut logs, mut metrics) = (Vec::new(), Vec::new());
+    for row in rows {
+        let us = row.ts.timestamp_micros();
+        if row.typed.is_some() && !take(stored_metrics.get_mut(&us)) {
+            metrics.push(row.clone());
+        }
+        if !take(stored_logs.get_mut(&(us, row.payload.clone()))) {
+            logs.push(LogRow { typed: None, ..row });
+        }
+    }
+    (logs, metrics)
+}
+
+/// Fila lista para insertar, con los campos tipados del vuelo si los tiene
+fn to_row(l: SpoolLine, typed: &HashMap<String, Option<Arc<[String]>>>) -> LogRow {
+    let typed = typed.get(&l.flight_id).cloned().flatten().map(|fields| {
+        let msg: serde_json::Value = serde_json::from_str(&l.payload).unwrap_or_default();
+        let obj = msg.get("payload").and_then(|p| p.as_object());
+        let values = fields.iter().map(|f| obj.and_then(|o| field_value(o, f))).collect();
+        TypedValues { fields, values }
+    });
+    LogRow { ts: l.ts, flight_id: l.flight_id, payload: l.payload, typed }
+}
+
+/// `flight_id` como nombre de fichero (sin separadores ni `@`)
+fn file_stem(flight_id: &str) -> String {
+    flight_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect()
+}
+
+/// Bytes por reimportar: ficheros `.jsonl` de `dir` menos lo ya hecho según sus `.offset`
+fn pending_bytes(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    entries
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".jsonl"))
+        .map(|e| {
+            let len = e.metadata().map(|m| m.len()).unwrap_or(0);
+            let done: u64 = std::fs::read_to_string(e.path().with_extension("jsonl.offset")).ok()
+                .and_then(|s| s.trim().parse().ok())
+                .unwrap_or(0);
+            len.saturating_sub(done)
+        })
+        .sum()
+}
+
+/// Importador de fondo: cuando hay spool y QuestDB responde, lo reimporta
+pub async fn run_importer(spool: Arc<Spool>, db: OptionalDb) {
+    let mut tick = tokio::time::interval(REPLAY_EVERY);
+    loop {
+        tick.tick().await;
+        if spool.bytes.load(Ordering::Relaxed) == 0 || !db.probe().await {
+            continue;
+        }
+        if let Err(e) = spool.replay(&db).await {
+            warn!("⚠️  Reimportado del spool interrumpido: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(ms: i64, payload: &str, typed: bool) -> LogRow {
+        LogRow {
+            ts: DateTime::from_timestamp_millis(ms).unwrap(),
+            flight_id: "f".into(),
+            payload: payload.into(),
+            typed: typed.then(|| TypedValues { fields: vec!["a".to_string()].into(), values: vec![Some(1.0)] }),
+        }
+    }
+
+    #[test]
+    fn missing_rows_skips_what_is_already_stored() {
+        let rows = vec![row(1, "a", false), row(2, "b", false), row(3, "c", false)];
+        let stored = vec![(rows[0].ts, "a".to_string()), (rows[1].ts, "b".to_string())];
+        let (logs, metrics) = missing_rows(rows, stored, Vec::new());
+        assert_eq!(logs.iter().map(|r| r.payload.as_str()).collect::<Vec<_>>(), ["c"]);
+        assert!(metrics.is_empty());
+    }
+
+    #[test]
+    fn missing_rows_completes_metrics_of_a_half_written_batch() {
+        // flight_logs entero, flight_metrics solo la primera fila
+        let rows = vec![row(1, "a", true), row(2, "b", true)];
+        let stored = rows.iter().map(|r| (r.ts, r.payload.clone())).collect();
+        let (logs, metrics) = missing_rows(rows.clone(), stored, vec![rows[0].ts]);
+        assert!(logs.is_empty());
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].payload, "b");
+        assert!(metrics[0].typed.is_some());
+    }
+
+    #[test]
+    fn missing_rows_compares_at_microsecond_precision() {
+        let mut r = row(0, "a", false);
+        r.ts = DateTime::from_timestamp(10, 123_456_789).unwrap();
+        let stored = vec![(DateTime::from_timestamp(10, 123_456_000).unwrap(), "a".to_string())];
+        let (logs, _) = missing_rows(vec![r], stored, Vec::new());
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn missing_rows_keeps_identical_samples_not_yet_stored() {
+        let rows = vec![row(5, "x", false), row(5, "x", false)];
+        let stored = vec![(rows[0].ts, "x".to_string())];
+        let (logs, _) = missing_rows(rows, stored, Vec::new());
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].typed.is_none());
+    }
+}
@@ -0,0 +1,293 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::info;
+
+use super::questdb::{FlightFilter, FlightInfo, FlightMeta, FlightPoint, FlightRow, LogRow};
+use super::store::FlightStore;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS flight_logs (
+        ts INTEGER NOT NULL,
+        flight_id TEXT NOT NULL,
+        payload TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS flight_logs_flight_ts ON flight_logs (flight_id, ts);
+    CREATE TABLE IF NOT EXISTS logger_configs (
+        ts INTEGER NOT NULL,
+        config_json TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS deleted_flights (
+        ts INTEGER NOT NULL,
+        flight_id TEXT PRIMARY KEY
+    );
+";
+
+/// Vuelos no borrados con su rango y nº de puntos (como `FLIGHTS_GROUPED` en QuestDB)
+const FLIGHTS_GROUPED: &str = "SELECT flight_id, min(ts) AS first_ts, max(ts) AS last_ts, count(*) AS points
+     FROM flight_logs
+     WHERE flight_id NOT IN (SELECT flight_id FROM deleted_flights)
+     GROUP BY flight_id";
+
+/// Filtros de `list_flights`; los `NULL` no filtran
+const FLIGHTS_WHERE: &str = "(?1 IS NULL OR last_ts >= ?1) AND (?2 IS NULL OR first_ts <= ?2) AND (?3 IS NULL OR points >= ?3)";
+
+/// Almacenamiento embebido en un fichero SQLite para equipos sin QuestDB. Los `ts` se
+/// guardan en microsegundos (la misma precisión que QuestDB) y los payloads tal cual
+/// llegan. Sin `flight_meta`: ningún vuelo tiene etiquetas ni está archivado
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path.as_ref())?;
+        conn.execute_batch(SCHEMA)?;
+        info!("🗄️  Almacenamiento SQLite en {}", path.as_ref().display());
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Ejecuta `f` con la conexión en un hilo de bloqueo
+    async fn with<T: Send + 'static>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static) -> Result<T> {
+        let conn = self.conn.clone();
+        Ok(tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap())).await??)
+    }
+}
+
+fn micros(ts: DateTime<Utc>) -> i64 {
+    ts.timestamp_micros()
+}
+
+fn from_micros(us: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(us).unwrap_or_default()
+}
+
+fn is_deleted(conn: &Connection, flight_id: &str) -> rusqlite::Result<bool> {
+    conn.query_row("SELECT 1 FROM deleted_flights WHERE flight_id = ?1", [flight_id], |_| Ok(()))
+        .optional()
+        .map(|r| r.is_some())
+}
+
+#[async_trait]
+impl FlightStore for SqliteStore {
+    async fn insert_flight_log(&self, flight_id: &str, payload: &str, ts: Option<DateTime<Utc>>) -> Result<()> {
+        let (flight_id, payload, ts) = (flight_id.to_owned(), payload.to_owned(), micros(ts.unwrap_or_else(Utc::now)));
+        self.with(move |conn| {
+            conn.execute("INSERT INTO flight_logs (ts, flight_id, payload) VALUES (?1, ?2, ?3)", params![ts, flight_id, payload])
+                .map(|_| ())
+        }).await
+    }
+
+    async fn insert_flight_logs(&self, rows: &[LogRow]) -> Result<()> {
+        let rows: Vec<(i64, String, String)> = rows.iter().map(|r| (micros(r.ts), r.flight_id.clone(), r.payload.clone())).collect();
+        self.with(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut insert = tx.prepare_cached("INSERT INTO flight_logs (ts, flight_id, payload) VALUES (?1, ?2, ?3)")?;
+                for (ts, flight_id, payload) in &rows {
+                    insert.execute(params![ts, flight_id, payload])?;
+                }
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn insert_logger_config(&self, config: &str) -> Result<()> {
+        let config = config.to_owned();
+        self.with(move |conn| {
+            conn.execute("INSERT INTO logger_configs (ts, config_json) VALUES (?1, ?2)", params![micros(Utc::now()), config])
+                .map(|_| ())
+        }).await
+    }
+
+    async fn latest_logger_config(&self) -> Result<Option<(DateTime<Utc>, String)>> {
+        self.with(|conn| {
+            conn.query_row(
+                "SELECT ts, config_json FROM logger_configs
+                 WHERE config_json NOT LIKE '%\"event\":%'
+                 ORDER BY ts DESC, rowid DESC LIMIT 1",
+                [],
+                |r| Ok((from_micros(r.get(0)?), r.get(1)?)),
+            )
+            .optional()
+        }).await
+    }
+
+    async fn latest_logger_event(&self, event: &str) -> Result<Option<(DateTime<Utc>, String)>> {
+        let pattern = format!("%\"event\":\"{event}\"%");
+        self.with(move |conn| {
+            conn.query_row(
+                "SELECT ts, config_json FROM logger_configs
+                 WHERE config_json LIKE ?1
+                 ORDER BY ts DESC, rowid DESC LIMIT 1",
+                [pattern],
+                |r| Ok((from_micros(r.get(0)?), r.get(1)?)),
+            )
+            .optional()
+        }).await
+    }
+
+    async fn list_flights(&self, filter: &FlightFilter) -> Result<(Vec<FlightRow>, i64)> {
+        // Sin metadatos no hay etiquetas: el filtro por etiqueta no deja ninguno
+        if filter.tag.is_some() {
+            return Ok((Vec::new(), 0));
+        }
+        let from = filter.from.map(micros);
+        let to = filter.to.map(micros);
+        let before = filter.before.map(micros);
+        let (min_points, limit) = (filter.min_points, filter.limit);
+        self.with(move |conn| {
+            let total: i64 = conn.query_row(
+                &format!("SELECT count(*) FROM ({FLIGHTS_GROUPED}) WHERE {FLIGHTS_WHERE}"),
+                params![from, to, min_points],
+                |r| r.get(0),
+            )?;
+            let mut select = conn.prepare(&format!(
+                "SELECT flight_id, first_ts, last_ts, points FROM ({FLIGHTS_GROUPED})
+                 WHERE {FLIGHTS_WHERE} AND (?4 IS NULL OR last_ts < ?4)
+                 ORDER BY last_ts DESC LIMIT ?5"
+            ))?;
+            let items = select
+                .query_map(params![from, to, min_points, before, limit], |r| {
+                    Ok(FlightRow {
+                        flight_id: r.get(0)?,
+                        first_ts: from_micros(r.get(1)?),
+                        last_ts: from_micros(r.get(2)?),
+                        points: r.get(3)?,
+                        meta: FlightMeta::default(),
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok((items, total))
+        }).await
+    }
+
+    async fn fetch_flight_points(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<FlightPoint>> {
+        let flight_id = flight_id.to_owned();
+        let (from, to) = (from.map(micros), to.map(micros));
+        let rows: Vec<(i64, String)> = self.with(move |conn| {
+            if is_deleted(conn, &flight_id)? {
+                return Ok(Vec::new());
+            }
+            let mut select = conn.prepare_cached(
+                "SELECT ts, payload FROM flight_logs
+                 WHERE flight_id = ?1 AND (?2 IS NULL OR ts >= ?2) AND (?3 IS NULL OR ts <= ?3)
+                 ORDER BY ts LIMIT ?4",
+            )?;
+            select.query_map(params![flight_id, from, to, limit], |r| Ok((r.get(0)?, r.get(1)?)))?.collect()
+        }).await?;
+        // Mismo formato que QuestDB: lo que no es JSON va como `{"raw": ...}`
+        Ok(rows
+            .into_iter()
+            .map(|(ts, payload)| FlightPoint {
+                ts: from_micros(ts),
+                payload: serde_json::from_str(&payload).unwrap_or_else(|_| serde_json::json!({ "raw": payload })),
+            })
+            .collect())
+    }
+
+    async fn count_flight_points(&self, flight_id: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<i64> {
+        let flight_id = flight_id.to_owned();
+        let (from, to) = (from.map(micros), to.map(micros));
+        self.with(move |conn| {
+            if is_deleted(conn, &flight_id)? {
+                return Ok(0);
+            }
+            conn.query_row(
+                "SELECT count(*) FROM flight_logs
+                 WHERE flight_id = ?1 AND (?2 IS NULL OR ts >= ?2) AND (?3 IS NULL OR ts <= ?3)",
+                params![flight_id, from, to],
+                |r| r.get(0),
+            )
+        }).await
+    }
+
+    async fn flight_exists(&self, flight_id: &str) -> Result<bool> {
+        Ok(self.count_flight_points(flight_id, None, None).await? > 0)
+    }
+
+    /// Sin metadatos ni resúmenes: etiquetas vacías y `has_summary` siempre `false`
+    async fn flight_info(&self, flight_id: &str) -> Result<Option<FlightInfo>> {
+        let flight_id = flight_id.to_owned();
+        self.with(move |conn| {
+            if is_deleted(conn, &flight_id)? {
+                return Ok(None);
+            }
+            let (first, last, points): (Option<i64>, Option<i64>, i64) = conn.query_row(
+                "SELECT min(ts), max(ts), count(*) FROM flight_logs WHERE flight_id = ?1",
+                [&flight_id],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )?;
+            let (Some(first), Some(last)) = (first, last) else { return Ok(None) };
+            let config = conn
+                .query_row(
+                    "SELECT ts, config_json FROM logger_configs
+                     WHERE ts <= ?1
+                       AND (config_json NOT LIKE '%\"event\":%' OR config_json LIKE '%\"event\":\"start\"%')
+                     ORDER BY ts DESC, rowid DESC LIMIT 1",
+                    [first],
+                    |r| Ok((from_micros(r.get(0)?), r.get(1)?)),
+                )
+                .optional()?;
+            Ok(Some(FlightInfo {
+                first_ts: from_micros(first),
+                last_ts: from_micros(last),
+                points,
+                meta: FlightMeta::default(),
+                has_summary: false,
+                config,
+            }))
+        }).await
+    }
+
+    async fn stored_rows(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, String)>> {
+        let flight_id = flight_id.to_owned();
+        let (from, to) = (micros(from), micros(to));
+        let rows: Vec<(i64, String)> = self.with(move |conn| {
+            let mut select = conn.prepare_cached(
+                "SELECT ts, payload FROM flight_logs WHERE flight_id = ?1 AND ts >= ?2 AND ts <= ?3",
+            )?;
+            select.query_map(params![flight_id, from, to], |r| Ok((r.get(0)?, r.get(1)?)))?.collect()
+        }).await?;
+        Ok(rows.into_iter().map(|(ts, payload)| (from_micros(ts), payload)).collect())
+    }
+
+    async fn flight_span(&self, flight_id: &str) -> Result<(i64, Option<DateTime<Utc>>)> {
+        let flight_id = flight_id.to_owned();
+        self.with(move |conn| {
+            if is_deleted(conn, &flight_id)? {
+                return Ok((0, None));
+            }
+            conn.query_row(
+                "SELECT count(*), max(ts) FROM flight_logs WHERE flight_id = ?1",
+                [flight_id],
+                |r| Ok((r.get(0)?, r.get::<_, Option<i64>>(1)?.map(from_micros))),
+            )
+        }).await
+    }
+
+    /// Como en QuestDB queda la marca (un `flight_id` borrado no reaparece), pero aquí
+    /// las filas sí se eliminan
+    async fn mark_flight_deleted(&self, flight_id: &str) -> Result<()> {
+        let id = flight_id.to_owned();
+        self.with(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("INSERT OR IGNORE INTO deleted_flights (ts, flight_id) VALUES (?1, ?2)", params![micros(Utc::now()), id])?;
+            tx.execute("DELETE FROM flight_logs WHERE flight_id = ?1", [&id])?;
+            tx.commit()
+        }).await?;
+        info!("🗑️  Vuelo {flight_id} borrado");
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::Stream;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+use utoipa::IntoParams;
+
+use super::api_error::{ApiError, ErrorBody};
+use super::bus::BusReceiver;
+use super::server::WsContext;
+
+/// Comentario `: keepalive` para que proxies y navegadores no corten la conexión
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SseQuery {
+    /// `types=telemetry,ack`; sin él se envía todo
+    types: Option<String>,
+    /// `ARTHERIS_TOKEN` para `EventSource`, que no puede mandar cabeceras
+    token: Option<String>,
+}
+
+/// Misma regla que el WS: con token y sin `ARTHERIS_WS_READONLY` la difusión solo va a
+/// quien presente el token (`Authorization: Bearer` o `?token=`)
+fn authorized(ctx: &WsContext, headers: &HeaderMap, token: Option<&str>) -> bool {
+    if !ctx.auth.enabled() || ctx.auth.ws_read_only {
+        return true;
+    }
+    let bearer = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    bearer.or(token).is_some_and(|t| ctx.auth.check(t))
+}
+
+struct SseState {
+    rx: BusReceiver,
+    types: Option<Vec<String>>,
+    ctx: WsContext,
+}
+
+/// `GET /api/stream`: la misma difusión que el WS como eventos SSE (`data:` = el JSON tal cual).
+/// La suscripción vive en el stream: al desconectar el cliente axum lo suelta y con él el receptor.
+#[utoipa::path(
+    get,
+    path = "/api/stream",
+    tag = "stream",
+    params(SseQuery),
+    responses(
+        (status = 200, description = "Eventos SSE; `event: lagged` con `{\"dropped\":n}` si el cliente se atrasa", body = String, content_type = "text/event-stream"),
+        (status = 401, description = "Falta el token (con `ARTHERIS_TOKEN` y sin `ARTHERIS_WS_READONLY`)", body = ErrorBody),
+    )
+)]
+pub async fn stream(
+    State(ctx): State<WsContext>,
+    headers: HeaderMap,
+    Query(q): Query<SseQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    if !authorized(&ctx, &headers, q.token.as_deref()) {
+        return Err(ApiError::Unauthorized("Missing or invalid token".to_string()));
+    }
+    let types = q.types.map(|t| super::series::split_fields(&t)).filter(|t| !t.is_empty());
+    let state = SseState { rx: ctx.bus.subscribe(), types, ctx };
+
+    let events = futures_util::stream::unfold(state, |mut st| async move {
+        loop {
+            let recv = tokio::select! {
+                _ = st.ctx.shutdown.cancelled() => return None,
+                recv = st.rx.recv() => recv,
+            };
+            match recv {
+                Ok(frame) => {
+                    let wanted = st.types.as_ref().is_none_or(|t| frame.kind.as_ref().is_some_and(|k| t.contains(k)));
+                    if wanted {
+                        let event = Event::default().data(frame.text.as_str());
+                        return Some((Ok(event), st));
+                    }
+                }
+                // Cliente lento: se avisa y se sigue desde lo más reciente
+                Err(RecvError::Lagged(n)) => {
+                    st.ctx.ws_stats.record_lagged(n);
+                    debug!("Cliente SSE atrasado, {n} mensajes descartados");
+                    let event = Event::default().event("lagged").data(format!("{{\"dropped\":{n}}}"));
+                    return Some((Ok(event), st));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(SSE_KEEPALIVE)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::http::HeaderValue;
+    use super::super::auth::AuthConfig;
+
+    fn ctx_with(token: Option<&str>, ws_read_only: bool) -> WsContext {
+        let mut ctx = WsContext::for_tests(None);
+        ctx.auth = Arc::new(AuthConfig { token: token.map(Into::into), ws_read_only, ..Default::default() });
+        ctx
+    }
+
+    async fn opens(ctx: &WsContext, bearer: Option<&str>, token: Option<&str>) -> bool {
+        let mut headers = HeaderMap::new();
+        if let Some(b) = bearer {
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {b}")).unwrap());
+        }
+        let q = SseQuery { types: None, token: token.map(Into::into) };
+        stream(State(ctx.clone()), headers, Query(q)).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn stream_requires_the_token_like_the_ws() {
+        let ctx = ctx_with(Some("s3cret"), false);
+        assert!(!opens(&ctx, None, None).await);
+        assert!(!opens(&ctx, Some("wrong"), None).await);
+        assert!(!opens(&ctx, None, Some("wrong")).await);
+        assert!(opens(&ctx, Some("s3cret"), None).await);
+        assert!(opens(&ctx, None, Some("s3cret")).await);
+    }
+
+    #[tokio::test]
+    async fn stream_stays_open_without_token_or_in_read_only_mode() {
+        assert!(opens(&ctx_with(None, false), None, None).await);
+        assert!(opens(&ctx_with(Some("s3cret"), true), None, None).await);
+    }
+}
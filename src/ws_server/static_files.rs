@@ -0,0 +1,49 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+
+use super::api_error::ApiError;
+
+/// Assets con hash en el nombre: si cambian, cambia la URL
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// `index.html` y lo demás se revalida siempre (Last-Modified)
+const NO_CACHE: &str = "no-cache";
+
+/// Fallback del router con `ARTHERIS_STATIC_DIR`: sirve el bundle del panel y, para rutas
+/// que no son ficheros, `index.html` (rutas de la SPA). Lo que empieza por `/api` nunca
+/// cae aquí como HTML: sin ruta propia responde el 404 JSON de siempre
+pub async fn serve(dir: Arc<Path>, req: Request) -> Response {
+    let path = req.uri().path().to_string();
+    if path == "/api" || path.starts_with("/api/") {
+        return ApiError::NotFound(format!("No route for {path}")).into_response();
+    }
+    let service = ServeDir::new(&*dir).fallback(ServeFile::new(dir.join("index.html")));
+    let mut res = match service.oneshot(req).await {
+        Ok(res) => res.map(Body::new),
+        Err(never) => match never {},
+    };
+    if res.status() == StatusCode::OK {
+        let html = res.headers().get(header::CONTENT_TYPE).is_some_and(|v| v.as_bytes().starts_with(b"text/html"));
+        let cache = if !html && hashed(&path) { IMMUTABLE } else { NO_CACHE };
+        res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static(cache));
+    }
+    res
+}
+
+/// `/assets/...` (salida de Vite) o un nombre tipo `main.3f9a1c2b.js` / `index-BXk2a9Qz.css`
+fn hashed(path: &str) -> bool {
+    if path.starts_with("/assets/") {
+        return true;
+    }
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    stem.split(['.', '-']).skip(1).any(|part| {
+        part.len() >= 8 && part.chars().all(|c| c.is_ascii_alphanumeric()) && part.chars().any(|c| c.is_ascii_digit())
+    })
+}
@@ -0,0 +1,83 @@
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::server::WsContext;
+
+/// Versión del mensaje `status`; los campos nuevos se añaden sin subirla,
+/// solo cambia si alguno existente cambia de significado
+pub const STATUS_VERSION: u32 = 1;
+
+/// `{"type":"status"}` que se difunde periódicamente por WS
+#[derive(Debug, Serialize)]
+pub struct Status {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub v: u32,
+    pub uptime_s: u64,
+    pub ws_clients: usize,
+    pub flight_id: Option<String>,
+    pub recording: bool,
+    pub udp_pps: f64,
+    pub esp32_last_contact_ms: Option<u64>,
+    pub questdb_connected: bool,
+}
+
+/// Arranque del servidor y ventana para calcular paquetes/s entre dos estados
+pub struct StatusReporter {
+    started: Instant,
+    window: Mutex<(Instant, u64)>,
+}
+
+impl Default for StatusReporter {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self { started: now, window: Mutex::new((now, 0)) }
+    }
+}
+
+impl StatusReporter {
+    /// Paquetes/s desde el estado anterior
+    fn packets_per_sec(&self, total: u64) -> f64 {
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        let (since, prev) = *window;
+        *window = (now, total);
+        let secs = now.duration_since(since).as_secs_f64();
+        if secs > 0.0 { total.saturating_sub(prev) as f64 / secs } else { 0.0 }
+    }
+
+    pub async fn build(&self, ctx: &WsContext) -> Status {
+        let flight_id = ctx.flight_id.read().await.clone();
+        Status {
+            kind: "status",
+            v: STATUS_VERSION,
+            uptime_s: self.started.elapsed().as_secs(),
+            ws_clients: ctx.clients.len(),
+            recording: flight_id.is_some(),
+            flight_id,
+            udp_pps: self.packets_per_sec(ctx.udp_stats.packets.load(Ordering::Relaxed)),
+            esp32_last_contact_ms: ctx.udp_stats.last_packet_age().map(|d| d.as_millis() as u64),
+            questdb_connected: ctx.questdb.is_connected().await,
+        }
+    }
+}
+
+/// Difunde el estado ahora (p. ej. al empezar o parar una grabación)
+pub async fn push_status(ctx: &WsContext) {
+    let status = ctx.status.build(ctx).await;
+    if let Ok(text) = serde_json::to_string(&status) {
+        let _ = ctx.broadcast(text);
+    }
+}
+
+/// Estado del servidor cada `every` (`ARTHERIS_STATUS_SECS`)
+pub async fn run_status(ctx: WsContext, every: Duration) {
+    let mut tick = tokio::time::interval(every);
+    loop {
+        tick.tick().await;
+        push_status(&ctx).await;
+    }
+}
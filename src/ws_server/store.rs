@@ -0,0 +1,102 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::questdb::{FlightFilter, FlightInfo, FlightPoint, FlightRow, LogRow, QuestDb};
+
+/// Operaciones de vuelos que tiene cualquier backend de almacenamiento: la telemetría,
+/// las configs del logger y los borrados. QuestDB es el completo; el embebido (SQLite)
+/// solo tiene esto, y lo demás (metadatos, resúmenes, agregados...) da `Unavailable`
+#[async_trait]
+pub trait FlightStore: Send + Sync {
+    /// Sin `ts`, con la hora actual
+    async fn insert_flight_log(&self, flight_id: &str, payload: &str, ts: Option<DateTime<Utc>>) -> Result<()>;
+    async fn insert_flight_logs(&self, rows: &[LogRow]) -> Result<()>;
+    async fn insert_logger_config(&self, config: &str) -> Result<()>;
+    /// Última config (fila sin `"event"`)
+    async fn latest_logger_config(&self) -> Result<Option<(DateTime<Utc>, String)>>;
+    /// Último evento `{"event":"<event>",...}`
+    async fn latest_logger_event(&self, event: &str) -> Result<Option<(DateTime<Utc>, String)>>;
+    /// Página de vuelos (más recientes primero) y el total con los filtros
+    async fn list_flights(&self, filter: &FlightFilter) -> Result<(Vec<FlightRow>, i64)>;
+    async fn fetch_flight_points(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<FlightPoint>>;
+    async fn count_flight_points(&self, flight_id: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<i64>;
+    async fn flight_exists(&self, flight_id: &str) -> Result<bool>;
+    /// Cabecera de `GET /api/flights/:id`; `None` si no existe o está borrado
+    async fn flight_info(&self, flight_id: &str) -> Result<Option<FlightInfo>>;
+    /// `ts` y payload tal cual de las filas del vuelo en `[from, to]`, para reimportar sin duplicar
+    async fn stored_rows(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, String)>>;
+    /// Nº de puntos y último `ts` (para cachés y ETag)
+    async fn flight_span(&self, flight_id: &str) -> Result<(i64, Option<DateTime<Utc>>)>;
+    async fn mark_flight_deleted(&self, flight_id: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl FlightStore for QuestDb {
+    async fn insert_flight_log(&self, flight_id: &str, payload: &str, ts: Option<DateTime<Utc>>) -> Result<()> {
+        match ts {
+            Some(ts) => self.insert_flight_log_at(flight_id, payload, ts).await,
+            None => QuestDb::insert_flight_log(self, flight_id, payload).await,
+        }
+    }
+
+    async fn insert_flight_logs(&self, rows: &[LogRow]) -> Result<()> {
+        QuestDb::insert_flight_logs(self, rows).await
+    }
+
+    async fn insert_logger_config(&self, config: &str) -> Result<()> {
+        QuestDb::insert_logger_config(self, config).await
+    }
+
+    async fn latest_logger_config(&self) -> Result<Option<(DateTime<Utc>, String)>> {
+        QuestDb::latest_logger_config(self).await
+    }
+
+    async fn latest_logger_event(&self, event: &str) -> Result<Option<(DateTime<Utc>, String)>> {
+        QuestDb::latest_logger_event(self, event).await
+    }
+
+    async fn list_flights(&self, filter: &FlightFilter) -> Result<(Vec<FlightRow>, i64)> {
+        QuestDb::list_flights(self, filter).await
+    }
+
+    async fn fetch_flight_points(
+        &self,
+        flight_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<FlightPoint>> {
+        QuestDb::fetch_flight_points(self, flight_id, from, to, limit).await
+    }
+
+    async fn count_flight_points(&self, flight_id: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<i64> {
+        QuestDb::count_flight_points(self, flight_id, from, to).await
+    }
+
+    async fn flight_exists(&self, flight_id: &str) -> Result<bool> {
+        QuestDb::flight_exists(self, flight_id).await
+    }
+
+    async fn flight_info(&self, flight_id: &str) -> Result<Option<FlightInfo>> {
+        QuestDb::flight_info(self, flight_id).await
+    }
+
+    async fn stored_rows(&self, flight_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, String)>> {
+        QuestDb::stored_rows(self, flight_id, from, to).await
+    }
+
+    async fn flight_span(&self, flight_id: &str) -> Result<(i64, Option<DateTime<Utc>>)> {
+        QuestDb::flight_span(self, flight_id).await
+    }
+
+    async fn mark_flight_deleted(&self, flight_id: &str) -> Result<()> {
+        QuestDb::mark_flight_deleted(self, flight_id).await
+    }
+}
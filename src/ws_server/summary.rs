@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::questdb::{DbError, FlightPoint, OptionalDb};
+use super::series;
+
+/// Puntos leídos como mucho para calcular un resumen; la API rechaza vuelos más grandes
+pub const SUMMARY_MAX_POINTS: i64 = 1_000_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FlightSummary {
+    pub flight_id: String,
+    pub start_ts: String,
+    pub end_ts: String,
+    pub duration_sec: f64,
+    // ejemplo de métricas
+    pub max_roll: Option<f64>,
+    pub max_pitch: Option<f64>,
+    pub throttle_time_in_range_sec: f64,
+    pub throttle_time_out_range_sec: f64,
+    #[serde(default)]
+    pub metrics: SummaryMetrics,
+}
+
+/// Métricas ampliadas; lo que no aparece en el vuelo se omite
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct SummaryMetrics {
+    /// Estadísticas de cada campo pedido que tenga algún valor numérico
+    pub fields: BTreeMap<String, FieldStats>,
+    /// Muestras por segundo según el espaciado medio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loop_rate_hz: Option<f64>,
+    pub gaps: GapStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<VoltageStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rate_roll: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rate_pitch: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FieldStats {
+    pub samples: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub rms: f64,
+    pub std_dev: f64,
+}
+
+/// Huecos de telemetría mayores que `threshold_ms`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct GapStats {
+    pub threshold_ms: f64,
+    pub count: u64,
+    pub total_sec: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VoltageStats {
+    pub start: f64,
+    pub end: f64,
+    pub min: f64,
+}
+
+/// Suma y suma de cuadrados para media, RMS y desviación típica en una pasada
+#[derive(Default)]
+struct Acc {
+    n: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl Acc {
+    fn push(&mut self, v: f64) {
+        if self.n == 0 {
+            (self.min, self.max) = (v, v);
+        } else {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+        self.n += 1;
+        self.sum += v;
+        self.sum_sq += v * v;
+    }
+
+    fn stats(&self) -> Option<FieldStats> {
+        if self.n == 0 {
+            return None;
+        }
+        let n = self.n as f64;
+        let mean = self.sum / n;
+        let mean_sq = self.sum_sq / n;
+        Some(FieldStats {
+            samples: self.n,
+            min: self.min,
+            max: self.max,
+            mean,
+            rms: mean_sq.sqrt(),
+            std_dev: (mean_sq - mean * mean).max(0.0).sqrt(),
+        })
+    }
+}
+
+fn abs_max(cur: Option<f64>, v: f64) -> Option<f64> {
+    Some(cur.map_or(v.abs(), |m| m.max(v.abs())))
+}
+
+/// Parámetros que cambian el resultado; forman parte de la clave de la caché
+#[derive(Debug, Clone)]
+pub struct SummaryParams {
+    pub throttle_min: f64,
+    pub throttle_max: f64,
+    /// Campos con estadísticas en `metrics.fields`
+    pub fields: Vec<String>,
+    /// Espaciado entre muestras a partir del cual se cuenta un hueco
+    pub gap_ms: f64,
+}
+
+impl Default for SummaryParams {
+    fn default() -> Self {
+        Self { throttle_min: 1200.0, throttle_max: 2000.0, fields: series::default_fields(), gap_ms: 100.0 }
+    }
+}
+
+impl SummaryParams {
+    /// Clave estable para `flight_summaries.params`
+    pub fn key(&self) -> String {
+        format!(
+            "throttle={}:{};fields={};gap_ms={}",
+            self.throttle_min,
+            self.throttle_max,
+            self.fields.join(","),
+            self.gap_ms
+        )
+    }
+}
+
+/// Resumen de los puntos del vuelo; `None` si no hay ninguno
+pub fn compute(flight_id: &str, points: &[FlightPoint], params: &SummaryParams) -> Option<FlightSummary> {
+    let start_ts = points.first()?.ts;
+    let end_ts = points.last()?.ts;
+    let duration = (end_ts - start_ts).num_milliseconds() as f64 / 1000.0;
+
+    let mut max_roll = None::<f64>;
+    let mut max_pitch = None::<f64>;
+    let mut in_range = 0.0f64;
+    let mut out_range = 0.0f64;
+
+    // integramos por “tramos” (asumiendo frecuencia relativamente uniforme)
+    for w in points.windows(2) {
+        let a = &w[0];
+        let b = &w[1];
+        let dt = (b.ts - a.ts).num_milliseconds() as f64 / 1000.0;
+
+        if let Some(obj) = series::payload_obj(a) {
+            if let Some(v) = series::field_value(obj, "AngleRoll") {
+                max_roll = Some(max_roll.map(|m| m.max(v.abs())).unwrap_or(v.abs()));
+            }
+            if let Some(v) = series::field_value(obj, "AnglePitch") {
+                max_pitch = Some(max_pitch.map(|m| m.max(v.abs())).unwrap_or(v.abs()));
+            }
+            if let Some(th) = series::field_value(obj, "InputThrottle") {
+                if th >= params.throttle_min && th <= params.throttle_max { in_range += dt; } else { out_range += dt; }
+            }
+        }
+    }
+
+    let metrics = metrics(points, params, duration);
+
+    Some(FlightSummary {
+        flight_id: flight_id.to_string(),
+        start_ts: start_ts.to_rfc3339(),
+        end_ts: end_ts.to_rfc3339(),
+        duration_sec: duration,
+        max_roll,
+        max_pitch,
+        throttle_time_in_range_sec: in_range,
+        throttle_time_out_range_sec: out_range,
+        metrics,
+    })
+}
+
+fn metrics(points: &[FlightPoint], params: &SummaryParams, duration_sec: f64) -> SummaryMetrics {
+    let mut accs: Vec<Acc> = params.fields.iter().map(|_| Acc::default()).collect();
+    let mut voltage: Option<VoltageStats> = None;
+    let (mut max_rate_roll, mut max_rate_pitch) = (None, None);
+    let mut gaps = GapStats { threshold_ms: params.gap_ms, ..Default::default() };
+
+    for (i, p) in points.iter().enumerate() {
+        if i > 0 {
+            let dt_ms = (p.ts - points[i - 1].ts).num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0;
+            if dt_ms > params.gap_ms {
+                gaps.count += 1;
+                gaps.total_sec += dt_ms / 1000.0;
+            }
+        }
+        let values = series::extract_values(p, &params.fields);
+        for (acc, f) in accs.iter_mut().zip(&params.fields) {
+            if let Some(v) = values.get(f) {
+                acc.push(*v);
+            }
+        }
+        let Some(obj) = series::payload_obj(p) else { continue };
+        if let Some(v) = series::field_value(obj, "Voltage") {
+            let vs = voltage.get_or_insert(VoltageStats { start: v, end: v, min: v });
+            vs.end = v;
+            vs.min = vs.min.min(v);
+        }
+        if let Some(v) = series::field_value(obj, "RateRoll") {
+            max_rate_roll = abs_max(max_rate_roll, v);
+        }
+        if let Some(v) = series::field_value(obj, "RatePitch") {
+            max_rate_pitch = abs_max(max_rate_pitch, v);
+        }
+    }
+
+    SummaryMetrics {
+        fields: params.fields.iter().zip(&accs).filter_map(|(f, a)| Some((f.clone(), a.stats()?))).collect(),
+        loop_rate_hz: (points.len() > 1 && duration_sec > 0.0).then(|| (points.len() - 1) as f64 / duration_sec),
+        gaps,
+        voltage,
+        max_rate_roll,
+        max_rate_pitch,
+    }
+}
+
+/// Resumen cacheado en `flight_summaries` si sigue valiendo: misma clave y el vuelo
+/// no ha recibido puntos desde que se calculó (nº de puntos y último `ts` iguales)
+pub async fn cached(db: &OptionalDb, flight_id: &str, params: &SummaryParams) -> Result<Option<FlightSummary>, DbError> {
+    let Some(row) = db.cached_summary(flight_id, &params.key()).await? else {
+        return Ok(None);
+    };
+    let (points, last_ts) = db.flight_span(flight_id).await?;
+    if points != row.points || last_ts != Some(row.last_ts) {
+        return Ok(None);
+    }
+    Ok(serde_json::from_str(&row.summary).ok())
+}
+
+/// Calcula el resumen desde los puntos y lo guarda; un fallo al guardar solo se avisa.
+/// El estado del vuelo se lee antes que los puntos: si llega algo entre medias, la
+/// próxima lectura ve la caché desfasada y recalcula.
+pub async fn refresh(db: &OptionalDb, flight_id: &str, params: &SummaryParams) -> Result<Option<FlightSummary>, DbError> {
+    let (count, last_ts) = db.flight_span(flight_id).await?;
+    if count == 0 {
+        return Ok(None);
+    }
+    let points = db.fetch_flight_points(flight_id, None, None, count.min(SUMMARY_MAX_POINTS)).await?;
+    let (Some(summary), Some(last_ts)) = (compute(flight_id, &points, params), last_ts) else {
+        return Ok(None);
+    };
+    let json = serde_json::to_string(&summary).unwrap_or_default();
+    if let Err(e) = db.store_summary(flight_id, &params.key(), count, last_ts, &json).await {
+        warn!("⚠️  No se pudo cachear el resumen de {flight_id}: {e}");
+    }
+    Ok(Some(summary))
+}
@@ -0,0 +1,34 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// Carga certificado (cadena PEM) y clave privada PEM; falla con un mensaje claro
+pub fn load_acceptor(cert_path: &Path, key_path: &Path) -> anyhow::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(cert_path).with_context(|| format!("no se pudo abrir el certificado {}", cert_path.display()))?,
+    ))
+    .collect::<Result<Vec<_>, _>>()
+    .with_context(|| format!("certificado PEM inválido: {}", cert_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("{} no contiene ningún certificado", cert_path.display());
+    }
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(
+        File::open(key_path).with_context(|| format!("no se pudo abrir la clave {}", key_path.display()))?,
+    ))
+    .with_context(|| format!("clave PEM inválida: {}", key_path.display()))?
+    .with_context(|| format!("{} no contiene ninguna clave privada", key_path.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(tokio_rustls::rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("versiones TLS no soportadas")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("el certificado y la clave no coinciden")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
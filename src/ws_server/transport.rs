@@ -0,0 +1,199 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::UdpSocket;
+use tokio::sync::{watch, Mutex};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+/// Un mensaje recibido del dispositivo (datagrama UDP o línea serie)
+pub struct Inbound {
+    pub src: Option<SocketAddr>,
+    pub bytes: Vec<u8>,
+}
+
+/// Enlace con el controlador de vuelo: UDP (Wi-Fi) o puerto serie (USB).
+/// Los comandos de `config/function.rs` salen siempre por aquí.
+pub trait TelemetryTransport: Send + Sync {
+    /// Envía un comando al dispositivo
+    fn send<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Stream de mensajes entrantes; solo puede tomarse una vez
+    fn incoming(&self) -> BoxStream<'static, io::Result<Inbound>>;
+
+    /// Etiqueta de entrada para los mensajes (`port` en el JSON difundido)
+    fn ingress(&self) -> serde_json::Value;
+
+    /// Descripción para logs
+    fn describe(&self) -> String;
+}
+
+/// Intentos de bind al re-enlazar (el receptor tarda un instante en soltar el socket viejo)
+const REBIND_ATTEMPTS: u32 = 20;
+const REBIND_RETRY: Duration = Duration::from_millis(50);
+
+/// Socket UDP local + dirección remota del ESP32.
+/// El socket vive en un `watch` para poder re-enlazarlo sin reiniciar el proceso;
+/// `None` mientras se está re-enlazando.
+pub struct UdpTransport {
+    socket: watch::Sender<Option<Arc<UdpSocket>>>,
+    local: SocketAddr,
+    /// Lo cambia la conmutación entre direcciones del ESP32 (`failover.rs`)
+    remote: std::sync::RwLock<SocketAddr>,
+    send_failures: AtomicU32,
+}
+
+impl UdpTransport {
+    pub fn new(socket: UdpSocket, remote: SocketAddr) -> io::Result<Self> {
+        let local = socket.local_addr()?;
+        Ok(Self {
+            socket: watch::Sender::new(Some(Arc::new(socket))),
+            local,
+            remote: std::sync::RwLock::new(remote),
+            send_failures: AtomicU32::new(0),
+        })
+    }
+
+    pub fn local(&self) -> SocketAddr {
+        self.local
+    }
+
+    pub fn remote(&self) -> SocketAddr {
+        *self.remote.read().unwrap()
+    }
+
+    pub fn set_remote(&self, remote: SocketAddr) {
+        *self.remote.write().unwrap() = remote;
+    }
+
+    /// Envíos fallidos seguidos desde el último éxito
+    pub fn send_failures(&self) -> u32 {
+        self.send_failures.load(Ordering::Relaxed)
+    }
+
+    /// Cierra el socket y vuelve a enlazar el mismo puerto local
+    pub async fn rebind(&self) -> io::Result<()> {
+        // Suelta el socket viejo (el receptor lo libera al ver el cambio)
+        self.socket.send_replace(None);
+        let mut attempt = 0;
+        let sock = loop {
+            match UdpSocket::bind(self.local).await {
+                Ok(s) => break s,
+                Err(e) if attempt + 1 >= REBIND_ATTEMPTS => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    tokio::time::sleep(REBIND_RETRY).await;
+                }
+            }
+        };
+        self.socket.send_replace(Some(Arc::new(sock)));
+        self.send_failures.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl TelemetryTransport for UdpTransport {
+    fn send<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let socket = self.socket.borrow().clone();
+            let res = match socket {
+                Some(s) => s.send_to(bytes, self.remote()).await.map(|_| ()),
+                None => Err(io::Error::new(io::ErrorKind::NotConnected, "socket UDP re-enlazándose")),
+            };
+            match &res {
+                Ok(()) => self.send_failures.store(0, Ordering::Relaxed),
+                Err(_) => {
+                    self.send_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            res
+        })
+    }
+
+    fn incoming(&self) -> BoxStream<'static, io::Result<Inbound>> {
+        let rx = self.socket.subscribe();
+        Box::pin(stream::unfold((rx, vec![0u8; 4096]), |(mut rx, mut buf)| async move {
+            loop {
+                let current = rx.borrow_and_update().clone();
+                let Some(socket) = current else {
+                    rx.changed().await.ok()?;
+                    continue;
+                };
+                tokio::select! {
+                    res = socket.recv_from(&mut buf) => {
+                        let item = res.map(|(len, src)| Inbound { src: Some(src), bytes: buf[..len].to_vec() });
+                        return Some((item, (rx, buf)));
+                    }
+                    // Socket reemplazado: se suelta el viejo y se lee del nuevo
+                    changed = rx.changed() => changed.ok()?,
+                }
+            }
+        }))
+    }
+
+    fn ingress(&self) -> serde_json::Value {
+        self.local.port().into()
+    }
+
+    fn describe(&self) -> String {
+        format!("udp → {}", self.remote())
+    }
+}
+
+/// Puerto serie con JSON delimitado por saltos de línea
+pub struct SerialTransport {
+    path: String,
+    writer: Mutex<WriteHalf<SerialStream>>,
+    reader: std::sync::Mutex<Option<ReadHalf<SerialStream>>>,
+}
+
+impl SerialTransport {
+    pub fn open(path: &str, baud: u32) -> anyhow::Result<Self> {
+        let port = tokio_serial::new(path, baud)
+            .open_native_async()
+            .map_err(|e| anyhow::anyhow!("no se pudo abrir el puerto serie {path}: {e}"))?;
+        let (reader, writer) = tokio::io::split(port);
+        Ok(Self {
+            path: path.to_string(),
+            writer: Mutex::new(writer),
+            reader: std::sync::Mutex::new(Some(reader)),
+        })
+    }
+}
+
+impl TelemetryTransport for SerialTransport {
+    fn send<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut w = self.writer.lock().await;
+            w.write_all(bytes).await?;
+            w.write_all(b"\n").await?;
+            w.flush().await
+        })
+    }
+
+    fn incoming(&self) -> BoxStream<'static, io::Result<Inbound>> {
+        let Some(reader) = self.reader.lock().unwrap().take() else {
+            return Box::pin(stream::empty());
+        };
+        Box::pin(stream::unfold(BufReader::new(reader).lines(), |mut lines| async move {
+            match lines.next_line().await {
+                Ok(Some(line)) => Some((Ok(Inbound { src: None, bytes: line.into_bytes() }), lines)),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), lines)),
+            }
+        }))
+    }
+
+    fn ingress(&self) -> serde_json::Value {
+        "serial".into()
+    }
+
+    fn describe(&self) -> String {
+        format!("serial {}", self.path)
+    }
+}
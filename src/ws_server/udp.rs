@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use futures_util::StreamExt;
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
+
+use super::schema::{Verdict, QUARANTINE_FLIGHT_ID};
+use super::server::WsContext;
+use super::transport::{Inbound, TelemetryTransport, UdpTransport};
+
+/// Fuentes sin tráfico por más de este tiempo se olvidan
+const SOURCE_IDLE_EVICT: Duration = Duration::from_secs(10 * 60);
+
+/// Ventana y capacidad del ring buffer de tiempos entre llegadas (400 Hz × 30 s)
+const TIMING_WINDOW: Duration = Duration::from_secs(30);
+const TIMING_CAPACITY: usize = 16_384;
+
+/// Contadores del receptor UDP (compartidos vía `WsContext`)
+#[derive(Debug, Default)]
+pub struct UdpStats {
+    pub crc_verified: AtomicU64,
+    pub crc_mismatches: AtomicU64,
+    /// Mensajes MAVLink no soportados / con CRC inválido (feature `mavlink`)
+    pub mavlink_unknown: AtomicU64,
+    pub mavlink_bad_crc: AtomicU64,
+    /// Datagramas recibidos desde el arranque
+    pub packets: AtomicU64,
+    bytes: AtomicU64,
+    malformed: AtomicU64,
+    /// Huecos de llegada por encima de `gap_warn`; sin nº de secuencia es lo más
+    /// parecido a un contador de pérdidas
+    gaps: AtomicU64,
+    sources: Mutex<HashMap<SocketAddr, SourceEntry>>,
+    timing: Mutex<HashMap<String, ArrivalRing>>,
+    /// Huecos mayores a esto se registran con warn (0 = nunca)
+    gap_warn: Duration,
+}
+
+/// Intervalos entre llegadas de telemetría; tras llenarse no asigna memoria
+#[derive(Debug)]
+struct ArrivalRing {
+    last: Option<Instant>,
+    samples: Vec<(Instant, f64)>,
+    head: usize,
+}
+
+impl ArrivalRing {
+    fn new() -> Self {
+        Self { last: None, samples: Vec::with_capacity(TIMING_CAPACITY), head: 0 }
+    }
+
+    /// Devuelve el intervalo desde la llegada anterior
+    fn push(&mut self, now: Instant) -> Option<Duration> {
+        let gap = self.last.map(|prev| now.duration_since(prev));
+        self.last = Some(now);
+        let gap = gap?;
+        let sample = (now, gap.as_secs_f64() * 1000.0);
+        if self.samples.len() < TIMING_CAPACITY {
+            self.samples.push(sample);
+        } else {
+            self.samples[self.head] = sample;
+            self.head = (self.head + 1) % TIMING_CAPACITY;
+        }
+        Some(gap)
+    }
+
+    fn summary(&self, ingress: &str, now: Instant) -> TimingStats {
+        let recent = self.samples.iter()
+            .filter(|(at, _)| now.duration_since(*at) <= TIMING_WINDOW)
+            .map(|(_, ms)| *ms);
+
+        let (mut n, mut sum, mut sum_sq, mut max) = (0u64, 0.0f64, 0.0f64, 0.0f64);
+        for ms in recent {
+            n += 1;
+            sum += ms;
+            sum_sq += ms * ms;
+            max = max.max(ms);
+        }
+        let mean = if n > 0 { sum / n as f64 } else { 0.0 };
+        let var = if n > 0 { (sum_sq / n as f64 - mean * mean).max(0.0) } else { 0.0 };
+
+        TimingStats {
+            ingress: ingress.to_string(),
+            samples: n,
+            mean_interval_ms: mean,
+            jitter_ms: var.sqrt(),
+            max_gap_ms: max,
+            last_packet_age_ms: self.last.map(|t| now.duration_since(t).as_millis() as u64),
+        }
+    }
+}
+
+/// Estadísticas de llegada de los últimos 30 s por entrada (puerto UDP o "serial")
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TimingStats {
+    pub ingress: String,
+    pub samples: u64,
+    pub mean_interval_ms: f64,
+    pub jitter_ms: f64,
+    pub max_gap_ms: f64,
+    pub last_packet_age_ms: Option<u64>,
+}
+
+/// Respuesta de `GET /api/stats/udp`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UdpStatsSnapshot {
+    pub packets: u64,
+    pub bytes: u64,
+    pub malformed: u64,
+    pub gaps: u64,
+    pub crc_verified: u64,
+    pub crc_mismatches: u64,
+    pub mavlink_unknown: u64,
+    pub mavlink_bad_crc: u64,
+    pub timing: Vec<TimingStats>,
+}
+
+#[derive(Debug)]
+struct SourceEntry {
+    packets: u64,
+    bytes: u64,
+    malformed: u64,
+    last_seen: DateTime<Utc>,
+    last_seen_at: Instant,
+}
+
+/// Fila de `GET /api/stats/udp/sources`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SourceStats {
+    pub addr: String,
+    pub packets: u64,
+    pub bytes: u64,
+    pub malformed: u64,
+    pub last_seen: String,
+}
+
+impl UdpStats {
+    pub fn new(gap_warn: Duration) -> Self {
+        Self { gap_warn, ..Default::default() }
+    }
+
+    /// Llegada de telemetría por `ingress`; avisa si el hueco supera el umbral
+    pub fn record_arrival(&self, ingress: &str) {
+        let gap = {
+            let mut timing = self.timing.lock().unwrap();
+            match timing.get_mut(ingress) {
+                Some(ring) => ring.push(Instant::now()),
+                None => timing.entry(ingress.to_string()).or_insert_with(ArrivalRing::new).push(Instant::now()),
+            }
+        };
+        if let Some(gap) = gap.filter(|g| !self.gap_warn.is_zero() && *g > self.gap_warn) {
+            self.gaps.fetch_add(1, Ordering::Relaxed);
+            warn!("⚠️  Hueco de telemetría en {ingress}: {} ms", gap.as_millis());
+        }
+    }
+
+    pub fn timing(&self) -> Vec<TimingStats> {
+        let now = Instant::now();
+        let timing = self.timing.lock().unwrap();
+        let mut out: Vec<_> = timing.iter().map(|(ingress, ring)| ring.summary(ingress, now)).collect();
+        out.sort_by(|a, b| a.ingress.cmp(&b.ingress));
+        out
+    }
+
+    pub fn snapshot(&self) -> UdpStatsSnapshot {
+        UdpStatsSnapshot {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            gaps: self.gaps.load(Ordering::Relaxed),
+            crc_verified: self.crc_verified.load(Ordering::Relaxed),
+            crc_mismatches: self.crc_mismatches.load(Ordering::Relaxed),
+            mavlink_unknown: self.mavlink_unknown.load(Ordering::Relaxed),
+            mavlink_bad_crc: self.mavlink_bad_crc.load(Ordering::Relaxed),
+            timing: self.timing(),
+        }
+    }
+
+    /// Un datagrama recibido desde `src`
+    pub fn record_packet(&self, src: SocketAddr, len: usize) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut sources = self.sources.lock().unwrap();
+        match sources.get_mut(&src) {
+            Some(e) => {
+                e.packets += 1;
+                e.bytes += len as u64;
+                e.last_seen = Utc::now();
+                e.last_seen_at = now;
+            }
+            None => {
+                // Solo al aparecer una fuente nueva barremos las inactivas
+                sources.retain(|_, e| now.duration_since(e.last_seen_at) < SOURCE_IDLE_EVICT);
+                sources.insert(src, SourceEntry {
+                    packets: 1,
+                    bytes: len as u64,
+                    malformed: 0,
+                    last_seen: Utc::now(),
+                    last_seen_at: now,
+                });
+            }
+        }
+    }
+
+    /// Tiempo desde el último datagrama de cualquier fuente
+    pub fn last_packet_age(&self) -> Option<Duration> {
+        let sources = self.sources.lock().unwrap();
+        sources.values().map(|e| e.last_seen_at.elapsed()).min()
+    }
+
+    /// Datagrama no-UTF8, JSON inválido o CRC erróneo
+    pub fn record_malformed(&self, src: SocketAddr) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+        if let Some(e) = self.sources.lock().unwrap().get_mut(&src) {
+            e.malformed += 1;
+        }
+    }
+
+    pub fn sources(&self) -> Vec<SourceStats> {
+        let now = Instant::now();
+        let mut sources = self.sources.lock().unwrap();
+        sources.retain(|_, e| now.duration_since(e.last_seen_at) < SOURCE_IDLE_EVICT);
+        sources
+            .iter()
+            .map(|(addr, e)| SourceStats {
+                addr: addr.to_string(),
+                packets: e.packets,
+                bytes: e.bytes,
+                malformed: e.malformed,
+                last_seen: e.last_seen.to_rfc3339(),
+            })
+            .collect()
+    }
+
+    pub fn record_checksum(&self, check: &Checksum) {
+        match check {
+            Checksum::Valid => { self.crc_verified.fetch_add(1, Ordering::Relaxed); }
+            Checksum::Mismatch { .. } => { self.crc_mismatches.fetch_add(1, Ordering::Relaxed); }
+            Checksum::Absent => {}
+        }
+    }
+}
+
+/// Decimación del stream hacia WS (la BD sigue recibiendo todo).
+/// `max_hz == 0` → sin límite.
+#[derive(Debug, Default)]
+pub struct StreamRate {
+    max_hz: AtomicU32,
+    last_sent: Mutex<HashMap<String, Instant>>,
+}
+
+impl StreamRate {
+    pub fn new(max_hz: u32) -> Self {
+        Self { max_hz: AtomicU32::new(max_hz), last_sent: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn max_hz(&self) -> u32 {
+        self.max_hz.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_hz(&self, hz: u32) {
+        self.max_hz.store(hz, Ordering::Relaxed);
+        self.last_sent.lock().unwrap().clear();
+    }
+
+    /// ¿Se reenvía este mensaje a WS? Solo se decima telemetría (`telemetry*`);
+    /// acks y demás eventos pasan siempre.
+    pub fn allow(&self, kind: Option<&str>) -> bool {
+        let hz = self.max_hz();
+        let Some(kind) = kind.filter(|k| hz > 0 && k.starts_with("telemetry")) else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let min_gap = Duration::from_secs_f64(1.0 / hz as f64);
+        let mut last = self.last_sent.lock().unwrap();
+        match last.get_mut(kind) {
+            Some(prev) if now.duration_since(*prev) < min_gap => false,
+            Some(prev) => { *prev = now; true }
+            None => { last.insert(kind.to_string(), now); true }
+        }
+    }
+}
+
+/// Columnas para firmwares antiguos que emiten CSV (`12.3,45.6,1500,...`).
+/// Vacío = las líneas no-JSON se guardan como texto opaco.
+#[derive(Debug, Default)]
+pub struct CsvMapping {
+    fields: RwLock<Vec<String>>,
+}
+
+impl CsvMapping {
+    pub fn set(&self, fields: Vec<String>) {
+        *self.fields.write().unwrap() = fields;
+    }
+
+    pub fn fields(&self) -> Vec<String> {
+        self.fields.read().unwrap().clone()
+    }
+
+    /// `None` si no hay mapeo o la línea no parece CSV;
+    /// `Some(Err(n))` si el número de columnas no coincide
+    pub fn convert(&self, line: &str) -> Option<Result<serde_json::Value, usize>> {
+        let fields = self.fields.read().unwrap();
+        if fields.is_empty() || !line.contains(',') {
+            return None;
+        }
+        let cols: Vec<&str> = line.trim().split(',').map(str::trim).collect();
+        if cols.len() != fields.len() {
+            return Some(Err(cols.len()));
+        }
+        let obj = fields
+            .iter()
+            .zip(cols)
+            .map(|(name, raw)| {
+                let v = raw.parse::<f64>().ok()
+                    .and_then(|x| serde_json::Number::from_f64(x).map(serde_json::Value::Number))
+                    .unwrap_or_else(|| raw.into());
+                (name.clone(), v)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        Some(Ok(serde_json::Value::Object(obj)))
+    }
+}
+
+/// Resultado de verificar el CRC32 opcional de un datagrama
+#[derive(Debug, PartialEq, Eq)]
+pub enum Checksum {
+    /// Sin campo `crc` → se acepta tal cual (firmware antiguo)
+    Absent,
+    Valid,
+    Mismatch { expected: Option<u32>, actual: Option<u32> },
+}
+
+/// `crc` puede venir como número o como string hex ("0x1a2b3c4d")
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CrcField {
+    Num(u64),
+    Text(String),
+}
+
+impl CrcField {
+    fn value(&self) -> Option<u32> {
+        match self {
+            CrcField::Num(n) => u32::try_from(*n).ok(),
+            CrcField::Text(s) => {
+                let s = s.trim();
+                let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+                u32::from_str_radix(hex, 16).ok()
+            }
+        }
+    }
+}
+
+/// { "type": ..., "payload": {...}, "crc": ... }
+#[derive(Deserialize)]
+struct CrcEnvelope<'a> {
+    #[serde(borrow)]
+    payload: Option<&'a RawValue>,
+    crc: Option<CrcField>,
+}
+
+/// Verifica el CRC32 calculado por el firmware sobre los bytes exactos de `payload`.
+/// Texto no-JSON, que no sea un objeto o sin `crc` → `Absent`.
+pub fn verify_checksum(text: &str) -> Checksum {
+    // serde también acepta el struct como array (`[payload, crc]`): solo objetos
+    if !text.trim_start().starts_with('{') {
+        return Checksum::Absent;
+    }
+    let env = match serde_json::from_str::<CrcEnvelope>(text) {
+        Ok(env) => env,
+        Err(_) => return Checksum::Absent,
+    };
+    let Some(crc) = env.crc else { return Checksum::Absent };
+
+    let expected = crc.value();
+    let actual = env.payload.map(|p| crc32fast::hash(p.get().as_bytes()));
+
+    match (expected, actual) {
+        (Some(e), Some(a)) if e == a => Checksum::Valid,
+        _ => Checksum::Mismatch { expected, actual },
+    }
+}
+
+/// Bucle de recepción de un enlace (UDP o serie): verifica, etiqueta con la
+/// entrada, difunde a WS (con decimación) y persiste en el vuelo activo.
+pub async fn run_receiver(link: Arc<dyn TelemetryTransport>, ctx: WsContext) {
+    let ingress = link.ingress();
+    let ingress_label = match &ingress {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    let mut incoming = link.incoming();
+
+    while let Some(item) = incoming.next().await {
+        match item {
+            Ok(Inbound { src, bytes }) => {
+                ctx.capture.record(src, &ingress_label, &bytes);
+                handle_inbound(&ctx, src, &bytes, &ingress, &ingress_label).await;
+            }
+            Err(e) => {
+                error!("❌ Error de recepción en {}: {e}", link.describe());
+                break;
+            }
+        }
+    }
+}
+
+/// Procesa un mensaje entrante (JSON, texto o ya traducido desde MAVLink)
+pub async fn handle_inbound(
+    ctx: &WsContext,
+    src: Option<SocketAddr>,
+    bytes: &[u8],
+    ingress: &serde_json::Value,
+    ingress_label: &str,
+) {
+    let arrival = Utc::now();
+    let stats = &ctx.udp_stats;
+    if let Some(src) = src { stats.record_packet(src, bytes.len()); }
+    let malformed = || {
+        if let Some(src) = src { stats.record_malformed(src); }
+    };
+
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        malformed();
+        return;
+    };
+    // CRC32 opcional: sin campo `crc` pasa igual que antes
+    let check = verify_checksum(text);
+    stats.record_checksum(&check);
+    if let Checksum::Mismatch { expected, actual } = check {
+        malformed();
+        warn!("⚠️  CRC inválido desde {ingress_label}: esperado={expected:?} calculado={actual:?}, descartado");
+        return;
+    }
+    let verified = check == Checksum::Valid;
+
+    let mut msg = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(mut v) => {
+            if verified && let Some(obj) = v.as_object_mut() {
+                obj.insert("verified".into(), serde_json::Value::Bool(true));
+            }
+            match v.get("type").and_then(|t| t.as_str()) {
+                Some("ack") | Some("telemetry") => v,
+                _ => serde_json::json!({ "type":"telemetry", "payload": v }),
+            }
+        }
+        Err(_) => match ctx.csv_map.convert(text) {
+            Some(Ok(obj)) => serde_json::json!({ "type":"telemetry", "payload": obj }),
+            Some(Err(cols)) => {
+                malformed();
+                debug!("CSV con {cols} columnas no coincide con el mapeo");
+                serde_json::json!({ "type":"telemetry", "payload": text })
+            }
+            None => {
+                malformed();
+                serde_json::json!({ "type":"telemetry", "payload": text })
+            }
+        },
+    };
+    msg["port"] = ingress.clone();
+
+    // Respuesta a un envío crudo de `POST /api/esp32/send` (ack con su request_id o eco)
+    let ack_rid = (msg.get("type").and_then(|t| t.as_str()) == Some("ack"))
+        .then(|| msg.get("request_id").and_then(|v| v.as_str()))
+        .flatten();
+    ctx.esp32_replies.offer(ack_rid, text);
+
+    // Esquema de campos opcional: corrige o manda a cuarentena
+    let quarantined = msg.get("type").and_then(|t| t.as_str()) == Some("telemetry")
+        && msg.get_mut("payload").is_some_and(|p| ctx.schema.validate(p) == Verdict::Quarantine);
+
+    if let (Some(src), Some(failover)) = (src, &ctx.esp32_failover)
+        && matches!(msg.get("type").and_then(|t| t.as_str()), Some("telemetry" | "ack"))
+    {
+        failover.heard(src.ip());
+    }
+
+    let mut routed = false;
+    match msg.get("type").and_then(|t| t.as_str()) {
+        Some("telemetry") => stats.record_arrival(ingress_label),
+        Some("ack") => {
+            let rid = msg.get("request_id").and_then(|v| v.as_str());
+            ctx.log_command(rid, "ack", text);
+            // Acks del firmware: al cliente que mandó el comando
+            if let Some(rid) = rid {
+                ctx.send_ack(rid, msg.to_string());
+                routed = true;
+            }
+        }
+        _ => {}
+    }
+
+    // Decimación solo hacia WS; la BD recibe el stream completo
+    let kind = msg.get("type").and_then(|t| t.as_str());
+    if !quarantined && !routed && ctx.stream_rate.allow(kind) {
+        let _ = ctx.broadcast(msg.to_string());
+    }
+
+    // La cuarentena se guarda siempre; el resto, solo con un vuelo grabando
+    let fid = if quarantined {
+        Some(QUARANTINE_FLIGHT_ID.to_string())
+    } else {
+        ctx.flight_id.read().await.clone()
+    };
+    if let Some(fid) = fid {
+        // Con timestamp propio o `t_us` del firmware se guarda el instante de muestreo, no el de llegada
+        let ts = ctx.clock.timestamp(&msg, arrival);
+        ctx.telemetry_writer.push(&fid, &msg, ts);
+    }
+}
+
+/// Difunde `{"type":"timing_stats"}` cada `every`
+pub async fn broadcast_timing_stats(ctx: WsContext, every: Duration) {
+    let mut tick = tokio::time::interval(every);
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let msg = serde_json::json!({ "type": "timing_stats", "ports": ctx.udp_stats.timing() });
+        let _ = ctx.broadcast(msg.to_string());
+    }
+}
+
+/// IP local por la que sale el tráfico hacia `remote` (cambia al cambiar de interfaz)
+async fn route_ip(remote: SocketAddr) -> Option<IpAddr> {
+    let probe = tokio::net::UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    probe.connect(remote).await.ok()?;
+    probe.local_addr().ok().map(|a| a.ip())
+}
+
+/// Re-enlaza el socket UDP tras `max_failures` envíos fallidos seguidos o cuando
+/// cambia la interfaz de salida hacia el ESP32 (sondeo cada `iface_poll`).
+pub async fn run_rebind_watchdog(
+    link: Arc<UdpTransport>,
+    ctx: WsContext,
+    max_failures: u32,
+    iface_poll: Option<Duration>,
+) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let mut last_ip = route_ip(link.remote()).await;
+    let mut last_poll = Instant::now();
+
+    loop {
+        tick.tick().await;
+
+        let mut reason = None;
+        if max_failures > 0 && link.send_failures() >= max_failures {
+            reason = Some("send_failures");
+        }
+        if iface_poll.is_some_and(|p| last_poll.elapsed() >= p) {
+            last_poll = Instant::now();
+            // Sin ruta (interfaz caída) no se re-enlaza; se espera a la nueva
+            if let Some(ip) = route_ip(link.remote()).await {
+                if last_ip.is_some_and(|prev| prev != ip) {
+                    reason = reason.or(Some("interface_change"));
+                }
+                last_ip = Some(ip);
+            }
+        }
+        let Some(reason) = reason else { continue };
+
+        let port = link.local().port();
+        warn!("🔁 Re-enlazando UDP {port} ({reason})");
+        match link.rebind().await {
+            Ok(()) => {
+                info!("✅ UDP {port} re-enlazado");
+                let msg = serde_json::json!({ "type": "udp_rebound", "port": port, "reason": reason });
+                let _ = ctx.broadcast(msg.to_string());
+            }
+            Err(e) => error!("❌ No se pudo re-enlazar UDP {port}: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `{"type":"telemetry","payload":<payload>,"crc":<crc>}` con el CRC32 real de `payload`
+    fn signed(payload: &str) -> (String, u32) {
+        let crc = crc32fast::hash(payload.as_bytes());
+        (format!(r#"{{"type":"telemetry","payload":{payload},"crc":{crc}}}"#), crc)
+    }
+
+    #[test]
+    fn checksum_accepts_number_and_hex() {
+        let payload = r#"{"AngleRoll":1.25,"AnglePitch":-3}"#;
+        let (text, crc) = signed(payload);
+        assert_eq!(verify_checksum(&text), Checksum::Valid);
+        let hex = format!(r#"{{"payload":{payload},"crc":"0x{crc:08X}"}}"#);
+        assert_eq!(verify_checksum(&hex), Checksum::Valid);
+        assert_eq!(verify_checksum(r#"{"payload":{"a":1}}"#), Checksum::Absent);
+        assert_eq!(verify_checksum("PING"), Checksum::Absent);
+    }
+
+    #[test]
+    fn checksum_rejects_a_corrupted_digit() {
+        let (text, crc) = signed(r#"{"AngleRoll":1.25}"#);
+        let corrupted = text.replace("1.25", "1.35");
+        assert_eq!(
+            verify_checksum(&corrupted),
+            Checksum::Mismatch { expected: Some(crc), actual: Some(crc32fast::hash(br#"{"AngleRoll":1.35}"#)) }
+        );
+    }
+
+    #[test]
+    fn checksum_rejects_bad_hex_and_missing_payload() {
+        let bad_hex = r#"{"payload":{"a":1},"crc":"0xZZ"}"#;
+        assert!(matches!(verify_checksum(bad_hex), Checksum::Mismatch { expected: None, actual: Some(_) }));
+        let no_payload = r#"{"type":"telemetry","crc":123}"#;
+        assert_eq!(verify_checksum(no_payload), Checksum::Mismatch { expected: Some(123), actual: None });
+    }
+
+    #[test]
+    fn checksum_ignores_array_envelopes() {
+        let text = format!("[{{}}, {}]", crc32fast::hash(b"{}"));
+        assert_eq!(verify_checksum(&text), Checksum::Absent);
+    }
+
+    #[tokio::test]
+    async fn array_envelope_does_not_stop_the_receiver() {
+        let ctx = WsContext::for_tests(None);
+        let mut rx = ctx.tx.subscribe();
+        let text = format!("[{{}}, {}]", crc32fast::hash(b"{}"));
+        handle_inbound(&ctx, None, text.as_bytes(), &"udp".into(), "udp").await;
+        let msg: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(msg["type"], "telemetry");
+        assert!(msg["payload"].is_array());
+        assert!(msg.get("verified").is_none());
+    }
+
+    #[tokio::test]
+    async fn quarantined_packets_are_stored_without_a_recording_flight() {
+        let ctx = WsContext::for_tests_sqlite();
+        ctx.schema.set(serde_json::from_value(serde_json::json!({
+            "fields": { "AngleRoll": { "type": "number", "min": -90.0, "max": 90.0 } }
+        })).unwrap());
+        assert!(ctx.flight_id.read().await.is_none());
+        for roll in [500.0, 1.0] {
+            let text = serde_json::json!({ "type": "telemetry", "payload": { "AngleRoll": roll } }).to_string();
+            handle_inbound(&ctx, None, text.as_bytes(), &"udp".into(), "udp").await;
+        }
+        ctx.telemetry_writer.flush(&ctx.questdb).await;
+        // Solo el paquete en cuarentena: el válido no tiene vuelo al que ir
+        assert_eq!(ctx.telemetry_writer.snapshot().written, 1);
+        let points = ctx.questdb.fetch_flight_points(QUARANTINE_FLIGHT_ID, None, None, 10).await.unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(ctx.schema.state().quarantined, 1);
+    }
+}
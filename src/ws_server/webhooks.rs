@@ -0,0 +1,216 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use super::server::WsContext;
+
+/// Espera máxima de cada POST: un endpoint muerto no retiene las entregas
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Reintentos tras el primer intento fallido, con espera 1 s, 2 s, 4 s...
+const RETRIES: u32 = 3;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+/// Eventos pendientes de repartir; si se llena se descartan con aviso
+const QUEUE: usize = 256;
+pub const MAX_WEBHOOKS: usize = 20;
+
+/// Qué avisa un webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// Empieza una grabación
+    Start,
+    /// Termina una grabación (también al apagar)
+    Stop,
+    /// El ESP32 deja de mandar datos
+    LinkLost,
+    /// Una regla de alerta salta
+    Alert,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Stop => "stop",
+            Self::LinkLost => "link_lost",
+            Self::Alert => "alert",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string())).ok()
+    }
+}
+
+/// Resultado de la última entrega
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeliveryStatus {
+    pub ts: String,
+    pub event: WebhookEvent,
+    pub ok: bool,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Entregas correctas / agotados los reintentos, desde el arranque
+    pub delivered: u64,
+    pub failed: u64,
+    pub last_delivery: Option<DeliveryStatus>,
+}
+
+struct Notification {
+    event: WebhookEvent,
+    body: serde_json::Value,
+}
+
+/// Webhooks registrados y cola hacia el repartidor (`run_dispatcher`)
+pub struct Webhooks {
+    hooks: Mutex<Vec<Webhook>>,
+    tx: mpsc::Sender<Notification>,
+    rx: Mutex<Option<mpsc::Receiver<Notification>>>,
+    client: reqwest::Client,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE);
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default();
+        Self { hooks: Mutex::new(Vec::new()), tx, rx: Mutex::new(Some(rx)), client }
+    }
+}
+
+/// `events` como se guardan en `webhooks.events`
+pub fn events_csv(events: &[WebhookEvent]) -> String {
+    events.iter().map(|e| e.as_str()).collect::<Vec<_>>().join(",")
+}
+
+impl Webhooks {
+    pub fn list(&self) -> Vec<Webhook> {
+        self.hooks.lock().unwrap().clone()
+    }
+
+    pub fn count(&self) -> usize {
+        self.hooks.lock().unwrap().len()
+    }
+
+    pub fn add(&self, id: String, url: String, events: Vec<WebhookEvent>) -> Webhook {
+        let hook = Webhook { id, url, events, delivered: 0, failed: 0, last_delivery: None };
+        self.hooks.lock().unwrap().push(hook.clone());
+        hook
+    }
+
+    /// Restaura uno guardado (`events` separados por comas; los desconocidos se ignoran)
+    pub fn restore(&self, id: String, url: String, events: &str) {
+        let events = events.split(',').filter_map(WebhookEvent::parse).collect();
+        self.add(id, url, events);
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        let mut hooks = self.hooks.lock().unwrap();
+        let before = hooks.len();
+        hooks.retain(|h| h.id != id);
+        hooks.len() != before
+    }
+
+    /// Encola el evento sin esperar; `fields` se añade al cuerpo junto a `event`, `ts` y `text`
+    pub fn notify(&self, event: WebhookEvent, text: String, fields: serde_json::Value) {
+        if self.hooks.lock().unwrap().iter().all(|h| !h.events.contains(&event)) {
+            return;
+        }
+        let mut body = serde_json::json!({ "event": event.as_str(), "ts": Utc::now().to_rfc3339(), "text": text });
+        if let (Some(body), serde_json::Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+        if self.tx.try_send(Notification { event, body }).is_err() {
+            warn!("⚠️  Cola de webhooks llena; evento {} descartado", event.as_str());
+        }
+    }
+
+    fn record(&self, id: &str, status: DeliveryStatus) {
+        let mut hooks = self.hooks.lock().unwrap();
+        let Some(hook) = hooks.iter_mut().find(|h| h.id == id) else { return };
+        if status.ok { hook.delivered += 1 } else { hook.failed += 1 }
+        hook.last_delivery = Some(status);
+    }
+}
+
+/// Reparte cada evento a los webhooks suscritos; cada entrega va en su propia task
+/// para que los reintentos de un endpoint caído no frenen a los demás
+pub async fn run_dispatcher(ctx: WsContext) {
+    let Some(mut rx) = ctx.webhooks.rx.lock().unwrap().take() else { return };
+    while let Some(n) = rx.recv().await {
+        let targets: Vec<(String, String)> = ctx.webhooks.hooks.lock().unwrap().iter()
+            .filter(|h| h.events.contains(&n.event))
+            .map(|h| (h.id.clone(), h.url.clone()))
+            .collect();
+        for (id, url) in targets {
+            let (ctx, body) = (ctx.clone(), n.body.clone());
+            tokio::spawn(async move {
+                let status = deliver(&ctx.webhooks.client, &url, n.event, &body).await;
+                if !status.ok {
+                    warn!("❌ Webhook {id} ({url}): {} tras {} intentos", status.error.as_deref().unwrap_or("?"), status.attempts);
+                }
+                ctx.webhooks.record(&id, status);
+            });
+        }
+    }
+}
+
+async fn deliver(client: &reqwest::Client, url: &str, event: WebhookEvent, body: &serde_json::Value) -> DeliveryStatus {
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let (http_status, error) = match client.post(url).json(body).send().await {
+            Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+            Ok(res) => (Some(res.status().as_u16()), Some(format!("HTTP {}", res.status()))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let ok = error.is_none();
+        if ok || attempts > RETRIES {
+            let ts = Utc::now().to_rfc3339();
+            return DeliveryStatus { ts, event, ok, attempts, http_status, error };
+        }
+        debug!("🔁 Webhook {url}: intento {attempts} fallido, reintento en {backoff:?}");
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Avisa `link_lost` cuando el ESP32 lleva `silence` sin mandar nada (una vez por corte)
+pub async fn run_link_watch(ctx: WsContext, silence: Duration) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let mut lost = false;
+    loop {
+        tick.tick().await;
+        // Sin ningún paquete todavía no hay enlace que perder
+        let Some(age) = ctx.udp_stats.last_packet_age() else { continue };
+        match (lost, age >= silence) {
+            (false, true) => {
+                lost = true;
+                info!("📡 Sin datos del ESP32 desde hace {} s", age.as_secs());
+                let flight_id = ctx.flight_id.read().await.clone();
+                ctx.webhooks.notify(
+                    WebhookEvent::LinkLost,
+                    format!("📡 Artheris: sin datos del ESP32 desde hace {} s", age.as_secs()),
+                    serde_json::json!({ "silence_s": age.as_secs(), "flight_id": flight_id }),
+                );
+            }
+            (true, false) => lost = false,
+            _ => {}
+        }
+    }
+}
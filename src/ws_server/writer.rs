@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use super::questdb::{LogRow, OptionalDb, TypedValues};
+use super::series::field_value;
+use super::spool::Spool;
+
+/// Tamaño del buffer, filas por `INSERT` y espera máxima entre vaciados
+#[derive(Debug, Clone, Copy)]
+pub struct WriterConfig {
+    pub capacity: usize,
+    pub batch_rows: usize,
+    pub flush_every: Duration,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self { capacity: 20_000, batch_rows: 500, flush_every: Duration::from_millis(200) }
+    }
+}
+
+/// Buffer de escritura diferida de la telemetría: el receptor UDP encola y sigue, y
+/// `run` lo vuelca a QuestDB en `INSERT` de varias filas cada `flush_every` o al juntar
+/// `batch_rows`. Lleno, se descarta la fila más antigua en vez de frenar al receptor
+pub struct TelemetryWriter {
+    config: WriterConfig,
+    queue: Mutex<VecDeque<LogRow>>,
+    /// Adonde va lo que QuestDB rechaza, para reimportarlo después
+    spool: Option<Arc<Spool>>,
+    /// Vuelo activo y sus campos con columna en `flight_metrics`
+    typed: Mutex<Option<(String, Arc<[String]>)>>,
+    wake: Notify,
+    /// Un solo vaciado a la vez: el periódico y los forzados (stop, apagado) no se pisan
+    flushing: tokio::sync::Mutex<()>,
+    dropped: AtomicU64,
+    written: AtomicU64,
+    spilled: AtomicU64,
+    failed: AtomicU64,
+    batches: AtomicU64,
+    last_flush_us: AtomicU64,
+    max_flush_us: AtomicU64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WriterSnapshot {
+    /// Filas esperando a QuestDB ahora mismo (gauge)
+    pub depth: usize,
+    pub capacity: usize,
+    /// Descartadas por buffer lleno
+    pub dropped: u64,
+    pub written: u64,
+    /// Lotes que QuestDB rechazó: al spool o, si tampoco se pudo, perdidas
+    pub spilled: u64,
+    pub failed: u64,
+    pub batches: u64,
+    /// Duración del último `INSERT` por lotes y la peor
+    pub last_flush_ms: f64,
+    pub max_flush_ms: f64,
+}
+
+impl TelemetryWriter {
+    pub fn new(config: WriterConfig, spool: Option<Arc<Spool>>) -> Self {
+        Self {
+            config: WriterConfig { capacity: config.capacity.max(1), batch_rows: config.batch_rows.max(1), ..config },
+            queue: Mutex::new(VecDeque::new()),
+            spool,
+            typed: Mutex::new(None),
+            wake: Notify::new(),
+            flushing: Default::default(),
+            dropped: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            batches: AtomicU64::new(0),
+            last_flush_us: AtomicU64::new(0),
+            max_flush_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Campos tipados del vuelo que se graba (`None` al parar)
+    pub fn set_typed(&self, typed: Option<(String, Vec<String>)>) {
+        *self.typed.lock().unwrap() = typed.map(|(fid, fields)| (fid, fields.into()));
+    }
+
+    /// Encola sin esperar; si `flight_id` tiene campos tipados se extraen del `payload` del mensaje
+    pub fn push(&self, flight_id: &str, msg: &serde_json::Value, ts: DateTime<Utc>) {
+        let typed = match &*self.typed.lock().unwrap() {
+            Some((fid, fields)) if fid == flight_id => {
+                let obj = msg.get("payload").and_then(|p| p.as_object());
+                let values = fields.iter().map(|f| obj.and_then(|o| field_value(o, f))).collect();
+                Some(TypedValues { fields: fields.clone(), values })
+            }
+            _ => None,
+        };
+        let row = LogRow { ts, flight_id: flight_id.to_owned(), payload: msg.to_string(), typed };
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.config.capacity {
+            queue.pop_front();
+            if self.dropped.fetch_add(1, Ordering::Relaxed).is_multiple_of(1000) {
+                warn!("⚠️  Buffer de telemetría lleno ({}); se descartan las filas más antiguas", self.config.capacity);
+            }
+        }
+        queue.push_back(row);
+        if queue.len() >= self.config.batch_rows {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Vuelca todo lo pendiente, lote a lote. Al parar una grabación y al apagar se
+    /// espera a esto para que el vuelo quede completo en la BD (o en el spool)
+    pub async fn flush(&self, db: &OptionalDb) {
+        let _guard = self.flushing.lock().await;
+        loop {
+            let batch: Vec<LogRow> = {
+                let mut queue = self.queue.lock().unwrap();
+                let n = queue.len().min(self.config.batch_rows);
+                queue.drain(..n).collect()
+            };
+            if batch.is_empty() {
+                return;
+            }
+            let started = Instant::now();
+            let res = db.insert_flight_logs(&batch).await;
+            let took = started.elapsed().as_micros() as u64;
+            self.last_flush_us.store(took, Ordering::Relaxed);
+            self.max_flush_us.fetch_max(took, Ordering::Relaxed);
+            self.batches.fetch_add(1, Ordering::Relaxed);
+            match res {
+                Ok(()) => { self.written.fetch_add(batch.len() as u64, Ordering::Relaxed); }
+                Err(e) => {
+                    let spilled = match &self.spool {
+                        Some(spool) => spool.spill(&batch).await.map_err(|io| error!("❌ Spool no escrito: {io}")).is_ok(),
+                        None => false,
+                    };
+                    if spilled {
+                        self.spilled.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                        warn!("💾 {} filas de telemetría al spool: {e}", batch.len());
+                    } else {
+                        self.failed.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                        error!("❌ Error guardando {} filas de telemetría en QuestDB: {e}", batch.len());
+                        // Ese lote se pierde; el resto espera al próximo tick en vez de insistir con la BD caída
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> WriterSnapshot {
+        WriterSnapshot {
+            depth: self.queue.lock().unwrap().len(),
+            capacity: self.config.capacity,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            batches: self.batches.load(Ordering::Relaxed),
+            last_flush_ms: self.last_flush_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            max_flush_ms: self.max_flush_us.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+/// Task de vaciado: cada `flush_every` o en cuanto hay un lote completo
+pub async fn run(writer: Arc<TelemetryWriter>, db: OptionalDb) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(writer.config.flush_every) => {}
+            _ = writer.wake.notified() => {}
+        }
+        writer.flush(&db).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::server::test_db_config;
+    use super::super::sqlite::SqliteStore;
+
+    fn sqlite() -> OptionalDb {
+        OptionalDb::with_store(test_db_config(), Arc::new(SqliteStore::open(":memory:").unwrap()))
+    }
+
+    fn burst(writer: &TelemetryWriter, seqs: std::ops::Range<u64>) {
+        let t0 = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        for seq in seqs {
+            let msg = serde_json::json!({ "type": "telemetry", "payload": { "seq": seq } });
+            writer.push("flt", &msg, t0 + chrono::Duration::milliseconds(seq as i64));
+        }
+    }
+
+    async fn stored_seqs(db: &OptionalDb) -> Vec<u64> {
+        let points = db.fetch_flight_points("flt", None, None, 100_000).await.unwrap();
+        points.iter().map(|p| p.payload["payload"]["seq"].as_u64().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn burst_larger_than_a_batch_is_written_whole_and_in_order() {
+        let db = sqlite();
+        let writer = TelemetryWriter::new(WriterConfig { capacity: 10_000, batch_rows: 100, ..Default::default() }, None);
+        burst(&writer, 0..1234);
+        writer.flush(&db).await;
+
+        let s = writer.snapshot();
+        assert_eq!((s.written, s.batches, s.depth, s.dropped, s.failed), (1234, 13, 0, 0, 0));
+        assert_eq!(stored_seqs(&db).await, (0..1234).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn full_buffer_keeps_the_newest_rows_in_order() {
+        let db = sqlite();
+        let writer = TelemetryWriter::new(WriterConfig { capacity: 300, batch_rows: 64, ..Default::default() }, None);
+        burst(&writer, 0..1000);
+        assert_eq!(writer.snapshot().depth, 300);
+        writer.flush(&db).await;
+
+        let s = writer.snapshot();
+        assert_eq!((s.written, s.dropped, s.batches), (300, 700, 5));
+        assert_eq!(stored_seqs(&db).await, (700..1000).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn unreachable_db_loses_one_batch_and_keeps_the_rest_queued() {
+        let writer = TelemetryWriter::new(WriterConfig { capacity: 1000, batch_rows: 100, ..Default::default() }, None);
+        burst(&writer, 0..250);
+        writer.flush(&OptionalDb::new(test_db_config())).await;
+
+        let s = writer.snapshot();
+        assert_eq!((s.written, s.failed, s.depth), (0, 100, 150));
+        // Al volver la BD sale lo que quedaba, a partir del lote perdido
+        let db = sqlite();
+        writer.flush(&db).await;
+        assert_eq!(stored_seqs(&db).await, (100..250).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Contadores del canal broadcast hacia los clientes WS
+#[derive(Debug)]
+pub struct BroadcastStats {
+    capacity: usize,
+    sent: AtomicU64,
+    /// `tx.send` sin ningún receptor suscrito
+    no_receivers: AtomicU64,
+    /// Mensajes perdidos por receptores atrasados (`RecvError::Lagged`)
+    lagged: AtomicU64,
+    lagged_events: AtomicU64,
+    warned: AtomicBool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BroadcastStatsSnapshot {
+    pub capacity: usize,
+    pub sent: u64,
+    pub no_receivers: u64,
+    pub lagged: u64,
+    pub lagged_events: u64,
+}
+
+impl BroadcastStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            sent: AtomicU64::new(0),
+            no_receivers: AtomicU64::new(0),
+            lagged: AtomicU64::new(0),
+            lagged_events: AtomicU64::new(0),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record_send(&self, receivers: Option<usize>) {
+        match receivers {
+            Some(_) => self.sent.fetch_add(1, Ordering::Relaxed),
+            None => self.no_receivers.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn record_lagged(&self, n: u64) {
+        self.lagged.fetch_add(n, Ordering::Relaxed);
+        self.lagged_events.fetch_add(1, Ordering::Relaxed);
+        // Un solo aviso: por mensaje inundaría el log justo cuando el canal va saturado
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                "⚠️  Canal WS saturado (capacidad {}); subir ARTHERIS_WS_CHANNEL_CAP",
+                self.capacity
+            );
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.lagged.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> BroadcastStatsSnapshot {
+        BroadcastStatsSnapshot {
+            capacity: self.capacity,
+            sent: self.sent.load(Ordering::Relaxed),
+            no_receivers: self.no_receivers.load(Ordering::Relaxed),
+            lagged: self.lagged.load(Ordering::Relaxed),
+            lagged_events: self.lagged_events.load(Ordering::Relaxed),
+        }
+    }
+}